@@ -0,0 +1,42 @@
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use rand::prelude::*;
+use rand_chacha::ChaChaRng;
+use veloren_world::heightmap::filter::{Edges, gaussian_blur, smooth_altitudes};
+
+const SIDES: [u32; 2] = [1024, 4096];
+
+/// Rolling terrain with some noise on top, generated from a fixed seed so
+/// that results are comparable between runs.
+fn terrain(side: u32) -> Vec<f64> {
+    let mut rng = ChaChaRng::seed_from_u64(side as u64);
+    (0..side * side)
+        .map(|i| {
+            let (x, y) = ((i % side) as f64, (i / side) as f64);
+            let hills = (x / 97.0).sin() * (y / 131.0).cos() * 400.0;
+            hills + rng.gen_range(-20.0..20.0)
+        })
+        .collect()
+}
+
+fn heightmap(c: &mut Criterion) {
+    let mut group = c.benchmark_group("heightmap");
+    group.sample_size(10);
+    for side in SIDES {
+        let alt = terrain(side);
+        group.throughput(Throughput::Elements(alt.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("smooth_altitudes", side),
+            &alt,
+            |b, alt| {
+                b.iter(|| black_box(smooth_altitudes(alt, side, side, Edges::Clamp)));
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("gaussian_blur", side), &alt, |b, alt| {
+            b.iter(|| black_box(gaussian_blur(alt, side, side, 2.0, Edges::Clamp)));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, heightmap);
+criterion_main!(benches);
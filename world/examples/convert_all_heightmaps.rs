@@ -1,130 +1,306 @@
-/// This example traverses all .bin files in a given folder (specified as the first command-line argument),
-/// extracts the `alt` array from each world file (formatted as Veloren 0.7.0),
-/// computes its minimum and maximum values,
-/// re-maps the alt values to the range 0\u2013255 for a grayscale height map,
-/// prints the original value range for each file,
-/// and saves the height map as a PNG file with the same base name (but with a .png extension).
-///
-/// To run this example:
-///   cargo run --example convert_all_heightmaps --release -- /path/to/folder
-use std::env;
-use std::fs::{File, read_dir};
-use std::io::{BufReader, Read, Write};
-use std::path::{Path, PathBuf};
-use image::{ImageBuffer, Rgb, codecs::png::PngEncoder, ExtendedColorType, ImageEncoder};
-use image::codecs::png::{CompressionType, FilterType};
-use veloren_world::sim::WorldFile;
-use bincode;
+//! This example traverses all .bin (and .bin.zst) files in a given folder,
+//! renders the altitudes of each as a grayscale PNG heightmap (scaled so that
+//! the lowest point is black and the highest white), prints the original value
+//! range for each file, and saves the heightmap with the same base name (but
+//! with a .png extension, or .pgm for 16-bit PGM images with `--extension
+//! pgm`).  The `--color` modes, `--pyramid`, `--layers` and `--name-template`
+//! of `convert_heightmap` apply to every file.
+//!
+//! With `--global-range`, every map is instead shaded over the lowest and
+//! highest altitudes of all of them (found by loading them all first, in
+//! parallel), or over `--min` and `--max` where given, so that the same gray
+//! means the same altitude in every image.  The range is recorded in a
+//! `.range.json` sidecar next to each image.
+//!
+//! With `--no-overwrite`, maps whose images all exist already are skipped,
+//! without being loaded, so that an interrupted run can be resumed.
+//!
+//! With `--recursive`, the maps in subfolders are rendered too, and with
+//! `--out-dir` the images are written under another folder instead of next
+//! to the maps, in subfolders mirroring theirs, so that
+//! `maps/seeds/42/world.bin` is rendered to `out/seeds/42/world.png`.
+//!
+//! To run this example:
+//!   cargo run --example convert_all_heightmaps --features cli --release --
+//! /path/to/folder
+//!
+//! It exits with status 2, after saying so, if the folder holds no .bin files,
+//! and with status 1 on other errors.
+//!
+//! Add the `memmap` feature to memory-map the .bin files instead of reading
+//! them through a buffer, which loads them a little faster but keeps each
+//! file's pages resident while it is loaded, about doubling peak memory use.
+use clap::Parser;
+use rayon::prelude::*;
+use std::{
+    fs::{self, read_dir},
+    path::{Path, PathBuf},
+};
+use veloren_world::heightmap::{
+    self, Error,
+    cli::{self, ExportArgs, OutputArgs, OverwriteArgs},
+    export::RangeSource,
+    template,
+};
 
-/// Loads the .bin file from the given path and extracts the alt array.
-/// This example expects the world file to be in the Veloren 0.7.0 format.
-fn load_alt_array(file_path: &Path) -> Vec<f32> {
-    let file = File::open(file_path).expect("Failed to open file");
-    let mut reader = BufReader::new(file);
-    let mut buffer = Vec::new();
-    reader.read_to_end(&mut buffer).expect("Failed to read file");
+#[derive(Parser)]
+struct Cli {
+    /// Folder containing the .bin files to render
+    folder: PathBuf,
+    /// Also render the .bin files in the folder's subfolders, at any depth
+    #[arg(long)]
+    recursive: bool,
+    /// Write the images under this folder, in subfolders mirroring those of
+    /// the maps, created as needed, rather than next to the maps
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+    /// Extension of the images to write, which selects their format: png, or
+    /// pgm for 16-bit PGM
+    #[arg(long, default_value = "png")]
+    extension: String,
+    /// Shade every map over the altitude range of all of them, narrowed by
+    /// --min and --max if given, rather than each over its own
+    #[arg(long)]
+    global_range: bool,
+    #[command(flatten)]
+    export: ExportArgs,
+    #[command(flatten)]
+    overwrite: OverwriteArgs,
+    #[command(flatten)]
+    verbosity: OutputArgs,
+}
 
-    // Deserialize the buffer to get the world file.
-    let world_file: WorldFile = bincode::deserialize(&buffer)
-        .expect("Failed to deserialize world file");
-    
-    if let WorldFile::Veloren0_7_0(map) = world_file {
-        // Convert Vec<f64> to Vec<f32>
-        return map.alt.iter().map(|&x| x as f32).collect();
+/// Renders a single .bin file to a PNG at `output_path`, over `shared` if
+/// given and otherwise over its own range, printing its original altitude
+/// range.  Returns the number of bytes of images written, or `None` if it was
+/// skipped as its images exist.
+fn process_bin_file(
+    bin_path: &Path,
+    output_path: &Path,
+    args: &Cli,
+    shared: Option<((f64, f64), RangeSource)>,
+) -> Result<Option<u64>, Error> {
+    let OutputArgs { quiet, verbose } = args.verbosity;
+    if !quiet {
+        println!("Processing file: {}", bin_path.display());
+    }
+    // The size the outputs are named after is read from the header, so that
+    // maps whose images exist are skipped without being loaded.
+    let mut map = None;
+    let size = match heightmap::read_header(bin_path)?.map_size_lg {
+        Some(map_size_lg) => heightmap::checked_map_size(map_size_lg)?,
+        // Unrecognized files fail to load, saying why.
+        None => heightmap::map_size(map.insert(heightmap::load_map(bin_path)?)),
+    };
+    let output_paths = args.export.output_paths(output_path, size)?;
+    if let Some(existing) = args.overwrite.existing(&output_paths) {
+        OverwriteArgs::report_skipped(existing, quiet);
+        return Ok(None);
+    }
+    let map = match map {
+        Some(map) => map,
+        None => heightmap::load_map(bin_path)?,
+    };
+    let (min_alt, max_alt) = cli::map_range(&map, &args.export);
+    let clipped_range = cli::clipped_range(&map, &args.export);
+    let (range, source) = shared.unwrap_or_else(|| {
+        (
+            args.export
+                .shade_range(clipped_range.unwrap_or((min_alt, max_alt))),
+            args.export.range_source(),
+        )
+    });
+    if args.out_dir.is_some() {
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    let clipped = cli::export_over(&map, output_path, &args.export, range, source)?;
+    let bytes = output_paths
+        .iter()
+        .map(|path| Ok(fs::metadata(path)?.len()))
+        .sum::<Result<u64, Error>>()?;
+    if verbose {
+        println!("  map size: {}x{}", size.x, size.y);
+    }
+    if !quiet {
+        println!("  alt range: min = {}, max = {}", min_alt, max_alt);
+        if let (Some(percentile), Some((lo, hi))) = (args.export.clip_percentile, clipped_range) {
+            println!(
+                "  percentiles {} to {}: min = {}, max = {}",
+                percentile,
+                100.0 - percentile,
+                lo,
+                hi
+            );
+        }
+        if clipped > 0 {
+            println!(
+                "  clipped {} values outside {}..{}",
+                clipped, range.0, range.1
+            );
+        }
+        for path in output_paths {
+            println!("  Heightmap saved to: {}", path.display());
+        }
     }
-    panic!("Unsupported world file version");
+    Ok(Some(bytes))
 }
 
-/// Computes the minimum and maximum values in the alt array.
-fn compute_min_max(alt_array: &[f32]) -> (f32, f32) {
-    let mut min = f32::MAX;
-    let mut max = f32::MIN;
-    for &val in alt_array {
-        if val < min {
-            min = val;
-        }
-        if val > max {
-            max = val;
+/// The world files in `folder`, and in its subfolders with `recursive`,
+/// sorted, and the number of other entries.  Symbolic links to folders
+/// aren't followed, so that they can't lead the walk in circles.
+fn find_maps(folder: &Path, recursive: bool) -> Result<(Vec<PathBuf>, usize), Error> {
+    let (mut paths, mut skipped) = (Vec::new(), 0);
+    let mut folders = vec![folder.to_owned()];
+    while let Some(folder) = folders.pop() {
+        for entry in read_dir(&folder)? {
+            let entry = entry?;
+            let path = entry.path();
+            // Process only world files, compressed or not.
+            if heightmap::is_map_path(&path) {
+                paths.push(path);
+            } else if recursive && entry.file_type()?.is_dir() {
+                folders.push(path);
+            } else {
+                skipped += 1;
+            }
         }
     }
-    (min, max)
+    paths.sort();
+    Ok((paths, skipped))
 }
 
-/// Generates a heightmap PNG image from the alt array.
-/// The alt values are scaled to [0, 255] using the provided min and max values.
-fn generate_heightmap(alt_array: Vec<f32>, width: u32, height: u32, output_path: &Path, min: f32, max: f32) {
-    let mut heightmap: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
-    let range = max - min;
-    let range = if range == 0.0 { 1.0 } else { range };
+/// Path of the image of the map at `bin_path`: next to it, or at the same
+/// place under `--out-dir` relative to the folder.
+fn output_path(bin_path: &Path, args: &Cli) -> Result<PathBuf, Error> {
+    let path = match &args.out_dir {
+        Some(out_dir) => heightmap::mirrored_path(&args.folder, bin_path, out_dir)?,
+        None => bin_path.to_owned(),
+    };
+    Ok(heightmap::with_map_extension(&path, &args.extension))
+}
 
-    for (x, y, pixel) in heightmap.enumerate_pixels_mut() {
-        let alt = alt_array[(y * width + x) as usize];
-        let pixel_value = (((alt - min) / range) * 255.0).round() as u8;
-        *pixel = Rgb([pixel_value, pixel_value, pixel_value]);
+/// The range shared by every map with `--global-range`: that of all of them
+/// (or the union of their clipped ranges, with `--clip-percentile`), loaded
+/// in parallel, with either end replaced by `--min` or `--max`.
+fn global_range(paths: &[PathBuf], args: &Cli) -> Result<((f64, f64), RangeSource), Error> {
+    let source = match args.export.range_source() {
+        RangeSource::Map | RangeSource::Percentile => RangeSource::Batch,
+        source => source,
+    };
+    if let (Some(min), Some(max)) = (args.export.min, args.export.max) {
+        return Ok(((min, max), source));
     }
+    let range = paths
+        .par_iter()
+        .map(|path| {
+            let map = heightmap::load_map(path)?;
+            Ok(cli::clipped_range(&map, &args.export)
+                .unwrap_or_else(|| cli::map_range(&map, &args.export)))
+        })
+        .collect::<Result<Vec<_>, Error>>()?
+        .into_iter()
+        .fold((f64::MAX, f64::MIN), |(min, max), (lo, hi)| {
+            (min.min(lo), max.max(hi))
+        });
+    Ok((args.export.shade_range(range), source))
+}
 
-    let mut heightmap_png = Vec::new();
-    let mut encoder = PngEncoder::new_with_quality(
-        &mut heightmap_png,
-        CompressionType::Best,
-        FilterType::Paeth,
-    );
-    encoder.write_image(
-        heightmap.as_raw(),
-        heightmap.width(),
-        heightmap.height(),
-        ExtendedColorType::Rgb8,
-    ).expect("Failed to write PNG image");
-
-    let mut f = File::create(output_path).expect("Failed to create output file");
-    f.write_all(&heightmap_png).expect("Failed to write PNG data to file");
+/// Fails, before anything is written, if the images of two maps at
+/// `outputs` would be named alike: as those of maps in the same folder with a
+/// `--name-template` leaving out `{stem}`, or of `map.bin` and `map.bin.zst`.
+fn check_names(outputs: &[PathBuf], args: &Cli) -> Result<(), Error> {
+    if let Some(output) = template::first_duplicate(outputs) {
+        return Err(Error::NameTemplate(format!(
+            "several maps would be rendered to {}",
+            output.display()
+        )));
+    }
+    let folders = outputs
+        .iter()
+        .map(|output| output.parent().unwrap_or(Path::new("")).to_owned())
+        .collect::<Vec<_>>();
+    if let Some(first) = outputs.first() {
+        if template::first_duplicate(&folders).is_some()
+            && !args.export.name_template(first)?.uses("stem")
+        {
+            return Err(Error::NameTemplate(
+                "include {stem} in the template to name the images of each map apart".to_owned(),
+            ));
+        }
+    }
+    Ok(())
 }
 
-/// Processes a single .bin file:
-/// - Loads the alt array, computes the min/max,
-/// - Generates a PNG heightmap with the same base filename,
-/// - Prints the original range.
-fn process_bin_file(bin_path: &Path, width: u32, height: u32) {
-    println!("Processing file: {}", bin_path.display());
-    let alt_array = load_alt_array(bin_path);
-    let (min_alt, max_alt) = compute_min_max(&alt_array);
-    println!("  alt range: min = {}, max = {}", min_alt, max_alt);
+/// Exit status when the folder holds no world files, so that scripts can tell
+/// it apart from a failed conversion.
+const NO_MAPS_EXIT_CODE: i32 = 2;
 
-    // Create the output path with the same base name but .png extension.
-    let mut output_path = bin_path.to_path_buf();
-    output_path.set_extension("png");
+/// Renders every world file in the folder, returning how many were rendered.
+fn run(args: &Cli) -> Result<usize, Error> {
+    if !args.verbosity.quiet {
+        args.export.describe()?;
+    }
+    let (paths, skipped) = find_maps(&args.folder, args.recursive)?;
+    let outputs = paths
+        .iter()
+        .map(|path| output_path(path, args))
+        .collect::<Result<Vec<_>, Error>>()?;
+    check_names(&outputs, args)?;
 
-    generate_heightmap(alt_array, width, height, &output_path, min_alt, max_alt);
-    println!("  Heightmap saved to: {}", output_path.display());
+    let shared = if args.global_range && !paths.is_empty() {
+        let shared @ ((min, max), _) = global_range(&paths, args)?;
+        if !args.verbosity.quiet {
+            println!("Shading every map over {}..{}", min, max);
+        }
+        Some(shared)
+    } else {
+        None
+    };
+    let (mut existing, mut bytes) = (0, 0);
+    for (path, output) in paths.iter().zip(&outputs) {
+        match process_bin_file(path, output, args, shared)? {
+            Some(written) => bytes += written,
+            None => existing += 1,
+        }
+    }
+    if !paths.is_empty() && !args.verbosity.quiet {
+        println!(
+            "Processed {} map files, skipped {} other entries",
+            paths.len() - existing,
+            skipped
+        );
+        if existing > 0 {
+            println!("Skipped {} map files whose images exist", existing);
+        }
+        println!(
+            "Wrote {} bytes of images under {}",
+            bytes,
+            args.out_dir.as_ref().unwrap_or(&args.folder).display()
+        );
+    }
+    Ok(paths.len())
 }
 
 fn main() {
-    // Get the folder path from the command-line arguments.
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <folder_path>", args[0]);
+    let args = Cli::parse();
+    if !args.folder.is_dir() {
+        eprintln!(
+            "The provided path is not a directory: {}",
+            args.folder.display()
+        );
         std::process::exit(1);
     }
-    let folder_path = PathBuf::from(&args[1]);
-    if !folder_path.is_dir() {
-        eprintln!("The provided path is not a directory: {}", folder_path.display());
-        std::process::exit(1);
-    }
-
-    // Set the dimensions. These should match your world dimensions.
-    let width = 1024;
-    let height = 1024;
-
-    // Iterate through all entries in the folder.
-    for entry in read_dir(folder_path).expect("Failed to read directory") {
-        if let Ok(entry) = entry {
-            let path = entry.path();
-            // Process only files with the .bin extension.
-            if let Some(ext) = path.extension() {
-                if ext == "bin" {
-                    process_bin_file(&path, width, height);
-                }
-            }
-        }
+    match run(&args) {
+        Ok(0) => {
+            eprintln!("No .bin files found in {}", args.folder.display());
+            std::process::exit(NO_MAPS_EXIT_CODE);
+        },
+        Ok(_) => {},
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        },
     }
-}
\ No newline at end of file
+}
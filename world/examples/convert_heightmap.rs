@@ -1,90 +1,113 @@
-use std::fs::File;
-use std::io::{BufReader, Read, Write};
-use image::{ImageBuffer, Rgb, codecs::png::PngEncoder, ExtendedColorType, ImageEncoder};
-use image::codecs::png::{CompressionType, FilterType};
-use veloren_world::sim::WorldFile;
-use bincode;
+//! Renders the altitudes of a .bin world file as a grayscale PNG heightmap,
+//! scaled so that the lowest point is black and the highest white.  Outputs
+//! with a .pgm extension are written as 16-bit PGM images instead.  With
+//! `--color biome`, cells are colored by altitude band instead, as a rough
+//! preview of the terrain, and with `--color relief` the map is drawn as a
+//! hillshade over a hypsometric tint, for posters.  `--color hypsometric` and
+//! `--color slope` color the altitudes or the steepness through a colormap,
+//! chosen with `--colormap` among the builtin ones or read from a file of
+//! stops, which also recolors reliefs and biome previews.  `--color roughness`
+//! shows the spread of altitudes in a `--roughness-window` around each cell,
+//! highlighting noisy terrain that needs smoothing and flat 8-bit patches, and
+//! `--color occlusion` bakes ambient occlusion, how much of the sky each cell
+//! sees past the terrain around it, which `--relief-occlusion` also darkens
+//! reliefs by.  `--color shadow` masks the shadows cast with the sun at
+//! `--sun-azimuth` and `--sun-elevation`, showing which valleys are gloomy at
+//! that time of day, and `--relief-shadows` darkens reliefs by them.
+//! `--color normal` writes a tangent-space normal map of the terrain, for
+//! other engines, with cells widened by the map's continent scale.
+//! `--pyramid` also writes area-averaged copies at half, quarter and eighth
+//! resolution, for map viewers, and `--layers` writes the basement and the
+//! sediment above it next to the altitudes, over a shared range;
+//! `--name-template` names their files, such as `{layer}-{width}-{stem}.png`.
+//! `--min` and `--max` shade over a fixed range instead of the map's own, and
+//! `--clip-percentile` over the range between two percentiles of its altitudes,
+//! so that a few outliers don't darken the rest; both clip altitudes outside
+//! the range, and record it in a `.range.json` sidecar next to the image.
+//! `--legend` adds a color bar labelled with the altitudes of that range beside
+//! the image, and `--grid` faint lines every 32 cells (or as many as given),
+//! labelled with their coordinates in cells and blocks, for planning builds.
+//! `--markers` draws the points of interest listed in a CSV file over the map.
+//! `--region X,Y,W,H` exports only that window of the map, in cells, shaded
+//! over its own range, or over the whole map's with `--region-full-range` so
+//! that its colors match those of a full export.
+//!
+//! Usage:
+//!   cargo run --example convert_heightmap --features cli --release --
+//! [maps/map.bin] [heightmap.png]
+use clap::Parser;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::{self, ExportArgs, OutputArgs, OverwriteArgs},
+    export::RangeSource,
+};
 
-/// Loads the `.bin` file from the given path and extracts the alt array.
-/// This example expects the file to be in the Veloren 0.7.0 format.
-fn load_alt_array(file_path: &str) -> Vec<f32> {
-    let file = File::open(file_path).expect("Failed to open file");
-    let mut reader = BufReader::new(file);
-    let mut buffer = Vec::new();
-    reader.read_to_end(&mut buffer).expect("Failed to read file");
-
-    // Deserialize the buffer to get the world file.
-    let world_file: WorldFile = bincode::deserialize(&buffer)
-        .expect("Failed to deserialize world file");
-
-    // Match based on world file version.
-    if let WorldFile::Veloren0_7_0(map) = world_file {
-        // Convert Vec<f64> to Vec<f32>
-        return map.alt.iter().map(|&x| x as f32).collect();
-    }
-
-    panic!("Unsupported world file version");
+#[derive(Parser)]
+struct Cli {
+    /// World file to render
+    #[arg(default_value = "maps/map.bin")]
+    input: PathBuf,
+    /// Path of the PNG (or 16-bit PGM, given a .pgm extension) to write
+    #[arg(default_value = "heightmap.png")]
+    output: PathBuf,
+    #[command(flatten)]
+    export: ExportArgs,
+    #[command(flatten)]
+    overwrite: OverwriteArgs,
+    #[command(flatten)]
+    verbosity: OutputArgs,
 }
 
-/// Computes the minimum and maximum values in the alt array.
-fn compute_min_max(alt_array: &[f32]) -> (f32, f32) {
-    let mut min = f32::MAX;
-    let mut max = f32::MIN;
-    for &val in alt_array {
-        if val < min {
-            min = val;
+fn run(args: &Cli) -> Result<(), Error> {
+    let map = heightmap::load_map(&args.input)?;
+    let output_paths = args
+        .export
+        .output_paths(&args.output, heightmap::map_size(&map))?;
+    if let Some(existing) = args.overwrite.existing(&output_paths) {
+        OverwriteArgs::report_skipped(existing, args.verbosity.quiet);
+        return Ok(());
+    }
+    let (min_alt, max_alt) = cli::map_range(&map, &args.export);
+    let clipped_range = cli::clipped_range(&map, &args.export);
+    let range = args
+        .export
+        .shade_range(clipped_range.unwrap_or((min_alt, max_alt)));
+    let source = args.export.range_source();
+    let clipped = cli::export_over(&map, &args.output, &args.export, range, source)?;
+    if !args.verbosity.quiet {
+        println!("Original alt range: min = {}, max = {}", min_alt, max_alt);
+        if let (Some(percentile), Some((lo, hi))) = (args.export.clip_percentile, clipped_range) {
+            println!(
+                "Percentiles {} to {}: min = {}, max = {}",
+                percentile,
+                100.0 - percentile,
+                lo,
+                hi
+            );
         }
-        if val > max {
-            max = val;
+        if source != RangeSource::Map {
+            println!(
+                "Shaded over {}..{}, clipping {} values outside it",
+                range.0, range.1, clipped
+            );
         }
+        args.export.describe()?;
     }
-    (min, max)
-}
-
-/// Generates a heightmap PNG image from the alt array.
-/// The alt values are scaled to the 0–255 range using the provided minimum and maximum.
-fn generate_heightmap(alt_array: Vec<f32>, width: u32, height: u32, output_path: &str, min: f32, max: f32) {
-    let mut heightmap: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
-    let range = max - min;
-    // Avoid division by zero in case of a flat map:
-    let range = if range == 0.0 { 1.0 } else { range };
-
-    for (x, y, pixel) in heightmap.enumerate_pixels_mut() {
-        let alt = alt_array[(y * width + x) as usize];
-        // Scale the altitude value to [0, 255].
-        let pixel_value = (((alt - min) / range) * 255.0).round() as u8;
-        *pixel = Rgb([pixel_value, pixel_value, pixel_value]);
+    if args.verbosity.verbose {
+        let size = heightmap::map_size(&map);
+        println!("Map size: {}x{}", size.x, size.y);
+        for path in output_paths {
+            println!("Heightmap saved to: {}", path.display());
+        }
     }
-
-    let mut heightmap_png = Vec::new();
-    let mut encoder = PngEncoder::new_with_quality(
-        &mut heightmap_png,
-        CompressionType::Best,
-        FilterType::Paeth,
-    );
-    encoder.write_image(
-        heightmap.as_raw(),
-        heightmap.width(),
-        heightmap.height(),
-        ExtendedColorType::Rgb8,
-    ).expect("Failed to write PNG image");
-
-    let mut f = File::create(output_path).expect("Failed to create output file");
-    f.write_all(&heightmap_png).expect("Failed to write PNG data to file");
+    Ok(())
 }
 
 fn main() {
-    // Set the path to your .bin file. Adjust as necessary.
-    let file_path = "maps/map.bin";
-    let alt_array = load_alt_array(file_path);
-
-    // Compute the minimum and maximum altitude values.
-    let (min_alt, max_alt) = compute_min_max(&alt_array);
-    println!("Original alt range: min = {}, max = {}", min_alt, max_alt);
-
-    // Set the dimensions. They must match your map's expected dimensions.
-    let width = 1024;
-    let height = 1024;
-    let output_path = "heightmap.png";
-    generate_heightmap(alt_array, width, height, output_path, min_alt, max_alt);
-}
\ No newline at end of file
+    let args = Cli::parse();
+    if let Err(error) = run(&args) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,152 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use vek::*;
+use veloren_world::heightmap::{
+    self, Error,
+    adjust::{self, AbyssalClamp},
+    cli::CompressArgs,
+    filter::{self, CoastlineSmoothing, Edges},
+    provenance::Provenance,
+    stats::AltStats,
+};
+
+#[derive(Args, Serialize)]
+pub struct AdjustArgs {
+    /// Map to adjust
+    input: PathBuf,
+    /// Before anything else, equalize the histogram of altitudes over this
+    /// many bins, spreading them evenly over their range to bring out flat
+    /// regions.  Not a physical transform: slopes and heights change
+    #[arg(long, value_name = "BINS", num_args = 0..=1, default_missing_value = "256")]
+    equalize: Option<usize>,
+    /// Factor to scale altitudes by, around the pivot
+    #[arg(long, default_value_t = 1.0, allow_negative_numbers = true)]
+    scale: f64,
+    /// Altitude that stays in place when scaling (defaults to sea level)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pivot: f64,
+    /// Altitude to add after scaling
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    offset: f64,
+    /// Gradient to add after offsetting, in meters per cell along x (east)
+    /// and y (north), for a map sloping towards one coast
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["DX", "DY"],
+        allow_negative_numbers = true
+    )]
+    tilt: Option<Vec<f64>>,
+    /// Raise everything deeper than this, after tilting, to it, so that deep
+    /// trenches don't take up the map's altitude range
+    #[arg(long, value_name = "FLOOR", allow_negative_numbers = true)]
+    abyss_floor: Option<f64>,
+    /// Ease the abyss clamp in over this many meters above the floor, instead
+    /// of cutting it off flat
+    #[arg(long, default_value_t = 0.0, requires = "abyss_floor")]
+    abyss_rolloff: f64,
+    /// Last, clean up the coastline with this many passes of a majority rule,
+    /// turning land cells surrounded mostly by water into water and the
+    /// other way around, to remove specks without smoothing the interior
+    #[arg(long, value_name = "ITERATIONS", num_args = 0..=1, default_missing_value = "4")]
+    smooth_coast: Option<u32>,
+    /// Sea level of the coastline cleaned up by --smooth-coast
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        allow_negative_numbers = true,
+        requires = "smooth_coast"
+    )]
+    coast_sea_level: f64,
+    /// Distance from the sea level, in meters, at which --smooth-coast leaves
+    /// the cells it moves across it
+    #[arg(long, default_value_t = 1.0, requires = "smooth_coast")]
+    coast_nudge: f64,
+    /// Treat the edges of the map as wrapping around with --smooth-coast
+    #[arg(long, requires = "smooth_coast")]
+    wrap: bool,
+    /// Path of the adjusted map
+    #[arg(short, long, required_unless_present = "in_place")]
+    output: Option<PathBuf>,
+    /// Overwrite the input map
+    #[arg(long, conflicts_with = "output")]
+    in_place: bool,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn adjust(args: AdjustArgs) -> Result<(), Error> {
+    let mut provenance = Provenance::new("adjust", &args)?.with_input(&args.input)?;
+    let mut map = heightmap::load_map(&args.input)?;
+    println!("Before: {}", AltStats::of(&map.alt));
+
+    if let Some(bins) = args.equalize {
+        adjust::equalize(&mut map, bins);
+        provenance = provenance.with_step(format!("equalize the histogram over {} bins", bins));
+    }
+    adjust::scale(&mut map, args.scale, args.pivot);
+    adjust::shift(&mut map, args.offset);
+    if args.scale != 1.0 {
+        provenance = provenance.with_step(format!("scale by {} about {}", args.scale, args.pivot));
+    }
+    if args.offset != 0.0 {
+        provenance = provenance.with_step(format!("shift by {}", args.offset));
+    }
+    if let Some(&[dx, dy]) = args.tilt.as_deref() {
+        let size = heightmap::map_size(&map);
+        adjust::apply_tilt(&mut map.alt, size, Vec2::new(dx, dy));
+        adjust::apply_tilt(&mut map.basement, size, Vec2::new(dx, dy));
+        provenance = provenance.with_step(format!("tilt by ({}, {})", dx, dy));
+    }
+    if let Some(floor) = args.abyss_floor {
+        let abyss = AbyssalClamp {
+            floor,
+            rolloff: args.abyss_rolloff,
+        };
+        let before = AltStats::of(&map.alt);
+        let cells = adjust::clamp_abyss(&mut map, &abyss);
+        provenance = provenance.with_step(format!(
+            "raise the abyss to {} over {}",
+            floor, args.abyss_rolloff
+        ));
+        let after = AltStats::of(&map.alt);
+        println!(
+            "Raised {} cells towards {}, recovering {:.2} m of altitude range",
+            cells,
+            floor,
+            (before.max - before.min) - (after.max - after.min)
+        );
+    }
+    if let Some(iterations) = args.smooth_coast {
+        let params = CoastlineSmoothing {
+            sea_level: args.coast_sea_level,
+            iterations,
+            nudge: args.coast_nudge,
+            edges: Edges::from_wrap(args.wrap),
+        };
+        let size = heightmap::map_size(&map);
+        let cells = filter::smooth_coastline(&mut map.alt, size, &params);
+        // Cells moved into the sea can fall below their basement.
+        for (basement, alt) in map.basement.iter_mut().zip(map.alt.iter()) {
+            *basement = basement.min(*alt);
+        }
+        provenance = provenance.with_step(format!(
+            "smooth the coastline at {} with {} passes",
+            params.sea_level, iterations
+        ));
+        println!(
+            "Moved {} cells across the sea level {} to smooth the coastline",
+            cells, params.sea_level
+        );
+    }
+    println!("After:  {}", AltStats::of(&map.alt));
+    if !adjust::is_finite(&map) {
+        return Err(Error::NonFinite);
+    }
+
+    let output = args.output.unwrap_or_else(|| args.input.clone());
+    provenance.save_map(&output, map, args.compress.compression())?;
+    println!("Adjusted {} -> {}", args.input.display(), output.display());
+    Ok(())
+}
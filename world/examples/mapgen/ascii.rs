@@ -0,0 +1,46 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    Error, ascii::AsciiGrid, cli::CompressArgs, provenance::Provenance, stats::AltStats,
+};
+
+#[derive(Args, Serialize)]
+pub struct FromAsciiArgs {
+    /// Esri ASCII grid (.asc) to convert; must be square with power-of-two
+    /// sides
+    input: PathBuf,
+    /// Path of the map to write (defaults to the input with a .bin, or
+    /// .bin.zst, extension)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Altitude of cells marked as NODATA
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    nodata_fill: f64,
+    /// Value stored as the map's continent_scale_hack
+    #[arg(long, default_value_t = 1.6)]
+    continent_scale: f64,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn from_ascii(args: FromAsciiArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("from-ascii", &args)?.with_input(&args.input)?;
+    let grid = AsciiGrid::load(&args.input)?;
+    println!(
+        "Grid dimensions: {}x{}, {} NODATA cells",
+        grid.size.x,
+        grid.size.y,
+        grid.nodata_count()
+    );
+    let map = grid.into_map(args.nodata_fill, args.continent_scale)?;
+    println!("Altitudes: {}", AltStats::of(&map.alt));
+
+    let compression = args.compress.compression();
+    let output = args
+        .output
+        .unwrap_or_else(|| args.input.with_extension(compression.extension()));
+    provenance.save_map(&output, map, compression)?;
+    println!("Converted {} -> {}", args.input.display(), output.display());
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use vek::*;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::{self, CompressArgs, ImportArgs},
+    combine::{self, BlendWeight, ComposeOp},
+    mask::Mask,
+    provenance::Provenance,
+    resample::{Antialias, resample_map},
+};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Op {
+    /// Weighted average of both inputs
+    Blend,
+    /// Higher of both inputs
+    Max,
+    /// Lower of both inputs
+    Min,
+    /// First input raised by the second
+    Add,
+    /// First input lowered by the second, down to --floor
+    Sub,
+}
+
+#[derive(Args, Serialize)]
+pub struct CombineArgs {
+    /// Base map (.bin) or heightmap image
+    a: PathBuf,
+    /// Map (.bin) or heightmap image blended into the base
+    b: PathBuf,
+    /// Path of the combined map
+    #[arg(short, long)]
+    output: PathBuf,
+    /// How to combine the inputs
+    #[arg(long, value_enum, default_value_t = Op::Blend)]
+    op: Op,
+    /// Weight of the second input when blending, from 0 (only A) to 1 (only
+    /// B)
+    #[arg(long, default_value_t = 0.5, conflicts_with = "mask")]
+    weight: f64,
+    /// Grayscale image giving the weight of the second input per cell when
+    /// blending
+    #[arg(long)]
+    mask: Option<PathBuf>,
+    /// Lowest altitude the `sub` operator may carve down to
+    #[arg(long, required_if_eq("op", "sub"), allow_negative_numbers = true)]
+    floor: Option<f64>,
+    /// Resample the second input and the mask to the size of the first if
+    /// they differ, instead of failing
+    #[arg(long)]
+    resample: bool,
+    /// Filter the second input before shrinking it when resampling, so that
+    /// fine detail doesn't alias into noise
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FILTER",
+        num_args = 0..=1,
+        default_missing_value = "gaussian",
+        requires = "resample"
+    )]
+    antialias: Option<Antialias>,
+    #[command(flatten)]
+    import: ImportArgs,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn combine(args: CombineArgs) -> Result<(), Error> {
+    let mut provenance = Provenance::new("combine", &args)?
+        .with_input(&args.a)?
+        .with_input(&args.b)?;
+    if let Some(mask) = &args.mask {
+        provenance = provenance.with_input(mask)?;
+    }
+    let a = cli::load_input(&args.a, &args.import)?;
+    let mut b = cli::load_input(&args.b, &args.import)?;
+    let size = heightmap::map_size(&a);
+    if args.resample && b.map_size_lg != a.map_size_lg {
+        println!("Resampling {} to {}x{}", args.b.display(), size.x, size.y);
+        b = resample_map(&b, a.map_size_lg, args.antialias);
+    }
+
+    let mask = match &args.mask {
+        Some(path) => Some(load_mask(path, size, args.resample)?),
+        None => None,
+    };
+
+    let map = match args.op {
+        Op::Blend => {
+            let weight = match &mask {
+                Some(mask) => BlendWeight::Mask(mask),
+                None => BlendWeight::Uniform(args.weight),
+            };
+            combine::blend(&a, &b, weight)?
+        },
+        Op::Max => combine::compose(&a, &b, ComposeOp::Max)?,
+        Op::Min => combine::compose(&a, &b, ComposeOp::Min)?,
+        Op::Add => combine::compose(&a, &b, ComposeOp::Add)?,
+        Op::Sub => combine::compose(&a, &b, ComposeOp::Sub {
+            floor: args.floor.unwrap_or(f64::NEG_INFINITY),
+        })?,
+    };
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+    println!(
+        "Combined {} and {} -> {}",
+        args.a.display(),
+        args.b.display(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn load_mask(path: &Path, size: Vec2<usize>, resample: bool) -> Result<Mask, Error> {
+    let mask = Mask::load(path)?;
+    if resample && mask.size != size {
+        println!("Resampling {} to {}x{}", path.display(), size.x, size.y);
+        Ok(mask.resample(size))
+    } else {
+        Ok(mask)
+    }
+}
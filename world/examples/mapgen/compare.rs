@@ -0,0 +1,138 @@
+use crate::verify::collect_files;
+use clap::{Args, ValueEnum};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use veloren_world::heightmap::{
+    self, Error,
+    cli::PngArgs,
+    export, map_size,
+    montage::{self, MontageParams, Preview},
+    provenance::Provenance,
+    relief::ReliefParams,
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Label {
+    /// The file name of each map
+    File,
+    /// The parameters that differ between the maps, from their provenance
+    /// records, such as the scale and offset of each map of a sweep
+    Params,
+}
+
+#[derive(Args)]
+pub struct CompareArgs {
+    /// Maps to compare, or directories of them (such as the outputs of a
+    /// sweep)
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// PNG image to write the contact sheet to
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Width and height of each preview, in pixels
+    #[arg(long, default_value_t = MontageParams::default().tile_size)]
+    tile_size: u32,
+    /// Number of previews in each row
+    #[arg(long, default_value_t = MontageParams::default().columns)]
+    columns: u32,
+    /// What to label each preview with
+    #[arg(long, value_enum, default_value_t = Label::File)]
+    label: Label,
+    /// Altitude at which the tint changes from water to land
+    #[arg(long, default_value_t = 0.0)]
+    sea_level: f64,
+    #[command(flatten)]
+    png: PngArgs,
+}
+
+fn file_label(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+/// Labels each map with the top-level parameters of its provenance record
+/// whose values differ between the maps, falling back to its file name for
+/// maps without a record, or if the records don't differ.
+fn params_labels(files: &[PathBuf]) -> Result<Vec<String>, Error> {
+    let params = files
+        .iter()
+        .map(|file| Ok(Provenance::load_for(file)?.map(|provenance| provenance.params)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let value = |params: &Option<Value>, key: &str| {
+        params.as_ref().and_then(|params| params.get(key)).cloned()
+    };
+    let mut keys = params
+        .iter()
+        .flatten()
+        .filter_map(Value::as_object)
+        .flat_map(|object| object.keys().cloned())
+        .collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    keys.retain(|key| {
+        let first = value(&params[0], key);
+        params.iter().any(|params| value(params, key) != first)
+    });
+
+    Ok(files
+        .iter()
+        .zip(&params)
+        .map(|(file, params)| {
+            let label = keys
+                .iter()
+                .filter_map(|key| Some(format!("{}={}", key, value(params, key)?)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            if params.is_none() || label.is_empty() {
+                file_label(file)
+            } else {
+                label
+            }
+        })
+        .collect())
+}
+
+/// Renders a contact sheet of shaded previews of several maps, sharing one
+/// altitude range and labelled with their names or parameters, to pick
+/// between candidates at a glance.
+pub fn compare(args: CompareArgs) -> Result<(), Error> {
+    let files = collect_files(&args.inputs)?;
+    let labels = match args.label {
+        Label::File => files.iter().map(|file| file_label(file)).collect(),
+        Label::Params => params_labels(&files)?,
+    };
+    let params = MontageParams {
+        tile_size: args.tile_size.max(1),
+        columns: args.columns.max(1),
+        relief: ReliefParams {
+            sea_level: args.sea_level,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    // Maps are shrunk as they are loaded rather than kept, as there may be
+    // many large ones.
+    let mut previews = Vec::with_capacity(files.len());
+    for (file, label) in files.iter().zip(labels) {
+        let map = heightmap::load_map(file)?;
+        previews.push(Preview::new(
+            &map.alt,
+            map_size(&map),
+            params.tile_size,
+            label,
+        ));
+    }
+    let img = montage::render_montage(&previews, &params);
+    export::save_png(&img, &args.output, args.png.options())?;
+    println!(
+        "Compared {} maps in {} ({}x{})",
+        previews.len(),
+        args.output.display(),
+        img.width(),
+        img.height()
+    );
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use clap::Args;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::PngArgs,
+    colormap::Colormap,
+    diff::{MapDiff, render_diff},
+    export::save_png,
+};
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Original map
+    a: PathBuf,
+    /// Changed map
+    b: PathBuf,
+    /// Write a PNG of the altitude difference (blue where B is lower, red
+    /// where it is higher)
+    #[arg(long)]
+    image: Option<PathBuf>,
+    /// Color the image through this colormap instead, from its first color
+    /// where B is lower by the most to its last where it is higher by the
+    /// most: a builtin name such as viridis, or a file of stops as for the
+    /// `--colormap` of exports
+    #[arg(long, value_name = "NAME|FILE")]
+    colormap: Option<String>,
+    /// Exit with status 1 if the maps differ
+    #[arg(long)]
+    exit_code: bool,
+    #[command(flatten)]
+    png: PngArgs,
+}
+
+pub fn diff(args: DiffArgs) -> Result<(), Error> {
+    let a = heightmap::load_map(&args.a)?;
+    let b = heightmap::load_map(&args.b)?;
+    let diff = MapDiff::new(&a, &b)?;
+    println!("alt:      {}", diff.alt);
+    println!("basement: {}", diff.basement);
+
+    if let Some(path) = &args.image {
+        let colormap = match &args.colormap {
+            Some(name) => Colormap::resolve(name)?,
+            None => Colormap::DIVERGING,
+        };
+        let img = render_diff(
+            &a.alt,
+            &b.alt,
+            heightmap::map_size(&a),
+            diff.alt.max_abs,
+            &colormap,
+        );
+        save_png(&img, path, args.png.options())?;
+        println!("Difference image saved to: {}", path.display());
+    }
+
+    if args.exit_code && !diff.is_identical() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
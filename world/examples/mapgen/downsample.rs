@@ -0,0 +1,48 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    provenance::Provenance,
+    resample::{BlockReduce, downsample_map},
+};
+
+#[derive(Args, Serialize)]
+pub struct DownsampleArgs {
+    /// Map to downsample
+    input: PathBuf,
+    /// Power of two to divide the width and height of the map by
+    #[arg(long, default_value_t = 2)]
+    factor: u32,
+    /// How to combine each block of cells: their mean, or their lowest or
+    /// highest cell to keep valleys or peaks that averaging would flatten
+    #[arg(long, value_enum, default_value_t = BlockReduce::Mean)]
+    reduce: BlockReduce,
+    /// Path of the downsampled map
+    #[arg(short, long)]
+    output: PathBuf,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn downsample(args: DownsampleArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("downsample", &args)?.with_input(&args.input)?;
+    let map = heightmap::load_map(&args.input)?;
+    let before = heightmap::map_size(&map);
+    let map = downsample_map(&map, args.factor, args.reduce)?;
+    let after = heightmap::map_size(&map);
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+
+    println!(
+        "Downsampled {} ({}x{}) -> {} ({}x{}), {:?} of each block",
+        args.input.display(),
+        before.x,
+        before.y,
+        args.output.display(),
+        after.x,
+        after.y,
+        args.reduce
+    );
+    Ok(())
+}
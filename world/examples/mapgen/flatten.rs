@@ -0,0 +1,107 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    flatten::{self, flatten_masked},
+    mask::Mask,
+    provenance::Provenance,
+};
+
+#[derive(Args, Serialize)]
+pub struct FlattenArgs {
+    /// Map to flatten
+    input: PathBuf,
+    /// Grayscale image of the same size as the map; white areas are moved to
+    /// the target altitude, gray ones part of the way
+    mask: PathBuf,
+    /// Path of the flattened map
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Altitude to move masked areas to
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    target: f64,
+    /// Width in cells of the transition band around masked areas
+    #[arg(long, default_value_t = 0.0)]
+    feather: f64,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+#[derive(Args, Serialize)]
+pub struct FlattenSitesArgs {
+    /// Map to flatten
+    input: PathBuf,
+    /// File listing the sites, one per line: `x, y, radius` for a disc, or `x,
+    /// y, width, height` for a rectangle, centered on cell (x, y)
+    #[arg(long, value_name = "CSV")]
+    sites: Option<PathBuf>,
+    /// A site in the same format, flattened after those of `--sites`; may be
+    /// repeated
+    #[arg(long = "site", value_name = "X,Y,SIZE")]
+    extra_sites: Vec<String>,
+    /// Width in cells of the ring around each site over which it blends back
+    /// into the surrounding terrain
+    #[arg(long, default_value_t = 8.0)]
+    falloff: f64,
+    /// Path of the flattened map
+    #[arg(short, long)]
+    output: PathBuf,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn flatten_sites(args: FlattenSitesArgs) -> Result<(), Error> {
+    let mut provenance = Provenance::new("flatten-sites", &args)?.with_input(&args.input)?;
+    if let Some(path) = &args.sites {
+        provenance = provenance.with_input(path)?;
+    }
+    let mut sites = match &args.sites {
+        Some(path) => flatten::load_sites(path)?,
+        None => Vec::new(),
+    };
+    for site in &args.extra_sites {
+        sites.extend(flatten::parse_sites(site)?);
+    }
+    let mut map = heightmap::load_map(&args.input)?;
+    let results = flatten::flatten_sites(&mut map, &sites, args.falloff);
+
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+    for (site, result) in sites.iter().zip(results) {
+        match result {
+            Some(result) => println!(
+                "Site at ({}, {}): flattened to {:.2}, {} cells changed",
+                site.center.x, site.center.y, result.target, result.changed
+            ),
+            None => println!(
+                "Site at ({}, {}): outside the map, skipped",
+                site.center.x, site.center.y
+            ),
+        }
+    }
+    println!(
+        "Flattened {} sites -> {}",
+        sites.len(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+pub fn flatten(args: FlattenArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("flatten", &args)?
+        .with_input(&args.input)?
+        .with_input(&args.mask)?;
+    let mut map = heightmap::load_map(&args.input)?;
+    let mask = Mask::load(&args.mask)?.feathered(args.feather);
+    let changed = flatten_masked(&mut map, &mask, args.target)?;
+
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+    println!(
+        "Flattened {} cells to {} -> {}",
+        changed,
+        args.target,
+        args.output.display()
+    );
+    Ok(())
+}
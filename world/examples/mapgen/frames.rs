@@ -0,0 +1,58 @@
+use clap::Args;
+use std::path::PathBuf;
+use veloren_world::heightmap::{self, Error, animate, cli::PngArgs, export, map_size};
+
+#[derive(Args)]
+pub struct FramesArgs {
+    /// Maps to render, in order, such as snapshots taken between erosion runs
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// GIF image to write the animation to
+    #[arg(short, long)]
+    output: PathBuf,
+    /// How long each frame is shown, in milliseconds
+    #[arg(long, default_value_t = 500)]
+    delay: u32,
+    /// Also write each frame to this directory, as frame_0000.png,
+    /// frame_0001.png...
+    #[arg(long, value_name = "DIR")]
+    frames_dir: Option<PathBuf>,
+    #[command(flatten)]
+    png: PngArgs,
+}
+
+/// Renders a sequence of maps as an animated GIF of grayscale frames sharing
+/// one altitude range, so that the same shade means the same altitude in
+/// every frame and changes between them stand out.
+pub fn frames(args: FramesArgs) -> Result<(), Error> {
+    // Maps are loaded twice rather than kept, as there may be many large ones.
+    let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+    for input in &args.inputs {
+        let (map_min, map_max) = export::compute_min_max(&heightmap::load_map(input)?.alt);
+        min = min.min(map_min);
+        max = max.max(map_max);
+    }
+
+    if let Some(dir) = &args.frames_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    let frames = args.inputs.iter().enumerate().map(|(i, input)| {
+        let map = heightmap::load_map(input)?;
+        let img = export::render_grayscale(&map.alt, map_size(&map), min, max);
+        if let Some(dir) = &args.frames_dir {
+            let path = dir.join(format!("frame_{:04}.png", i));
+            export::save_png(&img, &path, args.png.options())?;
+            println!("{} -> {}", input.display(), path.display());
+        }
+        Ok(img)
+    });
+    animate::save_gif(frames, args.delay, &args.output)?;
+    println!(
+        "Animated {} maps over altitudes {:.1} to {:.1} -> {}",
+        args.inputs.len(),
+        min,
+        max,
+        args.output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use vek::*;
+use veloren_world::heightmap::{
+    Error, cli::CompressArgs, hills::HillParams, provenance::Provenance, stats::AltStats,
+};
+
+#[derive(Args, Serialize)]
+pub struct HillsArgs {
+    /// Path of the map to write
+    output: PathBuf,
+    /// Base-2 logarithm of the width and height of the map
+    #[arg(long, default_value_t = 10)]
+    size_lg: u32,
+    /// Number of hills to place
+    #[arg(long, default_value_t = 64)]
+    count: usize,
+    /// Seed of the random placement; the same seed always gives the same map
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Altitude of the flat ground the hills stand on
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    base: f64,
+    /// Smallest radius of a hill, in cells
+    #[arg(long, default_value_t = 4.0)]
+    min_radius: f64,
+    /// Largest radius of a hill, in cells
+    #[arg(long, default_value_t = 32.0)]
+    max_radius: f64,
+    /// Smallest height of a hill above the ground (negative for hollows)
+    #[arg(long, default_value_t = 50.0, allow_negative_numbers = true)]
+    min_height: f64,
+    /// Largest height of a hill above the ground
+    #[arg(long, default_value_t = 500.0, allow_negative_numbers = true)]
+    max_height: f64,
+    /// Value stored as the map's continent_scale_hack
+    #[arg(long, default_value_t = 1.6)]
+    continent_scale: f64,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn hills(args: HillsArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("hills", &args)?.with_seed(args.seed);
+    let params = HillParams {
+        base: args.base,
+        radius: (args.min_radius, args.max_radius),
+        height: (args.min_height, args.max_height),
+    };
+    let map = params.generate_map(
+        Vec2::broadcast(args.size_lg),
+        args.count,
+        args.seed,
+        args.continent_scale,
+    )?;
+    println!("Altitudes: {}", AltStats::of(&map.alt));
+
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+    println!(
+        "Placed {} hills (seed {}) -> {}",
+        args.count,
+        args.seed,
+        args.output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    inpaint::{self, InpaintNoise, InpaintParams},
+    mask::Mask,
+    provenance::Provenance,
+    stamp::PoissonParams,
+    stats::AltStats,
+};
+
+#[derive(Args, Serialize)]
+pub struct InpaintArgs {
+    /// Map to fill holes in
+    input: PathBuf,
+    /// Grayscale image of the same size as the map; cells that aren't black
+    /// are erased and filled back in from the terrain around them
+    mask: PathBuf,
+    /// Path of the filled map
+    #[arg(short, long)]
+    output: PathBuf,
+    /// The solve stops once no altitude changes by more than this many meters
+    /// in an iteration
+    #[arg(long, default_value_t = PoissonParams::default().tolerance)]
+    tolerance: f64,
+    /// The most iterations of the solve
+    #[arg(long, default_value_t = PoissonParams::default().max_iterations)]
+    max_iterations: usize,
+    /// Add fractal noise to the fill with this amplitude, as a fraction of the
+    /// local relief around the hole
+    #[arg(long)]
+    noise: Option<f64>,
+    /// Seed of the noise
+    #[arg(long, default_value_t = 0, requires = "noise")]
+    seed: u32,
+    /// Width in cells of the band inside the edge of the hole over which the
+    /// noise fades in
+    #[arg(long, default_value_t = 4.0, requires = "noise")]
+    ramp: f64,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn inpaint(args: InpaintArgs) -> Result<(), Error> {
+    let mut provenance = Provenance::new("inpaint", &args)?
+        .with_input(&args.input)?
+        .with_input(&args.mask)?;
+    if args.noise.is_some() {
+        provenance = provenance.with_seed(args.seed.into());
+    }
+    let mut map = heightmap::load_map(&args.input)?;
+    let mask = Mask::load(&args.mask)?;
+    let params = InpaintParams {
+        solve: PoissonParams {
+            tolerance: args.tolerance,
+            max_iterations: args.max_iterations,
+        },
+        noise: args.noise.map(|amplitude| InpaintNoise {
+            seed: args.seed,
+            amplitude,
+            ramp: args.ramp,
+        }),
+    };
+    let result = inpaint::inpaint(&mut map, &mask, &params)?;
+
+    let filled = mask
+        .values
+        .iter()
+        .zip(&*map.alt)
+        .filter(|&(&weight, _)| weight > 0.0)
+        .map(|(_, &alt)| alt)
+        .collect::<Vec<_>>();
+    println!(
+        "Filled {} cells ({:.2}% of the map)",
+        result.filled,
+        100.0 * result.filled as f64 / map.alt.len() as f64
+    );
+    if !filled.is_empty() {
+        println!("Reconstructed altitudes: {}", AltStats::of(&filled));
+    }
+    for (grid, solve) in [("altitude", result.alt), ("basement", result.basement)] {
+        if !solve.converged {
+            println!(
+                "Warning: the {} solve stopped after {} iterations, still changing by {:.2e} m; \
+                 raise --max-iterations",
+                grid, solve.iterations, solve.change
+            );
+        }
+    }
+
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+    println!(
+        "Inpainted {} -> {}",
+        args.input.display(),
+        args.output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use clap::Args;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    biome::{self, Biome},
+    cli::BandArgs,
+    export,
+    filter::Edges,
+    map_size,
+    provenance::Provenance,
+    stats::{self, AltStats, REPORTED_PERCENTILES},
+};
+
+#[derive(Args)]
+pub struct InspectArgs {
+    /// Map to describe
+    input: PathBuf,
+    /// Also print altitude percentiles, for choosing a sea level that
+    /// submerges a given fraction of the map (sorts a copy of the altitudes)
+    #[arg(long)]
+    quantiles: bool,
+    /// Also print the share of the map in each altitude band of `--color
+    /// biome` exports
+    #[arg(long)]
+    biomes: bool,
+    #[command(flatten)]
+    bands: BandArgs,
+}
+
+pub fn inspect(args: InspectArgs) -> Result<(), Error> {
+    let map = heightmap::load_map(&args.input)?;
+    let size = map_size(&map);
+    println!(
+        "{}: {}x{} cells (map_size_lg {}x{}), continent scale {}",
+        args.input.display(),
+        size.x,
+        size.y,
+        map.map_size_lg.x,
+        map.map_size_lg.y,
+        map.continent_scale_hack
+    );
+    let header = heightmap::read_header(&args.input)?;
+    if let Some(format) = header.format {
+        let compression = if header.compressed {
+            ", zstd-compressed"
+        } else {
+            ""
+        };
+        println!("Format: {}{}", format, compression);
+    }
+    println!("Altitudes: {}", AltStats::of(&map.alt));
+    println!("Basement:  {}", AltStats::of(&map.basement));
+    if let Some((min_index, min, max_index, max)) = export::altitude_extrema(&map.alt) {
+        for (name, index, alt) in [("Lowest", min_index, min), ("Highest", max_index, max)] {
+            println!(
+                "{} cell: {}, {} at {:.2}",
+                name,
+                index % size.x,
+                index / size.x,
+                alt
+            );
+        }
+    }
+    if args.quantiles {
+        let quantiles = stats::quantiles(&map.alt, &REPORTED_PERCENTILES);
+        for (percentile, alt) in REPORTED_PERCENTILES.iter().zip(quantiles) {
+            println!("  p{:<3} {:.2}", percentile, alt);
+        }
+    }
+    if args.biomes {
+        let bands = args.bands.bands()?;
+        println!("Bands: {}", bands);
+        let biomes = biome::classify(&map.alt, size, &bands, Edges::Clamp);
+        for (biome, count) in Biome::ALL.iter().zip(biome::coverage(&biomes)) {
+            println!(
+                "  {:<13} {:6.2}%",
+                biome,
+                100.0 * count as f64 / biomes.len().max(1) as f64
+            );
+        }
+    }
+    match Provenance::load_for(&args.input) {
+        Ok(Some(provenance)) => print!("{}", provenance),
+        Ok(None) => println!("No provenance record"),
+        Err(error) => println!("Could not read the provenance record: {}", error),
+    }
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! Command-line tools for inspecting and editing `.bin` world files.
+//!
+//! Usage:
+//!   cargo run --example mapgen --features cli --release -- <COMMAND> --help
+//!
+//! `frames` and `sun-sweep`, which write animated GIFs, also need the `gif`
+//! feature.
+mod adjust;
+mod ascii;
+mod combine;
+mod compare;
+mod diff;
+mod downsample;
+mod flatten;
+#[cfg(feature = "gif")] mod frames;
+mod hills;
+mod inpaint;
+mod inspect;
+mod packed;
+mod polyline;
+mod profile;
+mod reconvert;
+mod seamless;
+mod seams;
+mod smooth;
+mod stamp;
+#[cfg(feature = "gif")] mod sun_sweep;
+mod sweep;
+mod tile;
+mod transform;
+mod upscale;
+mod verify;
+
+use clap::{Parser, Subcommand};
+use veloren_world::heightmap::Error;
+
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Split a map into a grid of smaller maps, plus a manifest describing
+    /// the layout
+    Split(tile::SplitArgs),
+    /// Reassemble a map from the manifest written by `split`
+    Stitch(tile::StitchArgs),
+    /// Generate a map of randomly placed gaussian hills on flat ground, as a
+    /// starting point for hand-editing
+    Hills(hills::HillsArgs),
+    /// Repeat a conversion recorded in a JSON sidecar
+    Reconvert(reconvert::ReconvertArgs),
+    /// Convert an image with every combination of ranges of scales and
+    /// offsets, summarizing the land and peaks of each
+    Sweep(sweep::SweepArgs),
+    /// Convert an Esri ASCII grid, as exported by GIS tools, into a map
+    FromAscii(ascii::FromAsciiArgs),
+    /// Flip, rotate or transpose a map
+    Transform(transform::TransformArgs),
+    /// Scale, shift, tilt or equalize all altitudes of a map, or clean up its
+    /// coastline
+    Adjust(adjust::AdjustArgs),
+    /// Reduce the resolution of a map by a power of two, averaging (or taking
+    /// the extremes of) each block of cells
+    Downsample(downsample::DownsampleArgs),
+    /// Enlarge a map by a power of two with bicubic interpolation, optionally
+    /// adding seeded detail noise, or bilinearly with fractal detail
+    Upscale(upscale::UpscaleArgs),
+    /// Cross-fade the edges of a map with their opposite edges, so that it
+    /// wraps around without a seam
+    Seamless(seamless::SeamlessArgs),
+    /// Combine two maps or images, by blending or with a per-cell operator
+    Combine(combine::CombineArgs),
+    /// Composite a brush, such as a volcano or crater, onto a map
+    Stamp(stamp::StampArgs),
+    /// Smooth a circle of a map around a point, fading back to the original
+    /// terrain at its edge
+    SmoothRegion(smooth::SmoothRegionArgs),
+    /// Move the areas of a map painted in a mask to a target altitude
+    Flatten(flatten::FlattenArgs),
+    /// Flatten discs or rectangles of a map, such as settlement sites, to
+    /// their mean altitude, blending them into the surrounding terrain
+    FlattenSites(flatten::FlattenSitesArgs),
+    /// Carve, raise or grade the terrain along polylines read from GeoJSON,
+    /// such as canals, causeways and roads
+    Draw(polyline::DrawArgs),
+    /// Erase the areas of a map painted in a mask and fill them back in by
+    /// diffusion from the terrain around them
+    Inpaint(inpaint::InpaintArgs),
+    /// Store a map's altitudes losslessly in the channels of an RGBA PNG, for
+    /// exchanging maps as images
+    Pack(packed::PackArgs),
+    /// Restore a map from a PNG written by `pack`
+    Unpack(packed::UnpackArgs),
+    /// Print the size and altitude statistics of a map, where its lowest and
+    /// highest cells are, and the record of how it was produced
+    Inspect(inspect::InspectArgs),
+    /// Sample the altitude of a map along a route through two or more points,
+    /// as CSV and optionally as a chart
+    Profile(profile::ProfileArgs),
+    /// Compare two maps, optionally rendering their difference
+    Diff(diff::DiffArgs),
+    /// Render a sequence of maps as an animated GIF whose frames share one
+    /// altitude range
+    #[cfg(feature = "gif")]
+    Frames(frames::FramesArgs),
+    /// Render an animated GIF of the shaded relief of a map as the sun turns
+    /// around it
+    #[cfg(feature = "gif")]
+    SunSweep(sun_sweep::SunSweepArgs),
+    /// Render shaded previews of several maps side by side on one labelled
+    /// contact sheet
+    Compare(compare::CompareArgs),
+    /// Check maps for invalid sizes, non-finite values and implausible
+    /// altitudes before they are used by a server, showing how each was
+    /// produced
+    Verify(verify::VerifyArgs),
+    /// Report rows and columns across which altitudes jump far more than
+    /// across their neighbours, such as the seams of a bad stitch
+    Seams(seams::SeamsArgs),
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let result: Result<(), Error> = match cli.command {
+        Command::Split(args) => tile::split(args),
+        Command::Stitch(args) => tile::stitch(args),
+        Command::Hills(args) => hills::hills(args),
+        Command::Reconvert(args) => reconvert::reconvert(args),
+        Command::Sweep(args) => sweep::sweep(args),
+        Command::FromAscii(args) => ascii::from_ascii(args),
+        Command::Transform(args) => transform::transform(args),
+        Command::Adjust(args) => adjust::adjust(args),
+        Command::Downsample(args) => downsample::downsample(args),
+        Command::Upscale(args) => upscale::upscale(args),
+        Command::Seamless(args) => seamless::seamless(args),
+        Command::Combine(args) => combine::combine(args),
+        Command::Stamp(args) => stamp::stamp(args),
+        Command::SmoothRegion(args) => smooth::smooth_region(args),
+        Command::Flatten(args) => flatten::flatten(args),
+        Command::FlattenSites(args) => flatten::flatten_sites(args),
+        Command::Draw(args) => polyline::draw(args),
+        Command::Inpaint(args) => inpaint::inpaint(args),
+        Command::Pack(args) => packed::pack(args),
+        Command::Unpack(args) => packed::unpack(args),
+        Command::Inspect(args) => inspect::inspect(args),
+        Command::Profile(args) => profile::profile(args),
+        Command::Diff(args) => diff::diff(args),
+        #[cfg(feature = "gif")]
+        Command::Frames(args) => frames::frames(args),
+        #[cfg(feature = "gif")]
+        Command::SunSweep(args) => sun_sweep::sun_sweep(args),
+        Command::Compare(args) => compare::compare(args),
+        Command::Verify(args) => verify::verify(args),
+        Command::Seams(args) => seams::seams(args),
+    };
+
+    if let Err(error) = result {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,57 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    packed::{self, PackedSidecar},
+    provenance::Provenance,
+};
+
+#[derive(Args, Serialize)]
+pub struct PackArgs {
+    /// Map to pack
+    input: PathBuf,
+    /// PNG to write; the sidecar needed to unpack it is written next to it,
+    /// with a .packed.json extension
+    output: PathBuf,
+}
+
+#[derive(Args, Serialize)]
+pub struct UnpackArgs {
+    /// PNG written by `pack`
+    input: PathBuf,
+    /// Path of the unpacked map (defaults to the input with a .bin, or
+    /// .bin.zst, extension)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn pack(args: PackArgs) -> Result<(), Error> {
+    let map = heightmap::load_map(&args.input)?;
+    let sidecar = packed::save_packed(&map, &args.output)?;
+    println!(
+        "Packed {} -> {} (step {:e}, sidecar {})",
+        args.input.display(),
+        args.output.display(),
+        sidecar.step,
+        PackedSidecar::path_for(&args.output).display()
+    );
+    Ok(())
+}
+
+pub fn unpack(args: UnpackArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("unpack", &args)?
+        .with_input(&args.input)?
+        .with_input(PackedSidecar::path_for(&args.input))?;
+    let map = packed::load_packed(&args.input)?;
+    let compression = args.compress.compression();
+    let output = args
+        .output
+        .unwrap_or_else(|| args.input.with_extension(compression.extension()));
+    provenance.save_map(&output, map, compression)?;
+    println!("Unpacked {} -> {}", args.input.display(), output.display());
+    Ok(())
+}
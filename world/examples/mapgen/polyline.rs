@@ -0,0 +1,41 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{self, Error, cli::CompressArgs, polyline, provenance::Provenance};
+
+#[derive(Args, Serialize)]
+pub struct DrawArgs {
+    /// Map to draw into
+    input: PathBuf,
+    /// GeoJSON file of `LineString` features in cell coordinates, whose
+    /// properties give the `width` in cells, the `mode` (`carve`, `raise` or
+    /// `flatten`), the `depth` in meters and the `feather` in cells
+    lines: PathBuf,
+    /// Path of the map with the lines drawn in
+    #[arg(short, long)]
+    output: PathBuf,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn draw(args: DrawArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("draw", &args)?
+        .with_input(&args.input)?
+        .with_input(&args.lines)?;
+    let lines = polyline::load_polylines(&args.lines)?;
+    let mut map = heightmap::load_map(&args.input)?;
+    let changed = polyline::draw_polylines(&mut map, &lines);
+
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+    for (i, (line, changed)) in lines.iter().zip(changed).enumerate() {
+        println!(
+            "Line {} ({:?}, {} points): {} cells changed",
+            i + 1,
+            line.params.mode,
+            line.points.len(),
+            changed
+        );
+    }
+    println!("Drew {} lines -> {}", lines.len(), args.output.display());
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use clap::Args;
+use std::path::PathBuf;
+use vek::*;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::PngArgs,
+    export,
+    io::write_atomically,
+    map_size,
+    overlay::{BLOCKS_PER_CELL, Units},
+    profile,
+};
+
+#[derive(Args)]
+pub struct ProfileArgs {
+    /// Map to sample
+    input: PathBuf,
+    /// Points of the route, each `X,Y`: at least the two ends, and any turns
+    /// between them, such as the bends of a road over a pass
+    #[arg(required = true, num_args = 2.., value_name = "X,Y", allow_negative_numbers = true)]
+    points: Vec<String>,
+    /// Units of the coordinates of the points
+    #[arg(long, value_enum, default_value_t = Units::Cells)]
+    units: Units,
+    /// Longest distance between samples, in cells
+    #[arg(long, default_value_t = 0.25)]
+    step: f64,
+    /// Path of the CSV of distances (in blocks) and altitudes along the route;
+    /// printed if not given
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Also render the profile as a chart with labelled axes to this PNG
+    #[arg(long, value_name = "PNG")]
+    chart: Option<PathBuf>,
+    /// Width and height of the chart, in pixels
+    #[arg(long, num_args = 2, value_names = ["WIDTH", "HEIGHT"], default_values_t = [800, 300])]
+    chart_size: Vec<u32>,
+    #[command(flatten)]
+    png: PngArgs,
+}
+
+/// Samples the altitude of a map along a route, writing it as CSV and
+/// optionally as a chart.
+pub fn profile(args: ProfileArgs) -> Result<(), Error> {
+    let points = args
+        .points
+        .iter()
+        .map(|point| profile::parse_point(point, args.units))
+        .collect::<Result<Vec<_>, _>>()?;
+    let map = heightmap::load_map(&args.input)?;
+    let samples = profile::profile(&map.alt, map_size(&map), &points, args.step)?;
+    match &args.output {
+        Some(path) => write_atomically(path, |writer| profile::write_csv(&samples, writer))?,
+        None => profile::write_csv(&samples, std::io::stdout().lock())?,
+    }
+    if let Some(path) = &args.chart {
+        let size = Vec2::new(args.chart_size[0], args.chart_size[1]);
+        let chart = profile::render_profile(&samples, size);
+        export::save_png(&chart, path, args.png.options())?;
+    }
+
+    // Keep stdout to the CSV when it is printed there.
+    let last = samples.last().expect("Routes have at least two points");
+    let (lo, hi) = samples
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), sample| {
+            (lo.min(sample.alt), hi.max(sample.alt))
+        });
+    let climb = samples
+        .windows(2)
+        .map(|pair| (pair[1].alt - pair[0].alt).max(0.0))
+        .sum::<f64>();
+    eprintln!(
+        "{} samples over {:.0} blocks: altitude {:.2} to {:.2}, {:.2} m of climb",
+        samples.len(),
+        last.distance * BLOCKS_PER_CELL as f64,
+        lo,
+        hi,
+        climb
+    );
+    if let Some(path) = &args.output {
+        eprintln!("Profile saved to: {}", path.display());
+    }
+    if let Some(path) = &args.chart {
+        eprintln!("Chart saved to: {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    Error, cli::CompressArgs, import::ImportSidecar, provenance::Provenance,
+};
+
+#[derive(Args, Serialize)]
+pub struct ReconvertArgs {
+    /// Sidecar written by `convert_to_bin --sidecar`
+    sidecar: PathBuf,
+    /// Path of the regenerated map (defaults to the .bin, or .bin.zst, next
+    /// to the sidecar)
+    #[arg(long)]
+    output: Option<PathBuf>,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn reconvert(args: ReconvertArgs) -> Result<(), Error> {
+    let sidecar = ImportSidecar::load(&args.sidecar)?;
+    if sidecar.version != env!("CARGO_PKG_VERSION") {
+        println!(
+            "Note: sidecar was written by version {}, this is version {}",
+            sidecar.version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    let mut provenance = Provenance::new("reconvert", &sidecar)?
+        .with_input(&args.sidecar)?
+        .with_input(&sidecar.source)?;
+    if let Some(rivers) = &sidecar.params.rivers {
+        provenance = provenance.with_input(&rivers.image)?;
+    }
+    provenance.pipeline = sidecar.params.pipeline();
+
+    let map = sidecar.reconvert()?;
+    let compression = args.compress.compression();
+    let output = args
+        .output
+        .unwrap_or_else(|| args.sidecar.with_extension(compression.extension()));
+    provenance.save_map(&output, map, compression)?;
+
+    println!(
+        "Reconverted {} -> {}",
+        sidecar.source.display(),
+        output.display()
+    );
+    Ok(())
+}
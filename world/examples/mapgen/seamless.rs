@@ -0,0 +1,43 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    provenance::Provenance,
+    seamless::{make_map_seamless, map_edge_mismatch},
+};
+
+#[derive(Args, Serialize)]
+pub struct SeamlessArgs {
+    /// Map to make seamless
+    input: PathBuf,
+    /// Width in cells of the band along each edge that is cross-faded with
+    /// the opposite edge; at most half the map's width or height is used
+    #[arg(long, default_value_t = 16)]
+    band: usize,
+    /// Path of the seamless map
+    #[arg(short, long)]
+    output: PathBuf,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn seamless(args: SeamlessArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("seamless", &args)?.with_input(&args.input)?;
+    let mut map = heightmap::load_map(&args.input)?;
+    let before = map_edge_mismatch(&map);
+    make_map_seamless(&mut map, args.band);
+    let after = map_edge_mismatch(&map);
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+
+    println!(
+        "Made {} seamless over {} cells -> {}: edge mismatch {:.3} m before, {:.3} m after",
+        args.input.display(),
+        args.band,
+        args.output.display(),
+        before,
+        after
+    );
+    Ok(())
+}
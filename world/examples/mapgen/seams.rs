@@ -0,0 +1,63 @@
+use crate::verify::collect_files;
+use clap::Args;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error, map_size,
+    seams::{SeamParams, find_seams},
+};
+
+#[derive(Args)]
+pub struct SeamsArgs {
+    /// Maps to check, or directories of them
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Number of rows or columns on each side of a line that it is compared
+    /// with
+    #[arg(long, default_value_t = SeamParams::default().radius)]
+    radius: usize,
+    /// How many times larger than across nearby lines the average jump across
+    /// a line must be to report it
+    #[arg(long, default_value_t = SeamParams::default().min_ratio)]
+    min_ratio: f64,
+    /// Smallest average jump reported
+    #[arg(long, default_value_t = SeamParams::default().min_jump)]
+    min_jump: f64,
+    /// Only report seams between tiles of this many cells, as stitched
+    #[arg(long)]
+    tile_size: Option<usize>,
+}
+
+/// Reports the rows and columns of maps across which altitudes jump far more
+/// than across their neighbours, as bad stitches leave, exiting with an error
+/// if any are found.
+pub fn seams(args: SeamsArgs) -> Result<(), Error> {
+    let params = SeamParams {
+        radius: args.radius.max(1),
+        min_ratio: args.min_ratio,
+        min_jump: args.min_jump,
+    };
+    let mut found = 0;
+    for path in collect_files(&args.paths)? {
+        let map = heightmap::load_map(&path)?;
+        let seams = find_seams(&map.alt, map_size(&map), &params)
+            .into_iter()
+            .filter(|seam| {
+                args.tile_size
+                    .is_none_or(|size| size > 0 && seam.position % size == 0)
+            })
+            .collect::<Vec<_>>();
+        if seams.is_empty() {
+            println!("{}: no seams", path.display());
+        } else {
+            println!("{}: {} seams", path.display(), seams.len());
+            for seam in &seams {
+                println!("  {}", seam);
+            }
+        }
+        found += seams.len();
+    }
+    if found > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
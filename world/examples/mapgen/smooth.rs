@@ -0,0 +1,81 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use vek::*;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    filter::{self, Edges},
+    map_size,
+    provenance::Provenance,
+};
+
+#[derive(Args, Serialize)]
+pub struct SmoothRegionArgs {
+    /// Map to smooth
+    input: PathBuf,
+    /// X coordinate of the cell at the center of the circle
+    #[arg(long)]
+    x: u32,
+    /// Y coordinate of the cell at the center of the circle
+    #[arg(long)]
+    y: u32,
+    /// Radius of the circle in cells; its outer half fades back to the
+    /// original terrain
+    #[arg(long)]
+    radius: u32,
+    /// Number of box filter passes
+    #[arg(long, default_value_t = 4)]
+    iterations: u32,
+    /// Treat the map as wrapping around at its edges, so that circles near
+    /// one reach across to the opposite one
+    #[arg(long)]
+    wrap: bool,
+    /// Path of the smoothed map
+    #[arg(short, long, required_unless_present = "in_place")]
+    output: Option<PathBuf>,
+    /// Overwrite the input map
+    #[arg(long, conflicts_with = "output")]
+    in_place: bool,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn smooth_region(args: SmoothRegionArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("smooth-region", &args)?
+        .with_input(&args.input)?
+        .with_step(format!(
+            "smooth {} times within {} cells of ({}, {})",
+            args.iterations, args.radius, args.x, args.y
+        ));
+    let mut map = heightmap::load_map(&args.input)?;
+    let size = map_size(&map);
+    let changed = filter::smooth_region(
+        &mut map.alt,
+        size,
+        Vec2::new(args.x, args.y),
+        args.radius,
+        args.iterations,
+        Edges::from_wrap(args.wrap),
+    );
+    if changed == 0 {
+        println!("Warning: nothing changed; the circle may not overlap the map");
+    }
+    // Smoothing can lower cells below their basement.
+    for (basement, alt) in map.basement.iter_mut().zip(map.alt.iter()) {
+        *basement = basement.min(*alt);
+    }
+
+    let output = args.output.unwrap_or_else(|| args.input.clone());
+    provenance.save_map(&output, map, args.compress.compression())?;
+    println!(
+        "Smoothed {} cells within {} cells of ({}, {}) of {} -> {}",
+        changed,
+        args.radius,
+        args.x,
+        args.y,
+        args.input.display(),
+        output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use vek::*;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    import::Channel,
+    provenance::Provenance,
+    stamp::{self, Brush, PoissonParams, StampMode},
+};
+
+#[derive(Args, Serialize)]
+pub struct StampArgs {
+    /// Map to stamp onto
+    target: PathBuf,
+    /// Brush: a .bin map, or an image whose pixel values give heights from 0
+    /// to 1 and whose alpha channel gives weights
+    brush: PathBuf,
+    /// X coordinate of the cell the centre of the brush is placed at
+    #[arg(long, allow_negative_numbers = true)]
+    x: i64,
+    /// Y coordinate of the cell the centre of the brush is placed at
+    #[arg(long, allow_negative_numbers = true)]
+    y: i64,
+    /// Factor the brush heights are multiplied by
+    #[arg(long, default_value_t = 1.0, allow_negative_numbers = true)]
+    scale: f64,
+    /// Altitude the scaled brush heights are relative to, for `max` and
+    /// `replace`
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    base: f64,
+    #[arg(long, value_enum, default_value_t = StampMode::Add)]
+    mode: StampMode,
+    /// Fraction of the brush radius over which it fades out (0 to disable)
+    #[arg(long, default_value_t = 0.25)]
+    falloff: f64,
+    /// With `--mode poisson`, the solve stops once no altitude changes by
+    /// more than this many meters in an iteration
+    #[arg(long, default_value_t = PoissonParams::default().tolerance)]
+    tolerance: f64,
+    /// With `--mode poisson`, the most iterations of the solve
+    #[arg(long, default_value_t = PoissonParams::default().max_iterations)]
+    max_iterations: usize,
+    /// Channel of image brushes to read heights from
+    #[arg(long, value_enum, default_value_t = Channel::Red)]
+    channel: Channel,
+    /// Path of the stamped map
+    #[arg(short, long, required_unless_present = "in_place")]
+    output: Option<PathBuf>,
+    /// Overwrite the target map
+    #[arg(long, conflicts_with = "output")]
+    in_place: bool,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn stamp(args: StampArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("stamp", &args)?
+        .with_input(&args.target)?
+        .with_input(&args.brush)?;
+    let mut map = heightmap::load_map(&args.target)?;
+    let mut brush = if heightmap::is_map_path(&args.brush) {
+        Brush::from_map(&heightmap::load_map(&args.brush)?)
+    } else {
+        Brush::load_image(&args.brush, args.channel)?
+    };
+    brush.feather(args.falloff);
+
+    let center = Vec2::new(args.x, args.y);
+    let affected = if args.mode == StampMode::Poisson {
+        let params = PoissonParams {
+            tolerance: args.tolerance,
+            max_iterations: args.max_iterations,
+        };
+        let result = stamp::stamp_poisson(&mut map, &brush, center, args.scale, &params);
+        if result.affected > 0 {
+            println!(
+                "Solved in {} iterations, last change {:.2e} m{}",
+                result.iterations,
+                result.change,
+                if result.converged {
+                    ""
+                } else {
+                    " (not converged; raise --max-iterations)"
+                }
+            );
+        }
+        result.affected
+    } else {
+        stamp::stamp(&mut map, &brush, center, args.scale, args.base, args.mode)
+    };
+    if affected == 0 {
+        println!("Warning: the brush does not overlap the map");
+    }
+
+    let output = args.output.unwrap_or_else(|| args.target.clone());
+    provenance.save_map(&output, map, args.compress.compression())?;
+    println!(
+        "Stamped {} onto {} at ({}, {}), affecting {} cells -> {}",
+        args.brush.display(),
+        args.target.display(),
+        args.x,
+        args.y,
+        affected,
+        output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use clap::Args;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    animate::{self, SunSweep},
+    export::compute_min_max,
+    filter::Edges,
+    map_size,
+    relief::ReliefParams,
+    resample::{Antialias, resample_antialiased},
+};
+
+#[derive(Args)]
+pub struct SunSweepArgs {
+    /// Map to animate
+    input: PathBuf,
+    /// GIF image to write the animation to
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Number of frames, over which the sun turns a full circle, such as 24
+    /// for steps of 15 degrees
+    #[arg(long, default_value_t = SunSweep::default().frames)]
+    frames: usize,
+    /// Azimuth of the sun in the first frame, in degrees clockwise from north
+    #[arg(long, default_value_t = SunSweep::default().start)]
+    start: f64,
+    /// Height of the sun above the horizon, in degrees
+    #[arg(long, default_value_t = SunSweep::default().elevation)]
+    elevation: f64,
+    /// How long each frame is shown, in milliseconds
+    #[arg(long, default_value_t = 100)]
+    delay: u32,
+    /// Shrink the map to fit this many pixels along its longer side, to keep
+    /// the file small
+    #[arg(long, value_name = "PIXELS")]
+    max_size: Option<u32>,
+    /// Altitude at which the tint changes from water to land
+    #[arg(long, default_value_t = 0.0)]
+    sea_level: f64,
+    /// Strength of the hillshade, from 0 for the bare tint to 1 for the full
+    /// shade
+    #[arg(long, default_value_t = 1.0)]
+    contrast: f64,
+    /// Shade the edges of the map as if it wrapped around
+    #[arg(long)]
+    wrap: bool,
+}
+
+/// Renders an animated GIF of the shaded relief of a map as the sun turns
+/// around it.
+///
+/// Maps shrunk to fit `--max-size` have their altitudes divided by the width
+/// of their new cells for shading, like `compare` previews, so that shrinking
+/// doesn't steepen their slopes.
+pub fn sun_sweep(args: SunSweepArgs) -> Result<(), Error> {
+    let map = heightmap::load_map(&args.input)?;
+    let size = map_size(&map);
+    let longest = size.x.max(size.y);
+    let cell_width = args.max_size.map_or(1.0, |max_size| {
+        (longest as f64 / max_size.max(1) as f64).max(1.0)
+    });
+    let (alt, size) = if cell_width > 1.0 {
+        let new_size = size.map(|e| ((e as f64 / cell_width).round() as usize).max(1));
+        let alt = resample_antialiased(&map.alt, size, new_size, Antialias::Box)
+            .into_iter()
+            .map(|alt| alt / cell_width)
+            .collect();
+        (alt, new_size)
+    } else {
+        (map.alt.into_vec(), size)
+    };
+
+    let (min, max) = compute_min_max(&alt);
+    let params = ReliefParams {
+        sea_level: args.sea_level / cell_width,
+        contrast: args.contrast,
+        edges: Edges::from_wrap(args.wrap),
+        ..Default::default()
+    };
+    let sweep = SunSweep {
+        frames: args.frames.max(1),
+        start: args.start,
+        elevation: args.elevation,
+    };
+    let frames = animate::render_sweep(&alt, size, min, max, &params, &sweep);
+    animate::save_gif(frames.map(Ok), args.delay, &args.output)?;
+    println!(
+        "Animated {} -> {} ({}x{}, {} frames {:.1} degrees apart)",
+        args.input.display(),
+        args.output.display(),
+        size.x,
+        size.y,
+        sweep.frames,
+        360.0 / sweep.frames as f64
+    );
+    Ok(())
+}
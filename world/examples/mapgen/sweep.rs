@@ -0,0 +1,119 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use veloren_world::heightmap::{
+    self, Error,
+    cli::{CompressArgs, OverwriteArgs},
+    filter::Edges,
+    import::{self, Channel, ImportParams},
+    provenance::Provenance,
+    stats::AltStats,
+    sweep::{self, SweepRange},
+    template::NameTemplate,
+};
+
+#[derive(Args, Serialize)]
+pub struct SweepArgs {
+    /// Image to convert
+    input: PathBuf,
+    /// Scales to try, as START..END:STEP (such as 800..1600:200) or a single
+    /// value
+    #[arg(long, default_value = "1000", allow_hyphen_values = true)]
+    scale: String,
+    /// Offsets (altitudes of a black pixel) to try, as START..END:STEP or a
+    /// single value
+    #[arg(long, default_value = "-600", allow_hyphen_values = true)]
+    offset: String,
+    /// Path of each map, in which {scale} and {offset} are replaced by the
+    /// values it was converted with, {stem} by the input's name without its
+    /// extension and {ext} by that of the maps (defaults to
+    /// {stem}_scale{scale}_offset{offset}.{ext} next to the input)
+    #[arg(short, long, visible_alias = "name-template")]
+    output: Option<String>,
+    /// Refuse to write more maps than this
+    #[arg(long, default_value_t = 64)]
+    max_outputs: usize,
+    /// Value stored as the continent_scale_hack of the maps
+    #[arg(long, default_value_t = 1.6)]
+    continent_scale: f64,
+    /// Number of box filter passes used to smooth the image
+    #[arg(long, default_value_t = 0)]
+    smooth: u32,
+    /// Smooth the image as if it wrapped around at its edges
+    #[arg(long)]
+    wrap: bool,
+    /// Channel of the image to read altitudes from
+    #[arg(long, value_enum, default_value_t = Channel::Red)]
+    channel: Channel,
+    #[command(flatten)]
+    compress: CompressArgs,
+    #[command(flatten)]
+    overwrite: OverwriteArgs,
+}
+
+pub fn sweep(args: SweepArgs) -> Result<(), Error> {
+    let combinations = sweep::combinations(
+        &SweepRange::parse(&args.scale)?,
+        &SweepRange::parse(&args.offset)?,
+        args.max_outputs,
+    )?;
+    let compression = args.compress.compression();
+    let template = args.output.clone().unwrap_or_else(|| {
+        let dir = args.input.parent().unwrap_or(Path::new(""));
+        let dir = NameTemplate::escape(&dir.join("").to_string_lossy());
+        format!("{}{{stem}}_scale{{scale}}_offset{{offset}}.{{ext}}", dir)
+    });
+    let template = NameTemplate::parse(&template, &sweep::PLACEHOLDERS)?;
+    let stem = args.input.file_stem().unwrap_or_default().to_string_lossy();
+    let values = [("stem", stem.as_ref()), ("ext", compression.extension())];
+    let paths = sweep::output_paths(&template, &values, &combinations)?;
+
+    let mut params = ImportParams {
+        continent_scale: args.continent_scale,
+        smooth_iterations: args.smooth,
+        edges: Edges::from_wrap(args.wrap),
+        channel: args.channel,
+        ..ImportParams::new(1.0, 0.0)
+    };
+    let img = import::load_image(&args.input)?;
+    let unit = import::import_altitudes(&img, &sweep::unit_params(&params)?)?;
+    drop(img);
+    for warning in &unit.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    println!(
+        "{:>10}  {:>10}  {:>6}  {:>10}  file",
+        "scale", "offset", "land", "max alt"
+    );
+    for (&(scale, offset), path) in combinations.iter().zip(&paths) {
+        if let Some(existing) = args.overwrite.existing(std::slice::from_ref(path)) {
+            OverwriteArgs::report_skipped(existing, false);
+            continue;
+        }
+        let alt = sweep::apply(&unit.alt, scale, offset);
+        let stats = AltStats::of(&alt);
+        params.scale = scale;
+        params.offset = offset;
+        let mut provenance = Provenance::new("sweep", &params)?.with_input(&args.input)?;
+        provenance.pipeline = params.pipeline();
+        provenance.save_with(path, |temp| {
+            heightmap::save_map_with_alt_basement(
+                temp,
+                unit.map_size_lg,
+                args.continent_scale,
+                &alt,
+                compression,
+            )
+        })?;
+        println!(
+            "{:>10}  {:>10}  {:>5.1}%  {:>10.2}  {}",
+            scale,
+            offset,
+            stats.land_fraction * 100.0,
+            stats.max,
+            path.display()
+        );
+    }
+    Ok(())
+}
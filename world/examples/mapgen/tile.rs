@@ -0,0 +1,128 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    provenance::Provenance,
+    tile::{LazyStitch, TileEntry, TileManifest},
+};
+
+#[derive(Args, Serialize)]
+pub struct SplitArgs {
+    /// Map to split
+    input: PathBuf,
+    /// Side length of each tile, in cells; must evenly divide the map
+    #[arg(long)]
+    tile_size: u32,
+    /// Directory to write the tiles and manifest to (defaults to the
+    /// directory of the input)
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+#[derive(Args, Serialize)]
+pub struct StitchArgs {
+    /// Manifest written by `split`
+    manifest: PathBuf,
+    /// Path of the reassembled map
+    output: PathBuf,
+    /// Load each row of tiles only while its part of the map is written,
+    /// instead of all of them at once, for maps too large to hold in memory
+    #[arg(long)]
+    lazy: bool,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn split(args: SplitArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("split", &args)?.with_input(&args.input)?;
+    let map = heightmap::load_map(&args.input)?;
+    let map_size_lg = map.map_size_lg;
+    let continent_scale_hack = map.continent_scale_hack;
+    let tiles = heightmap::tile::split(&map, args.tile_size)?;
+    drop(map);
+
+    let out_dir = args
+        .out_dir
+        .unwrap_or_else(|| args.input.parent().map(PathBuf::from).unwrap_or_default());
+    std::fs::create_dir_all(&out_dir)?;
+    let stem = heightmap::with_map_extension(&args.input, "")
+        .file_name()
+        .map_or_else(|| "map".into(), |stem| stem.to_string_lossy().into_owned());
+
+    let compression = args.compress.compression();
+    let mut manifest = TileManifest {
+        map_size_lg,
+        tile_size_lg: tiles[0].1.map_size_lg,
+        continent_scale_hack,
+        tiles: Vec::with_capacity(tiles.len()),
+    };
+    for (pos, tile) in tiles {
+        let file = format!("{}_{}_{}.{}", stem, pos.x, pos.y, compression.extension());
+        provenance
+            .clone()
+            .with_step(format!("tile ({}, {})", pos.x, pos.y))
+            .save_map(out_dir.join(&file), tile, compression)?;
+        manifest.tiles.push(TileEntry { pos, file });
+    }
+
+    let manifest_path = out_dir.join(format!("{}.tiles.json", stem));
+    manifest.save(&manifest_path)?;
+
+    let grid = manifest.grid_size()?;
+    println!(
+        "Split {} into {}x{} tiles of {}x{} cells",
+        args.input.display(),
+        grid.x,
+        grid.y,
+        args.tile_size,
+        args.tile_size
+    );
+    println!("Manifest written to {}", manifest_path.display());
+    Ok(())
+}
+
+pub fn stitch(args: StitchArgs) -> Result<(), Error> {
+    let provenance = Provenance::new("stitch", &args)?.with_input(&args.manifest)?;
+    let manifest = TileManifest::load(&args.manifest)?;
+    let base_dir = args
+        .manifest
+        .parent()
+        .map(PathBuf::from)
+        .unwrap_or_default();
+
+    if args.lazy {
+        let lazy = LazyStitch::from_manifest(&manifest, &base_dir);
+        provenance.save_with(&args.output, |temp| {
+            lazy.save(temp, args.compress.compression())
+        })?;
+        println!(
+            "Stitched {} tiles into {}, one row at a time",
+            manifest.tiles.len(),
+            args.output.display()
+        );
+        return Ok(());
+    }
+    let tiles = manifest
+        .tiles
+        .iter()
+        .map(|entry| Ok((entry.pos, heightmap::load_map(base_dir.join(&entry.file))?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let map = heightmap::tile::stitch(
+        manifest.map_size_lg,
+        manifest.tile_size_lg,
+        manifest.continent_scale_hack,
+        tiles,
+    )?;
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+
+    println!(
+        "Stitched {} tiles into {}",
+        manifest.tiles.len(),
+        args.output.display()
+    );
+    Ok(())
+}
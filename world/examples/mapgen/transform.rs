@@ -0,0 +1,48 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    provenance::Provenance,
+    transform::{Transform, transform_map},
+};
+
+#[derive(Args, Serialize)]
+pub struct TransformArgs {
+    /// Map to transform
+    input: PathBuf,
+    /// Transforms to apply, in order
+    #[arg(value_enum, required = true)]
+    transforms: Vec<Transform>,
+    /// Path of the transformed map
+    #[arg(short, long, required_unless_present = "in_place")]
+    output: Option<PathBuf>,
+    /// Overwrite the input map
+    #[arg(long, conflicts_with = "output")]
+    in_place: bool,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn transform(args: TransformArgs) -> Result<(), Error> {
+    let mut provenance = Provenance::new("transform", &args)?.with_input(&args.input)?;
+    let mut map = heightmap::load_map(&args.input)?;
+    for &transform in &args.transforms {
+        map = transform_map(&map, transform);
+        provenance = provenance.with_step(format!("{:?}", transform));
+    }
+
+    let output = args.output.unwrap_or_else(|| args.input.clone());
+    let size = heightmap::map_size(&map);
+    provenance.save_map(&output, map, args.compress.compression())?;
+
+    println!(
+        "Transformed {} -> {} ({}x{})",
+        args.input.display(),
+        output.display(),
+        size.x,
+        size.y
+    );
+    Ok(())
+}
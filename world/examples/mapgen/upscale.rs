@@ -0,0 +1,95 @@
+use clap::Args;
+use serde::Serialize;
+use std::path::PathBuf;
+use veloren_world::heightmap::{
+    self, Error,
+    cli::CompressArgs,
+    provenance::Provenance,
+    resample::{DetailNoise, FractalDetail, upscale_map, upscale_map_fractal},
+};
+
+#[derive(Args, Serialize)]
+#[command(group(clap::ArgGroup::new("noise").args(["detail", "fractal"])))]
+pub struct UpscaleArgs {
+    /// Map to upscale
+    input: PathBuf,
+    /// Power of two to multiply the width and height of the map by
+    #[arg(long, default_value_t = 2)]
+    factor: u32,
+    /// Add fractal noise with this amplitude, as a fraction of the local
+    /// relief, so that the new cells get detail of their own
+    #[arg(long, value_name = "AMPLITUDE")]
+    detail: Option<f64>,
+    /// Instead, upscale bilinearly and add fractal noise that fades on steep
+    /// slopes, each octave of which has 2^-EXPONENT times the amplitude of
+    /// the one before (1 for natural-looking terrain, higher for smoother)
+    #[arg(
+        long,
+        value_name = "EXPONENT",
+        allow_negative_numbers = true,
+        conflicts_with = "detail"
+    )]
+    fractal: Option<f64>,
+    /// Amplitude of the --fractal noise on flat ground, in meters
+    #[arg(long, default_value_t = 10.0, requires = "fractal")]
+    amplitude: f64,
+    /// Number of octaves of the --fractal noise
+    #[arg(long, default_value_t = 4, requires = "fractal")]
+    octaves: usize,
+    /// Seed of the detail or fractal noise
+    #[arg(long, default_value_t = 0, requires = "noise")]
+    seed: u32,
+    /// Path of the upscaled map
+    #[arg(short, long)]
+    output: PathBuf,
+    #[command(flatten)]
+    compress: CompressArgs,
+}
+
+pub fn upscale(args: UpscaleArgs) -> Result<(), Error> {
+    let mut provenance = Provenance::new("upscale", &args)?.with_input(&args.input)?;
+    if args.detail.is_some() || args.fractal.is_some() {
+        provenance = provenance.with_seed(args.seed.into());
+    }
+    let map = heightmap::load_map(&args.input)?;
+    let before = heightmap::map_size(&map);
+    let detail = args.detail.map(|amplitude| DetailNoise {
+        seed: args.seed,
+        amplitude,
+    });
+    let fractal = args.fractal.map(|exponent| FractalDetail {
+        seed: args.seed,
+        exponent,
+        amplitude: args.amplitude,
+        octaves: args.octaves,
+    });
+    let map = match fractal {
+        Some(fractal) => upscale_map_fractal(&map, args.factor, fractal)?,
+        None => upscale_map(&map, args.factor, detail)?,
+    };
+    let after = heightmap::map_size(&map);
+    provenance.save_map(&args.output, map, args.compress.compression())?;
+
+    println!(
+        "Upscaled {} ({}x{}) -> {} ({}x{})",
+        args.input.display(),
+        before.x,
+        before.y,
+        args.output.display(),
+        after.x,
+        after.y
+    );
+    if let Some(detail) = detail {
+        println!(
+            "Detail noise: seed {}, amplitude {} of the local relief",
+            detail.seed, detail.amplitude
+        );
+    }
+    if let Some(fractal) = fractal {
+        println!(
+            "Fractal noise: seed {}, exponent {}, {} octaves, {} m on flat ground",
+            fractal.seed, fractal.exponent, fractal.octaves, fractal.amplitude
+        );
+    }
+    Ok(())
+}
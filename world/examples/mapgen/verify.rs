@@ -0,0 +1,140 @@
+use clap::Args;
+use std::{fs::read_dir, path::PathBuf};
+use veloren_world::heightmap::{
+    self, Error,
+    provenance::Provenance,
+    verify::{Check, Outcome, Report, VerifyParams, verify as verify_map},
+};
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// Maps to check; directories are searched for .bin files
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Lowest plausible altitude
+    #[arg(long, default_value_t = VerifyParams::default().min_alt, allow_negative_numbers = true)]
+    min_alt: f64,
+    /// Highest plausible altitude
+    #[arg(long, default_value_t = VerifyParams::default().max_alt, allow_negative_numbers = true)]
+    max_alt: f64,
+    /// Warn about maps whose altitudes have a smaller standard deviation
+    #[arg(long, default_value_t = VerifyParams::default().min_std_dev)]
+    min_std_dev: f64,
+}
+
+/// Expands directories in `paths` to the .bin (and .bin.zst) files they
+/// contain.
+pub fn collect_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut bins = Vec::new();
+            for entry in read_dir(path)? {
+                let path = entry?.path();
+                if heightmap::is_map_path(&path) {
+                    bins.push(path);
+                }
+            }
+            bins.sort();
+            files.extend(bins);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn summary_cell(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Passed => "ok".to_string(),
+        Outcome::Failed { count, .. } => format!("FAIL ({})", count),
+        Outcome::Skipped => "-".to_string(),
+    }
+}
+
+fn print_summary(results: &[(PathBuf, Result<Report, Error>)]) {
+    let names = results
+        .iter()
+        .map(|(path, _)| path.display().to_string())
+        .collect::<Vec<_>>();
+    let name_width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    let header = Check::ALL.map(|check| check.name());
+    let widths = header.map(|name| name.len().max(12));
+
+    print!("{:<name_width$}", "file");
+    for (name, width) in header.iter().zip(widths) {
+        print!("  {:<width$}", name);
+    }
+    println!();
+    for (name, (_, result)) in names.iter().zip(results) {
+        print!("{:<name_width$}", name);
+        match result {
+            Ok(report) => {
+                for ((_, outcome), width) in report.outcomes.iter().zip(widths) {
+                    print!("  {:<width$}", summary_cell(outcome));
+                }
+            },
+            Err(_) => print!("  could not load"),
+        }
+        println!();
+    }
+}
+
+pub fn verify(args: VerifyArgs) -> Result<(), Error> {
+    let params = VerifyParams {
+        min_alt: args.min_alt,
+        max_alt: args.max_alt,
+        min_std_dev: args.min_std_dev,
+    };
+    let results = collect_files(&args.paths)?
+        .into_iter()
+        .map(|path| {
+            let report =
+                heightmap::io::load_map_unchecked(&path).map(|map| verify_map(&map, &params));
+            (path, report)
+        })
+        .collect::<Vec<_>>();
+
+    let mut failed = 0;
+    for (path, result) in &results {
+        for warning in result.iter().flat_map(|report| &report.warnings) {
+            println!("{}: warning: {}", path.display(), warning);
+        }
+        match result {
+            Ok(report) if report.passed() => println!("{}: ok", path.display()),
+            Ok(report) => {
+                failed += 1;
+                println!("{}: FAILED", path.display());
+                for (check, outcome) in &report.outcomes {
+                    if let Outcome::Failed { .. } = outcome {
+                        println!("  {}: {}", check.name(), outcome);
+                    }
+                }
+            },
+            Err(error) => {
+                failed += 1;
+                println!("{}: could not load: {}", path.display(), error);
+            },
+        }
+        match Provenance::load_for(path) {
+            Ok(Some(provenance)) => print!("{:2}", provenance),
+            Ok(None) => {},
+            Err(error) => println!("  could not read the provenance record: {}", error),
+        }
+    }
+
+    if results.len() > 1 {
+        println!();
+        print_summary(&results);
+        println!();
+        println!(
+            "{} of {} maps passed",
+            results.len() - failed,
+            results.len()
+        );
+    }
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -8,9 +8,7 @@ use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 
-use bincode;
-use veloren_world::sim::{WorldFile, WorldMap_0_7_0};
-use vek::Vec2;
+use veloren_world::sim::WorldFile;
 
 /// Computes and prints basic statistics (count, minimum and maximum)
 /// of an altitude array.
@@ -51,9 +49,9 @@ fn main() {
     let world_file: WorldFile = bincode::deserialize(&buffer)
         .expect("Failed to deserialize world file");
 
-    // Print information by matching on the world file variant.
-    match world_file {
-        WorldFile::Veloren0_7_0(map) => {
+    // Print information if the world file is of the latest version.
+    match world_file.as_v0_7_0() {
+        Some(map) => {
             println!("Map variant: Veloren0_7_0");
             println!(
                 "map_size_lg (exponent): {} x {}  [Actual size: {}x{}]",
@@ -67,8 +65,7 @@ fn main() {
             println!("Number of basement values: {}", map.basement.len());
             print_alt_stats(&map.alt);
         }
-        // Print a message for other variants if desired.
-        _ => {
+        None => {
             println!("Other world file variant detected.");
         }
     }
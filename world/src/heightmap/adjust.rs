@@ -0,0 +1,296 @@
+//! Uniform adjustments of a map's altitudes.
+
+use super::export::compute_min_max;
+use crate::sim::ModernMap;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use vek::*;
+
+/// Adds `delta` to every altitude and basement altitude of `map`.
+pub fn shift(map: &mut ModernMap, delta: f64) {
+    map.alt
+        .iter_mut()
+        .chain(map.basement.iter_mut())
+        .for_each(|alt| *alt += delta);
+}
+
+/// Scales every altitude and basement altitude of `map` by `factor` around
+/// the altitude `pivot`, which stays in place.
+pub fn scale(map: &mut ModernMap, factor: f64, pivot: f64) {
+    map.alt
+        .iter_mut()
+        .chain(map.basement.iter_mut())
+        .for_each(|alt| *alt = pivot + (*alt - pivot) * factor);
+}
+
+/// Adds a linear ramp to the row-major grid `alt` of `size` cells: the cell
+/// at `(x, y)` rises by `grad.x * x + grad.y * y`.
+///
+/// `grad` is in meters per cell.  Rows with a higher `y` lie further north, so
+/// a positive `grad.y` raises the north of the map relative to the south,
+/// while the cell at `(0, 0)` stays in place.
+pub fn apply_tilt(alt: &mut [f64], size: Vec2<usize>, grad: Vec2<f64>) {
+    for (y, row) in alt.chunks_exact_mut(size.x).enumerate() {
+        for (x, alt) in row.iter_mut().enumerate() {
+            *alt += grad.x * x as f64 + grad.y * y as f64;
+        }
+    }
+}
+
+/// Whether every altitude and basement altitude of `map` is finite.
+pub fn is_finite(map: &ModernMap) -> bool {
+    map.alt
+        .iter()
+        .chain(map.basement.iter())
+        .all(|alt| alt.is_finite())
+}
+
+/// Shifts `map` so that the altitude `current_sea` becomes exactly 0, which
+/// the engine treats as sea level.  Returns the shift that was applied.
+pub fn sea_to_zero(map: &mut ModernMap, current_sea: f64) -> f64 {
+    let delta = -current_sea;
+    shift(map, delta);
+    delta
+}
+
+/// Floor below which deep water is raised, so that trenches nobody will see
+/// don't take up the altitude range of a map.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AbyssalClamp {
+    /// Lowest altitude left after clamping.
+    pub floor: f64,
+    /// Height of the band above `floor` over which the clamp eases in, so
+    /// that no shelf forms where it starts; 0 for a hard clamp.
+    #[serde(default)]
+    pub rolloff: f64,
+}
+
+impl AbyssalClamp {
+    /// Altitude `alt` after clamping: unchanged above the roll-off band, and
+    /// otherwise approaching `floor` exponentially, with the same slope as
+    /// the unclamped altitude where the band starts.
+    #[inline]
+    pub fn clamp(&self, alt: f64) -> f64 {
+        if self.rolloff <= 0.0 {
+            return alt.max(self.floor);
+        }
+        let knee = self.floor + self.rolloff;
+        if alt >= knee {
+            alt
+        } else {
+            self.floor + self.rolloff * ((alt - knee) / self.rolloff).exp()
+        }
+    }
+}
+
+/// Clamps every altitude in `grid` with `abyss`, returning the number of
+/// cells that changed.
+pub fn clamp_abyss_grid(grid: &mut [f64], abyss: &AbyssalClamp) -> usize {
+    let mut changed = 0;
+    for alt in grid {
+        let clamped = abyss.clamp(*alt);
+        if clamped != *alt {
+            *alt = clamped;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// Clamps every altitude and basement altitude of `map` with `abyss`; since
+/// the clamp never reorders altitudes, the basement stays below the
+/// altitudes.  Returns the number of cells whose altitude changed.
+pub fn clamp_abyss(map: &mut ModernMap, abyss: &AbyssalClamp) -> usize {
+    clamp_abyss_grid(&mut map.basement, abyss);
+    clamp_abyss_grid(&mut map.alt, abyss)
+}
+
+/// Basement placed at a depth proportional to the altitude's height above
+/// (or depth below) sea level, rather than at a constant depth.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProportionalBasement {
+    pub sea_level: f64,
+    /// Fraction of the height above sea level kept by the basement.
+    pub factor: f64,
+}
+
+impl ProportionalBasement {
+    /// The basement below altitude `alt`: `sea_level + (alt - sea_level) *
+    /// factor`, but never above `alt`.
+    #[inline]
+    pub fn basement(&self, alt: f64) -> f64 {
+        (self.sea_level + (alt - self.sea_level) * self.factor).min(alt)
+    }
+}
+
+/// Recomputes the basement of every cell of `map` from its altitude.
+pub fn set_proportional_basement(map: &mut ModernMap, basement: &ProportionalBasement) {
+    for (alt, b) in map.alt.iter().zip(map.basement.iter_mut()) {
+        *b = basement.basement(*alt);
+    }
+}
+
+/// Redistributes the altitudes of `alt` over their range so that each of
+/// `bins` equal bands of it holds about as many cells, stretching the
+/// altitudes most cells share apart and squeezing together the rare ones, to
+/// bring out detail in bland maps.
+///
+/// This is not a physical transform: slopes, the height of peaks above the
+/// plains and the depth of the sea all change, and only the order of the
+/// altitudes, the lowest and the highest are kept.  Altitudes within a bin
+/// are spread linearly across its share, so that equal altitudes stay equal
+/// and the result doesn't fall into `bins` terraces.  NaNs are left alone,
+/// and flat grids unchanged.  `bins` is at least 1.
+pub fn histogram_equalize(alt: &mut [f64], bins: usize) {
+    let (min, max) = compute_min_max(alt);
+    if max <= min {
+        return;
+    }
+    let bins = bins.max(1);
+    let width = (max - min) / bins as f64;
+    let bin_of = |alt: f64| (((alt - min) / width) as usize).min(bins - 1);
+    // The number of cells below each bin, and below the next, by the end.
+    let mut below = vec![0usize; bins + 1];
+    for &alt in alt.iter().filter(|alt| !alt.is_nan()) {
+        below[bin_of(alt) + 1] += 1;
+    }
+    for i in 1..=bins {
+        below[i] += below[i - 1];
+    }
+    let cells = below[bins] as f64;
+    alt.par_iter_mut()
+        .filter(|alt| !alt.is_nan())
+        .for_each(|alt| {
+            let bin = bin_of(*alt);
+            let within = ((*alt - min) / width - bin as f64).clamp(0.0, 1.0);
+            let rank = below[bin] as f64 + (below[bin + 1] - below[bin]) as f64 * within;
+            *alt = min + rank / cells * (max - min);
+        });
+}
+
+/// [`histogram_equalize`]s the altitudes of `map`, moving its basement with
+/// them so that the sediment above it keeps its depth.
+pub fn equalize(map: &mut ModernMap, bins: usize) {
+    let before = map.alt.to_vec();
+    histogram_equalize(&mut map.alt, bins);
+    for ((basement, before), after) in map.basement.iter_mut().zip(before).zip(map.alt.iter()) {
+        *basement += after - before;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+
+    #[test]
+    fn sea_level_becomes_exactly_zero() {
+        let sea = 123.456;
+        let mut map = test_map(Vec2::new(1, 1), |x, y| sea + (x + 2 * y) as f64 * 0.1);
+        assert_eq!(sea_to_zero(&mut map, sea), -sea);
+        assert_eq!(map.alt[0], 0.0);
+        assert_eq!(map.basement[0], -10.0);
+        for (alt, basement) in map.alt.iter().zip(map.basement.iter()) {
+            assert!((alt - basement - 10.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn scaling_keeps_pivot_in_place() {
+        let mut map = test_map(Vec2::new(1, 1), |x, y| [-20.0, 0.0, 10.0, 40.0][y * 2 + x]);
+        scale(&mut map, 1.5, 0.0);
+        assert_eq!(&*map.alt, &[-30.0, 0.0, 15.0, 60.0]);
+        assert_eq!(&*map.basement, &[-45.0, -15.0, 0.0, 45.0]);
+
+        scale(&mut map, 2.0, 15.0);
+        assert_eq!(&*map.alt, &[-75.0, -15.0, 15.0, 105.0]);
+        assert!(is_finite(&map));
+
+        scale(&mut map, f64::INFINITY, 0.0);
+        assert!(!is_finite(&map));
+    }
+
+    #[test]
+    fn tilt_adds_a_ramp_from_the_origin() {
+        let mut alt = vec![100.0; 6];
+        apply_tilt(&mut alt, Vec2::new(3, 2), Vec2::new(0.5, -2.0));
+        assert_eq!(alt, [100.0, 100.5, 101.0, 98.0, 98.5, 99.0]);
+    }
+
+    #[test]
+    fn abyss_is_clamped_to_the_floor() {
+        let mut map = test_map(Vec2::new(1, 1), |x, y| {
+            [-9000.0, -1000.0, -400.0, 50.0][y * 2 + x]
+        });
+        let hard = AbyssalClamp {
+            floor: -1000.0,
+            rolloff: 0.0,
+        };
+        assert_eq!(clamp_abyss(&mut map, &hard), 1);
+        assert_eq!(&*map.alt, &[-1000.0, -1000.0, -400.0, 50.0]);
+        assert_eq!(&*map.basement, &[-1000.0, -1000.0, -410.0, 40.0]);
+
+        // Easing in, the clamp meets the unclamped altitudes without a kink,
+        // and flattens out at the floor.
+        let soft = AbyssalClamp {
+            floor: -1000.0,
+            rolloff: 200.0,
+        };
+        let knee = -800.0;
+        assert_eq!(soft.clamp(knee), knee);
+        assert!((soft.clamp(knee - 1e-3) - (knee - 1e-3)).abs() < 1e-8);
+        assert!((soft.clamp(-9000.0) + 1000.0).abs() < 1e-9);
+        let alts = [-5000.0, -1200.0, -1000.0, -900.0, -800.0, 0.0];
+        for pair in alts.windows(2) {
+            assert!(soft.clamp(pair[0]) < soft.clamp(pair[1]));
+        }
+    }
+
+    #[test]
+    fn equalizing_spreads_crowded_altitudes() {
+        // Nine in ten cells crowd into the lowest hundredth of the range.
+        let mut map = test_map(Vec2::new(5, 5), |x, y| {
+            let i = (y * 32 + x) as f64;
+            if i < 922.0 {
+                i / 922.0 * 10.0
+            } else {
+                10.0 + (i - 922.0) / 101.0 * 990.0
+            }
+        });
+        let before = map.alt.to_vec();
+        equalize(&mut map, 256);
+        let (min, max) = compute_min_max(&map.alt);
+        assert_eq!((min, max), (0.0, 1000.0));
+        // The order is kept, and the crowded cells now take most of the range.
+        assert!(map.alt.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!((map.alt[512] - 500.0).abs() < 10.0, "{}", map.alt[512]);
+        assert!(map.alt[900] > 800.0);
+        for ((alt, basement), before) in map.alt.iter().zip(map.basement.iter()).zip(before) {
+            assert!(
+                (alt - basement - 10.0).abs() < 1e-9,
+                "{} from {}",
+                alt,
+                before
+            );
+        }
+
+        let mut flat = vec![5.0, 5.0, f64::NAN];
+        histogram_equalize(&mut flat, 16);
+        assert_eq!(flat[..2], [5.0, 5.0]);
+        assert!(flat[2].is_nan());
+    }
+
+    #[test]
+    fn proportional_basement_stays_below_alt() {
+        let mut map = test_map(Vec2::new(1, 1), |x, y| {
+            [-40.0, 10.0, 110.0, 1010.0][y * 2 + x]
+        });
+        set_proportional_basement(&mut map, &ProportionalBasement {
+            sea_level: 10.0,
+            factor: 0.5,
+        });
+        // Underwater, the formula would put the basement above the seabed.
+        assert_eq!(&*map.basement, &[-40.0, 10.0, 60.0, 510.0]);
+        assert_eq!(&*map.alt, &[-40.0, 10.0, 110.0, 1010.0]);
+    }
+}
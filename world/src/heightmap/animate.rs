@@ -0,0 +1,171 @@
+//! Animations of a shaded relief as the sun sweeps around the map, which
+//! shows every ridge and valley in turn, for presentations.
+
+use super::{
+    Error,
+    export::gradient,
+    io::write_atomically,
+    relief::{ReliefParams, composite, hillshade_towards, light_towards},
+};
+use image::{
+    Delay, DynamicImage, Frame, RgbImage,
+    codecs::gif::{GifEncoder, Repeat},
+};
+use rayon::prelude::*;
+use std::{io::Write, path::Path};
+use vek::*;
+
+/// The positions of the sun over an animation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SunSweep {
+    /// Number of frames, with the sun turning `360 / frames` degrees between
+    /// them, so that the animation loops seamlessly.
+    pub frames: usize,
+    /// Azimuth of the sun in the first frame, in degrees clockwise from north
+    /// (towards higher y).
+    pub start: f64,
+    /// Height of the sun above the horizon, in degrees, in every frame.
+    pub elevation: f64,
+}
+
+impl Default for SunSweep {
+    fn default() -> Self {
+        Self {
+            frames: 24,
+            start: 315.0,
+            elevation: 45.0,
+        }
+    }
+}
+
+impl SunSweep {
+    /// Azimuth of the sun in `frame`, between 0 and 360 degrees.
+    pub fn azimuth(&self, frame: usize) -> f64 {
+        (self.start + 360.0 * frame as f64 / self.frames.max(1) as f64).rem_euclid(360.0)
+    }
+}
+
+/// Renders the altitude grid of size `size`, spanning `min` to `max`, as a
+/// shaded relief like [`render_relief`](super::relief::render_relief) for
+/// each frame of `sweep`.
+///
+/// The gradient and tint of each cell don't depend on the sun, so they are
+/// computed once, and only the shading is redone for each frame.  Frames are
+/// rendered as the iterator is advanced, so that passing it to [`save_gif`]
+/// keeps a single one in memory however many there are.
+pub fn render_sweep(
+    alt: &[f64],
+    size: Vec2<usize>,
+    min: f64,
+    max: f64,
+    params: &ReliefParams,
+    sweep: &SunSweep,
+) -> impl Iterator<Item = RgbImage> + use<> {
+    let cells = (0..size.product())
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % size.x, i / size.x);
+            (
+                gradient(alt, size, x, y, params.edges),
+                params.tint(alt[i], min, max),
+            )
+        })
+        .collect::<Vec<_>>();
+    let (blend, contrast, sweep) = (params.blend, params.contrast, *sweep);
+    (0..sweep.frames).map(move |frame| {
+        let light = light_towards(sweep.azimuth(frame), sweep.elevation);
+        let buf = cells
+            .par_iter()
+            .flat_map_iter(|&(grad, color)| {
+                let shade = hillshade_towards(grad, light);
+                composite(color, shade, blend, contrast).map(|c| c.round().clamp(0.0, 255.0) as u8)
+            })
+            .collect::<Vec<_>>();
+        RgbImage::from_raw(size.x as u32, size.y as u32, buf)
+            .expect("Buffer matches the image size")
+    })
+}
+
+/// Writes `frames` to `path` as a looping animated GIF, showing each for
+/// `delay_ms` milliseconds, atomically like
+/// [`save_png`](super::export::save_png).
+///
+/// GIFs hold at most 256 colors a frame, so each frame is quantized to its
+/// own palette.  Frames are taken one at a time, so that they can be loaded
+/// or rendered as they are needed, and the first error among them aborts the
+/// write, leaving `path` untouched.
+pub fn save_gif(
+    frames: impl IntoIterator<Item = Result<RgbImage, Error>>,
+    delay_ms: u32,
+    path: impl AsRef<Path>,
+) -> Result<(), Error> {
+    write_atomically(path, |writer| encode_gif(frames, delay_ms, writer))
+}
+
+/// Encodes `frames` as a looping animated GIF into `writer`, like
+/// [`save_gif`].
+///
+/// Each frame is encoded and dropped before the next is taken, so that lazy
+/// iterators such as those of [`render_sweep`] are never held in full.
+pub fn encode_gif(
+    frames: impl IntoIterator<Item = Result<RgbImage, Error>>,
+    delay_ms: u32,
+    writer: impl Write,
+) -> Result<(), Error> {
+    // The slowest, finest quantization takes seconds a frame for no visible
+    // gain on smooth shading.
+    let mut encoder = GifEncoder::new_with_speed(writer, 10);
+    encoder.set_repeat(Repeat::Infinite)?;
+    for frame in frames {
+        let rgba = DynamicImage::ImageRgb8(frame?).into_rgba8();
+        let delay = Delay::from_numer_denom_ms(delay_ms, 1);
+        encoder.encode_frame(Frame::from_parts(rgba, 0, 0, delay))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::relief::render_relief;
+    use image::{AnimationDecoder, codecs::gif::GifDecoder};
+
+    #[test]
+    fn frames_follow_the_sun_around() {
+        // A cone, whose lit side turns with the sun.
+        let size = Vec2::new(16, 16);
+        let alt = (0..size.product())
+            .map(|i| {
+                let pos = Vec2::new((i % 16) as f64 - 7.5, (i / 16) as f64 - 7.5);
+                500.0 - pos.magnitude() * 60.0
+            })
+            .collect::<Vec<_>>();
+        let sweep = SunSweep {
+            frames: 8,
+            ..SunSweep::default()
+        };
+        assert_eq!(sweep.azimuth(0), 315.0);
+        assert_eq!(sweep.azimuth(1), 0.0);
+        let frames = render_sweep(&alt, size, 0.0, 500.0, &ReliefParams::default(), &sweep)
+            .collect::<Vec<_>>();
+        assert_eq!(frames.len(), 8);
+        for pair in frames.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+        // The first frame is the relief lit from the northwest.
+        assert_eq!(
+            frames[0],
+            render_relief(&alt, size, 0.0, 500.0, &ReliefParams::default())
+        );
+
+        let mut gif = Vec::new();
+        encode_gif(frames.into_iter().map(Ok), 100, &mut gif).unwrap();
+        let decoded = GifDecoder::new(std::io::Cursor::new(gif))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(decoded.len(), 8);
+        assert_eq!(decoded[0].buffer().dimensions(), (16, 16));
+    }
+}
@@ -0,0 +1,102 @@
+//! Views of the altitude grids of maps as [`ndarray`] arrays, for numerical
+//! work such as convolutions, FFTs or slicing.
+//!
+//! Grids are stored row by row, so arrays are indexed `[[y, x]]`: axis 0 runs
+//! along y, over the rows of the map, from the first at the top of exported
+//! images to the last (the north of the world), and axis 1 along x, over the
+//! columns, from west to east.  An array of `rows` by `columns` cells is a map
+//! `columns` cells wide and `rows` cells high.
+
+use super::{Error, map_size};
+use crate::sim::ModernMap;
+use ndarray::{Array2, ArrayView2};
+use vek::*;
+
+/// The altitudes of `map` as an array of `[[y, x]]`, borrowing them without
+/// copying.
+///
+/// # Panics
+///
+/// If the map doesn't pass [`validate`](super::validate), which all maps
+/// returned by [`load_map`](super::load_map) do.
+pub fn alt_as_array2(map: &ModernMap) -> ArrayView2<'_, f64> { view(map, &map.alt) }
+
+/// The basement of `map`, like [`alt_as_array2`].
+pub fn basement_as_array2(map: &ModernMap) -> ArrayView2<'_, f64> { view(map, &map.basement) }
+
+fn view<'a>(map: &ModernMap, grid: &'a [f64]) -> ArrayView2<'a, f64> {
+    let size = map_size(map);
+    ArrayView2::from_shape((size.y, size.x), grid).expect("Grid matches the map size")
+}
+
+/// A map with the altitudes of `alt`, an array of `[[y, x]]` whose sides must
+/// be powers of two, and a basement copied from them, as for image imports.
+/// Arrays in the standard (row-major) layout are moved into the map rather
+/// than copied.
+pub fn map_from_array2(alt: Array2<f64>, continent_scale_hack: f64) -> Result<ModernMap, Error> {
+    let (rows, columns) = alt.dim();
+    if !rows.is_power_of_two() || !columns.is_power_of_two() {
+        return Err(Error::ArrayShape { rows, columns });
+    }
+    let cells = rows * columns;
+    let (data, offset) = if alt.is_standard_layout() {
+        alt.into_raw_vec_and_offset()
+    } else {
+        alt.as_standard_layout()
+            .into_owned()
+            .into_raw_vec_and_offset()
+    };
+    // Arrays sliced from larger ones keep the whole of their buffer.
+    let offset = offset.unwrap_or(0);
+    let alt = if offset == 0 && data.len() == cells {
+        data.into_boxed_slice()
+    } else {
+        data[offset..offset + cells].into()
+    };
+    Ok(ModernMap {
+        map_size_lg: Vec2::new(columns.trailing_zeros(), rows.trailing_zeros()),
+        continent_scale_hack,
+        basement: alt.clone(),
+        alt,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+    use ndarray::s;
+
+    #[test]
+    fn arrays_are_indexed_by_row_then_column() {
+        let map = test_map(Vec2::new(2, 1), |x, y| (y * 10 + x) as f64);
+        let alt = alt_as_array2(&map);
+        assert_eq!(alt.dim(), (2, 4));
+        assert_eq!(alt[[1, 3]], 13.0);
+        assert_eq!(alt.as_ptr(), map.alt.as_ptr());
+        assert_eq!(basement_as_array2(&map)[[0, 2]], map.basement[2]);
+
+        let back = map_from_array2(alt.to_owned(), map.continent_scale_hack).unwrap();
+        assert_eq!(back.map_size_lg, map.map_size_lg);
+        assert_eq!(back.alt, map.alt);
+        assert_eq!(back.basement, map.alt);
+        // Transposed arrays are laid out anew.
+        let transposed = map_from_array2(alt.t().to_owned(), 1.0).unwrap();
+        assert_eq!(transposed.map_size_lg, Vec2::new(1, 2));
+        assert_eq!(transposed.alt[..4], [0.0, 10.0, 1.0, 11.0]);
+        // So are arrays sliced out of others.
+        let mut sliced = alt.to_owned();
+        sliced.slice_collapse(s![1.., ..]);
+        let last = map_from_array2(sliced, 1.0).unwrap();
+        assert_eq!(last.map_size_lg, Vec2::new(2, 0));
+        assert_eq!(last.alt[..], [10.0, 11.0, 12.0, 13.0]);
+
+        assert!(matches!(
+            map_from_array2(Array2::zeros((3, 4)), 1.0),
+            Err(Error::ArrayShape {
+                rows: 3,
+                columns: 4
+            })
+        ));
+    }
+}
@@ -0,0 +1,238 @@
+//! Reading altitudes from Esri ASCII grids, as exported by most GIS tools.
+//!
+//! A grid starts with a header of `key value` lines (`ncols`, `nrows`, and
+//! optionally `NODATA_value` and the georeferencing keys, which are ignored),
+//! followed by `nrows` rows of `ncols` whitespace-separated numbers.  As for
+//! images, the first row of the file becomes map row 0.
+
+use super::{Error, MAX_MAP_CELLS, import::map_size_lg};
+use crate::sim::ModernMap;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+use vek::*;
+
+const GEOREFERENCE_KEYS: [&str; 5] = [
+    "xllcorner",
+    "xllcenter",
+    "yllcorner",
+    "yllcenter",
+    "cellsize",
+];
+
+/// Whether `value` is the NODATA value, which may be NaN.
+fn is_nodata(value: f64, nodata: Option<f64>) -> bool {
+    nodata.is_some_and(|nodata| nodata == value || (nodata.is_nan() && value.is_nan()))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsciiGrid {
+    pub size: Vec2<usize>,
+    /// Value marking cells without data, if the header declares one.
+    pub nodata: Option<f64>,
+    /// Row-major values, starting with the first row of the file.
+    pub values: Vec<f64>,
+}
+
+impl AsciiGrid {
+    /// Parses a grid, checking that the header is complete and consistent
+    /// with the number of values that follow it.
+    pub fn read(reader: impl BufRead) -> Result<Self, Error> {
+        let invalid =
+            |line: usize, reason: String| Error::AsciiGrid(format!("line {}: {}", line, reason));
+        let mut seen = Vec::new();
+        let (mut ncols, mut nrows, mut nodata) = (None, None, None);
+        let mut values: Option<Vec<f64>> = None;
+        let mut expected = 0;
+        let mut last_line = 0;
+
+        for (i, line) in reader.lines().enumerate() {
+            let (line, line_no) = (line?, i + 1);
+            last_line = line_no;
+            let mut tokens = line.split_whitespace().peekable();
+            let Some(first) = tokens.peek().copied() else {
+                continue;
+            };
+
+            if values.is_none() && first.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                let key = first.to_ascii_lowercase();
+                tokens.next();
+                let (Some(value), None) = (tokens.next(), tokens.next()) else {
+                    return Err(invalid(line_no, format!("expected `{} <value>`", first)));
+                };
+                let value = value
+                    .parse::<f64>()
+                    .map_err(|_| invalid(line_no, format!("invalid value for {}", first)))?;
+                if seen.contains(&key) {
+                    return Err(invalid(line_no, format!("duplicate header key {}", first)));
+                }
+                let dimension = || {
+                    if value >= 1.0 && value.fract() == 0.0 && value <= u32::MAX as f64 {
+                        Ok(value as usize)
+                    } else {
+                        Err(invalid(
+                            line_no,
+                            format!("{} must be a positive integer", first),
+                        ))
+                    }
+                };
+                match key.as_str() {
+                    "ncols" => ncols = Some(dimension()?),
+                    "nrows" => nrows = Some(dimension()?),
+                    "nodata_value" => nodata = Some(value),
+                    key if GEOREFERENCE_KEYS.contains(&key) => {},
+                    _ => return Err(invalid(line_no, format!("unknown header key {}", first))),
+                }
+                seen.push(key);
+                continue;
+            }
+
+            let values = match &mut values {
+                Some(values) => values,
+                None => {
+                    let (Some(ncols), Some(nrows)) = (ncols, nrows) else {
+                        return Err(invalid(line_no, "header lacks ncols or nrows".to_string()));
+                    };
+                    expected = ncols
+                        .checked_mul(nrows)
+                        .filter(|&cells| cells <= MAX_MAP_CELLS)
+                        .ok_or_else(|| {
+                            invalid(line_no, format!("{}x{} grid is too large", ncols, nrows))
+                        })?;
+                    values.insert(Vec::with_capacity(expected))
+                },
+            };
+            for token in tokens {
+                let value = token
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|&value| value.is_finite() || is_nodata(value, nodata))
+                    .ok_or_else(|| invalid(line_no, format!("invalid value {}", token)))?;
+                if values.len() == expected {
+                    return Err(invalid(
+                        line_no,
+                        format!("more than the {} values given by the header", expected),
+                    ));
+                }
+                values.push(value);
+            }
+        }
+
+        let values = values.unwrap_or_default();
+        if values.len() != expected || expected == 0 {
+            return Err(invalid(
+                last_line,
+                format!(
+                    "expected {} values, found {}",
+                    ncols.unwrap_or(0) * nrows.unwrap_or(0),
+                    values.len()
+                ),
+            ));
+        }
+        Ok(Self {
+            size: Vec2::new(ncols.unwrap_or(0), nrows.unwrap_or(0)),
+            nodata,
+            values,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Number of cells marked as having no data.
+    pub fn nodata_count(&self) -> usize {
+        self.values
+            .iter()
+            .filter(|&&value| is_nodata(value, self.nodata))
+            .count()
+    }
+
+    /// Converts the grid, which must be square with power-of-two sides, into a
+    /// map whose altitudes are the grid's values, with `nodata_fill` in cells
+    /// without data.  Basement is a copy of the altitudes.
+    pub fn into_map(self, nodata_fill: f64, continent_scale: f64) -> Result<ModernMap, Error> {
+        let map_size_lg = map_size_lg(self.size.x as u32, self.size.y as u32).map_err(|_| {
+            Error::AsciiGrid(format!(
+                "grid must be square with power-of-two sides (found {}x{})",
+                self.size.x, self.size.y
+            ))
+        })?;
+        let alt = self
+            .values
+            .iter()
+            .map(|&value| {
+                if is_nodata(value, self.nodata) {
+                    nodata_fill
+                } else {
+                    value
+                }
+            })
+            .collect::<Box<[_]>>();
+        Ok(ModernMap {
+            map_size_lg,
+            continent_scale_hack: continent_scale,
+            basement: alt.clone(),
+            alt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<AsciiGrid, Error> { AsciiGrid::read(text.as_bytes()) }
+
+    #[test]
+    fn parses_grid_and_fills_nodata() {
+        let grid = parse(
+            "ncols 2\nNROWS 2\nxllcorner 100.5\nyllcorner -20\ncellsize 30\nNODATA_value \
+             -9999\n1.5 -9999\n\n-3 4e2\n",
+        )
+        .unwrap();
+        assert_eq!(grid.size, Vec2::new(2, 2));
+        assert_eq!(grid.nodata, Some(-9999.0));
+        assert_eq!(grid.values, vec![1.5, -9999.0, -3.0, 400.0]);
+        assert_eq!(grid.nodata_count(), 1);
+
+        let map = grid.into_map(-50.0, 1.6).unwrap();
+        assert_eq!(map.map_size_lg, Vec2::new(1, 1));
+        assert_eq!(&*map.alt, &[1.5, -50.0, -3.0, 400.0]);
+        assert_eq!(map.alt, map.basement);
+    }
+
+    #[test]
+    fn rejects_inconsistent_headers() {
+        for text in [
+            // Too few and too many values.
+            "ncols 2\nnrows 2\n1 2 3\n",
+            "ncols 2\nnrows 2\n1 2\n3 4\n5\n",
+            // Missing, repeated, unknown or malformed keys.
+            "ncols 2\n1 2\n",
+            "ncols 2\nnrows 1\nncols 2\n1 2\n",
+            "ncols 2\nnrows 1\nfoo 3\n1 2\n",
+            "ncols 2.5\nnrows 1\n1 2\n",
+            "ncols 2 3\nnrows 1\n1 2\n",
+            // Values that aren't numbers, or are NaN without being NODATA.
+            "ncols 2\nnrows 1\n1 x\n",
+            "ncols 2\nnrows 1\n1 nan\n",
+            "",
+        ] {
+            assert!(
+                matches!(parse(text), Err(Error::AsciiGrid(_))),
+                "{:?}",
+                text
+            );
+        }
+        assert!(parse("ncols 2\nnrows 1\nNODATA_value nan\n1 nan\n").is_ok());
+    }
+
+    #[test]
+    fn requires_square_power_of_two_grids() {
+        let grid = parse("ncols 2\nnrows 1\n1 2\n").unwrap();
+        assert!(matches!(grid.into_map(0.0, 1.0), Err(Error::AsciiGrid(_))));
+    }
+}
@@ -0,0 +1,288 @@
+//! Rough previews of how a map's terrain will look, colored by altitude band.
+//!
+//! The bands only approximate the biomes worldgen produces, which also
+//! depend on temperature, humidity and rivers, but they give a feel for where
+//! a map's coasts, forests and mountains lie.
+
+use super::{colormap::Colormap, export::gradient, filter::Edges};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use vek::*;
+
+/// The altitude band of a cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Biome {
+    DeepWater,
+    ShallowWater,
+    Beach,
+    Grass,
+    Forest,
+    Rock,
+    Snow,
+}
+
+impl Biome {
+    /// Every band, from the lowest to the highest, in the order of their
+    /// discriminants.
+    pub const ALL: [Biome; 7] = [
+        Biome::DeepWater,
+        Biome::ShallowWater,
+        Biome::Beach,
+        Biome::Grass,
+        Biome::Forest,
+        Biome::Rock,
+        Biome::Snow,
+    ];
+
+    /// Color of the band in previews.
+    pub fn color(self) -> Rgb<u8> {
+        Rgb(match self {
+            Biome::DeepWater => [20, 40, 120],
+            Biome::ShallowWater => [50, 100, 190],
+            Biome::Beach => [220, 205, 150],
+            Biome::Grass => [110, 170, 70],
+            Biome::Forest => [40, 100, 40],
+            Biome::Rock => [130, 125, 120],
+            Biome::Snow => [245, 245, 250],
+        })
+    }
+
+    /// Color of the band in previews colored with `colormap`, which gives
+    /// the bands evenly spaced colors from its first to its last, or with
+    /// [`Biome::color`] if it is `None`.
+    pub fn color_in(self, colormap: Option<&Colormap>) -> Rgb<u8> {
+        match colormap {
+            Some(colormap) => colormap.color(self as usize as f64 / (Self::ALL.len() - 1) as f64),
+            None => self.color(),
+        }
+    }
+}
+
+/// Thresholds between the altitude bands, in meters.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BiomeBands {
+    pub sea_level: f64,
+    /// Depth below sea level beyond which water is deep.
+    pub shallow_depth: f64,
+    /// Height above sea level up to which land is beach.
+    pub beach_height: f64,
+    /// Altitude above which grass gives way to forest.
+    pub forest_line: f64,
+    /// Altitude above which rock is exposed.
+    pub rock_line: f64,
+    /// Altitude above which everything is snow.
+    pub snow_line: f64,
+    /// If set, land above the beach that rises by more than this per cell is
+    /// rock, wherever it lies.
+    pub rock_slope: Option<f64>,
+}
+
+impl Default for BiomeBands {
+    fn default() -> Self {
+        Self {
+            sea_level: 0.0,
+            shallow_depth: 20.0,
+            beach_height: 3.0,
+            forest_line: 150.0,
+            rock_line: 700.0,
+            snow_line: 1400.0,
+            rock_slope: None,
+        }
+    }
+}
+
+impl BiomeBands {
+    /// The band of a cell at altitude `alt`, rising by `slope` per cell.
+    pub fn biome(&self, alt: f64, slope: f64) -> Biome {
+        if alt < self.sea_level - self.shallow_depth {
+            Biome::DeepWater
+        } else if alt < self.sea_level {
+            Biome::ShallowWater
+        } else if alt < self.sea_level + self.beach_height {
+            Biome::Beach
+        } else if alt >= self.snow_line {
+            Biome::Snow
+        } else if alt >= self.rock_line || self.rock_slope.is_some_and(|steep| slope > steep) {
+            Biome::Rock
+        } else if alt >= self.forest_line {
+            Biome::Forest
+        } else {
+            Biome::Grass
+        }
+    }
+}
+
+impl fmt::Display for BiomeBands {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sea level {}, deep water below {}, beach up to {}, forest from {}, rock from {}",
+            self.sea_level,
+            self.sea_level - self.shallow_depth,
+            self.sea_level + self.beach_height,
+            self.forest_line,
+            self.rock_line
+        )?;
+        if let Some(steep) = self.rock_slope {
+            write!(f, " (or steeper than {} per cell)", steep)?;
+        }
+        write!(f, ", snow from {}", self.snow_line)
+    }
+}
+
+impl fmt::Display for Biome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Biome::DeepWater => "deep water",
+            Biome::ShallowWater => "shallow water",
+            Biome::Beach => "beach",
+            Biome::Grass => "grass",
+            Biome::Forest => "forest",
+            Biome::Rock => "rock",
+            Biome::Snow => "snow",
+        })
+    }
+}
+
+/// The band in `bands` of every cell of the altitude grid of size `size`, in
+/// the same order as the altitudes.
+///
+/// Slopes are only computed if `bands.rock_slope` is set, treating the edges
+/// of the grid as `edges` says.
+pub fn classify(alt: &[f64], size: Vec2<usize>, bands: &BiomeBands, edges: Edges) -> Vec<Biome> {
+    (0..size.product())
+        .into_par_iter()
+        .map(|i| {
+            let slope = match bands.rock_slope {
+                Some(_) => gradient(alt, size, i % size.x, i / size.x, edges).magnitude(),
+                None => 0.0,
+            };
+            bands.biome(alt[i], slope)
+        })
+        .collect()
+}
+
+/// Number of cells of `biomes` in each band, in the order of [`Biome::ALL`].
+pub fn coverage(biomes: &[Biome]) -> [usize; Biome::ALL.len()] {
+    let mut counts = [0; Biome::ALL.len()];
+    for &biome in biomes {
+        counts[biome as usize] += 1;
+    }
+    counts
+}
+
+/// Renders the altitude grid of size `size` with each cell in the color of
+/// its band in `bands`, as [`classify`] finds it, given by [`Biome::color_in`]
+/// `colormap`.
+///
+/// Rows are rendered in parallel, like
+/// [`render_grayscale`](super::export::render_grayscale).
+pub fn render_biomes(
+    alt: &[f64],
+    size: Vec2<usize>,
+    bands: &BiomeBands,
+    edges: Edges,
+    colormap: Option<&Colormap>,
+) -> RgbImage {
+    let biomes = classify(alt, size, bands, edges);
+    let mut buf = vec![0; size.product() * 3];
+    buf.par_chunks_mut((size.x * 3).max(1))
+        .zip(biomes.par_chunks(size.x.max(1)))
+        .for_each(|(pixels, biomes)| {
+            for (pixel, biome) in pixels.chunks_exact_mut(3).zip(biomes) {
+                pixel.copy_from_slice(&biome.color_in(colormap).0);
+            }
+        });
+    RgbImage::from_raw(size.x as u32, size.y as u32, buf).expect("Buffer matches the image size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::colormap::Builtin;
+
+    #[test]
+    fn ramp_passes_through_every_band() {
+        // Rising 1 m per column, from 100 m below sea level.
+        let size = Vec2::new(1600, 2);
+        let alt = (0..size.product())
+            .map(|i| (i % size.x) as f64 - 100.0)
+            .collect::<Vec<_>>();
+        let img = render_biomes(&alt, size, &BiomeBands::default(), Edges::Clamp, None);
+        for (x, expected) in [
+            (0, Biome::DeepWater),
+            (79, Biome::DeepWater),
+            (80, Biome::ShallowWater),
+            (99, Biome::ShallowWater),
+            (100, Biome::Beach),
+            (102, Biome::Beach),
+            (103, Biome::Grass),
+            (249, Biome::Grass),
+            (250, Biome::Forest),
+            (799, Biome::Forest),
+            (800, Biome::Rock),
+            (1499, Biome::Rock),
+            (1500, Biome::Snow),
+            (1599, Biome::Snow),
+        ] {
+            assert_eq!(*img.get_pixel(x, 1), expected.color(), "column {}", x);
+        }
+
+        let bands = BiomeBands {
+            beach_height: 60.0,
+            ..BiomeBands::default()
+        };
+        let img = render_biomes(&alt, size, &bands, Edges::Clamp, None);
+        assert_eq!(*img.get_pixel(159, 0), Biome::Beach.color());
+        assert_eq!(*img.get_pixel(160, 0), Biome::Grass.color());
+
+        let counts = coverage(&classify(&alt, size, &BiomeBands::default(), Edges::Clamp));
+        assert_eq!(counts, [80, 20, 3, 147, 550, 700, 100].map(|n| n * 2));
+        assert_eq!(counts.iter().sum::<usize>(), size.product());
+    }
+
+    #[test]
+    fn steep_land_is_rock() {
+        // A cliff in the middle of lowland.
+        let size = Vec2::new(5, 1);
+        let alt = [10.0, 10.0, 10.0, 100.0, 100.0];
+        let bands = BiomeBands {
+            rock_slope: Some(30.0),
+            ..BiomeBands::default()
+        };
+        let img = render_biomes(&alt, size, &bands, Edges::Clamp, None);
+        let biomes = [
+            Biome::Grass,
+            Biome::Grass,
+            Biome::Rock,
+            Biome::Rock,
+            Biome::Grass,
+        ];
+        for (x, biome) in biomes.into_iter().enumerate() {
+            assert_eq!(*img.get_pixel(x as u32, 0), biome.color(), "column {}", x);
+        }
+        assert_eq!(
+            render_biomes(&alt, size, &BiomeBands::default(), Edges::Clamp, None).get_pixel(2, 0),
+            &Biome::Grass.color()
+        );
+    }
+
+    #[test]
+    fn colormaps_color_the_bands_in_order() {
+        let viridis = Builtin::Viridis.colormap();
+        let alt = [-100.0, 0.0, 2000.0];
+        let img = render_biomes(
+            &alt,
+            Vec2::new(3, 1),
+            &BiomeBands::default(),
+            Edges::Clamp,
+            Some(&viridis),
+        );
+        assert_eq!(*img.get_pixel(0, 0), viridis.color(0.0));
+        assert_eq!(*img.get_pixel(1, 0), viridis.color(2.0 / 6.0));
+        assert_eq!(*img.get_pixel(2, 0), viridis.color(1.0));
+    }
+}
@@ -0,0 +1,1629 @@
+//! Command-line arguments and reporting shared by the heightmap example tools.
+
+use super::{
+    Compression, DEFAULT_MAX_ALTITUDE, DEFAULT_MIN_STD_DEV, Error,
+    adjust::{AbyssalClamp, ProportionalBasement},
+    biome::{self, BiomeBands},
+    check_input,
+    colormap::{self, Builtin, Colormap},
+    export::{self, ColorMode, PngCompression, PngFilter, PngOptions, RangeSidecar, RangeSource},
+    filter::Edges,
+    grid::Window,
+    import::{
+        self, AlphaWater, Channel, ImportParams, ImportSidecar, InputRange, Padding, SmoothTarget,
+    },
+    is_map_path,
+    legend::{self, Legend},
+    load_map, map_size,
+    occlusion::{self, OcclusionParams},
+    overlay::{self, BLOCKS_PER_CELL, Grid, Marker, Units},
+    provenance::Provenance,
+    read_json,
+    relief::{self, Blend, ReliefParams},
+    resample,
+    rivers::{RiverParams, Rivers},
+    save_map_with_alt_basement,
+    shadow::{self, Sun},
+    stats::{self, AltStats},
+    stats_warnings, stream,
+    template::{self, NameTemplate},
+    warnings,
+};
+use crate::sim::ModernMap;
+use clap::Args;
+use image::Rgb;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
+use vek::*;
+
+/// Options shared by the tools converting images into `.bin` maps.
+#[derive(Args)]
+pub struct ConvertArgs {
+    /// Also write a JSON sidecar recording the conversion parameters next to
+    /// the output, which `mapgen reconvert` can replay
+    #[arg(long)]
+    pub sidecar: bool,
+    /// Channel of the image to read altitudes from
+    #[arg(long, value_enum, default_value_t = Channel::Red)]
+    pub channel: Channel,
+    /// Shift all altitudes so that this altitude becomes sea level (0.0)
+    #[arg(long, value_name = "CURRENT_SEA", allow_negative_numbers = true)]
+    pub sea_to_zero: Option<f64>,
+    /// Convert the image in horizontal strips instead of all at once, for
+    /// maps too large to fit in memory (8-bit, non-interlaced PNGs only)
+    #[arg(long)]
+    pub streaming: bool,
+    /// Number of rows per strip when streaming
+    #[arg(long, default_value_t = 256, requires = "streaming")]
+    pub strip_rows: usize,
+    /// Use pixel values as altitudes directly, ignoring the scale and offset
+    #[arg(long)]
+    pub raw_altitude: bool,
+    /// Factor converting raw pixel values to altitudes, for sources not in
+    /// meters
+    #[arg(long, default_value_t = 1.0, requires = "raw_altitude")]
+    pub raw_altitude_scale: f64,
+    /// Decode altitudes in meters from all three channels of 8-bit RGB images
+    /// in the Terrain-RGB encoding of Mapbox and other elevation tile sources,
+    /// ignoring the scale, offset and channel
+    #[arg(long, conflicts_with = "raw_altitude")]
+    pub terrain_rgb: bool,
+    /// Range of pixel values the image is expected to hold (0 to 255 whatever
+    /// its bit depth, raw values with `--raw-altitude`, or meters with
+    /// `--terrain-rgb`); values outside it are clamped to it rather than
+    /// extrapolated
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["MIN", "MAX"],
+        allow_negative_numbers = true
+    )]
+    pub range: Option<Vec<f64>>,
+    /// Altitude of pixels outside `--range`, such as sentinel no-data pixels,
+    /// instead of clamping them
+    #[arg(
+        long,
+        value_name = "ALT",
+        requires = "range",
+        allow_negative_numbers = true
+    )]
+    pub oob_fill: Option<f64>,
+    /// Raise everything deeper than this, in final (shifted) altitudes, to
+    /// it, so that deep trenches don't take up the map's altitude range
+    #[arg(long, value_name = "FLOOR", allow_negative_numbers = true)]
+    pub abyss_floor: Option<f64>,
+    /// Ease the abyss clamp in over this many meters above the floor, instead
+    /// of cutting it off flat
+    #[arg(long, default_value_t = 0.0, requires = "abyss_floor")]
+    pub abyss_rolloff: f64,
+    /// Smooth the image as if it wrapped around at its edges, for worlds that
+    /// do, so that no seam appears along them (not supported when streaming)
+    #[arg(long)]
+    pub wrap: bool,
+    /// Which of the altitudes and the basement smoothing applies to; smoothing
+    /// only the basement keeps sharp detail over a softer rock layer (only
+    /// `both` is supported when streaming)
+    #[arg(long, value_enum, default_value_t = SmoothTarget::Both)]
+    pub smooth_target: SmoothTarget,
+    /// Pad images that aren't square with power-of-two sides to the next size
+    /// that is, placing them in the first rows and columns
+    #[arg(long)]
+    pub pad: bool,
+    /// Center padded images, with borders of equal width on opposite sides
+    #[arg(long, requires = "pad")]
+    pub preserve_aspect: bool,
+    /// Altitude of padding cells (defaults to that of a black pixel)
+    #[arg(long, requires = "pad", allow_negative_numbers = true)]
+    pub pad_altitude: Option<f64>,
+    /// Place the basement at this fraction of each cell's height above (or
+    /// depth below) sea level, instead of at its altitude; it never rises
+    /// above the altitude, so it follows the seabed underwater
+    #[arg(long)]
+    pub basement_factor: Option<f64>,
+    /// Sea level for `--basement-factor`, in final (shifted) altitudes
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        requires = "basement_factor",
+        allow_negative_numbers = true
+    )]
+    pub sea_level: f64,
+    /// Read water from the alpha channel: cells that aren't fully transparent
+    /// are lowered below `--water-level` by their opacity times
+    /// `--water-depth-scale`, whatever their color, with the basement just
+    /// below
+    #[arg(long)]
+    pub alpha_water: bool,
+    /// Altitude of the water surface for `--alpha-water`, in final (shifted)
+    /// altitudes
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        requires = "alpha_water",
+        allow_negative_numbers = true
+    )]
+    pub water_level: f64,
+    /// Depth of the water below fully opaque pixels for `--alpha-water`
+    #[arg(long, default_value_t = 100.0, requires = "alpha_water")]
+    pub water_depth_scale: f64,
+    /// Grayscale image in which rivers are painted as bright lines, to carve
+    /// channels along; resampled, with a warning, unless it matches the input
+    #[arg(long, value_name = "PNG")]
+    pub rivers: Option<PathBuf>,
+    /// Depth of the channels carved below white river pixels
+    #[arg(long, default_value_t = 20.0, requires = "rivers")]
+    pub river_depth: f64,
+    /// Width in cells of the carved channels, including their banks
+    #[arg(long, default_value_t = 3.0, requires = "rivers")]
+    pub river_width: f64,
+    /// Warn if the standard deviation of the converted altitudes is below
+    /// this, which suggests a solid-colored image or the wrong channel
+    #[arg(long, default_value_t = DEFAULT_MIN_STD_DEV)]
+    pub min_std_dev: f64,
+    /// Warn if any converted altitude is further than this from sea level,
+    /// which suggests a bad scale or offset
+    #[arg(long, default_value_t = DEFAULT_MAX_ALTITUDE)]
+    pub max_altitude: f64,
+    #[command(flatten)]
+    pub compress: CompressArgs,
+    #[command(flatten)]
+    pub overwrite: OverwriteArgs,
+    #[command(flatten)]
+    pub verbosity: OutputArgs,
+}
+
+impl ConvertArgs {
+    /// The `raw_altitude` factor of the conversion, if enabled.
+    pub fn raw_altitude(&self) -> Option<f64> {
+        self.raw_altitude.then_some(self.raw_altitude_scale)
+    }
+
+    /// The basement of the conversion, if not a copy of the altitudes.
+    pub fn basement(&self) -> Option<ProportionalBasement> {
+        self.basement_factor.map(|factor| ProportionalBasement {
+            sea_level: self.sea_level,
+            factor,
+        })
+    }
+
+    /// The water read from the alpha channel, if enabled.
+    pub fn alpha_water(&self) -> Option<AlphaWater> {
+        self.alpha_water.then_some(AlphaWater {
+            sea_level: self.water_level,
+            depth_scale: self.water_depth_scale,
+        })
+    }
+
+    /// The rivers carved during the conversion, if any.
+    pub fn rivers(&self) -> Option<Rivers> {
+        self.rivers.clone().map(|image| Rivers {
+            image,
+            params: RiverParams {
+                max_depth: self.river_depth,
+                width: self.river_width,
+            },
+        })
+    }
+
+    /// The clamp of deep water, if enabled.
+    pub fn abyss(&self) -> Option<AbyssalClamp> {
+        self.abyss_floor.map(|floor| AbyssalClamp {
+            floor,
+            rolloff: self.abyss_rolloff,
+        })
+    }
+
+    /// The expected range of pixel values, if given.
+    pub fn range(&self) -> Option<InputRange> {
+        match *self.range.as_deref()? {
+            [a, b] => Some(InputRange {
+                min: a.min(b),
+                max: a.max(b),
+                fill: self.oob_fill,
+            }),
+            _ => None,
+        }
+    }
+
+    /// How smoothing treats the edges of the image.
+    pub fn edges(&self) -> Edges { Edges::from_wrap(self.wrap) }
+
+    /// The padding of the conversion, if enabled.
+    pub fn padding(&self) -> Option<Padding> {
+        self.pad.then_some(Padding {
+            centered: self.preserve_aspect,
+            altitude: self.pad_altitude,
+        })
+    }
+}
+
+/// Options for tools writing `.bin` maps.
+#[derive(Args, Serialize)]
+pub struct CompressArgs {
+    /// Compress written maps with zstd; output paths that aren't given
+    /// explicitly end in .bin.zst
+    #[arg(long)]
+    pub compress: bool,
+    /// zstd compression level, from 1 (fastest) to 22 (smallest)
+    #[arg(long, default_value_t = zstd::DEFAULT_COMPRESSION_LEVEL, requires = "compress")]
+    pub compression_level: i32,
+}
+
+impl CompressArgs {
+    pub fn compression(&self) -> Compression {
+        if self.compress {
+            Compression::Zstd {
+                level: self.compression_level,
+            }
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Options deciding what happens to outputs that already exist.
+#[derive(Args, Clone, Copy, Serialize)]
+pub struct OverwriteArgs {
+    /// Replace outputs that already exist (the default)
+    #[arg(long, overrides_with = "no_overwrite")]
+    pub overwrite: bool,
+    /// Skip inputs whose outputs all exist already, so that interrupted
+    /// batches can be resumed; inputs missing any of their outputs are
+    /// written in full, so that they share one range
+    #[arg(long, overrides_with = "overwrite")]
+    pub no_overwrite: bool,
+}
+
+impl OverwriteArgs {
+    /// The first of `paths`, if outputs mustn't be overwritten and all of
+    /// them exist already, in which case whatever writes them should be
+    /// skipped.  If any is missing, as after an interrupted run, they are
+    /// all written again.
+    pub fn existing<'a>(&self, paths: &'a [PathBuf]) -> Option<&'a Path> {
+        if !self.no_overwrite || !paths.iter().all(|path| path.exists()) {
+            return None;
+        }
+        paths.first().map(PathBuf::as_path)
+    }
+
+    /// Prints that the output `path` was skipped since it exists, unless
+    /// `quiet`.
+    pub fn report_skipped(path: &Path, quiet: bool) {
+        if !quiet {
+            println!("{}: skipped, exists", path.display());
+        }
+    }
+}
+
+/// Options controlling how much the tools print.  Errors are always printed,
+/// to stderr, and reflected in the exit code.
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Only print errors and warnings
+    #[arg(long, short, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Also print the parameters and results of each step in detail
+    #[arg(long, short)]
+    pub verbose: bool,
+}
+
+/// Options controlling how image inputs are converted, for tools that accept
+/// either a `.bin` map or an image.
+#[derive(Args, Serialize)]
+pub struct ImportArgs {
+    /// Altitude range covered by the 0-255 pixel values of image inputs
+    #[arg(long, default_value_t = 1000.0)]
+    pub scale: f64,
+    /// Altitude of a black pixel in image inputs
+    #[arg(long, default_value_t = -600.0, allow_negative_numbers = true)]
+    pub offset: f64,
+    /// Value stored as the continent_scale_hack of image inputs
+    #[arg(long, default_value_t = 1.6)]
+    pub continent_scale: f64,
+    /// Number of box filter passes used to smooth image inputs
+    #[arg(long, default_value_t = 0)]
+    pub smooth: u32,
+    /// Smooth image inputs as if they wrapped around at their edges
+    #[arg(long)]
+    pub wrap: bool,
+    /// Channel of image inputs to read altitudes from
+    #[arg(long, value_enum, default_value_t = Channel::Red)]
+    pub channel: Channel,
+}
+
+impl ImportArgs {
+    pub fn params(&self) -> ImportParams {
+        ImportParams {
+            continent_scale: self.continent_scale,
+            smooth_iterations: self.smooth,
+            edges: Edges::from_wrap(self.wrap),
+            channel: self.channel,
+            ..ImportParams::new(self.scale, self.offset)
+        }
+    }
+}
+
+/// Options shared by the tools exporting maps as images.
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Draw contour lines at every multiple of this altitude
+    #[arg(long, value_name = "INTERVAL")]
+    pub contours: Option<f64>,
+    /// How to color the image
+    #[arg(long, value_enum, default_value_t = ColorMode::Gray)]
+    pub color: ColorMode,
+    /// Colormap of the colored modes: terrain, viridis, magma, grayscale or
+    /// bathymetric, or a file of stops, one `POSITION #RRGGBB` per line.
+    /// Hypsometric exports default to terrain, and slope and roughness
+    /// exports to magma;
+    /// given one, reliefs tint from the lowest to the highest altitude
+    /// through it, and biome previews spread their bands along it
+    #[arg(long, value_name = "NAME|FILE")]
+    pub colormap: Option<String>,
+    #[command(flatten)]
+    pub bands: BandArgs,
+    /// How `--color relief` composites its hillshade over the tint
+    #[arg(long, value_enum, default_value_t = Blend::Multiply)]
+    pub blend: Blend,
+    /// Strength of the hillshade in `--color relief` exports, from 0 for the
+    /// bare tint to 1 for the full shade
+    #[arg(long, default_value_t = 1.0)]
+    pub shade_contrast: f64,
+    /// Side of the square window, in cells, over which `--color roughness`
+    /// measures the spread of altitudes around each cell: an odd number, such
+    /// as 5 or 9
+    #[arg(long, value_name = "CELLS", default_value_t = 5)]
+    pub roughness_window: usize,
+    /// Shade `--color roughness` between the P and 100 - P percentiles of the
+    /// roughness, so that a few cliffs don't wash out the rest
+    #[arg(long, value_name = "P", default_value_t = 1.0)]
+    pub roughness_percentile: f64,
+    /// Number of directions around each cell along which `--color occlusion`
+    /// searches for terrain blocking the sky
+    #[arg(long, value_name = "COUNT", default_value_t = 16)]
+    pub occlusion_directions: usize,
+    /// Furthest distance, in cells, at which terrain blocks the sky of
+    /// `--color occlusion`
+    #[arg(long, value_name = "CELLS", default_value_t = 32)]
+    pub occlusion_radius: usize,
+    /// Factor to exaggerate altitudes by for `--color occlusion`, deepening
+    /// its shadows
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    pub occlusion_scale: f64,
+    /// Darken `--color relief` exports by their ambient occlusion, as set by
+    /// the --occlusion options, so that valleys stand out
+    #[arg(long)]
+    pub relief_occlusion: bool,
+    /// Direction of the sun casting the shadows of `--color shadow`, in
+    /// degrees clockwise from north
+    #[arg(long, value_name = "DEGREES", default_value_t = 315.0)]
+    pub sun_azimuth: f64,
+    /// Height of the sun above the horizon, in degrees, for `--color shadow`
+    #[arg(
+        long,
+        value_name = "DEGREES",
+        default_value_t = 20.0,
+        allow_negative_numbers = true
+    )]
+    pub sun_elevation: f64,
+    /// Fade shadows in over this many meters below their edge, for soft
+    /// edges, rather than drawing a binary mask
+    #[arg(long, value_name = "METERS", default_value_t = 0.0)]
+    pub shadow_softness: f64,
+    /// Darken `--color relief` exports by the shadows the terrain casts with
+    /// the sun at --sun-azimuth and --sun-elevation, from 0 for not at all to
+    /// 1 for black
+    #[arg(long, value_name = "STRENGTH", num_args = 0..=1, default_missing_value = "0.5")]
+    pub relief_shadows: Option<f64>,
+    /// Also write the map at half, quarter... resolution, for this many
+    /// levels in all, suffixing each file with its width (map_1024.png,
+    /// map_512.png...)
+    #[arg(long, value_name = "LEVELS", num_args = 0..=1, default_missing_value = "4")]
+    pub pyramid: Option<u32>,
+    /// Compute slopes, for shading and rock, occlusion, cast shadows and
+    /// roughness as if the map wrapped around at its edges
+    #[arg(long)]
+    pub wrap: bool,
+    /// Write the altitudes, the basement and the sediment above it (alt -
+    /// basement) as separate grayscale images, suffixed _alt, _basement and
+    /// _sediment, shaded over one range so their depths can be compared
+    #[arg(long)]
+    pub layers: bool,
+    /// Name of each image written, next to the output, in which {stem} and
+    /// {ext} are replaced by the output's name without its extension and by
+    /// its extension, {layer} by that of --layers and {width} by that of
+    /// the --pyramid level (defaults to {stem}_{layer}_{width}.{ext}, less
+    /// the placeholders of options not given)
+    #[arg(long, value_name = "TEMPLATE")]
+    pub name_template: Option<String>,
+    /// Shade over the altitudes between the P and 100 - P percentiles of the
+    /// map, rather than its lowest and highest, clipping the rest, so that a
+    /// few outliers don't darken everything else (0.1 is a good start)
+    #[arg(long, value_name = "P")]
+    pub clip_percentile: Option<f64>,
+    /// Altitude shown as black (or the lowest tint) instead of the map's
+    /// lowest, clipping any below it
+    #[arg(long, allow_negative_numbers = true)]
+    pub min: Option<f64>,
+    /// Altitude shown as white (or the highest tint) instead of the map's
+    /// highest, clipping any above it
+    #[arg(long, allow_negative_numbers = true)]
+    pub max: Option<f64>,
+    /// Add a color bar with labelled altitudes beside the image, over the
+    /// range it was actually shaded over, and a title with its name, size and
+    /// range beneath it, enlarging the image rather than covering terrain
+    #[arg(long)]
+    pub legend: bool,
+    /// Draw a grid line every this many cells (chunks), labelled along the
+    /// top and left edges with their coordinates in cells and in blocks
+    #[arg(long, value_name = "CELLS", num_args = 0..=1, default_missing_value = "32")]
+    pub grid: Option<u32>,
+    /// Opacity of the --grid lines over the terrain, from 0 to 1
+    #[arg(long, default_value_t = 0.3)]
+    pub grid_opacity: f64,
+    /// Draw a labelled marker at each point of interest listed in this CSV
+    /// file, one `x, y, label, color` per line
+    #[arg(long, value_name = "CSV")]
+    pub markers: Option<PathBuf>,
+    /// Units of the coordinates of --markers
+    #[arg(long, value_enum, default_value_t = Units::Cells)]
+    pub marker_units: Units,
+    /// Export only the window of W by H cells whose first cell is at X, Y,
+    /// shaded over its own range unless --region-full-range is given
+    #[arg(long, value_name = "X,Y,W,H", value_parser = Window::parse)]
+    pub region: Option<Window>,
+    /// Shade a --region over the range of the whole map, so that its colors
+    /// match those of a full export
+    #[arg(long, requires = "region")]
+    pub region_full_range: bool,
+    #[command(flatten)]
+    pub png: PngArgs,
+}
+
+/// Encoder settings of the PNGs written by the tools.
+#[derive(Args)]
+pub struct PngArgs {
+    /// How hard to compress PNGs: fast for quick previews, best for the
+    /// smallest files
+    #[arg(long, value_enum, default_value_t = PngCompression::Best)]
+    pub png_compression: PngCompression,
+    /// Filter applied to each row of PNGs before compressing them
+    #[arg(long, value_enum, default_value_t = PngFilter::Paeth)]
+    pub png_filter: PngFilter,
+}
+
+impl PngArgs {
+    pub fn options(&self) -> PngOptions {
+        PngOptions {
+            compression: self.png_compression,
+            filter: self.png_filter,
+        }
+    }
+}
+
+/// Names of the images written by `--layers` exports, in the order they are
+/// written.
+pub const LAYERS: [&str; 3] = ["alt", "basement", "sediment"];
+
+/// Altitude band thresholds of `--color biome` exports, in meters.  Bands not
+/// given are read from `--bands`, if given, and take their default otherwise.
+#[derive(Args)]
+pub struct BandArgs {
+    /// JSON file of band thresholds, in the format biome exports print
+    #[arg(long, value_name = "JSON")]
+    pub bands: Option<PathBuf>,
+    /// Altitude of the sea surface, which also separates water from land in
+    /// `--color relief` exports
+    #[arg(long, allow_negative_numbers = true)]
+    pub sea_level: Option<f64>,
+    /// Depth below sea level beyond which water is deep
+    #[arg(long)]
+    pub shallow_depth: Option<f64>,
+    /// Height above sea level up to which land is beach
+    #[arg(long)]
+    pub beach_height: Option<f64>,
+    /// Altitude above which grass gives way to forest
+    #[arg(long, allow_negative_numbers = true)]
+    pub forest_line: Option<f64>,
+    /// Altitude above which rock is exposed
+    #[arg(long, allow_negative_numbers = true)]
+    pub rock_line: Option<f64>,
+    /// Altitude above which everything is snow
+    #[arg(long, allow_negative_numbers = true)]
+    pub snow_line: Option<f64>,
+    /// Show land rising by more than this many meters per cell as rock
+    #[arg(long)]
+    pub rock_slope: Option<f64>,
+}
+
+impl BandArgs {
+    /// The thresholds selected by these options.
+    pub fn bands(&self) -> Result<BiomeBands, Error> {
+        let mut bands = match &self.bands {
+            Some(path) => read_json(path)?,
+            None => BiomeBands::default(),
+        };
+        for (value, band) in [
+            (self.sea_level, &mut bands.sea_level),
+            (self.shallow_depth, &mut bands.shallow_depth),
+            (self.beach_height, &mut bands.beach_height),
+            (self.forest_line, &mut bands.forest_line),
+            (self.rock_line, &mut bands.rock_line),
+            (self.snow_line, &mut bands.snow_line),
+        ] {
+            if let Some(value) = value {
+                *band = value;
+            }
+        }
+        if self.rock_slope.is_some() {
+            bands.rock_slope = self.rock_slope;
+        }
+        Ok(bands)
+    }
+}
+
+impl ExportArgs {
+    /// Paths of the images written when exporting a map of size `size` to
+    /// `output_path`: just it, or one per pyramid level of the `--region`,
+    /// down to a single cell at most, for each of the [`LAYERS`] with
+    /// `--layers`, named by `--name-template`.
+    ///
+    /// Fails, before anything is written, if the template is malformed or
+    /// names two images alike.
+    pub fn output_paths(
+        &self,
+        output_path: &Path,
+        size: Vec2<usize>,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let template = self.name_template(output_path)?;
+        let stem = output_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let ext = output_path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let layers = if self.layers {
+            LAYERS.map(Some).to_vec()
+        } else {
+            vec![None]
+        };
+        let widths = self.level_widths(self.view(size).size);
+        let paths = layers
+            .iter()
+            .flat_map(|layer| {
+                widths.iter().map(|width| {
+                    let width = width.map(|width| width.to_string());
+                    let mut values = vec![("stem", stem.as_ref()), ("ext", ext.as_ref())];
+                    values.extend(layer.map(|layer| ("layer", layer)));
+                    values.extend(width.as_deref().map(|width| ("width", width)));
+                    output_path.with_file_name(template.expand(&values))
+                })
+            })
+            .collect::<Vec<_>>();
+        if let Some(path) = template::first_duplicate(&paths) {
+            return Err(Error::NameTemplate(format!(
+                "{} would be written more than once; include {{layer}} and {{width}} in the \
+                 template",
+                path.display()
+            )));
+        }
+        Ok(paths)
+    }
+
+    /// The `--name-template`, or the default naming images by appending the
+    /// layer and pyramid width to the stem of `output_path`.  Only
+    /// `{layer}` and `{width}` of the options given are accepted.
+    pub fn name_template(&self, output_path: &Path) -> Result<NameTemplate, Error> {
+        let mut placeholders = vec!["stem", "ext"];
+        placeholders.extend(self.layers.then_some("layer"));
+        placeholders.extend(self.pyramid.map(|_| "width"));
+        let text = self.name_template.clone().unwrap_or_else(|| {
+            let mut text = "{stem}".to_owned();
+            for placeholder in &placeholders[2..] {
+                text += &format!("_{{{}}}", placeholder);
+            }
+            if output_path.extension().is_some() {
+                text += ".{ext}";
+            }
+            text
+        });
+        NameTemplate::parse(&text, &placeholders)
+    }
+
+    /// Widths of the pyramid levels of the `--region` of size `size`, or
+    /// `None` for the single image written without `--pyramid`; see
+    /// [`output_paths`](Self::output_paths).
+    fn level_widths(&self, size: Vec2<usize>) -> Vec<Option<usize>> {
+        let Some(levels) = self.pyramid else {
+            return vec![None];
+        };
+        let mut widths = Vec::new();
+        let mut size = size;
+        for _ in 0..levels.max(1) {
+            widths.push(Some(size.x));
+            if size.product() <= 1 {
+                break;
+            }
+            size = size.map(|e| e.div_ceil(2));
+        }
+        widths
+    }
+
+    /// The settings of `--color relief` exports selected by these options.
+    pub fn relief(&self) -> Result<ReliefParams, Error> {
+        Ok(ReliefParams {
+            sea_level: self.bands.bands()?.sea_level,
+            blend: self.blend,
+            contrast: self.shade_contrast,
+            edges: self.edges(),
+            colormap: self.colormap()?,
+        })
+    }
+
+    /// The part of a map of size `size` exported: the `--region`, if given,
+    /// and otherwise all of it.
+    pub fn view(&self, size: Vec2<usize>) -> Window {
+        self.region.unwrap_or_else(|| Window::whole(size))
+    }
+
+    /// How slopes, occlusion, shadows and roughness treat the edges of the
+    /// image.  Regions don't wrap around, as the cells beyond their edges
+    /// aren't those of the other side.
+    fn edges(&self) -> Edges { Edges::from_wrap(self.wrap && self.region.is_none()) }
+
+    /// Title of the legend of the image at `path` of size `size`, shaded over
+    /// a range from `source`: its name, its size and `--region`, what its
+    /// colors show and how their range was chosen.
+    fn legend_title(&self, path: &Path, size: Vec2<usize>, source: RangeSource) -> String {
+        let name = path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        let shown = match self.color {
+            ColorMode::Slope => "slope per cell",
+            ColorMode::Roughness => "altitude std dev per window",
+            ColorMode::Occlusion => "fraction of sky visible",
+            ColorMode::Shadow => "shadow",
+            ColorMode::Biome => "biome bands by altitude",
+            _ => "altitude",
+        };
+        // Slopes are always shown over the image's own range, and roughness
+        // between its own percentiles.
+        let (source, percentile) = match self.color {
+            ColorMode::Slope => (RangeSource::Map, None),
+            ColorMode::Occlusion | ColorMode::Shadow => (RangeSource::Explicit, None),
+            ColorMode::Roughness => (RangeSource::Percentile, Some(self.roughness_percentile)),
+            _ => (source, self.clip_percentile),
+        };
+        let range = match (source, percentile) {
+            (RangeSource::Map, _) => "own range".to_owned(),
+            (RangeSource::Batch, _) => "shared range".to_owned(),
+            (RangeSource::Percentile, Some(p)) => format!("p{} to p{}", p, 100.0 - p),
+            (RangeSource::Percentile, None) => "percentile range".to_owned(),
+            (RangeSource::Explicit, _) => "fixed range".to_owned(),
+        };
+        let region = match self.region {
+            Some(window) => format!(" at {},{}", window.offset.x, window.offset.y),
+            None => String::new(),
+        };
+        format!(
+            "{} {}x{}{}, {}, {}",
+            name, size.x, size.y, region, shown, range
+        )
+    }
+
+    /// The grid selected by `--grid` and `--grid-opacity`, if any.
+    pub fn grid(&self) -> Result<Option<Grid>, Error> {
+        let Some(spacing) = self.grid else {
+            return Ok(None);
+        };
+        if spacing == 0 || !(0.0..=1.0).contains(&self.grid_opacity) {
+            return Err(Error::UnsupportedImage(format!(
+                "--grid needs a spacing of at least 1 and an opacity from 0 to 1, not {} and {}",
+                spacing, self.grid_opacity
+            )));
+        }
+        Ok(Some(Grid {
+            spacing,
+            opacity: self.grid_opacity,
+        }))
+    }
+
+    /// The settings of `--color occlusion`, and of `--relief-occlusion`.
+    pub fn occlusion(&self) -> Result<OcclusionParams, Error> {
+        if self.occlusion_directions == 0
+            || self.occlusion_scale.is_nan()
+            || self.occlusion_scale < 0.0
+        {
+            return Err(Error::UnsupportedImage(format!(
+                "--occlusion-directions needs at least 1 direction and --occlusion-scale a factor \
+                 of at least 0, not {} and {}",
+                self.occlusion_directions, self.occlusion_scale
+            )));
+        }
+        Ok(OcclusionParams {
+            directions: self.occlusion_directions,
+            radius: self.occlusion_radius,
+            vertical_scale: self.occlusion_scale,
+            edges: self.edges(),
+        })
+    }
+
+    /// The sun of `--color shadow`, and of `--relief-shadows`.
+    pub fn sun(&self) -> Sun {
+        Sun {
+            azimuth: self.sun_azimuth,
+            elevation: self.sun_elevation,
+            softness: self.shadow_softness.max(0.0),
+        }
+    }
+
+    /// The window of `--color roughness`, which must be odd, so that it is
+    /// centred on its cell, and span more than the cell itself.
+    pub fn roughness_window(&self) -> Result<usize, Error> {
+        let window = self.roughness_window;
+        if window % 2 == 0 || window < 3 {
+            return Err(Error::UnsupportedImage(format!(
+                "--roughness-window needs an odd size of at least 3, not {}",
+                window
+            )));
+        }
+        Ok(window)
+    }
+
+    /// The markers listed in the `--markers` file, if any.
+    pub fn markers(&self) -> Result<Vec<Marker>, Error> {
+        match &self.markers {
+            Some(path) => overlay::load_markers(path, self.marker_units),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The colormap selected by `--colormap`, if any.
+    pub fn colormap(&self) -> Result<Option<Colormap>, Error> {
+        self.colormap.as_deref().map(Colormap::resolve).transpose()
+    }
+
+    /// The colormap used without `--colormap` by the modes that need one.
+    fn default_colormap(&self) -> Option<Builtin> {
+        match self.color {
+            ColorMode::Hypsometric => Some(Builtin::Terrain),
+            ColorMode::Slope | ColorMode::Roughness => Some(Builtin::Magma),
+            _ => None,
+        }
+    }
+
+    /// The colormap of `--color hypsometric` and `--color slope` exports.
+    fn colormap_or_default(&self) -> Result<Colormap, Error> {
+        let default = self.default_colormap().unwrap_or(Builtin::Terrain);
+        Ok(self.colormap()?.unwrap_or_else(|| default.colormap()))
+    }
+
+    /// Prints the settings of the export that aren't apparent from the
+    /// image, such as the band thresholds it was colored with.
+    pub fn describe(&self) -> Result<(), Error> {
+        if self.layers {
+            println!("Layers {} share this range", LAYERS.join(", "));
+        }
+        match self.color {
+            ColorMode::Gray => {},
+            ColorMode::Biome => {
+                let bands = self.bands.bands()?;
+                println!("Biome bands: {}", bands);
+                println!("As JSON, for --bands: {}", serde_json::to_string(&bands)?);
+            },
+            ColorMode::Relief => {
+                let params = self.relief()?;
+                println!(
+                    "Shaded relief: {:?} blend, contrast {}, sea level {}",
+                    params.blend, params.contrast, params.sea_level
+                );
+            },
+            ColorMode::Roughness => println!(
+                "Roughness over {0}x{0} windows, shaded between p{1} and p{2}",
+                self.roughness_window()?,
+                self.roughness_percentile,
+                100.0 - self.roughness_percentile
+            ),
+            ColorMode::Occlusion => {
+                let params = self.occlusion()?;
+                println!(
+                    "Ambient occlusion: {} directions, {} cells out, altitudes scaled by {}",
+                    params.directions, params.radius, params.vertical_scale
+                );
+            },
+            ColorMode::Shadow => {
+                let sun = self.sun();
+                println!(
+                    "Sun at {} degrees from north, {} degrees high, softness {} m",
+                    sun.azimuth, sun.elevation, sun.softness
+                );
+            },
+            ColorMode::Normal => println!(
+                "Normal map, with cells {} m wide times the continent scale",
+                relief::CELL_WIDTH
+            ),
+            ColorMode::Hypsometric | ColorMode::Slope => {},
+        }
+        if !matches!(
+            self.color,
+            ColorMode::Gray | ColorMode::Occlusion | ColorMode::Shadow | ColorMode::Normal
+        ) {
+            let name = self
+                .colormap
+                .as_deref()
+                .or(self.default_colormap().map(Builtin::name))
+                .unwrap_or("built-in colors");
+            println!("Colormap: {}", name);
+        }
+        if let Some(window) = self.region {
+            println!(
+                "Region: {}x{} cells at {}, {}, shaded over the range of {}",
+                window.size.x,
+                window.size.y,
+                window.offset.x,
+                window.offset.y,
+                if self.region_full_range {
+                    "the whole map"
+                } else {
+                    "the region"
+                }
+            );
+        }
+        if let Some(grid) = self.grid()? {
+            println!(
+                "Grid every {} cells ({} blocks), opacity {}",
+                grid.spacing,
+                grid.spacing * BLOCKS_PER_CELL,
+                grid.opacity
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Altitude range of `map`: its lowest and highest altitudes, over all the
+/// [`LAYERS`] with `--layers`, and within the `--region` unless
+/// `--region-full-range` is given.
+pub fn map_range(map: &ModernMap, args: &ExportArgs) -> (f64, f64) {
+    grids_range(map, args, export::compute_min_max)
+}
+
+/// With `--clip-percentile P`, the range between the P and 100 - P
+/// percentiles of the altitudes of `map` (of each of the [`LAYERS`] with
+/// `--layers`), which [`export`] shades over instead of [`map_range`].
+pub fn clipped_range(map: &ModernMap, args: &ExportArgs) -> Option<(f64, f64)> {
+    let percentile = args.clip_percentile?;
+    Some(grids_range(map, args, |grid| {
+        stats::percentile_range(grid, percentile)
+    }))
+}
+
+/// The union of the `range`s of the grids exported from `map`, cropped to the
+/// `--region` unless `--region-full-range` is given.  Regions beyond the map
+/// are left to [`export_over`] to report.
+fn grids_range(
+    map: &ModernMap,
+    args: &ExportArgs,
+    range: impl Fn(&[f64]) -> (f64, f64),
+) -> (f64, f64) {
+    let size = map_size(map);
+    let window = args
+        .region
+        .filter(|window| !args.region_full_range && window.check(size).is_ok());
+    let range = |grid: &[f64]| match window {
+        Some(window) => range(&window.crop(grid, size)),
+        None => range(grid),
+    };
+    if args.layers {
+        layer_grids(map)
+            .iter()
+            .map(|grid| range(grid))
+            .fold((f64::MAX, f64::MIN), |(min, max), (lo, hi)| {
+                (min.min(lo), max.max(hi))
+            })
+    } else {
+        range(&map.alt)
+    }
+}
+
+/// The altitudes, basement and sediment depth of `map`, in the order of
+/// [`LAYERS`].
+fn layer_grids(map: &ModernMap) -> [Cow<'_, [f64]>; 3] {
+    let sediment = map
+        .alt
+        .iter()
+        .zip(map.basement.iter())
+        .map(|(alt, basement)| alt - basement)
+        .collect::<Vec<_>>();
+    [(&*map.alt).into(), (&*map.basement).into(), sediment.into()]
+}
+
+impl ExportArgs {
+    /// [`RangeSource::Explicit`] if `--min` or `--max` was given, otherwise
+    /// [`RangeSource::Percentile`] with `--clip-percentile`, and
+    /// [`RangeSource::Map`] without.
+    pub fn range_source(&self) -> RangeSource {
+        if self.min.is_some() || self.max.is_some() {
+            RangeSource::Explicit
+        } else if self.clip_percentile.is_some() {
+            RangeSource::Percentile
+        } else {
+            RangeSource::Map
+        }
+    }
+
+    /// `range` with either end replaced by `--min` or `--max`, if given.
+    pub fn shade_range(&self, (min, max): (f64, f64)) -> (f64, f64) {
+        (self.min.unwrap_or(min), self.max.unwrap_or(max))
+    }
+}
+
+/// Renders `map` as a heightmap image at `output_path`, returning the
+/// altitude range it spans (see [`map_range`]).  The image is shaded over
+/// that range, or the [`clipped_range`] with `--clip-percentile`, with either
+/// end replaced by `--min` or `--max` if given.  The image is a 16-bit
+/// PGM if `output_path` has a `.pgm` extension, and an 8-bit PNG otherwise;
+/// biome previews and shaded reliefs are always PNGs.
+///
+/// With `--pyramid`, the images of every level are written instead, at the
+/// paths of [`ExportArgs::output_paths`].  Each level is downsampled from the
+/// one before, and all are shaded over the altitude range of the full map.
+///
+/// With `--layers`, the basement and the sediment depth are written next to
+/// the altitudes, in grayscale only, all shaded over one range.
+///
+/// With `--region`, only that window of the map is written, and overlays are
+/// drawn at the cells it shows.
+pub fn export(map: &ModernMap, output_path: &Path, args: &ExportArgs) -> Result<(f64, f64), Error> {
+    let range = map_range(map, args);
+    let shaded = args.shade_range(clipped_range(map, args).unwrap_or(range));
+    export_over(map, output_path, args, shaded, args.range_source())?;
+    Ok(range)
+}
+
+/// Renders `map` like [`export`], but over `range`, such as one shared by a
+/// batch of maps, returning how many values were outside it and clipped.
+///
+/// Unless `source` is [`RangeSource::Map`], the range is recorded in a
+/// [`RangeSidecar`] next to `output_path`; otherwise any stale sidecar there
+/// is removed.  `--markers` outside the map, or the `--region`, are skipped
+/// with a warning, and regions extending beyond the map are rejected.
+pub fn export_over(
+    map: &ModernMap,
+    output_path: &Path,
+    args: &ExportArgs,
+    range: (f64, f64),
+    source: RangeSource,
+) -> Result<usize, Error> {
+    let size = map_size(map);
+    let view = args.view(size);
+    view.check(size)?;
+    let area = if args.region.is_some() {
+        "region"
+    } else {
+        "map"
+    };
+    let markers = args.markers()?;
+    for marker in &markers {
+        if !view.contains(marker.pos) {
+            eprintln!(
+                "Warning: skipping marker {:?} at {}, {}, outside the {}",
+                marker.label, marker.pos.x, marker.pos.y, area
+            );
+        }
+    }
+    let paths = args.output_paths(output_path, size)?;
+    let clipped = if !args.layers {
+        let alt = view.crop(&map.alt, size);
+        let scale = map.continent_scale_hack;
+        export_levels(&alt, view, range, source, &paths, args, scale, &markers)?;
+        export::count_outside(&alt, range.0, range.1)
+    } else {
+        if args.color != ColorMode::Gray {
+            return Err(Error::UnsupportedImage(
+                "layers are only exported in grayscale".to_owned(),
+            ));
+        }
+        let mut clipped = 0;
+        let levels = paths.chunks(paths.len() / LAYERS.len());
+        for (paths, grid) in levels.zip(layer_grids(map)) {
+            let grid = view.crop(&grid, size);
+            let scale = map.continent_scale_hack;
+            export_levels(&grid, view, range, source, paths, args, scale, &markers)?;
+            clipped += export::count_outside(&grid, range.0, range.1);
+        }
+        clipped
+    };
+
+    let sidecar_path = RangeSidecar::path_for(output_path);
+    if source == RangeSource::Map {
+        match std::fs::remove_file(&sidecar_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {},
+        }
+    } else {
+        RangeSidecar {
+            min: range.0,
+            max: range.1,
+            source,
+        }
+        .save(&sidecar_path)?;
+    }
+    Ok(clipped)
+}
+
+/// Renders the grid `alt`, holding the cells of `view`, to the first of
+/// `paths`, and to its pyramid levels at the others with `--pyramid`, over
+/// the range `(min, max)`.  The map's `continent_scale` widens its cells in
+/// normal maps, and `markers` are those of `--markers`, read once for all the
+/// images.
+fn export_levels(
+    alt: &[f64],
+    view: Window,
+    (min, max): (f64, f64),
+    source: RangeSource,
+    paths: &[PathBuf],
+    args: &ExportArgs,
+    continent_scale: f64,
+    markers: &[Marker],
+) -> Result<(), Error> {
+    if args.pyramid.is_none() {
+        return export_grid(
+            alt,
+            view.size,
+            view,
+            (min, max),
+            source,
+            &paths[0],
+            args,
+            continent_scale,
+            markers,
+        );
+    }
+    let (mut alt, mut size) = (alt.to_vec(), view.size);
+    for (level, path) in paths.iter().enumerate() {
+        if level > 0 {
+            (alt, size) = resample::downsample_half(&alt, size);
+        }
+        export_grid(
+            &alt,
+            size,
+            view,
+            (min, max),
+            source,
+            path,
+            args,
+            continent_scale,
+            markers,
+        )?;
+    }
+    Ok(())
+}
+
+/// Renders the altitude grid `alt` of size `size` like [`export`], shading it
+/// over the altitude range `(min, max)`, which came from `source`.  The grid
+/// covers the cells of `view`, which it is smaller than at pyramid levels, and
+/// `markers` are drawn over it.
+fn export_grid(
+    alt: &[f64],
+    size: Vec2<usize>,
+    view: Window,
+    (min, max): (f64, f64),
+    source: RangeSource,
+    output_path: &Path,
+    args: &ExportArgs,
+    continent_scale: f64,
+    markers: &[Marker],
+) -> Result<(), Error> {
+    if matches!(
+        args.color,
+        ColorMode::Gray | ColorMode::Occlusion | ColorMode::Shadow | ColorMode::Normal
+    ) && args.colormap.is_some()
+    {
+        return Err(Error::UnsupportedImage(
+            "--colormap needs a colored --color mode, such as hypsometric".to_owned(),
+        ));
+    }
+    if args.color == ColorMode::Normal && args.legend {
+        return Err(Error::UnsupportedImage(
+            "normal maps have no legend".to_owned(),
+        ));
+    }
+    let pgm = output_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pgm"));
+    if pgm {
+        if args.color != ColorMode::Gray {
+            return Err(Error::UnsupportedImage(
+                "colored exports can't be written as PGM".to_owned(),
+            ));
+        }
+        if args.legend || args.grid.is_some() || args.markers.is_some() {
+            return Err(Error::UnsupportedImage(
+                "legends, grids and markers are only drawn on PNGs".to_owned(),
+            ));
+        }
+        let mut samples = export::render_grayscale16(alt, min, max);
+        if let Some(interval) = args.contours {
+            export::draw_contours16(&mut samples, size.x, alt, interval);
+        }
+        return export::save_pgm16(&samples, size, output_path);
+    }
+
+    // Each mode's image, with the range and colors of its legend.
+    let edges = args.edges();
+    // Cells of pyramid levels span several of the map's.
+    let cell_width = relief::CELL_WIDTH * continent_scale * view.size.x as f64 / size.x as f64;
+    let (mut img, range, colors): (_, _, Box<dyn Fn(f64) -> Rgb<u8>>) = match args.color {
+        ColorMode::Gray => (
+            export::render_grayscale(alt, size, min, max),
+            (min, max),
+            Box::new(|alt| Rgb([export::gray_at(alt, min, max); 3])),
+        ),
+        ColorMode::Relief => {
+            let params = args.relief()?;
+            let mut img = relief::render_relief(alt, size, min, max, &params);
+            if args.relief_occlusion {
+                let openness = occlusion::occlusion(alt, size, &args.occlusion()?);
+                occlusion::multiply(&mut img, &openness);
+            }
+            if let Some(strength) = args.relief_shadows {
+                let shadows = shadow::shadows(alt, size, &args.sun(), edges);
+                occlusion::multiply(&mut img, &shadow::light(&shadows, strength));
+            }
+            (
+                img,
+                (min, max),
+                Box::new(move |alt| Rgb(params.tint(alt, min, max).map(|c| c.round() as u8))),
+            )
+        },
+        ColorMode::Hypsometric => {
+            let colormap = args.colormap_or_default()?;
+            let img = colormap::render_colormap(alt, size, min, max, &colormap);
+            (
+                img,
+                (min, max),
+                Box::new(move |alt| colormap.color_between(alt, min, max)),
+            )
+        },
+        ColorMode::Slope => {
+            let colormap = args.colormap_or_default()?;
+            let slopes = export::slopes(alt, size, edges);
+            let steepest = slopes.iter().copied().fold(0.0, f64::max);
+            let img = colormap::render_colormap(&slopes, size, 0.0, steepest, &colormap);
+            (
+                img,
+                (0.0, steepest),
+                Box::new(move |slope| colormap.color_between(slope, 0.0, steepest)),
+            )
+        },
+        ColorMode::Occlusion => {
+            let openness = occlusion::occlusion(alt, size, &args.occlusion()?);
+            (
+                occlusion::render_occlusion(&openness, size),
+                (0.0, 1.0),
+                Box::new(|openness| Rgb([occlusion::gray(openness); 3])),
+            )
+        },
+        ColorMode::Shadow => {
+            let shadows = shadow::shadows(alt, size, &args.sun(), edges);
+            (
+                shadow::render_shadows(&shadows, size),
+                (0.0, 1.0),
+                Box::new(|shadow| Rgb([shadow::gray(shadow); 3])),
+            )
+        },
+        ColorMode::Normal => (
+            relief::render_normals(alt, size, cell_width, edges),
+            (min, max),
+            Box::new(|_| Rgb([128, 128, 255])),
+        ),
+        ColorMode::Roughness => {
+            let colormap = args.colormap_or_default()?;
+            let roughness = stats::roughness(alt, size, args.roughness_window()?, edges);
+            let (lo, hi) = stats::percentile_range(&roughness, args.roughness_percentile);
+            let img = colormap::render_colormap(&roughness, size, lo, hi, &colormap);
+            (
+                img,
+                (lo, hi),
+                Box::new(move |rough| colormap.color_between(rough, lo, hi)),
+            )
+        },
+        ColorMode::Biome => {
+            let bands = args.bands.bands()?;
+            let colormap = args.colormap()?;
+            let img = biome::render_biomes(alt, size, &bands, edges, colormap.as_ref());
+            (
+                img,
+                (min, max),
+                Box::new(move |alt| bands.biome(alt, 0.0).color_in(colormap.as_ref())),
+            )
+        },
+    };
+    if let Some(interval) = args.contours {
+        export::draw_contours(&mut img, alt, interval);
+    }
+    if let Some(grid) = args.grid()? {
+        overlay::draw_grid(&mut img, view, &grid);
+    }
+    overlay::draw_markers(&mut img, view, markers);
+    if args.legend {
+        let legend = Legend {
+            title: args.legend_title(output_path, size, source),
+            min: range.0,
+            max: range.1,
+            max_ticks: 8,
+        };
+        img = legend::add_legend(&img, &legend, colors);
+    }
+    export::save_png(&img, output_path, args.png.options())
+}
+
+/// Loads `path` as a map if it has a `.bin` or `.bin.zst` extension, and
+/// otherwise converts it from an image using `args`.
+pub fn load_input(path: &Path, args: &ImportArgs) -> Result<ModernMap, Error> {
+    check_input(path)?;
+    if is_map_path(path) {
+        load_map(path)
+    } else {
+        import::import_file(path, &args.params())
+    }
+}
+
+/// Converts the image at `input_path` into a `.bin` file with the same base
+/// name, along with a [`Provenance`] record, printing a summary of the
+/// conversion unless `args.verbosity.quiet` is set.  Warnings about the result
+/// are printed to stderr.  With `--no-overwrite`, nothing is done if the
+/// `.bin` file already exists.  Inputs that aren't readable files are
+/// rejected with [`Error::NotAFile`] or [`Error::Unreadable`] before anything
+/// else.
+pub fn convert(input_path: &Path, params: ImportParams, args: &ConvertArgs) -> Result<(), Error> {
+    check_input(input_path)?;
+    let compression = args.compress.compression();
+    let output_path = input_path.with_extension(compression.extension());
+    let OutputArgs { quiet, verbose } = args.verbosity;
+    if let Some(existing) = args.overwrite.existing(std::slice::from_ref(&output_path)) {
+        OverwriteArgs::report_skipped(existing, quiet);
+        return Ok(());
+    }
+    let mut region = None;
+    let mut stats = None;
+    let mut abyss_cells = None;
+    let mut out_of_range = None;
+    let command = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.file_stem()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "convert".to_owned());
+    let mut provenance = Provenance::new(&command, &params)?.with_input(input_path)?;
+    if let Some(rivers) = &params.rivers {
+        provenance = provenance.with_input(&rivers.image)?;
+    }
+    provenance.pipeline = params.pipeline();
+    let exponent = if args.streaming {
+        let mut streamed = None;
+        provenance.save_with(&output_path, |temp| {
+            streamed = Some(stream::stream_import_file(
+                input_path,
+                temp,
+                &params,
+                args.strip_rows,
+                compression,
+            )?);
+            Ok(())
+        })?;
+        let stream::StreamedMap {
+            map_size_lg,
+            stats: alt_stats,
+        } = streamed.expect("the map was written");
+        for warning in stats_warnings(&alt_stats, args.min_std_dev, args.max_altitude) {
+            eprintln!("Warning: {}", warning);
+        }
+        if verbose {
+            stats = Some(alt_stats);
+        }
+        map_size_lg.x
+    } else {
+        let img = import::load_image(input_path)?;
+        if !quiet {
+            println!("Image dimensions: {}x{}", img.width(), img.height());
+        }
+        if let Some(pad) = &params.pad {
+            region = Some(pad.layout(img.width(), img.height())?.1);
+        }
+        let import::ImportedAltitudes {
+            map_size_lg,
+            alt,
+            basement_alt,
+            water,
+            warnings: import_warnings,
+            abyss_cells: raised,
+            out_of_range: outside,
+        } = import::import_altitudes(&img, &params)?;
+        drop(img);
+        abyss_cells = Some(raised);
+        out_of_range = Some(outside);
+        for warning in
+            import_warnings
+                .into_iter()
+                .chain(warnings(&alt, args.min_std_dev, args.max_altitude))
+        {
+            eprintln!("Warning: {}", warning);
+        }
+        if verbose {
+            stats = Some(AltStats::of(&alt));
+        }
+        // Unless the basement differs, the altitudes are written twice rather
+        // than copied, so only one grid is ever held in memory.
+        match params.basement_grid(&alt, basement_alt.as_deref(), water.as_deref()) {
+            Some(basement) => provenance.save_map(
+                &output_path,
+                ModernMap {
+                    map_size_lg,
+                    continent_scale_hack: params.continent_scale,
+                    alt: alt.into_boxed_slice(),
+                    basement,
+                },
+                compression,
+            )?,
+            None => provenance.save_with(&output_path, |temp| {
+                save_map_with_alt_basement(
+                    temp,
+                    map_size_lg,
+                    params.continent_scale,
+                    &alt,
+                    compression,
+                )
+            })?,
+        }
+        map_size_lg.x
+    };
+    let sidecar_path = ImportSidecar::path_for(&output_path);
+    if args.sidecar {
+        let mut sidecar = ImportSidecar::new(input_path, params.clone());
+        sidecar.region = region;
+        sidecar.save(&sidecar_path)?;
+    }
+    if quiet {
+        return Ok(());
+    }
+
+    println!(
+        "Converted {} -> {}",
+        input_path.display(),
+        output_path.display()
+    );
+    print!(
+        "Map size: {}x{} (exponent: {}), ",
+        1 << exponent,
+        1 << exponent,
+        exponent
+    );
+    match params.raw_altitude {
+        _ if params.terrain_rgb => println!("Terrain-RGB altitudes"),
+        Some(factor) => println!("raw altitudes, multiplied by {}", factor),
+        None => println!(
+            "scale factor: {}, height offset: {}",
+            params.scale, params.offset
+        ),
+    }
+    if let Some(region) = region {
+        println!(
+            "Padded: image occupies the {}x{} cells starting at ({}, {})",
+            region.size.x, region.size.y, region.offset.x, region.offset.y
+        );
+    }
+    if let Some(current_sea) = params.sea_to_zero {
+        println!(
+            "Shifted altitudes by {} to move sea level from {} to 0",
+            -current_sea, current_sea
+        );
+    }
+    if let Some(range) = &params.range {
+        match range.fill {
+            Some(fill) => print!(
+                "Pixel values outside {} to {} filled with {}",
+                range.min, range.max, fill
+            ),
+            None => print!("Pixel values clamped to {} to {}", range.min, range.max),
+        }
+        match out_of_range {
+            Some(pixels) => println!(", {} pixels affected", pixels),
+            None => println!(),
+        }
+    }
+    if let Some(abyss) = &params.abyss {
+        print!(
+            "Raised the abyss to a floor at {} (easing in over {} m)",
+            abyss.floor, abyss.rolloff
+        );
+        match abyss_cells {
+            Some(cells) => println!(", {} cells affected", cells),
+            None => println!(),
+        }
+    }
+    if verbose {
+        println!(
+            "Channel: {:?}, smoothing passes: {} ({:?} edges, {:?}), continent scale: {}",
+            params.channel,
+            params.smooth_iterations,
+            params.edges,
+            params.smooth_target,
+            params.continent_scale
+        );
+        if let Some(basement) = &params.basement {
+            println!(
+                "Basement at {} of the height above sea level {}",
+                basement.factor, basement.sea_level
+            );
+        }
+        if let Some(water) = &params.alpha_water {
+            println!(
+                "Water from the alpha channel: surface at {}, up to {} deep",
+                water.sea_level, water.depth_scale
+            );
+        }
+        if let Some(rivers) = &params.rivers {
+            println!(
+                "Rivers from {}: carved up to {} deep, {} cells wide",
+                rivers.image.display(),
+                rivers.params.max_depth,
+                rivers.params.width
+            );
+        }
+        if let Some(stats) = stats {
+            println!("Altitudes: {}", stats);
+        }
+        if args.sidecar {
+            println!("Sidecar written to {}", sidecar_path.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        export: ExportArgs,
+    }
+
+    /// The export options parsed from `options`.
+    fn export_args(options: &[&str]) -> ExportArgs {
+        Cli::try_parse_from(["export"].iter().chain(options))
+            .unwrap()
+            .export
+    }
+
+    #[test]
+    fn outputs_are_named_by_layer_and_width() {
+        let names = |options: &[&str], output: &str| {
+            export_args(options)
+                .output_paths(Path::new(output), Vec2::new(512, 256))
+                .unwrap()
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&[], "out/map.png"), ["out/map.png"]);
+        assert_eq!(names(&["--pyramid", "2"], "map.png"), [
+            "map_512.png",
+            "map_256.png"
+        ]);
+        // Each layer's levels follow one another, as `export_over` chunks them.
+        assert_eq!(names(&["--layers", "--pyramid", "2"], "map.png"), [
+            "map_alt_512.png",
+            "map_alt_256.png",
+            "map_basement_512.png",
+            "map_basement_256.png",
+            "map_sediment_512.png",
+            "map_sediment_256.png",
+        ]);
+        assert_eq!(names(&["--layers"], "map"), [
+            "map_alt",
+            "map_basement",
+            "map_sediment"
+        ]);
+        assert_eq!(
+            names(
+                &["--pyramid", "2", "--name-template", "{width}/{stem}.{ext}"],
+                "out/map.png"
+            ),
+            ["out/512/map.png", "out/256/map.png"]
+        );
+
+        // Without {layer}, the layers would overwrite one another.
+        for template in ["{stem}.{ext}", "{stem}_{width}.{ext}"] {
+            let args = export_args(&["--layers", "--pyramid", "--name-template", template]);
+            assert!(matches!(
+                args.output_paths(Path::new("map.png"), Vec2::new(512, 256)),
+                Err(Error::NameTemplate(_))
+            ));
+        }
+        // {layer} is only accepted with --layers.
+        let args = export_args(&["--name-template", "{stem}_{layer}.{ext}"]);
+        assert!(matches!(
+            args.name_template(Path::new("map.png")),
+            Err(Error::NameTemplate(_))
+        ));
+    }
+
+    #[test]
+    fn layers_are_shaded_over_one_range() {
+        let mut map = crate::heightmap::test_map(Vec2::new(3, 2), |x, y| (x * 100 + y) as f64);
+        for (i, basement) in map.basement.iter_mut().enumerate() {
+            *basement = -((i % 5) as f64) * 30.0;
+        }
+        let output = std::env::temp_dir().join(format!(
+            "veloren-heightmap-layers-{}.png",
+            std::process::id()
+        ));
+        let args = export_args(&["--layers"]);
+        let paths = args.output_paths(&output, map_size(&map)).unwrap();
+        let (min, max) = export(&map, &output, &args).unwrap();
+        // The lowest basement and the deepest sediment, under 702 m of land.
+        assert_eq!((min, max), (-120.0, 792.0));
+
+        let sediment = map
+            .alt
+            .iter()
+            .zip(map.basement.iter())
+            .map(|(alt, basement)| alt - basement)
+            .collect::<Vec<_>>();
+        for (path, grid) in paths.iter().zip([&map.alt[..], &map.basement, &sediment]) {
+            let img = image::open(path).unwrap().into_rgb8();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(
+                img,
+                export::render_grayscale(grid, map_size(&map), min, max),
+                "{}",
+                path.display()
+            );
+        }
+
+        let args = export_args(&["--layers", "--color", "relief"]);
+        assert!(matches!(
+            export(&map, &output, &args),
+            Err(Error::UnsupportedImage(_))
+        ));
+    }
+
+    #[test]
+    fn no_overwrite_skips_only_when_every_output_exists() {
+        let dir = std::env::temp_dir().join(format!(
+            "veloren-heightmap-overwrite-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = ["map.png", "map_512.png", "map_256.png"].map(|name| dir.join(name));
+        let keep = OverwriteArgs {
+            overwrite: false,
+            no_overwrite: true,
+        };
+        let replace = OverwriteArgs {
+            no_overwrite: false,
+            ..keep
+        };
+
+        assert_eq!(keep.existing(&paths), None);
+        // Interrupted after the first two: all are written again.
+        for path in &paths[..2] {
+            std::fs::write(path, b"").unwrap();
+        }
+        assert_eq!(keep.existing(&paths), None);
+        std::fs::write(&paths[2], b"").unwrap();
+        assert_eq!(keep.existing(&paths), Some(paths[0].as_path()));
+        assert_eq!(keep.existing(&paths[1..]), Some(paths[1].as_path()));
+        assert_eq!(replace.existing(&paths), None);
+        assert_eq!(keep.existing(&[]), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+//! Colormaps: ramps of colors between stops, shared by every colored export.
+//!
+//! Colors are interpolated in Oklab, a perceptual color space, so that equal
+//! steps along a ramp look like equal changes of color, rather than bunching
+//! up where interpolating sRGB components would pass through murky or overly
+//! bright mixtures.
+
+use super::Error;
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use std::{borrow::Cow, fmt, path::Path};
+use vek::*;
+
+/// A color of a colormap, at a position between 0 and 1 along it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stop {
+    pub position: f64,
+    pub color: Rgb<u8>,
+}
+
+impl Stop {
+    pub const fn new(position: f64, [r, g, b]: [u8; 3]) -> Self {
+        Self {
+            position,
+            color: Rgb([r, g, b]),
+        }
+    }
+}
+
+/// A ramp of colors, interpolated between at least two stops whose positions
+/// rise from 0 to 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Colormap {
+    stops: Cow<'static, [Stop]>,
+}
+
+/// The colormaps that can be selected by name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Builtin {
+    /// Blue water, green lowlands, sandy and brown hills and white peaks, for
+    /// altitudes.
+    Terrain,
+    /// Matplotlib's perceptually uniform dark blue to yellow.
+    Viridis,
+    /// Matplotlib's perceptually uniform black to pale yellow, through purple
+    /// and orange.
+    Magma,
+    /// Black to white.
+    Grayscale,
+    /// Dark navy to pale cyan, for depths below sea level.
+    Bathymetric,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 5] = [
+        Builtin::Terrain,
+        Builtin::Viridis,
+        Builtin::Magma,
+        Builtin::Grayscale,
+        Builtin::Bathymetric,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Terrain => "terrain",
+            Builtin::Viridis => "viridis",
+            Builtin::Magma => "magma",
+            Builtin::Grayscale => "grayscale",
+            Builtin::Bathymetric => "bathymetric",
+        }
+    }
+
+    /// The builtin colormap called `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|builtin| builtin.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn colormap(self) -> Colormap {
+        Colormap::from_static(match self {
+            Builtin::Terrain => &TERRAIN,
+            Builtin::Viridis => &VIRIDIS,
+            Builtin::Magma => &MAGMA,
+            Builtin::Grayscale => &GRAYSCALE,
+            Builtin::Bathymetric => &BATHYMETRIC,
+        })
+    }
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(self.name()) }
+}
+
+const TERRAIN: [Stop; 6] = [
+    Stop::new(0.0, [51, 51, 153]),
+    Stop::new(0.15, [0, 153, 255]),
+    Stop::new(0.25, [0, 204, 102]),
+    Stop::new(0.5, [255, 255, 153]),
+    Stop::new(0.75, [128, 92, 84]),
+    Stop::new(1.0, [255, 255, 255]),
+];
+
+const VIRIDIS: [Stop; 9] = [
+    Stop::new(0.0, [68, 1, 84]),
+    Stop::new(0.125, [72, 40, 120]),
+    Stop::new(0.25, [62, 73, 137]),
+    Stop::new(0.375, [49, 104, 142]),
+    Stop::new(0.5, [38, 130, 142]),
+    Stop::new(0.625, [31, 158, 137]),
+    Stop::new(0.75, [53, 183, 121]),
+    Stop::new(0.875, [110, 206, 88]),
+    Stop::new(1.0, [253, 231, 37]),
+];
+
+const MAGMA: [Stop; 9] = [
+    Stop::new(0.0, [0, 0, 4]),
+    Stop::new(0.125, [28, 16, 68]),
+    Stop::new(0.25, [79, 18, 123]),
+    Stop::new(0.375, [129, 37, 129]),
+    Stop::new(0.5, [181, 54, 122]),
+    Stop::new(0.625, [229, 80, 100]),
+    Stop::new(0.75, [251, 135, 97]),
+    Stop::new(0.875, [254, 194, 135]),
+    Stop::new(1.0, [252, 253, 191]),
+];
+
+const GRAYSCALE: [Stop; 2] = [Stop::new(0.0, [0, 0, 0]), Stop::new(1.0, [255, 255, 255])];
+
+const BATHYMETRIC: [Stop; 5] = [
+    Stop::new(0.0, [8, 16, 58]),
+    Stop::new(0.3, [13, 59, 120]),
+    Stop::new(0.6, [42, 127, 184]),
+    Stop::new(0.85, [124, 196, 224]),
+    Stop::new(1.0, [214, 240, 245]),
+];
+
+/// Blue through white to red, for signed differences centered on 0.5.
+const DIVERGING: [Stop; 3] = [
+    Stop::new(0.0, [0, 0, 255]),
+    Stop::new(0.5, [255, 255, 255]),
+    Stop::new(1.0, [255, 0, 0]),
+];
+
+impl Colormap {
+    /// The colormap of differences: blue below the middle, white at it and red
+    /// above it.
+    pub const DIVERGING: Colormap = Colormap::from_static(&DIVERGING);
+
+    /// A colormap through `stops`, whose positions must already rise from 0
+    /// to 1, for colormaps built into the tools.
+    pub const fn from_static(stops: &'static [Stop]) -> Self {
+        Self {
+            stops: Cow::Borrowed(stops),
+        }
+    }
+
+    /// A colormap through `stops`, whose positions must be finite and
+    /// increasing; they are rescaled to run from 0 to 1.
+    pub fn new(mut stops: Vec<Stop>) -> Result<Self, Error> {
+        if stops.len() < 2 {
+            return Err(Error::Colormap("at least two stops are needed".to_owned()));
+        }
+        if stops.iter().any(|stop| !stop.position.is_finite()) {
+            return Err(Error::Colormap("positions must be finite".to_owned()));
+        }
+        if stops
+            .windows(2)
+            .any(|pair| pair[1].position <= pair[0].position)
+        {
+            return Err(Error::Colormap(
+                "positions must increase from one stop to the next".to_owned(),
+            ));
+        }
+        let (first, last) = (stops[0].position, stops[stops.len() - 1].position);
+        for stop in &mut stops {
+            stop.position = (stop.position - first) / (last - first);
+        }
+        Ok(Self {
+            stops: Cow::Owned(stops),
+        })
+    }
+
+    /// Parses a colormap, one stop per line: a position and a hex color such
+    /// as `#2a7fb8`, separated by spaces or a comma.  Positions may use any
+    /// increasing scale, such as percentages.  Blank lines and lines starting
+    /// with `#` are skipped.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut stops = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |reason: &str| Error::Colormap(format!("line {}: {}", i + 1, reason));
+            let fields = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|field| !field.is_empty())
+                .collect::<Vec<_>>();
+            let [position, color] = fields[..] else {
+                return Err(error("expected a position and a color"));
+            };
+            let position = position.parse::<f64>().map_err(|e| error(&e.to_string()))?;
+            let color = parse_hex(color).ok_or_else(|| error("expected a color like #2a7fb8"))?;
+            stops.push(Stop { position, color });
+        }
+        Self::new(stops)
+    }
+
+    /// The builtin colormap called `name`, or else the colormap in the file
+    /// at that path; see [`Colormap::parse`].
+    pub fn resolve(name: &str) -> Result<Self, Error> {
+        match Builtin::from_name(name) {
+            Some(builtin) => Ok(builtin.colormap()),
+            None => Self::load(name),
+        }
+    }
+
+    /// Reads the colormap at `path`; see [`Colormap::parse`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn stops(&self) -> &[Stop] { &self.stops }
+
+    /// Color at `t` along the colormap, clamped to between 0 and 1 (NaN
+    /// giving the first color), with components between 0 and 255.
+    ///
+    /// Stops are returned exactly; between them, colors are interpolated in
+    /// Oklab.
+    pub fn sample(&self, t: f64) -> [f64; 3] {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let upper = self
+            .stops
+            .iter()
+            .position(|stop| stop.position >= t)
+            .unwrap_or(self.stops.len() - 1)
+            .max(1);
+        let (lo, hi) = (self.stops[upper - 1], self.stops[upper]);
+        let along = (t - lo.position) / (hi.position - lo.position);
+        let channels = |color: Rgb<u8>| color.0.map(f64::from);
+        if along <= 0.0 {
+            return channels(lo.color);
+        } else if along >= 1.0 {
+            return channels(hi.color);
+        }
+        let (from, to) = (to_oklab(channels(lo.color)), to_oklab(channels(hi.color)));
+        from_oklab(from + (to - from) * along)
+    }
+
+    /// Like [`Colormap::sample`], rounded to a pixel.
+    pub fn color(&self, t: f64) -> Rgb<u8> {
+        Rgb(self.sample(t).map(|c| c.round().clamp(0.0, 255.0) as u8))
+    }
+
+    /// Color of `value` for a colormap spanning `min` to `max`, the last color
+    /// if they are equal.
+    pub fn color_between(&self, value: f64, min: f64, max: f64) -> Rgb<u8> {
+        self.color(fraction(value, min, max))
+    }
+}
+
+/// Where `value` lies between `min` and `max`, from 0 to 1, or 1 if they are
+/// equal.
+pub fn fraction(value: f64, min: f64, max: f64) -> f64 {
+    if max > min {
+        ((value - min) / (max - min)).clamp(0.0, 1.0)
+    } else {
+        1.0
+    }
+}
+
+/// Parses a color like `#2a7fb8`, with or without the `#`.
+pub(crate) fn parse_hex(text: &str) -> Option<Rgb<u8>> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok();
+    Some(Rgb([channel(0)?, channel(1)?, channel(2)?]))
+}
+
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The Oklab coordinates (lightness, a, b) of an sRGB color with components
+/// between 0 and 255.
+fn to_oklab(color: [f64; 3]) -> Vec3<f64> {
+    let [r, g, b] = color.map(|c| srgb_to_linear(c / 255.0));
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    Vec3::new(
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    )
+}
+
+/// The sRGB color, with components between 0 and 255, of Oklab coordinates.
+fn from_oklab(lab: Vec3<f64>) -> [f64; 3] {
+    let l = (lab.x + 0.3963377774 * lab.y + 0.2158037573 * lab.z).powi(3);
+    let m = (lab.x - 0.1055613458 * lab.y - 0.0638541728 * lab.z).powi(3);
+    let s = (lab.x - 0.0894841775 * lab.y - 1.2914855480 * lab.z).powi(3);
+    [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+    .map(|c| linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0)
+}
+
+/// Renders the grid `values` of size `size` through `colormap`, from its
+/// first color at `min` to its last at `max`.
+///
+/// Rows are rendered in parallel, like
+/// [`render_grayscale`](super::export::render_grayscale).
+pub fn render_colormap(
+    values: &[f64],
+    size: Vec2<usize>,
+    min: f64,
+    max: f64,
+    colormap: &Colormap,
+) -> RgbImage {
+    let mut buf = vec![0; size.product() * 3];
+    buf.par_chunks_mut((size.x * 3).max(1))
+        .enumerate()
+        .for_each(|(y, pixels)| {
+            for (x, pixel) in pixels.chunks_exact_mut(3).enumerate() {
+                let color = colormap.color_between(values[y * size.x + x], min, max);
+                pixel.copy_from_slice(&color.0);
+            }
+        });
+    RgbImage::from_raw(size.x as u32, size.y as u32, buf).expect("Buffer matches the image size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_are_sampled_exactly() {
+        for builtin in Builtin::ALL {
+            let colormap = builtin.colormap();
+            for stop in colormap.stops() {
+                assert_eq!(colormap.color(stop.position), stop.color, "{}", builtin);
+            }
+            assert_eq!(
+                Builtin::from_name(&builtin.name().to_uppercase()),
+                Some(builtin)
+            );
+        }
+        let gray = Builtin::Grayscale.colormap();
+        assert_eq!(gray.color(-1.0), Rgb([0, 0, 0]));
+        assert_eq!(gray.color(f64::NAN), Rgb([0, 0, 0]));
+        assert_eq!(gray.color(2.0), Rgb([255, 255, 255]));
+        assert_eq!(gray.color_between(7.0, 7.0, 7.0), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn interpolation_is_monotonic_between_stops() {
+        // Oklab lightness rises steadily along viridis and magma, and between
+        // the two grays.
+        for builtin in [Builtin::Viridis, Builtin::Magma, Builtin::Grayscale] {
+            let colormap = builtin.colormap();
+            let lightness = (0..=1000)
+                .map(|i| to_oklab(colormap.sample(i as f64 / 1000.0)).x)
+                .collect::<Vec<_>>();
+            assert!(
+                lightness.windows(2).all(|pair| pair[1] >= pair[0] - 1e-3),
+                "{}",
+                builtin
+            );
+        }
+        // Between two stops, each channel moves from one to the other without
+        // overshooting, and the middle is perceptually halfway.
+        let colormap = Colormap::parse("0 #ff0000\n1 #0000ff").unwrap();
+        let samples = (0..=100)
+            .map(|i| colormap.sample(i as f64 / 100.0))
+            .collect::<Vec<_>>();
+        for pair in samples.windows(2) {
+            assert!(pair[1][0] <= pair[0][0] + 1e-9 && pair[1][2] >= pair[0][2] - 1e-9);
+        }
+        let (red, blue) = (to_oklab([255.0, 0.0, 0.0]), to_oklab([0.0, 0.0, 255.0]));
+        let middle = to_oklab(colormap.sample(0.5));
+        assert!((middle.x - (red.x + blue.x) / 2.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn custom_colormaps_are_parsed() {
+        let colormap =
+            Colormap::parse("# position, color\n0 #000000\n\n25, 808080\n100 #FFFFFF\n").unwrap();
+        assert_eq!(colormap.stops().len(), 3);
+        assert_eq!(colormap.stops()[1].position, 0.25);
+        assert_eq!(colormap.color(0.25), Rgb([128, 128, 128]));
+        assert_eq!(colormap.color(1.0), Rgb([255, 255, 255]));
+
+        for text in [
+            "",
+            "0 #000000",
+            "0 #000000\n0 #ffffff",
+            "1 #000000\n0 #ffffff",
+            "0 #000000\n1 #fffff",
+            "0 #000000\n1 #gggggg",
+            "0 #000000\n1",
+            "0 #000000\ninf #ffffff",
+        ] {
+            assert!(
+                matches!(Colormap::parse(text), Err(Error::Colormap(_))),
+                "{:?}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Combining two maps into one.
+
+use super::{Error, map_size, mask::Mask};
+use crate::sim::ModernMap;
+
+/// How much of the second map goes into a blend.
+#[derive(Clone, Copy, Debug)]
+pub enum BlendWeight<'a> {
+    /// The same weight for every cell.
+    Uniform(f64),
+    /// A separate weight for every cell.
+    Mask(&'a Mask),
+}
+
+/// A per-cell operator combining two maps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ComposeOp {
+    /// The higher of both maps, e.g. to add a mountain range to a terrain.
+    Max,
+    /// The lower of both maps, e.g. to carve valleys into a terrain.
+    Min,
+    /// Raises the first map by the altitude of the second.
+    Add,
+    /// Lowers the first map by the altitude of the second, but no further
+    /// than `floor`.
+    Sub { floor: f64 },
+}
+
+/// Fails unless `a` and `b` have the same size.
+pub fn check_same_size(a: &ModernMap, b: &ModernMap) -> Result<(), Error> {
+    if a.map_size_lg != b.map_size_lg {
+        return Err(Error::SizeMismatch {
+            expected: map_size(a),
+            found: map_size(b),
+        });
+    }
+    Ok(())
+}
+
+/// Blends `b` into `a`, computing `a * (1 - w) + b * w` for both the altitude
+/// and basement of each cell.  The result keeps the `continent_scale_hack` of
+/// `a`.
+pub fn blend(a: &ModernMap, b: &ModernMap, weight: BlendWeight) -> Result<ModernMap, Error> {
+    check_same_size(a, b)?;
+    if let BlendWeight::Mask(mask) = weight {
+        mask.check_size(map_size(a))?;
+    }
+    let weight_at = |i: usize| match weight {
+        BlendWeight::Uniform(w) => w,
+        BlendWeight::Mask(mask) => mask.values[i],
+    };
+    let mix = |a: &[f64], b: &[f64]| {
+        a.iter()
+            .zip(b)
+            .enumerate()
+            .map(|(i, (a, b))| {
+                let w = weight_at(i);
+                a * (1.0 - w) + b * w
+            })
+            .collect::<Box<[_]>>()
+    };
+
+    Ok(ModernMap {
+        map_size_lg: a.map_size_lg,
+        continent_scale_hack: a.continent_scale_hack,
+        alt: mix(&a.alt, &b.alt),
+        basement: mix(&a.basement, &b.basement),
+    })
+}
+
+fn zip_with(a: &[f64], b: &[f64], f: impl Fn(f64, f64) -> f64) -> Box<[f64]> {
+    a.iter().zip(b).map(|(a, b)| f(*a, *b)).collect()
+}
+
+/// Combines `a` and `b` cell by cell.  The result keeps the
+/// `continent_scale_hack` of `a`.
+///
+/// [`ComposeOp::Max`] and [`ComposeOp::Min`] combine altitudes and basements
+/// separately, while [`ComposeOp::Add`] and [`ComposeOp::Sub`] treat `b` as a
+/// displacement, moving both the altitude and basement of `a` by the altitude
+/// of `b`.
+pub fn compose(a: &ModernMap, b: &ModernMap, op: ComposeOp) -> Result<ModernMap, Error> {
+    check_same_size(a, b)?;
+    let (alt, basement) = match op {
+        ComposeOp::Max => (
+            zip_with(&a.alt, &b.alt, f64::max),
+            zip_with(&a.basement, &b.basement, f64::max),
+        ),
+        ComposeOp::Min => (
+            zip_with(&a.alt, &b.alt, f64::min),
+            zip_with(&a.basement, &b.basement, f64::min),
+        ),
+        ComposeOp::Add => (
+            zip_with(&a.alt, &b.alt, |a, b| a + b),
+            zip_with(&a.basement, &b.alt, |a, b| a + b),
+        ),
+        ComposeOp::Sub { floor } => (
+            zip_with(&a.alt, &b.alt, |a, b| (a - b).max(floor)),
+            zip_with(&a.basement, &b.alt, |a, b| (a - b).max(floor)),
+        ),
+    };
+
+    Ok(ModernMap {
+        map_size_lg: a.map_size_lg,
+        continent_scale_hack: a.continent_scale_hack,
+        alt,
+        basement,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+    use vek::*;
+
+    #[test]
+    fn uniform_blend() {
+        let a = test_map(Vec2::new(1, 1), |_, _| 100.0);
+        let b = test_map(Vec2::new(1, 1), |x, _| x as f64 * 200.0);
+        let out = blend(&a, &b, BlendWeight::Uniform(0.25)).unwrap();
+        assert_eq!(&*out.alt, &[75.0, 125.0, 75.0, 125.0]);
+        assert_eq!(&*out.basement, &[65.0, 115.0, 65.0, 115.0]);
+
+        let out = blend(&a, &b, BlendWeight::Uniform(0.0)).unwrap();
+        assert_eq!(out.alt, a.alt);
+    }
+
+    #[test]
+    fn mask_blend() {
+        let a = test_map(Vec2::new(1, 1), |_, _| 0.0);
+        let b = test_map(Vec2::new(1, 1), |_, _| 100.0);
+        let mask = Mask {
+            size: Vec2::new(2, 2),
+            values: vec![0.0, 0.5, 1.0, 0.1],
+        };
+        let out = blend(&a, &b, BlendWeight::Mask(&mask)).unwrap();
+        assert_eq!(&*out.alt, &[0.0, 50.0, 100.0, 10.0]);
+
+        let small = Mask {
+            size: Vec2::new(1, 1),
+            values: vec![1.0],
+        };
+        assert!(matches!(
+            blend(&a, &b, BlendWeight::Mask(&small)),
+            Err(Error::SizeMismatch { .. })
+        ));
+    }
+
+    /// Two 2x2 maps with hand-picked altitudes and basements.
+    fn operands() -> (ModernMap, ModernMap) {
+        let a = test_map(Vec2::new(1, 1), |x, y| [10.0, 50.0, -20.0, 0.0][y * 2 + x]);
+        let b = test_map(Vec2::new(1, 1), |x, y| [30.0, 5.0, -40.0, 100.0][y * 2 + x]);
+        (a, b)
+    }
+
+    #[test]
+    fn max_takes_higher_cells() {
+        let (a, b) = operands();
+        let out = compose(&a, &b, ComposeOp::Max).unwrap();
+        assert_eq!(&*out.alt, &[30.0, 50.0, -20.0, 100.0]);
+        assert_eq!(&*out.basement, &[20.0, 40.0, -30.0, 90.0]);
+    }
+
+    #[test]
+    fn min_takes_lower_cells() {
+        let (a, b) = operands();
+        let out = compose(&a, &b, ComposeOp::Min).unwrap();
+        assert_eq!(&*out.alt, &[10.0, 5.0, -40.0, 0.0]);
+        assert_eq!(&*out.basement, &[0.0, -5.0, -50.0, -10.0]);
+    }
+
+    #[test]
+    fn add_displaces_by_second_altitude() {
+        let (a, b) = operands();
+        let out = compose(&a, &b, ComposeOp::Add).unwrap();
+        assert_eq!(&*out.alt, &[40.0, 55.0, -60.0, 100.0]);
+        assert_eq!(&*out.basement, &[30.0, 45.0, -70.0, 90.0]);
+    }
+
+    #[test]
+    fn sub_clamps_to_floor() {
+        let (a, b) = operands();
+        let out = compose(&a, &b, ComposeOp::Sub { floor: -50.0 }).unwrap();
+        assert_eq!(&*out.alt, &[-20.0, 45.0, 20.0, -50.0]);
+        assert_eq!(&*out.basement, &[-30.0, 35.0, 10.0, -50.0]);
+    }
+
+    #[test]
+    fn mismatched_sizes_are_rejected() {
+        let a = test_map(Vec2::new(2, 2), |_, _| 0.0);
+        let b = test_map(Vec2::new(1, 1), |_, _| 0.0);
+        assert!(matches!(
+            blend(&a, &b, BlendWeight::Uniform(0.5)),
+            Err(Error::SizeMismatch { .. })
+        ));
+        assert!(matches!(
+            compose(&a, &b, ComposeOp::Max),
+            Err(Error::SizeMismatch { .. })
+        ));
+    }
+}
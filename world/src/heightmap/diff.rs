@@ -0,0 +1,186 @@
+//! Comparing two versions of a map.
+
+use super::{Error, colormap::Colormap, combine::check_same_size, map_size};
+use crate::sim::ModernMap;
+use image::RgbImage;
+use std::fmt;
+use vek::*;
+
+/// Statistics of the per-cell difference between two grids.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridDiff {
+    pub max_abs: f64,
+    pub mean_abs: f64,
+    pub rms: f64,
+    /// Number of cells whose values are not bit-identical.
+    pub differing: usize,
+    /// Smallest rectangle containing all differing cells, with inclusive
+    /// bounds, or `None` if the grids are identical.
+    pub bounds: Option<Aabr<usize>>,
+}
+
+impl GridDiff {
+    /// Compares two row-major grids with rows of `width` cells.
+    pub fn new(a: &[f64], b: &[f64], width: usize) -> Self {
+        let mut max_abs = 0.0f64;
+        let mut sum_abs = 0.0;
+        let mut sum_sq = 0.0;
+        let mut differing = 0;
+        let mut bounds: Option<Aabr<usize>> = None;
+        for (i, (a, b)) in a.iter().zip(b).enumerate() {
+            if a.to_bits() == b.to_bits() {
+                continue;
+            }
+            let diff = (b - a).abs();
+            max_abs = max_abs.max(diff);
+            sum_abs += diff;
+            sum_sq += diff * diff;
+            differing += 1;
+
+            let pos = Vec2::new(i % width, i / width);
+            bounds = Some(match bounds {
+                Some(bounds) => Aabr {
+                    min: Vec2::new(bounds.min.x.min(pos.x), bounds.min.y.min(pos.y)),
+                    max: Vec2::new(bounds.max.x.max(pos.x), bounds.max.y.max(pos.y)),
+                },
+                None => Aabr { min: pos, max: pos },
+            });
+        }
+        let cells = a.len().max(1) as f64;
+        Self {
+            max_abs,
+            mean_abs: sum_abs / cells,
+            rms: (sum_sq / cells).sqrt(),
+            differing,
+            bounds,
+        }
+    }
+
+    pub fn is_identical(&self) -> bool { self.differing == 0 }
+}
+
+impl fmt::Display for GridDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "max |diff| {}, mean |diff| {}, RMS {}, {} cells differ",
+            self.max_abs, self.mean_abs, self.rms, self.differing
+        )?;
+        if let Some(bounds) = self.bounds {
+            write!(
+                f,
+                " within ({}, {})..=({}, {})",
+                bounds.min.x, bounds.min.y, bounds.max.x, bounds.max.y
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Differences in altitude and basement between two maps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MapDiff {
+    pub alt: GridDiff,
+    pub basement: GridDiff,
+}
+
+impl MapDiff {
+    /// Compares `a` with `b`, which must have the same size.
+    pub fn new(a: &ModernMap, b: &ModernMap) -> Result<Self, Error> {
+        check_same_size(a, b)?;
+        let width = map_size(a).x;
+        Ok(Self {
+            alt: GridDiff::new(&a.alt, &b.alt, width),
+            basement: GridDiff::new(&a.basement, &b.basement, width),
+        })
+    }
+
+    pub fn is_identical(&self) -> bool { self.alt.is_identical() && self.basement.is_identical() }
+}
+
+/// Renders `b - a` through `colormap`, from its first color where `b` is lower
+/// by `max_abs` to its last where it is higher by as much, with the middle of
+/// the colormap where the grids agree.  [`Colormap::DIVERGING`] is white where
+/// they agree, shading to blue where `b` is lower and to red where it is
+/// higher.
+pub fn render_diff(
+    a: &[f64],
+    b: &[f64],
+    size: Vec2<usize>,
+    max_abs: f64,
+    colormap: &Colormap,
+) -> RgbImage {
+    let scale = if max_abs > 0.0 { 1.0 / max_abs } else { 0.0 };
+    RgbImage::from_fn(size.x as u32, size.y as u32, |x, y| {
+        let i = y as usize * size.x + x as usize;
+        let t = ((b[i] - a[i]) * scale).clamp(-1.0, 1.0);
+        colormap.color((t + 1.0) / 2.0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+    use image::Rgb;
+
+    #[test]
+    fn identical_maps_report_zero() {
+        let a = test_map(Vec2::new(2, 2), |x, y| (x * y) as f64 / 7.0);
+        let b = test_map(Vec2::new(2, 2), |x, y| (x * y) as f64 / 7.0);
+        let diff = MapDiff::new(&a, &b).unwrap();
+        assert!(diff.is_identical());
+        assert_eq!(diff.alt, GridDiff {
+            max_abs: 0.0,
+            mean_abs: 0.0,
+            rms: 0.0,
+            differing: 0,
+            bounds: None,
+        });
+    }
+
+    #[test]
+    fn statistics_and_bounds_of_changes() {
+        let a = test_map(Vec2::new(2, 2), |_, _| 10.0);
+        let mut b = test_map(Vec2::new(2, 2), |_, _| 10.0);
+        b.alt[4 + 1] = 13.0;
+        b.alt[2 * 4 + 3] = 6.0;
+
+        let diff = MapDiff::new(&a, &b).unwrap();
+        assert!(!diff.is_identical());
+        assert!(diff.basement.is_identical());
+        assert_eq!(diff.alt.max_abs, 4.0);
+        assert_eq!(diff.alt.mean_abs, 7.0 / 16.0);
+        assert_eq!(diff.alt.rms, (25.0f64 / 16.0).sqrt());
+        assert_eq!(diff.alt.differing, 2);
+        assert_eq!(
+            diff.alt.bounds,
+            Some(Aabr {
+                min: Vec2::new(1, 1),
+                max: Vec2::new(3, 2),
+            })
+        );
+
+        let img = render_diff(
+            &a.alt,
+            &b.alt,
+            Vec2::new(4, 4),
+            diff.alt.max_abs,
+            &Colormap::DIVERGING,
+        );
+        assert_eq!(img.get_pixel(0, 0), &Rgb([255, 255, 255]));
+        assert_eq!(img.get_pixel(3, 2), &Rgb([0, 0, 255]));
+        // Three quarters of the way from white to red, in Oklab.
+        assert_eq!(img.get_pixel(1, 1), &Rgb([255, 107, 89]));
+    }
+
+    #[test]
+    fn mismatched_sizes_are_rejected() {
+        let a = test_map(Vec2::new(2, 2), |_, _| 0.0);
+        let b = test_map(Vec2::new(2, 1), |_, _| 0.0);
+        assert!(matches!(
+            MapDiff::new(&a, &b),
+            Err(Error::SizeMismatch { .. })
+        ));
+    }
+}
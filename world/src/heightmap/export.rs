@@ -0,0 +1,617 @@
+//! Rendering maps as images.
+//!
+//! Image row `y` shows map row `y`, matching the layout expected by the
+//! image importers.
+
+use super::{Error, filter::Edges, io::write_atomically, read_json, write_json};
+use image::{
+    ExtendedColorType, ImageEncoder, RgbImage, RgbaImage,
+    codecs::png::{CompressionType, FilterType, PngEncoder},
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+use vek::*;
+
+/// Number of cells in each of the chunks the grid is split into for parallel
+/// processing.
+const CHUNK_CELLS: usize = 1 << 16;
+
+/// Computes the minimum and maximum values in the altitude grid.
+///
+/// NaNs are ignored.  The grid is scanned in parallel chunks whose results are
+/// combined in order, so that the result is the same as that of a sequential
+/// scan, down to which of equal values (such as `0.0` and `-0.0`) is kept.
+pub fn compute_min_max(alt: &[f64]) -> (f64, f64) {
+    fn merge((min, max): (f64, f64), (other_min, other_max): (f64, f64)) -> (f64, f64) {
+        (
+            if other_min < min { other_min } else { min },
+            if other_max > max { other_max } else { max },
+        )
+    }
+
+    alt.par_chunks(CHUNK_CELLS)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .fold((f64::MAX, f64::MIN), |acc, &val| merge(acc, (val, val)))
+        })
+        .reduce(|| (f64::MAX, f64::MIN), merge)
+}
+
+/// The lowest and highest cells of the altitude grid, as `(min_index, min,
+/// max_index, max)`, for marking them on an image, or `None` if it holds no
+/// altitudes other than NaNs, which are ignored.
+///
+/// Of equal extremes, the first in the grid is kept, and chunks scanned in
+/// parallel are combined in order like in [`compute_min_max`], so that the
+/// same cells are found on every run.  Altitudes are `f64`, as stored in
+/// [`ModernMap`](crate::sim::ModernMap) and taken by the other functions
+/// here, so `f32` grids must be widened first.
+pub fn altitude_extrema(alt: &[f64]) -> Option<(usize, f64, usize, f64)> {
+    type Extrema = Option<(usize, f64, usize, f64)>;
+    fn merge(first: Extrema, second: Extrema) -> Extrema {
+        let (Some(first), Some(second)) = (first, second) else {
+            return first.or(second);
+        };
+        let (min_index, min) = if second.1 < first.1 {
+            (second.0, second.1)
+        } else {
+            (first.0, first.1)
+        };
+        let (max_index, max) = if second.3 > first.3 {
+            (second.2, second.3)
+        } else {
+            (first.2, first.3)
+        };
+        Some((min_index, min, max_index, max))
+    }
+
+    alt.par_chunks(CHUNK_CELLS)
+        .enumerate()
+        .map(|(chunk_index, chunk)| {
+            let start = chunk_index * CHUNK_CELLS;
+            chunk
+                .iter()
+                .enumerate()
+                .filter(|(_, alt)| !alt.is_nan())
+                .fold(None, |acc, (i, &alt)| {
+                    merge(acc, Some((start + i, alt, start + i, alt)))
+                })
+        })
+        .reduce(|| None, merge)
+}
+
+/// Range that altitudes are divided by to map `min` to 0 and `max` to 1.
+fn value_range(min: f64, max: f64) -> f64 {
+    let range = max - min;
+    // Avoid division by zero in case of a flat map.
+    if range == 0.0 { 1.0 } else { range }
+}
+
+/// Number of values of `alt` outside `min..=max`, NaNs included, which
+/// renders clip to the nearest end of it.
+pub fn count_outside(alt: &[f64], min: f64, max: f64) -> usize {
+    alt.par_iter()
+        .filter(|alt| !(min..=max).contains(*alt))
+        .count()
+}
+
+/// Where the altitude range an image was shaded over came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RangeSource {
+    /// The lowest and highest altitudes of the map itself.
+    Map,
+    /// The lowest and highest altitudes (or percentiles) of all the maps
+    /// exported together.
+    Batch,
+    /// Percentiles of the map's altitudes, clipping the few outside them.
+    Percentile,
+    /// Given by the user, clipping any altitudes outside it.
+    Explicit,
+}
+
+/// Altitude range an image was shaded over, stored next to it when it isn't
+/// the map's own, so that its shades can be read back as altitudes: a gray
+/// image converts back with `--offset min --scale (max - min)`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RangeSidecar {
+    /// Altitude shown as black, or the lowest tint.
+    pub min: f64,
+    /// Altitude shown as white, or the highest tint.
+    pub max: f64,
+    pub source: RangeSource,
+}
+
+impl RangeSidecar {
+    /// Path of the sidecar accompanying the image at `image_path`, which also
+    /// covers its pyramid levels and layers.
+    pub fn path_for(image_path: &Path) -> PathBuf { image_path.with_extension("range.json") }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> { read_json(path) }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> { write_json(path, self) }
+}
+
+/// How exported images are colored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ColorMode {
+    /// Altitudes in grayscale, from black at the lowest to white at the
+    /// highest.
+    #[default]
+    Gray,
+    /// A preview of biomes by altitude band, see
+    /// [`render_biomes`](super::biome::render_biomes).
+    Biome,
+    /// A hillshade over a hypsometric tint, see
+    /// [`render_relief`](super::relief::render_relief).
+    Relief,
+    /// Altitudes through a colormap, from its first color at the lowest to
+    /// its last at the highest, see
+    /// [`render_colormap`](super::colormap::render_colormap).
+    Hypsometric,
+    /// The steepness of each cell, from [`slopes`], through a colormap from
+    /// flat ground to the steepest cell.
+    Slope,
+    /// The spread of altitudes around each cell, from
+    /// [`roughness`](super::stats::roughness), through a colormap between two
+    /// percentiles of it, showing noisy terrain and suspiciously smooth
+    /// patches.
+    Roughness,
+    /// How much of the sky each cell sees, from
+    /// [`occlusion`](super::occlusion::occlusion), in grayscale from black
+    /// for none to white for all of it.
+    Occlusion,
+    /// The shadows cast by the terrain with the sun at a given position, from
+    /// [`shadows`](super::shadow::shadows), as a mask from white in sunlight
+    /// to black in shadow.
+    Shadow,
+    /// A tangent-space normal map, for other engines and renderers, see
+    /// [`render_normals`](super::relief::render_normals).
+    Normal,
+}
+
+/// The slope of the altitude grid of size `size` at `(x, y)`, as the change
+/// in altitude per cell along x and y.
+///
+/// Uses central differences, and one-sided ones along the edges of the grid
+/// unless `edges` wraps it.
+pub fn gradient(alt: &[f64], size: Vec2<usize>, x: usize, y: usize, edges: Edges) -> Vec2<f64> {
+    let at = |x: usize, y: usize| alt[y * size.x + x];
+    // The neighbours on either side of `pos`, or `pos` itself past an edge,
+    // with the number of steps between them.
+    let around = |pos: usize, len: usize| {
+        let before = edges.neighbor(pos, -1, len);
+        let after = edges.neighbor(pos, 1, len);
+        let span = before.is_some() as usize + after.is_some() as usize;
+        (before.unwrap_or(pos), after.unwrap_or(pos), span)
+    };
+    let slope = |before: f64, after: f64, span: usize| {
+        if span == 0 {
+            0.0
+        } else {
+            (after - before) / span as f64
+        }
+    };
+    let (left, right, dx) = around(x, size.x);
+    let (down, up, dy) = around(y, size.y);
+    Vec2::new(
+        slope(at(left, y), at(right, y), dx),
+        slope(at(x, down), at(x, up), dy),
+    )
+}
+
+/// The steepness of every cell of the altitude grid of size `size`, as the
+/// magnitude of its [`gradient`], in rows like the altitudes.
+pub fn slopes(alt: &[f64], size: Vec2<usize>, edges: Edges) -> Vec<f64> {
+    (0..size.product())
+        .into_par_iter()
+        .map(|i| gradient(alt, size, i % size.x, i / size.x, edges).magnitude())
+        .collect()
+}
+
+/// Gray level of `alt` in [`render_grayscale`], for a map shaded from `min`
+/// over `range`.
+fn gray_level(alt: f64, min: f64, range: f64) -> u8 {
+    (((alt - min) / range) * 255.0).round() as u8
+}
+
+/// Gray level of `alt` in images rendered by [`render_grayscale`] over `min`
+/// to `max`.
+pub fn gray_at(alt: f64, min: f64, max: f64) -> u8 { gray_level(alt, min, value_range(min, max)) }
+
+/// Renders the altitude grid of size `size` in grayscale, with `min` shown as
+/// black and `max` as white.
+///
+/// Rows are rendered in parallel, straight into the image's buffer.
+pub fn render_grayscale(alt: &[f64], size: Vec2<usize>, min: f64, max: f64) -> RgbImage {
+    let range = value_range(min, max);
+    let mut buf = vec![0; size.product() * 3];
+    buf.par_chunks_mut((size.x * 3).max(1))
+        .zip(alt.par_chunks(size.x.max(1)))
+        .for_each(|(pixels, alt)| {
+            for (pixel, &alt) in pixels.chunks_exact_mut(3).zip(alt) {
+                pixel.fill(gray_level(alt, min, range));
+            }
+        });
+    RgbImage::from_raw(size.x as u32, size.y as u32, buf).expect("Buffer matches the image size")
+}
+
+/// Renders the altitude grid like [`render_grayscale`], but as 16-bit samples
+/// in the same order as the altitudes, with `min` as 0 and `max` as 65535.
+pub fn render_grayscale16(alt: &[f64], min: f64, max: f64) -> Vec<u16> {
+    let range = value_range(min, max);
+    alt.par_iter()
+        .map(|&alt| (((alt - min) / range) * 65535.0).round() as u16)
+        .collect()
+}
+
+/// Brightness of contour lines, relative to the pixels beneath them.
+const CONTOUR_SHADE: f32 = 0.3;
+
+/// Darkens the pixels of `img` along the contour lines at every multiple of
+/// `interval`.
+///
+/// A contour passes between two horizontally or vertically adjacent cells
+/// when `alt - k * interval` changes sign between them for some `k`; the
+/// higher of the two cells is drawn as part of the line, so each contour is
+/// one pixel wide.
+pub fn draw_contours(img: &mut RgbImage, alt: &[f64], interval: f64) {
+    let on_line = contour_cells(alt, img.width() as usize, interval);
+    for (i, pixel) in img.pixels_mut().enumerate() {
+        if on_line[i] {
+            pixel.0 = pixel.0.map(|c| (c as f32 * CONTOUR_SHADE) as u8);
+        }
+    }
+}
+
+/// Darkens the 16-bit samples of a `width` cells wide image, as rendered by
+/// [`render_grayscale16`], along the same contour lines as [`draw_contours`].
+pub fn draw_contours16(samples: &mut [u16], width: usize, alt: &[f64], interval: f64) {
+    let on_line = contour_cells(alt, width, interval);
+    for (sample, on_line) in samples.iter_mut().zip(on_line) {
+        if on_line {
+            *sample = (*sample as f32 * CONTOUR_SHADE) as u16;
+        }
+    }
+}
+
+/// Which cells of the `width` cells wide grid `alt` lie on a contour line; see
+/// [`draw_contours`].
+fn contour_cells(alt: &[f64], width: usize, interval: f64) -> Vec<bool> {
+    let height = alt.len() / width.max(1);
+    let level = |i: usize| (alt[i] / interval).floor();
+    let mut on_line = vec![false; alt.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let neighbors = [
+                (x + 1 < width).then_some(i + 1),
+                (y + 1 < height).then_some(i + width),
+            ];
+            for j in neighbors.into_iter().flatten() {
+                if level(i) != level(j) {
+                    on_line[if alt[i] > alt[j] { i } else { j }] = true;
+                }
+            }
+        }
+    }
+    on_line
+}
+
+/// How hard PNGs are compressed, trading encoding time for file size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PngCompression {
+    /// Fastest to encode, for quick previews.
+    Fast,
+    Default,
+    /// Smallest files, for archiving.
+    #[default]
+    Best,
+}
+
+/// Filter applied to each row of a PNG before it is compressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    #[default]
+    Paeth,
+    /// Picks the best filter for each row, which is slower.
+    Adaptive,
+}
+
+/// Encoder settings of the PNGs written by [`save_png`]; the default gives
+/// the smallest files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PngOptions {
+    pub compression: PngCompression,
+    pub filter: PngFilter,
+}
+
+impl PngOptions {
+    fn encoder<W: Write>(self, writer: W) -> PngEncoder<W> {
+        let compression = match self.compression {
+            PngCompression::Fast => CompressionType::Fast,
+            PngCompression::Default => CompressionType::Default,
+            PngCompression::Best => CompressionType::Best,
+        };
+        let filter = match self.filter {
+            PngFilter::None => FilterType::NoFilter,
+            PngFilter::Sub => FilterType::Sub,
+            PngFilter::Up => FilterType::Up,
+            PngFilter::Avg => FilterType::Avg,
+            PngFilter::Paeth => FilterType::Paeth,
+            PngFilter::Adaptive => FilterType::Adaptive,
+        };
+        PngEncoder::new_with_quality(writer, compression, filter)
+    }
+}
+
+/// Writes `img` to `path` as a PNG encoded with `options`, straight to a
+/// temporary file that only replaces `path` once complete (see
+/// [`write_atomically`]).
+pub fn save_png(img: &RgbImage, path: impl AsRef<Path>, options: PngOptions) -> Result<(), Error> {
+    write_png(
+        path,
+        img.as_raw(),
+        img.width(),
+        img.height(),
+        ExtendedColorType::Rgb8,
+        options,
+    )
+}
+
+/// Writes `img` to `path` as an RGBA PNG, like [`save_png`].
+pub fn save_rgba_png(
+    img: &RgbaImage,
+    path: impl AsRef<Path>,
+    options: PngOptions,
+) -> Result<(), Error> {
+    write_png(
+        path,
+        img.as_raw(),
+        img.width(),
+        img.height(),
+        ExtendedColorType::Rgba8,
+        options,
+    )
+}
+
+fn write_png(
+    path: impl AsRef<Path>,
+    buf: &[u8],
+    width: u32,
+    height: u32,
+    color_type: ExtendedColorType,
+    options: PngOptions,
+) -> Result<(), Error> {
+    write_atomically(path, |writer| {
+        options
+            .encoder(writer)
+            .write_image(buf, width, height, color_type)?;
+        Ok(())
+    })
+}
+
+/// Writes the 16-bit `samples` of an image of `size` pixels to `path` as a
+/// binary PGM (`P5`), atomically like [`save_png`].
+///
+/// PGM is a minimal format that many scientific tools read: a short text
+/// header followed by the samples, big-endian since they exceed 255.
+pub fn save_pgm16(samples: &[u16], size: Vec2<usize>, path: impl AsRef<Path>) -> Result<(), Error> {
+    write_atomically(path, |writer| {
+        write!(writer, "P5\n{} {}\n{}\n", size.x, size.y, u16::MAX)?;
+        for sample in samples {
+            writer.write_all(&sample.to_be_bytes())?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::random_grids;
+    use image::Rgb;
+
+    #[test]
+    fn values_outside_the_range_are_clipped() {
+        let alt = [-300.0, -100.0, 0.0, 300.0, 500.0, f64::NAN];
+        assert_eq!(count_outside(&alt, -100.0, 300.0), 3);
+        let img = render_grayscale(&alt[..4], Vec2::new(2, 2), -100.0, 200.0);
+        let values = img.pixels().map(|p| p[0]).collect::<Vec<_>>();
+        assert_eq!(values, [0, 0, 85, 255]);
+    }
+
+    #[test]
+    fn grayscale_spans_min_to_max() {
+        let alt = [-100.0, 0.0, 50.0, 300.0];
+        let (min, max) = compute_min_max(&alt);
+        assert_eq!((min, max), (-100.0, 300.0));
+        let img = render_grayscale(&alt, Vec2::new(2, 2), min, max);
+        let values = img.pixels().map(|p| p[0]).collect::<Vec<_>>();
+        assert_eq!(values, vec![0, 64, 96, 255]);
+
+        let flat = render_grayscale(&[7.0; 4], Vec2::new(2, 2), 7.0, 7.0);
+        assert!(flat.pixels().all(|p| p[0] == 0));
+    }
+
+    #[test]
+    fn pgm_holds_16_bit_big_endian_samples() {
+        let alt = [-100.0, 0.0, 50.0, 300.0, 300.0, 300.0];
+        let (min, max) = compute_min_max(&alt);
+        let samples = render_grayscale16(&alt, min, max);
+        assert_eq!(samples, vec![0, 16384, 24576, 65535, 65535, 65535]);
+        // The 8-bit rendering is the same normalization, more coarsely
+        // quantized.
+        let img = render_grayscale(&alt, Vec2::new(3, 2), min, max);
+        for (pixel, sample) in img.pixels().zip(&samples) {
+            assert_eq!(pixel[0], (*sample as f64 / 257.0).round() as u8);
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "veloren-heightmap-export-{}.pgm",
+            std::process::id()
+        ));
+        save_pgm16(&samples, Vec2::new(3, 2), &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let header = b"P5\n3 2\n65535\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(&bytes[header.len()..header.len() + 6], &[
+            0, 0, 0x40, 0, 0x60, 0
+        ]);
+        assert_eq!(bytes.len(), header.len() + 12);
+    }
+
+    #[test]
+    fn png_options_only_change_the_encoding() {
+        let alt = (0..64 * 64)
+            .map(|i| ((i % 64) as f64 * 0.2).sin() * 100.0 + (i / 64) as f64)
+            .collect::<Vec<_>>();
+        let (min, max) = compute_min_max(&alt);
+        let img = render_grayscale(&alt, Vec2::new(64, 64), min, max);
+        let path = std::env::temp_dir().join(format!(
+            "veloren-heightmap-png-options-{}.png",
+            std::process::id()
+        ));
+        for compression in [
+            PngCompression::Fast,
+            PngCompression::Default,
+            PngCompression::Best,
+        ] {
+            for filter in [PngFilter::None, PngFilter::Up, PngFilter::Adaptive] {
+                save_png(&img, &path, PngOptions {
+                    compression,
+                    filter,
+                })
+                .unwrap();
+                let decoded = image::open(&path).unwrap().to_rgb8();
+                assert!(decoded == img, "{:?} {:?}", compression, filter);
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn gradients_are_one_sided_at_edges_unless_wrapped() {
+        let size = Vec2::new(4, 1);
+        let alt = [0.0, 10.0, 30.0, 60.0];
+        let slopes = |edges| {
+            (0..4)
+                .map(|x| gradient(&alt, size, x, 0, edges).x)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(slopes(Edges::Clamp), [10.0, 15.0, 25.0, 30.0]);
+        assert_eq!(slopes(Edges::Wrap), [-25.0, 15.0, 25.0, -15.0]);
+        assert_eq!(gradient(&alt, size, 0, 0, Edges::Wrap).y, 0.0);
+        assert_eq!(super::slopes(&alt, size, Edges::Wrap), [
+            25.0, 15.0, 25.0, 15.0
+        ]);
+    }
+
+    #[test]
+    fn contours_follow_level_crossings() {
+        // A ramp rising by 3 per column crosses multiples of 10 between
+        // columns 3 and 4 (9 -> 12) and 6 and 7 (18 -> 21).
+        let alt = (0..8 * 2).map(|i| (i % 8) as f64 * 3.0).collect::<Vec<_>>();
+        let mut img = RgbImage::from_pixel(8, 2, Rgb([200, 200, 200]));
+        draw_contours(&mut img, &alt, 10.0);
+        for y in 0..2 {
+            let row = (0..8).map(|x| img.get_pixel(x, y)[0]).collect::<Vec<_>>();
+            assert_eq!(row, vec![200, 200, 200, 200, 60, 200, 200, 60]);
+        }
+
+        let mut samples = vec![20000; 16];
+        draw_contours16(&mut samples, 8, &alt, 10.0);
+        assert_eq!(&samples[8..], &[
+            20000, 20000, 20000, 20000, 6000, 20000, 20000, 6000
+        ]);
+    }
+
+    #[test]
+    fn extrema_are_the_first_lowest_and_highest_cells() {
+        assert_eq!(altitude_extrema(&[]), None);
+        assert_eq!(altitude_extrema(&[f64::NAN; 3]), None);
+        assert_eq!(
+            altitude_extrema(&[f64::NAN, 3.0, -1.0, 5.0, -1.0, 5.0]),
+            Some((2, -1.0, 3, 5.0))
+        );
+        // Across chunks, ties still go to the first cell.
+        let mut alt = vec![0.0; CHUNK_CELLS * 3];
+        alt[CHUNK_CELLS * 2 + 7] = 9.0;
+        alt[CHUNK_CELLS + 3] = 9.0;
+        alt[CHUNK_CELLS * 2] = -4.0;
+        assert_eq!(
+            altitude_extrema(&alt),
+            Some((CHUNK_CELLS * 2, -4.0, CHUNK_CELLS + 3, 9.0))
+        );
+        assert_eq!(altitude_extrema(&[-0.0, 0.0]), Some((0, -0.0, 0, -0.0)));
+    }
+
+    #[test]
+    fn parallel_scans_match_sequential_ones() {
+        fn sequential_min_max(alt: &[f64]) -> (f64, f64) {
+            let mut min = f64::MAX;
+            let mut max = f64::MIN;
+            for &val in alt {
+                if val < min {
+                    min = val;
+                }
+                if val > max {
+                    max = val;
+                }
+            }
+            (min, max)
+        }
+
+        fn sequential_render(alt: &[f64], size: Vec2<usize>, min: f64, max: f64) -> RgbImage {
+            let range = if max - min == 0.0 { 1.0 } else { max - min };
+            RgbImage::from_fn(size.x as u32, size.y as u32, |x, y| {
+                let alt = alt[y as usize * size.x + x as usize];
+                let value = (((alt - min) / range) * 255.0).round() as u8;
+                Rgb([value, value, value])
+            })
+        }
+
+        // Grids spanning several chunks, with NaNs and signed zeros whose
+        // order matters.
+        let large = Vec2::new(512, 300);
+        let mut grids = random_grids(20).collect::<Vec<_>>();
+        grids.push((
+            large,
+            (0..large.product())
+                .map(|i| match i % 7919 {
+                    0 => f64::NAN,
+                    1 => -0.0,
+                    2 => 0.0,
+                    _ => ((i * 31) % 1000) as f64 - 500.0,
+                })
+                .collect(),
+        ));
+        grids.push((Vec2::new(4, 2), vec![
+            0.0, -0.0, 0.0, -0.0, 0.0, 0.0, -0.0, 0.0,
+        ]));
+
+        for (size, alt) in grids {
+            let (min, max) = compute_min_max(&alt);
+            let (seq_min, seq_max) = sequential_min_max(&alt);
+            assert_eq!(min.to_bits(), seq_min.to_bits());
+            assert_eq!(max.to_bits(), seq_max.to_bits());
+            assert_eq!(
+                render_grayscale(&alt, size, min, max),
+                sequential_render(&alt, size, min, max)
+            );
+        }
+    }
+}
@@ -0,0 +1,626 @@
+//! Filters over altitude grids.
+
+use serde::{Deserialize, Serialize};
+use vek::*;
+
+/// How filters treat the cells beyond the edges of a grid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edges {
+    /// There are none: cells along the border only see their neighbours
+    /// inside the grid.
+    #[default]
+    Clamp,
+    /// The grid is a torus: cells along each edge neighbour those along the
+    /// opposite one, so that filtering leaves no seam where the world wraps.
+    Wrap,
+}
+
+impl Edges {
+    /// [`Edges::Wrap`] if `wrap` is set, as by a `--wrap` flag, and
+    /// [`Edges::Clamp`] otherwise.
+    pub fn from_wrap(wrap: bool) -> Self { if wrap { Edges::Wrap } else { Edges::Clamp } }
+
+    /// Index of the cell `delta` cells from `pos` along an axis of `len`
+    /// cells, or `None` if it lies beyond the edge.
+    #[inline]
+    pub fn neighbor(self, pos: usize, delta: isize, len: usize) -> Option<usize> {
+        let target = pos as isize + delta;
+        match self {
+            Edges::Clamp => (0..len as isize)
+                .contains(&target)
+                .then_some(target as usize),
+            Edges::Wrap => Some(target.rem_euclid(len as isize) as usize),
+        }
+    }
+}
+
+/// Applies a single iteration of a 3x3 box filter to the altitude grid.
+///
+/// Cells along the border are averaged over those of their neighbours that
+/// lie inside the grid, unless `edges` wraps it.
+pub fn smooth_altitudes(alt: &[f64], width: u32, height: u32, edges: Edges) -> Vec<f64> {
+    let w = width as usize;
+    let h = height as usize;
+    let mut out = alt.to_vec();
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = 0.0;
+            let mut count = 0.0;
+            for dy in -1..=1 {
+                let Some(ny) = edges.neighbor(y, dy, h) else {
+                    continue;
+                };
+                for dx in -1..=1 {
+                    if let Some(nx) = edges.neighbor(x, dx, w) {
+                        sum += alt[ny * w + nx];
+                        count += 1.0;
+                    }
+                }
+            }
+            out[y * w + x] = sum / count;
+        }
+    }
+    out
+}
+
+/// Smooths the altitude grid of size `size` with `iterations` passes of the
+/// box filter of [`smooth_altitudes`], but only within `radius` cells of
+/// `center`, returning how many cells changed.
+///
+/// The inner half of the circle takes the smoothed altitudes, and the outer
+/// half fades back to the original ones, so that the smoothed area has no
+/// edge.  Only the square around the circle is filtered, with enough margin
+/// that the smoothed altitudes are the same as if the whole grid had been.
+/// The circle is clipped to the map, unless `edges` wraps it around to the
+/// opposite edges, where the whole map is filtered if the square would
+/// overlap itself.
+pub fn smooth_region(
+    alt: &mut [f64],
+    size: Vec2<usize>,
+    center: Vec2<u32>,
+    radius: u32,
+    iterations: u32,
+    edges: Edges,
+) -> usize {
+    if radius == 0 || iterations == 0 || size.product() == 0 {
+        return 0;
+    }
+    let reach = radius as usize + iterations as usize;
+    // The first cell of the square, which may lie past the west or south
+    // edge when wrapping, its size and how its own edges are filtered.
+    let (lo, window, window_edges) = match edges {
+        Edges::Clamp => {
+            let center = center.as_::<usize>();
+            let lo = center.map(|e| e.saturating_sub(reach));
+            let hi = center.map2(size, |e, len| (e + reach + 1).min(len));
+            if lo.x >= hi.x || lo.y >= hi.y {
+                return 0;
+            }
+            (lo.as_::<isize>(), hi - lo, Edges::Clamp)
+        },
+        Edges::Wrap if size.map(|len| 2 * reach < len).reduce_and() => (
+            center.as_::<isize>() - reach as isize,
+            Vec2::broadcast(2 * reach + 1),
+            Edges::Clamp,
+        ),
+        Edges::Wrap => (Vec2::zero(), size, Edges::Wrap),
+    };
+    let cell = |x: usize, y: usize| {
+        (lo + Vec2::new(x, y).as_::<isize>())
+            .map2(size, |e, len| e.rem_euclid(len as isize) as usize)
+    };
+    let mut smoothed = (0..window.y)
+        .flat_map(|y| (0..window.x).map(move |x| cell(x, y)))
+        .map(|pos| alt[pos.y * size.x + pos.x])
+        .collect::<Vec<_>>();
+    for _ in 0..iterations {
+        smoothed = smooth_altitudes(&smoothed, window.x as u32, window.y as u32, window_edges);
+    }
+
+    // Distance along an axis of length `len`, around it if it wraps.
+    let distance = |pos: usize, center: u32, len: usize| match edges {
+        Edges::Clamp => pos.abs_diff(center as usize),
+        Edges::Wrap => {
+            let d = (pos as isize - center as isize).rem_euclid(len as isize) as usize;
+            d.min(len - d)
+        },
+    };
+    let mut changed = 0;
+    for y in 0..window.y {
+        for x in 0..window.x {
+            let pos = cell(x, y);
+            let d = Vec2::new(
+                distance(pos.x, center.x, size.x),
+                distance(pos.y, center.y, size.y),
+            )
+            .as_::<f64>()
+            .magnitude()
+                / radius as f64;
+            let weight = if d <= 0.5 {
+                1.0
+            } else if d < 1.0 {
+                let t = 2.0 * (1.0 - d);
+                t * t * (3.0 - 2.0 * t)
+            } else {
+                continue;
+            };
+            let i = pos.y * size.x + pos.x;
+            let target = smoothed[y * window.x + x];
+            let blended = if weight >= 1.0 {
+                target
+            } else {
+                alt[i] + (target - alt[i]) * weight
+            };
+            if blended != alt[i] {
+                alt[i] = blended;
+                changed += 1;
+            }
+        }
+    }
+    changed
+}
+
+/// Unnormalized Gaussian weight of a squared distance `d2`; a distance of zero
+/// always has weight 1, even for a zero `sigma`.
+#[inline]
+fn gaussian_weight(d2: f64, sigma: f64) -> f64 {
+    if d2 == 0.0 {
+        1.0
+    } else {
+        (-d2 / (2.0 * sigma * sigma)).exp()
+    }
+}
+
+/// Radius beyond which Gaussian weights of standard deviation `sigma` are
+/// negligible.
+fn kernel_radius(sigma: f64) -> usize { (3.0 * sigma.max(0.0)).ceil() as usize }
+
+/// Convolves the `len` cells starting at `start`, `stride` apart, with the
+/// symmetric `kernel`, renormalizing it where it extends past either end
+/// unless `edges` wraps the line.
+fn blur_line(
+    src: &[f64],
+    out: &mut [f64],
+    (start, stride, len): (usize, usize, usize),
+    kernel: &[f64],
+    edges: Edges,
+) {
+    let radius = (kernel.len() / 2) as isize;
+    for i in 0..len {
+        let (sum, total) = (-radius..=radius)
+            .zip(kernel)
+            .filter_map(|(delta, weight)| Some((edges.neighbor(i, delta, len)?, weight)))
+            .fold((0.0, 0.0), |(sum, total), (j, weight)| {
+                (sum + src[start + j * stride] * weight, total + weight)
+            });
+        out[start + i * stride] = sum / total;
+    }
+}
+
+/// Gaussian kernel of standard deviation `sigma` cells, for
+/// [`convolve_separable`]; a zero `sigma` gives the identity kernel.
+pub fn gaussian_kernel(sigma: f64) -> Vec<f64> {
+    let radius = kernel_radius(sigma) as isize;
+    (-radius..=radius)
+        .map(|d| gaussian_weight((d * d) as f64, sigma))
+        .collect()
+}
+
+/// Convolves the altitude grid with the symmetric, odd-length `kernel_x`
+/// along rows and then `kernel_y` along columns.  The kernels needn't be
+/// normalized.
+///
+/// Near the border, the kernels are renormalized over the cells inside the
+/// grid, unless `edges` wraps it.
+pub fn convolve_separable(
+    alt: &[f64],
+    width: u32,
+    height: u32,
+    kernel_x: &[f64],
+    kernel_y: &[f64],
+    edges: Edges,
+) -> Vec<f64> {
+    let w = width as usize;
+    let h = height as usize;
+    let mut rows = vec![0.0; alt.len()];
+    for y in 0..h {
+        blur_line(alt, &mut rows, (y * w, 1, w), kernel_x, edges);
+    }
+    let mut out = vec![0.0; alt.len()];
+    for x in 0..w {
+        blur_line(&rows, &mut out, (x, w, h), kernel_y, edges);
+    }
+    out
+}
+
+/// Blurs the altitude grid with a Gaussian kernel of standard deviation
+/// `sigma` cells.
+///
+/// Near the border, the kernel is renormalized over the cells inside the grid,
+/// unless `edges` wraps it.
+pub fn gaussian_blur(alt: &[f64], width: u32, height: u32, sigma: f64, edges: Edges) -> Vec<f64> {
+    // The kernel is separable, so blur rows and then columns.
+    let kernel = gaussian_kernel(sigma);
+    convolve_separable(alt, width, height, &kernel, &kernel, edges)
+}
+
+/// Edge-preserving smoothing of the altitude grid.
+///
+/// Each cell becomes a weighted average of its neighbours, where the weight of
+/// a neighbour falls off both with its distance (with standard deviation
+/// `spatial_sigma` cells) and with its altitude difference from the cell
+/// (with standard deviation `range_sigma`).  Noise much smaller than
+/// `range_sigma` is smoothed away like with [`gaussian_blur`], while steps
+/// much larger than it, such as ridges and cliffs, stay sharp.
+///
+/// Unlike the Gaussian blur, this is not separable, so its cost grows with the
+/// square of `spatial_sigma`.  `edges` is treated like by the Gaussian blur.
+pub fn smooth_bilateral(
+    alt: &[f64],
+    width: u32,
+    height: u32,
+    spatial_sigma: f64,
+    range_sigma: f64,
+    edges: Edges,
+) -> Vec<f64> {
+    let w = width as usize;
+    let h = height as usize;
+    let radius = kernel_radius(spatial_sigma) as isize;
+    let side = 2 * radius as usize + 1;
+    let spatial = (-radius..=radius)
+        .flat_map(|dy| (-radius..=radius).map(move |dx| (dx * dx + dy * dy) as f64))
+        .map(|d2| gaussian_weight(d2, spatial_sigma))
+        .collect::<Vec<_>>();
+
+    let mut out = vec![0.0; alt.len()];
+    for y in 0..h {
+        for x in 0..w {
+            let center = alt[y * w + x];
+            let mut sum = 0.0;
+            let mut total = 0.0;
+            for dy in -radius..=radius {
+                let Some(ny) = edges.neighbor(y, dy, h) else {
+                    continue;
+                };
+                for dx in -radius..=radius {
+                    let Some(nx) = edges.neighbor(x, dx, w) else {
+                        continue;
+                    };
+                    let neighbor = alt[ny * w + nx];
+                    let diff = neighbor - center;
+                    let weight = spatial[(dy + radius) as usize * side + (dx + radius) as usize]
+                        * gaussian_weight(diff * diff, range_sigma);
+                    sum += neighbor * weight;
+                    total += weight;
+                }
+            }
+            out[y * w + x] = sum / total;
+        }
+    }
+    out
+}
+
+/// Settings of [`smooth_coastline`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoastlineSmoothing {
+    /// Altitude above which cells are land.
+    pub sea_level: f64,
+    /// Number of passes of the majority rule.
+    pub iterations: u32,
+    /// Distance from the sea level at which the cells that changed sides are
+    /// left, so that they don't sit exactly on the coastline.
+    pub nudge: f64,
+    /// Whether cells along the edges neighbour those along the opposite ones.
+    pub edges: Edges,
+}
+
+/// Cleans up the jagged, speckled coastline of the altitude grid of size
+/// `size`, returning how many cells changed sides.
+///
+/// The land (above [`sea_level`](CoastlineSmoothing::sea_level)) and water
+/// of the grid are run through a majority rule: on each pass, a cell whose
+/// neighbours, of the eight around it, are mostly of the other kind joins
+/// them, as lone islets and lakes and the tips of thin spits and inlets do.
+/// The passes stop early once nothing changes.  Only the cells that ended up
+/// on the other side are then moved, to
+/// [`nudge`](CoastlineSmoothing::nudge) across the sea level, so that the
+/// rest of the terrain is left as it was.
+pub fn smooth_coastline(alt: &mut [f64], size: Vec2<usize>, params: &CoastlineSmoothing) -> usize {
+    let original = alt
+        .iter()
+        .map(|&alt| alt > params.sea_level)
+        .collect::<Vec<_>>();
+    let mut land = original.clone();
+    for _ in 0..params.iterations {
+        let next = (0..size.product())
+            .map(|i| {
+                let (x, y) = (i % size.x, i / size.x);
+                let (mut neighbors, mut land_neighbors) = (0, 0);
+                for dy in -1..=1 {
+                    let Some(ny) = params.edges.neighbor(y, dy, size.y) else {
+                        continue;
+                    };
+                    for dx in -1..=1 {
+                        let Some(nx) = params.edges.neighbor(x, dx, size.x) else {
+                            continue;
+                        };
+                        if (dx, dy) != (0, 0) {
+                            neighbors += 1;
+                            land_neighbors += land[ny * size.x + nx] as usize;
+                        }
+                    }
+                }
+                let others = if land[i] {
+                    neighbors - land_neighbors
+                } else {
+                    land_neighbors
+                };
+                land[i] != (others * 2 > neighbors)
+            })
+            .collect::<Vec<_>>();
+        if next == land {
+            break;
+        }
+        land = next;
+    }
+
+    let mut changed = 0;
+    for ((alt, &was_land), &is_land) in alt.iter_mut().zip(&original).zip(&land) {
+        if was_land != is_land {
+            *alt = if is_land {
+                params.sea_level + params.nudge
+            } else {
+                params.sea_level - params.nudge
+            };
+            changed += 1;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::random_grids;
+
+    /// A 32x32 grid with a 100 high cliff at x = 16, plus some low noise.
+    fn noisy_cliff() -> Vec<f64> {
+        (0..32 * 32)
+            .map(|i| {
+                let (x, y) = (i % 32, i / 32);
+                let noise = ((x * 7 + y * 13) % 5) as f64 - 2.0;
+                if x < 16 { noise } else { 100.0 + noise }
+            })
+            .collect()
+    }
+
+    /// Mean absolute difference between horizontally adjacent cells, over
+    /// the flats well away from the cliff.
+    fn roughness(alt: &[f64]) -> f64 {
+        let diffs = (0..32 * 32)
+            .filter(|i| matches!(i % 32, 0..8 | 24..31))
+            .map(|i| (alt[i + 1] - alt[i]).abs())
+            .collect::<Vec<_>>();
+        diffs.iter().sum::<f64>() / diffs.len() as f64
+    }
+
+    /// Smallest altitude jump across the cliff.
+    fn cliff_height(alt: &[f64]) -> f64 {
+        (0..32)
+            .map(|y| alt[y * 32 + 16] - alt[y * 32 + 15])
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    #[test]
+    fn bilateral_preserves_edges_where_gaussian_does_not() {
+        let alt = noisy_cliff();
+        let gaussian = gaussian_blur(&alt, 32, 32, 2.0, Edges::Clamp);
+        let bilateral = smooth_bilateral(&alt, 32, 32, 2.0, 10.0, Edges::Clamp);
+
+        // Both flatten the noise...
+        assert!(roughness(&gaussian) < roughness(&alt) / 2.0);
+        assert!(roughness(&bilateral) < roughness(&alt) / 2.0);
+        // ...but only the bilateral filter keeps the cliff.
+        assert!(cliff_height(&alt) > 95.0);
+        assert!(cliff_height(&bilateral) > 95.0);
+        assert!(cliff_height(&gaussian) < 40.0);
+    }
+
+    #[test]
+    fn filters_keep_constant_grids_constant() {
+        let alt = vec![42.0; 8 * 4];
+        for edges in [Edges::Clamp, Edges::Wrap] {
+            for out in [
+                smooth_altitudes(&alt, 8, 4, edges),
+                gaussian_blur(&alt, 8, 4, 1.5, edges),
+                smooth_bilateral(&alt, 8, 4, 1.5, 1.0, edges),
+            ] {
+                assert!(out.iter().all(|e| (e - 42.0).abs() < 1e-9));
+            }
+        }
+    }
+
+    #[test]
+    fn wrapped_filters_leave_no_seam() {
+        // A hill in the middle of the map, and the same hill rolled by half
+        // the map so that it straddles the edges.
+        let (w, h) = (16, 8);
+        let hill = |x: usize, y: usize| {
+            let d2 = (x as f64 - 8.0).powi(2) + (y as f64 - 4.0).powi(2);
+            100.0 * (-d2 / 4.0).exp() + ((x * 7 + y * 13) % 5) as f64
+        };
+        let roll = |grid: &[f64]| {
+            (0..w * h)
+                .map(|i| grid[((i / w + h / 2) % h) * w + (i % w + w / 2) % w])
+                .collect::<Vec<_>>()
+        };
+        let centered = (0..w * h).map(|i| hill(i % w, i / w)).collect::<Vec<_>>();
+        let straddling = roll(&centered);
+        assert_ne!(straddling, centered);
+
+        type Filter = fn(&[f64], Edges) -> Vec<f64>;
+        let filters: [Filter; 3] = [
+            |alt, edges| smooth_altitudes(alt, 16, 8, edges),
+            |alt, edges| gaussian_blur(alt, 16, 8, 1.5, edges),
+            |alt, edges| smooth_bilateral(alt, 16, 8, 1.5, 30.0, edges),
+        ];
+        for filter in filters {
+            let expected = roll(&filter(&centered, Edges::Wrap));
+            assert_eq!(filter(&straddling, Edges::Wrap), expected);
+            assert_ne!(filter(&straddling, Edges::Clamp), expected);
+        }
+    }
+
+    #[test]
+    fn region_smoothing_stays_inside_its_circle() {
+        let original = noisy_cliff();
+        let size = Vec2::new(32, 32);
+        let mut full = original.clone();
+        for _ in 0..3 {
+            full = smooth_altitudes(&full, 32, 32, Edges::Clamp);
+        }
+        let mut alt = original.clone();
+        let changed = smooth_region(&mut alt, size, Vec2::new(14, 12), 6, 3, Edges::Clamp);
+        assert!(changed > 0 && changed < 120, "{}", changed);
+        for (i, (alt, (original, full))) in alt.iter().zip(original.iter().zip(&full)).enumerate() {
+            let d = Vec2::new(i % 32, i / 32)
+                .as_::<f64>()
+                .distance(Vec2::new(14.0, 12.0));
+            if d >= 6.0 {
+                assert_eq!(alt, original);
+            } else if d <= 3.0 {
+                assert_eq!(alt, full);
+            }
+        }
+
+        // Circles are clipped to the map, and don't need to overlap it.
+        let mut alt = original.clone();
+        assert!(smooth_region(&mut alt, size, Vec2::zero(), 4, 2, Edges::Clamp) > 0);
+        let twice = smooth_altitudes(
+            &smooth_altitudes(&original, 32, 32, Edges::Clamp),
+            32,
+            32,
+            Edges::Clamp,
+        );
+        assert_eq!(alt[0], twice[0]);
+        assert_eq!(
+            smooth_region(&mut alt, size, Vec2::new(40, 40), 4, 2, Edges::Clamp),
+            0
+        );
+        assert_eq!(
+            smooth_region(&mut alt, size, Vec2::new(8, 8), 4, 0, Edges::Clamp),
+            0
+        );
+    }
+
+    #[test]
+    fn wrapped_region_smoothing_reaches_across_the_edges() {
+        let original = noisy_cliff();
+        let size = Vec2::new(32, 32);
+        let mut wrapped = original.clone();
+        for _ in 0..3 {
+            wrapped = smooth_altitudes(&wrapped, 32, 32, Edges::Wrap);
+        }
+        // Around the corner: the circle covers all four corners.
+        let mut alt = original.clone();
+        assert!(smooth_region(&mut alt, size, Vec2::new(31, 0), 6, 3, Edges::Wrap) > 0);
+        for (i, (alt, (original, wrapped))) in
+            alt.iter().zip(original.iter().zip(&wrapped)).enumerate()
+        {
+            let d = Vec2::new(i % 32, i / 32)
+                .map2(Vec2::new(31, 0), |e: usize, c: usize| {
+                    let d = e.abs_diff(c);
+                    d.min(32 - d)
+                })
+                .as_::<f64>()
+                .magnitude();
+            if d >= 6.0 {
+                assert_eq!(alt, original);
+            } else if d <= 3.0 {
+                assert_eq!(alt, wrapped, "cell {}", i);
+            }
+        }
+
+        // A circle too large for its square to fit smooths the whole map.
+        let mut alt = original.clone();
+        smooth_region(&mut alt, size, Vec2::new(16, 16), 50, 3, Edges::Wrap);
+        assert_eq!(alt, wrapped);
+    }
+
+    #[test]
+    fn zero_sigma_is_identity() {
+        let alt = noisy_cliff();
+        assert_eq!(gaussian_blur(&alt, 32, 32, 0.0, Edges::Clamp), alt);
+        assert_eq!(smooth_bilateral(&alt, 32, 32, 0.0, 0.0, Edges::Clamp), alt);
+    }
+
+    #[test]
+    fn filters_stay_within_input_range() {
+        for (case, (size, alt)) in random_grids(200).enumerate() {
+            let (w, h) = (size.x as u32, size.y as u32);
+            let sigma = 0.3 + (case % 8) as f64 * 0.5;
+            let min = alt.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = alt.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let eps = 1e-9 * (max - min).max(1.0);
+            let edges = [Edges::Clamp, Edges::Wrap][case % 2];
+            for (name, out) in [
+                ("box", smooth_altitudes(&alt, w, h, edges)),
+                ("gaussian", gaussian_blur(&alt, w, h, sigma, edges)),
+                (
+                    "bilateral",
+                    smooth_bilateral(&alt, w, h, sigma, 100.0, edges),
+                ),
+            ] {
+                assert_eq!(out.len(), alt.len());
+                assert!(
+                    out.iter().all(|e| (min - eps..=max + eps).contains(e)),
+                    "{} filter left [{}, {}] on case {} ({}x{})",
+                    name,
+                    min,
+                    max,
+                    case,
+                    w,
+                    h
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn coastline_smoothing_removes_specks_but_keeps_the_coast() {
+        // Sea to the west and land to the east, with a lone islet and a lone
+        // lake.
+        let size = Vec2::new(16, 16);
+        let mut alt = (0..size.product())
+            .map(|i| if i % 16 < 8 { -20.0 } else { 30.0 })
+            .collect::<Vec<_>>();
+        alt[4 * 16 + 3] = 5.0;
+        alt[10 * 16 + 12] = -3.0;
+        let before = alt.clone();
+        let params = CoastlineSmoothing {
+            sea_level: 0.0,
+            iterations: 3,
+            nudge: 1.0,
+            edges: Edges::Clamp,
+        };
+        assert_eq!(smooth_coastline(&mut alt, size, &params), 2);
+        assert_eq!(alt[4 * 16 + 3], -1.0);
+        assert_eq!(alt[10 * 16 + 12], 1.0);
+        // The straight coast and the rest of the terrain are untouched.
+        for i in (0..size.product()).filter(|&i| i != 4 * 16 + 3 && i != 10 * 16 + 12) {
+            assert_eq!(alt[i], before[i], "{}", i);
+        }
+
+        // Without passes, nothing changes.
+        let mut speckled = before.clone();
+        assert_eq!(
+            smooth_coastline(&mut speckled, size, &CoastlineSmoothing {
+                iterations: 0,
+                ..params
+            }),
+            0
+        );
+        assert_eq!(speckled, before);
+    }
+}
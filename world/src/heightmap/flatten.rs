@@ -0,0 +1,368 @@
+//! Forcing parts of a map to a given altitude.
+
+use super::{Error, map_size, mask::Mask};
+use crate::sim::ModernMap;
+use std::path::Path;
+use vek::*;
+
+/// Moves each cell of `map` towards `target` by its weight in `mask`: cells
+/// with weight 1 end up exactly at `target`, and cells with weight 0 are left
+/// untouched.  Basement altitudes move by the same amount as the altitude
+/// above them.
+///
+/// Returns the number of cells that were changed.
+pub fn flatten_masked(map: &mut ModernMap, mask: &Mask, target: f64) -> Result<usize, Error> {
+    mask.check_size(map_size(map))?;
+    let mut changed = 0;
+    for (i, &weight) in mask.values.iter().enumerate() {
+        if weight <= 0.0 {
+            continue;
+        }
+        let alt = map.alt[i];
+        let new_alt = if weight >= 1.0 {
+            target
+        } else {
+            alt + (target - alt) * weight
+        };
+        map.alt[i] = new_alt;
+        map.basement[i] += new_alt - alt;
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+/// Shape of the area flattened for a [`Site`], centered on it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SiteShape {
+    Disc { radius: f64 },
+    Rect { size: Vec2<f64> },
+}
+
+/// An area to flatten, such as the site of a settlement, in cell coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Site {
+    pub center: Vec2<f64>,
+    pub shape: SiteShape,
+}
+
+impl Site {
+    /// Distance from the cell at `pos` to the edge of the site, which is 0 or
+    /// negative inside it.
+    fn distance(&self, pos: Vec2<f64>) -> f64 {
+        let offset = pos - self.center;
+        match self.shape {
+            SiteShape::Disc { radius } => offset.magnitude() - radius,
+            SiteShape::Rect { size } => {
+                let outside = offset.map2(size, |o, s| o.abs() - s / 2.0);
+                outside.x.max(outside.y)
+            },
+        }
+    }
+
+    /// Largest distance from the center to a cell of the site, along either
+    /// axis.
+    fn extent(&self) -> Vec2<f64> {
+        match self.shape {
+            SiteShape::Disc { radius } => Vec2::broadcast(radius),
+            SiteShape::Rect { size } => size / 2.0,
+        }
+    }
+}
+
+/// Parses a list of sites, one per line: `x, y, radius` for a disc, or `x,
+/// y, width, height` for a rectangle.  Blank lines and lines starting with `#`
+/// are skipped.
+pub fn parse_sites(text: &str) -> Result<Vec<Site>, Error> {
+    let mut sites = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |reason: &str| Error::SiteList(format!("line {}: {}", i + 1, reason));
+        let fields = line
+            .split(',')
+            .map(|field| field.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| error(&e.to_string()))?;
+        if fields.iter().any(|field| !field.is_finite()) {
+            return Err(error("values must be finite"));
+        }
+        let (center, shape) = match fields[..] {
+            [x, y, radius] if radius >= 0.0 => (Vec2::new(x, y), SiteShape::Disc { radius }),
+            [x, y, width, height] if width >= 0.0 && height >= 0.0 => {
+                (Vec2::new(x, y), SiteShape::Rect {
+                    size: Vec2::new(width, height),
+                })
+            },
+            [_, _, _] | [_, _, _, _] => return Err(error("sizes can't be negative")),
+            _ => return Err(error("expected x, y and a radius, or a width and height")),
+        };
+        sites.push(Site { center, shape });
+    }
+    Ok(sites)
+}
+
+/// Reads the list of sites at `path`; see [`parse_sites`].
+pub fn load_sites(path: impl AsRef<Path>) -> Result<Vec<Site>, Error> {
+    parse_sites(&std::fs::read_to_string(path)?)
+}
+
+/// What flattening a site did, as returned by [`flatten_sites`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlattenedSite {
+    /// Mean altitude of the cells of the site, which it was flattened to.
+    pub target: f64,
+    /// Number of cells changed, including those in the falloff ring.
+    pub changed: usize,
+}
+
+/// Flattens each of `sites` in turn to the mean altitude of its cells that
+/// lie on the map, blending back into the surrounding terrain over a ring
+/// `falloff` cells wide around it.
+///
+/// Later sites see the result of earlier ones, so where sites overlap the
+/// later one wins within its own area, and falloff rings blend with what is
+/// already there.  Sites are clipped to the map; one with no cells on it is
+/// left out, and reported as `None`.  The basement is lowered wherever it
+/// would end up above the flattened altitude.
+pub fn flatten_sites(
+    map: &mut ModernMap,
+    sites: &[Site],
+    falloff: f64,
+) -> Vec<Option<FlattenedSite>> {
+    let size = map_size(map);
+    let falloff = falloff.max(0.0);
+    let weight = |site: &Site, pos: Vec2<f64>| {
+        let d = site.distance(pos);
+        if d <= 0.0 {
+            1.0
+        } else if d >= falloff {
+            0.0
+        } else {
+            let t = 1.0 - d / falloff;
+            t * t * (3.0 - 2.0 * t)
+        }
+    };
+    sites
+        .iter()
+        .map(|site| {
+            // The cells within reach of the site, clipped to the map.
+            let reach = site.extent() + falloff;
+            let lo = (site.center - reach).map(|e| e.floor().max(0.0) as usize);
+            let hi = (site.center + reach).map2(size, |e, len| {
+                (e.ceil() + 1.0).clamp(0.0, len as f64) as usize
+            });
+            let cells = || {
+                (lo.y..hi.y.max(lo.y))
+                    .flat_map(move |y| (lo.x..hi.x.max(lo.x)).map(move |x| Vec2::new(x, y)))
+            };
+
+            let (sum, count) = cells()
+                .filter(|pos| site.distance(pos.as_()) <= 0.0)
+                .fold((0.0, 0), |(sum, count), pos| {
+                    (sum + map.alt[pos.y * size.x + pos.x], count + 1)
+                });
+            if count == 0 {
+                return None;
+            }
+            let target = sum / count as f64;
+            let mut changed = 0;
+            for pos in cells() {
+                let weight = weight(site, pos.as_());
+                if weight <= 0.0 {
+                    continue;
+                }
+                let i = pos.y * size.x + pos.x;
+                let alt = map.alt[i];
+                map.alt[i] = if weight >= 1.0 {
+                    target
+                } else {
+                    alt + (target - alt) * weight
+                };
+                map.basement[i] = map.basement[i].min(map.alt[i]);
+                changed += 1;
+            }
+            Some(FlattenedSite { target, changed })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+
+    #[test]
+    fn black_regions_are_bit_identical() {
+        let alt = |x: usize, y: usize| (x as f64 * 0.7).sin() * 100.0 - y as f64 / 3.0;
+        let original = test_map(Vec2::new(4, 4), alt);
+        let mut map = test_map(Vec2::new(4, 4), alt);
+        // -0.0 would become +0.0 if black cells were touched at all.
+        map.alt[0] = -0.0;
+        let mut mask = Mask {
+            size: Vec2::new(16, 16),
+            values: vec![0.0; 256],
+        };
+        for y in 6..10 {
+            for x in 6..10 {
+                mask.values[y * 16 + x] = 1.0;
+            }
+        }
+        mask.values[4 * 16 + 3] = 0.5;
+        let mask = mask.feathered(2.0);
+
+        flatten_masked(&mut map, &mask, 42.0).unwrap();
+        for i in 0..256 {
+            let (x, y) = (i % 16, i / 16);
+            if mask.values[i] == 0.0 {
+                let expected = if i == 0 { -0.0f64 } else { original.alt[i] };
+                assert_eq!(map.alt[i].to_bits(), expected.to_bits(), "({}, {})", x, y);
+                assert_eq!(map.basement[i].to_bits(), original.basement[i].to_bits());
+            } else if (6..10).contains(&x) && (6..10).contains(&y) {
+                assert_eq!(map.alt[i], 42.0);
+            } else {
+                let lo = original.alt[i].min(42.0);
+                let hi = original.alt[i].max(42.0);
+                assert!(map.alt[i] >= lo && map.alt[i] <= hi);
+            }
+        }
+        // The feathered band around the square, and the gray cell.
+        assert!(mask.values[8 * 16 + 5] > 0.0 && mask.values[8 * 16 + 5] < 1.0);
+        assert_eq!(mask.values[8 * 16 + 3], 0.0);
+        let gray = 4 * 16 + 3;
+        let expected = original.alt[gray] + (42.0 - original.alt[gray]) * 0.5;
+        assert!((map.alt[gray] - expected).abs() < 1e-9);
+    }
+
+    fn disc(x: f64, y: f64, radius: f64) -> Site {
+        Site {
+            center: Vec2::new(x, y),
+            shape: SiteShape::Disc { radius },
+        }
+    }
+
+    #[test]
+    fn sites_are_flattened_to_their_mean() {
+        let original = test_map(Vec2::new(4, 4), |x, y| x as f64 * 10.0 + y as f64 * 0.5);
+        let mut map = test_map(Vec2::new(4, 4), |x, y| x as f64 * 10.0 + y as f64 * 0.5);
+        let site = disc(8.0, 8.0, 2.0);
+        let result = flatten_sites(&mut map, &[site], 3.0);
+        assert_eq!(result[0].unwrap().target, 84.0);
+        for y in 0..16 {
+            for x in 0..16 {
+                let i = y * 16 + x;
+                let d = site.distance(Vec2::new(x as f64, y as f64));
+                if d <= 0.0 {
+                    assert_eq!(map.alt[i], 84.0);
+                } else if d >= 3.0 {
+                    assert_eq!(map.alt[i].to_bits(), original.alt[i].to_bits());
+                    assert_eq!(map.basement[i].to_bits(), original.basement[i].to_bits());
+                } else {
+                    let (lo, hi) = (original.alt[i].min(84.0), original.alt[i].max(84.0));
+                    assert!(map.alt[i] > lo && map.alt[i] < hi, "({}, {})", x, y);
+                }
+                // Lowered cells take the basement down with them, where it
+                // would otherwise stick out; it is never raised.
+                assert!(map.basement[i] <= map.alt[i]);
+                assert!(map.basement[i] <= original.basement[i]);
+            }
+        }
+        assert_eq!(
+            result[0].unwrap().changed,
+            (0..256)
+                .filter(|i| site.distance(Vec2::new(i % 16, i / 16).as_()) < 3.0)
+                .count()
+        );
+    }
+
+    #[test]
+    fn later_sites_win_where_they_overlap() {
+        let mut map = test_map(Vec2::new(4, 4), |x, _| x as f64 * 10.0);
+        let square = Site {
+            center: Vec2::new(5.0, 5.0),
+            shape: SiteShape::Rect {
+                size: Vec2::new(6.0, 6.0),
+            },
+        };
+        let result = flatten_sites(&mut map, &[square, disc(8.0, 5.0, 1.0)], 0.0);
+        let [Some(first), Some(second)] = result[..] else {
+            panic!("{:?}", result);
+        };
+        assert_eq!(first.target, 50.0);
+        // The disc overlaps the square, which is already flat there.
+        assert_eq!(second.target, (50.0 * 4.0 + 90.0) / 5.0);
+        assert_eq!(map.alt[5 * 16 + 8], second.target);
+        assert_eq!(map.alt[5 * 16 + 7], second.target);
+        assert_eq!(map.alt[5 * 16 + 6], first.target);
+        assert_eq!(map.alt[5 * 16 + 10], 100.0);
+    }
+
+    #[test]
+    fn sites_are_clipped_to_the_map() {
+        let mut map = test_map(Vec2::new(3, 3), |x, y| (x + y) as f64);
+        let result = flatten_sites(
+            &mut map,
+            &[
+                disc(0.0, 0.0, 1.0),
+                disc(-5.0, 3.0, 2.0),
+                disc(7.0, 7.5, 0.5),
+            ],
+            4.0,
+        );
+        // Only (0, 0), (1, 0) and (0, 1) of the first disc are on the map.
+        assert_eq!(result[0].unwrap().target, 2.0 / 3.0);
+        // The second one only reaches the map with its falloff ring.
+        assert_eq!(result[1], None);
+        // The last one has a single cell on the map, (7, 7).
+        let reached = (0..64)
+            .filter(|i| {
+                Vec2::new(i % 8, i / 8)
+                    .as_::<f64>()
+                    .distance(Vec2::new(7.0, 7.5))
+                    < 4.5
+            })
+            .count();
+        assert_eq!(result[2].unwrap(), FlattenedSite {
+            target: 14.0,
+            changed: reached,
+        });
+    }
+
+    #[test]
+    fn site_lists_are_parsed() {
+        let sites = parse_sites("# x, y, radius\n10, 20.5, 8\n\n 3,4 , 6, 2 \n").unwrap();
+        assert_eq!(sites, vec![disc(10.0, 20.5, 8.0), Site {
+            center: Vec2::new(3.0, 4.0),
+            shape: SiteShape::Rect {
+                size: Vec2::new(6.0, 2.0)
+            },
+        }]);
+        for text in [
+            "1, 2",
+            "1, 2, -3",
+            "1, 2, 3, 4, 5",
+            "1, two, 3",
+            "1, 2, inf",
+        ] {
+            assert!(
+                matches!(parse_sites(text), Err(Error::SiteList(_))),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn mask_must_match_map() {
+        let mut map = test_map(Vec2::new(2, 2), |_, _| 0.0);
+        let mask = Mask {
+            size: Vec2::new(2, 2),
+            values: vec![1.0; 4],
+        };
+        assert!(matches!(
+            flatten_masked(&mut map, &mask, 0.0),
+            Err(Error::SizeMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,295 @@
+//! Views of a map's row-major grids addressed by cell coordinates, for tools
+//! that build or edit maps cell by cell, and windows onto parts of them.
+
+use super::{Error, map_size};
+use crate::sim::ModernMap;
+use vek::*;
+
+/// Checks that a grid of `len` cells has `size` cells.
+fn check_len(len: usize, size: Vec2<usize>) -> Result<(), Error> {
+    if len != size.product() {
+        return Err(Error::GridLength {
+            expected: size.product(),
+            found: len,
+        });
+    }
+    Ok(())
+}
+
+/// A read-only view of a grid, such as a map's altitudes.
+#[derive(Clone, Copy, Debug)]
+pub struct AltGrid<'a> {
+    cells: &'a [f64],
+    size: Vec2<usize>,
+}
+
+impl<'a> AltGrid<'a> {
+    /// Views the row-major `cells` as a grid of `size`, failing with
+    /// [`Error::GridLength`] if they don't fill it exactly.
+    pub fn new(cells: &'a [f64], size: Vec2<usize>) -> Result<Self, Error> {
+        check_len(cells.len(), size)?;
+        Ok(Self { cells, size })
+    }
+
+    /// The altitudes of `map`, which must be valid.
+    pub fn alt(map: &'a ModernMap) -> Self {
+        Self {
+            cells: &map.alt,
+            size: map_size(map),
+        }
+    }
+
+    /// The basement of `map`, which must be valid.
+    pub fn basement(map: &'a ModernMap) -> Self {
+        Self {
+            cells: &map.basement,
+            size: map_size(map),
+        }
+    }
+
+    pub fn size(&self) -> Vec2<usize> { self.size }
+
+    /// The cell at `(x, y)`, or `None` if it lies outside the grid.
+    pub fn get(&self, x: usize, y: usize) -> Option<f64> {
+        (x < self.size.x && y < self.size.y).then(|| self.cells[y * self.size.x + x])
+    }
+}
+
+/// A mutable view of a grid, which is bounds-checked on every access.
+#[derive(Debug)]
+pub struct AltGridMut<'a> {
+    cells: &'a mut [f64],
+    size: Vec2<usize>,
+}
+
+impl<'a> AltGridMut<'a> {
+    /// Views the row-major `cells` as a grid of `size`, failing with
+    /// [`Error::GridLength`] if they don't fill it exactly.
+    pub fn new(cells: &'a mut [f64], size: Vec2<usize>) -> Result<Self, Error> {
+        check_len(cells.len(), size)?;
+        Ok(Self { cells, size })
+    }
+
+    /// The altitudes of `map`, which must be valid.
+    pub fn alt(map: &'a mut ModernMap) -> Self {
+        let size = map_size(map);
+        Self {
+            cells: &mut map.alt,
+            size,
+        }
+    }
+
+    /// The basement of `map`, which must be valid.
+    pub fn basement(map: &'a mut ModernMap) -> Self {
+        let size = map_size(map);
+        Self {
+            cells: &mut map.basement,
+            size,
+        }
+    }
+
+    pub fn size(&self) -> Vec2<usize> { self.size }
+
+    /// A read-only view of the same grid.
+    pub fn as_grid(&self) -> AltGrid<'_> {
+        AltGrid {
+            cells: self.cells,
+            size: self.size,
+        }
+    }
+
+    /// The cell at `(x, y)`, or `None` if it lies outside the grid.
+    pub fn get(&self, x: usize, y: usize) -> Option<f64> { self.as_grid().get(x, y) }
+
+    /// Sets the cell at `(x, y)` to `value`.
+    ///
+    /// # Panics
+    ///
+    /// If `(x, y)` lies outside the grid.
+    pub fn set(&mut self, x: usize, y: usize, value: f64) {
+        assert!(
+            x < self.size.x && y < self.size.y,
+            "cell ({}, {}) is outside the {}x{} grid",
+            x,
+            y,
+            self.size.x,
+            self.size.y
+        );
+        self.cells[y * self.size.x + x] = value;
+    }
+
+    /// Sets the `size.x` by `size.y` cells starting at `origin` to `value`.
+    ///
+    /// # Panics
+    ///
+    /// If the rectangle extends outside the grid.
+    pub fn fill_rect(&mut self, origin: Vec2<usize>, size: Vec2<usize>, value: f64) {
+        let end = origin + size;
+        assert!(
+            end.x <= self.size.x && end.y <= self.size.y,
+            "{}x{} rectangle at ({}, {}) extends outside the {}x{} grid",
+            size.x,
+            size.y,
+            origin.x,
+            origin.y,
+            self.size.x,
+            self.size.y
+        );
+        for y in origin.y..end.y {
+            self.cells[y * self.size.x..][origin.x..end.x].fill(value);
+        }
+    }
+
+    /// Calls `f` with the coordinates and a mutable reference to every cell,
+    /// row by row.
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(Vec2<usize>, &mut f64)) {
+        let width = self.size.x;
+        for (i, cell) in self.cells.iter_mut().enumerate() {
+            f(Vec2::new(i % width, i / width), cell);
+        }
+    }
+}
+
+/// A rectangle of cells of a grid, such as the part of a map shown by an
+/// export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    /// The cell in the first row and column of the window.
+    pub offset: Vec2<usize>,
+    pub size: Vec2<usize>,
+}
+
+impl Window {
+    /// The whole of a grid of `size` cells.
+    pub fn whole(size: Vec2<usize>) -> Self {
+        Self {
+            offset: Vec2::zero(),
+            size,
+        }
+    }
+
+    /// Parses a window given as `X,Y,W,H`, in cells.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let values = text
+            .split(',')
+            .map(|field| field.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{:?}: {}", text, e))?;
+        match values[..] {
+            [x, y, w, h] if w > 0 && h > 0 => Ok(Self {
+                offset: Vec2::new(x, y),
+                size: Vec2::new(w, h),
+            }),
+            [_, _, _, _] => Err(format!("{:?}: windows can't be empty", text)),
+            _ => Err(format!("{:?}: expected X,Y,W,H", text)),
+        }
+    }
+
+    /// Checks that the window lies within a grid of `size` cells, failing
+    /// with [`Error::Window`] otherwise.
+    pub fn check(&self, size: Vec2<usize>) -> Result<(), Error> {
+        let end = self.offset.map2(self.size, usize::saturating_add);
+        if end.x > size.x || end.y > size.y {
+            return Err(Error::Window {
+                window: *self,
+                map_size: size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the cell at `pos`, in the coordinates of the whole grid, lies
+    /// in the window.
+    pub fn contains(&self, pos: Vec2<f64>) -> bool {
+        let (start, end) = (self.offset, self.offset + self.size);
+        (start.x as f64..end.x as f64).contains(&pos.x)
+            && (start.y as f64..end.y as f64).contains(&pos.y)
+    }
+
+    /// Copies the cells of the window out of the row-major `grid` of `size`
+    /// cells, which it must lie within.
+    pub fn crop(&self, grid: &[f64], size: Vec2<usize>) -> Vec<f64> {
+        let mut cells = Vec::with_capacity(self.size.product());
+        for y in self.offset.y..self.offset.y + self.size.y {
+            cells
+                .extend_from_slice(&grid[y * size.x..][self.offset.x..self.offset.x + self.size.x]);
+        }
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+
+    #[test]
+    fn windows_crop_their_cells() {
+        let size = Vec2::new(4, 3);
+        let grid = (0..12).map(f64::from).collect::<Vec<_>>();
+        let window = Window::parse("1, 1, 2,2").unwrap();
+        assert_eq!(window.offset, Vec2::new(1, 1));
+        assert_eq!(window.crop(&grid, size), [5.0, 6.0, 9.0, 10.0]);
+        assert_eq!(Window::whole(size).crop(&grid, size), grid);
+        assert!(window.check(size).is_ok());
+        assert!(window.contains(Vec2::new(2.5, 1.0)));
+        assert!(!window.contains(Vec2::new(3.0, 1.0)));
+        assert!(matches!(
+            Window::parse("3,0,2,1").unwrap().check(size),
+            Err(Error::Window { .. })
+        ));
+        assert!(Window::parse("0,0,0,1").is_err());
+        assert!(Window::parse("0,0,1").is_err());
+    }
+
+    #[test]
+    fn cells_are_addressed_by_coordinates() {
+        let mut map = test_map(Vec2::new(2, 1), |x, y| (x + 10 * y) as f64);
+        let mut grid = AltGridMut::alt(&mut map);
+        assert_eq!(grid.size(), Vec2::new(4, 2));
+        assert_eq!(grid.get(3, 1), Some(13.0));
+        assert_eq!(grid.get(4, 0), None);
+
+        grid.set(1, 0, -5.0);
+        grid.fill_rect(Vec2::new(2, 0), Vec2::new(2, 2), 7.0);
+        assert_eq!(&*map.alt, &[0.0, -5.0, 7.0, 7.0, 10.0, 11.0, 7.0, 7.0]);
+        assert_eq!(AltGrid::basement(&map).get(1, 0), Some(-9.0));
+
+        // A plateau: everything above 5 is cut down to it.
+        AltGridMut::alt(&mut map).for_each_mut(|pos, alt| {
+            if pos.y == 1 {
+                *alt = alt.min(5.0);
+            }
+        });
+        assert_eq!(&map.alt[4..], &[5.0; 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cell (0, 2) is outside the 4x2 grid")]
+    fn out_of_bounds_writes_panic() {
+        let mut cells = [0.0; 8];
+        AltGridMut::new(&mut cells, Vec2::new(4, 2))
+            .unwrap()
+            .set(0, 2, 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "extends outside the 4x2 grid")]
+    fn rectangles_must_fit() {
+        let mut cells = [0.0; 8];
+        AltGridMut::new(&mut cells, Vec2::new(4, 2))
+            .unwrap()
+            .fill_rect(Vec2::new(3, 0), Vec2::new(2, 1), 1.0);
+    }
+
+    #[test]
+    fn views_must_match_their_cells() {
+        assert!(matches!(
+            AltGrid::new(&[0.0; 6], Vec2::new(4, 2)),
+            Err(Error::GridLength {
+                expected: 8,
+                found: 6
+            })
+        ));
+    }
+}
@@ -0,0 +1,153 @@
+//! Generating terrain from randomly placed hills, as a starting point for
+//! hand-editing or as test terrain.
+
+use super::{Error, MAX_MAP_CELLS};
+use crate::sim::ModernMap;
+use rand::prelude::*;
+use rand_chacha::ChaChaRng;
+use vek::*;
+
+/// Hills are only added within this many radii of their centre, where they
+/// have fallen to 0.03% of their height.
+const CUTOFF: f64 = 4.0;
+
+/// Ranges the hills placed by [`HillParams::generate`] are drawn from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HillParams {
+    /// Altitude of the flat ground the hills stand on.
+    pub base: f64,
+    /// Smallest and largest radius of a hill in cells: the standard deviation
+    /// of its gaussian profile.
+    pub radius: (f64, f64),
+    /// Smallest and largest height of a hill above the ground; negative
+    /// heights make hollows.
+    pub height: (f64, f64),
+}
+
+impl Default for HillParams {
+    fn default() -> Self {
+        Self {
+            base: 0.0,
+            radius: (4.0, 32.0),
+            height: (50.0, 500.0),
+        }
+    }
+}
+
+impl HillParams {
+    /// Altitudes of a grid of size `size` with `count` gaussian hills, of
+    /// random radius and height, at random positions on flat ground.
+    ///
+    /// The same seed always gives the same grid.  Hills may be centered
+    /// anywhere on the grid, including near an edge, in which case part of
+    /// them lies beyond it; where they overlap, their heights add up.
+    pub fn generate(&self, size: Vec2<usize>, count: usize, seed: u64) -> Vec<f64> {
+        let mut rng = ChaChaRng::seed_from_u64(seed);
+        let mut draw = |(a, b): (f64, f64)| {
+            let (lo, hi) = (a.min(b), a.max(b));
+            if lo < hi { rng.gen_range(lo..hi) } else { lo }
+        };
+        let mut alt = vec![self.base; size.product()];
+        for _ in 0..count {
+            let center = size.map(|e| draw((0.0, e as f64)));
+            let radius = draw(self.radius).max(f64::MIN_POSITIVE);
+            let height = draw(self.height);
+
+            let reach = radius * CUTOFF;
+            let lo = (center - reach).map(|e| e.floor().max(0.0) as usize);
+            let hi = (center + reach).map2(size, |e, len| {
+                (e.ceil() + 1.0).clamp(0.0, len as f64) as usize
+            });
+            for y in lo.y..hi.y.max(lo.y) {
+                for x in lo.x..hi.x.max(lo.x) {
+                    let d = Vec2::new(x as f64, y as f64).distance(center) / radius;
+                    if d <= CUTOFF {
+                        alt[y * size.x + x] += height * (-0.5 * d * d).exp();
+                    }
+                }
+            }
+        }
+        alt
+    }
+
+    /// A map of size `2^map_size_lg` generated like [`HillParams::generate`],
+    /// with a basement equal to its altitudes.
+    pub fn generate_map(
+        &self,
+        map_size_lg: Vec2<u32>,
+        count: usize,
+        seed: u64,
+        continent_scale: f64,
+    ) -> Result<ModernMap, Error> {
+        if map_size_lg.sum() > MAX_MAP_CELLS.trailing_zeros() {
+            return Err(Error::SizeOverflow { map_size_lg });
+        }
+        let alt = self
+            .generate(map_size_lg.map(|e| 1 << e), count, seed)
+            .into_boxed_slice();
+        Ok(ModernMap {
+            map_size_lg,
+            continent_scale_hack: continent_scale,
+            basement: alt.clone(),
+            alt,
+        })
+    }
+}
+
+/// Altitudes of a grid of size `size` with `count` hills drawn from the
+/// default [`HillParams`].
+pub fn generate_hills(size: Vec2<usize>, count: usize, seed: u64) -> Vec<f64> {
+    HillParams::default().generate(size, count, seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_are_reproducible() {
+        let size = Vec2::new(48, 32);
+        let alt = generate_hills(size, 20, 7);
+        assert_eq!(alt, generate_hills(size, 20, 7));
+        assert_ne!(alt, generate_hills(size, 20, 8));
+        // Without hills the ground is flat.
+        assert!(generate_hills(size, 0, 7).iter().all(|&alt| alt == 0.0));
+    }
+
+    #[test]
+    fn hills_stay_within_their_ranges() {
+        let params = HillParams {
+            base: 100.0,
+            radius: (2.0, 6.0),
+            height: (10.0, 20.0),
+        };
+        let alt = params.generate(Vec2::new(64, 64), 30, 1);
+        assert!(
+            alt.iter()
+                .all(|&alt| (100.0..=100.0 + 30.0 * 20.0).contains(&alt))
+        );
+        assert!(alt.iter().any(|&alt| alt > 110.0));
+
+        // A single hill peaks at its height, close to its centre.
+        let one = HillParams {
+            radius: (8.0, 8.0),
+            height: (50.0, 50.0),
+            ..params
+        };
+        let alt = one.generate(Vec2::new(64, 64), 1, 3);
+        let peak = alt.iter().copied().fold(f64::MIN, f64::max);
+        assert!(peak <= 150.0 && peak > 150.0 - 50.0 * (1.0 - (-1.0 / 64.0f64).exp()));
+    }
+
+    #[test]
+    fn oversized_maps_are_refused() {
+        let params = HillParams::default();
+        assert!(matches!(
+            params.generate_map(Vec2::new(20, 20), 1, 0, 1.0),
+            Err(Error::SizeOverflow { .. })
+        ));
+        let map = params.generate_map(Vec2::new(4, 3), 3, 0, 1.6).unwrap();
+        assert_eq!(map.alt.len(), 16 * 8);
+        assert_eq!(map.alt, map.basement);
+    }
+}
@@ -0,0 +1,132 @@
+//! Preparing altitude grids for flow routing.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+use vek::*;
+
+/// A cell waiting to be visited by [`fill_depressions`], ordered so that the
+/// lowest cell is popped first.
+struct Queued {
+    alt: f64,
+    idx: usize,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .alt
+            .total_cmp(&self.alt)
+            .then_with(|| other.idx.cmp(&self.idx))
+    }
+}
+
+/// Raises every depression of the row-major grid `alt` of `size` cells to its
+/// spill level, the lowest altitude over which water could flow out of it, so
+/// that water can flow from every cell to the edge of the map without going
+/// uphill.
+///
+/// Uses priority-flood: cells are visited from the edge inwards, lowest first,
+/// and each cell is raised to at least the altitude of the neighbor (of its 8)
+/// it was reached from.  Filled depressions become flat; cells are never
+/// lowered, and grids without depressions are returned unchanged.  This takes
+/// `O(n log n)` time for `n` cells.
+pub fn fill_depressions(alt: &[f64], size: Vec2<usize>) -> Vec<f64> {
+    let (w, h) = (size.x, size.y);
+    let mut filled = alt.to_vec();
+    let mut visited = vec![false; alt.len()];
+    let mut queue = BinaryHeap::new();
+    for y in 0..h {
+        for x in 0..w {
+            if x == 0 || y == 0 || x + 1 == w || y + 1 == h {
+                let idx = y * w + x;
+                visited[idx] = true;
+                queue.push(Queued { alt: alt[idx], idx });
+            }
+        }
+    }
+
+    while let Some(Queued { alt: level, idx }) = queue.pop() {
+        let (x, y) = (idx % w, idx / w);
+        for (dx, dy) in [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ] {
+            let (nx, ny) = (x as isize + dx, y as isize + dy);
+            if nx < 0 || ny < 0 || nx as usize >= w || ny as usize >= h {
+                continue;
+            }
+            let neighbor = ny as usize * w + nx as usize;
+            if visited[neighbor] {
+                continue;
+            }
+            visited[neighbor] = true;
+            filled[neighbor] = filled[neighbor].max(level);
+            queue.push(Queued {
+                alt: filled[neighbor],
+                idx: neighbor,
+            });
+        }
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::random_grids;
+
+    #[test]
+    fn pits_are_filled_to_their_spill_level() {
+        // A pit at the centre, enclosed by a ring at 8 with a notch at 6
+        // leading to an outlet at 5 on the edge.
+        #[rustfmt::skip]
+        let alt = [
+            10.0, 10.0,  5.0, 10.0, 10.0,
+            10.0,  8.0,  6.0,  8.0, 10.0,
+            10.0,  8.0,  1.0,  8.0, 10.0,
+            10.0,  8.0,  8.0,  8.0, 10.0,
+            10.0, 10.0, 10.0, 10.0, 10.0,
+        ];
+        let filled = fill_depressions(&alt, Vec2::new(5, 5));
+        let mut expected = alt;
+        expected[12] = 6.0;
+        assert_eq!(filled, expected);
+    }
+
+    #[test]
+    fn filling_only_raises_and_leaves_no_pits() {
+        for (size, alt) in random_grids(50) {
+            let filled = fill_depressions(&alt, size);
+            assert!(filled.iter().zip(&alt).all(|(filled, alt)| filled >= alt));
+            assert_eq!(fill_depressions(&filled, size), filled);
+            // Every interior cell has a neighbor at most as high, so water
+            // can leave it.
+            for y in 1..size.y.saturating_sub(1) {
+                for x in 1..size.x.saturating_sub(1) {
+                    let here = filled[y * size.x + x];
+                    let lowest = (y - 1..=y + 1)
+                        .flat_map(|ny| (x - 1..=x + 1).map(move |nx| (nx, ny)))
+                        .filter(|&pos| pos != (x, y))
+                        .map(|(nx, ny)| filled[ny * size.x + nx])
+                        .fold(f64::INFINITY, f64::min);
+                    assert!(lowest <= here, "pit at ({}, {})", x, y);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1091 @@
+//! Converting grayscale heightmap images into maps.
+
+use super::{
+    Error, MAX_MAP_CELLS, Warning,
+    adjust::{AbyssalClamp, ProportionalBasement, clamp_abyss_grid},
+    filter::{Edges, smooth_altitudes},
+    packed::check_not_packed,
+    read_json,
+    rivers::{Rivers, carve_rivers},
+    with_map_extension, write_json,
+};
+use crate::sim::ModernMap;
+use image::{DynamicImage, GenericImageView, ImageBuffer, ImageReader, Pixel, Rgba};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use vek::*;
+
+/// Which part of a pixel altitudes are read from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Channel {
+    #[default]
+    Red,
+    Green,
+    Blue,
+    /// The mean of the red, green and blue channels, which evens out noise
+    /// from lossy compression in images with the height in all three.
+    Avg,
+}
+
+impl Channel {
+    /// Value of this channel of `pixel`, between 0 and 255.
+    #[inline]
+    pub fn value(self, pixel: Rgba<u8>) -> f64 {
+        self.pick([pixel[0] as f64, pixel[1] as f64, pixel[2] as f64])
+    }
+
+    /// This channel of a red, green and blue triple.
+    #[inline]
+    pub fn pick(self, [r, g, b]: [f64; 3]) -> f64 {
+        match self {
+            Channel::Red => r,
+            Channel::Green => g,
+            Channel::Blue => b,
+            Channel::Avg => (r + g + b) / 3.0,
+        }
+    }
+}
+
+/// Which layers of a map smoothing applies to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SmoothTarget {
+    /// Only the altitudes; the basement is computed from the unsmoothed ones.
+    Alt,
+    /// Only the basement, computed from smoothed altitudes, under the
+    /// unsmoothed ones.
+    Basement,
+    /// The altitudes, and the basement computed from them.
+    #[default]
+    Both,
+}
+
+/// Parameters controlling how pixel values are turned into altitudes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImportParams {
+    /// Altitude range covered by the pixel values: a black pixel maps to
+    /// `offset`, and a white pixel to `offset + scale`.
+    pub scale: f64,
+    pub offset: f64,
+    /// Value stored in the map's `continent_scale_hack`.
+    pub continent_scale: f64,
+    /// Number of passes of [`smooth_altitudes`] applied after conversion.
+    pub smooth_iterations: u32,
+    /// Which layers the smoothing applies to.
+    #[serde(default)]
+    pub smooth_target: SmoothTarget,
+    /// How smoothing treats the edges of the image: wrapped, for worlds that
+    /// wrap around, or not.
+    #[serde(default)]
+    pub edges: Edges,
+    /// Channel the pixel values are read from.
+    #[serde(default)]
+    pub channel: Channel,
+    /// Altitude to move to sea level (0.0) after conversion, see
+    /// [`sea_to_zero`](super::adjust::sea_to_zero).
+    #[serde(default)]
+    pub sea_to_zero: Option<f64>,
+    /// If set, pixel values are used as altitudes directly, multiplied by
+    /// this factor, and `scale` and `offset` are ignored, which suits 16-bit
+    /// and float sources that already store meters (or another unit, given a
+    /// factor).
+    #[serde(default)]
+    pub raw_altitude: Option<f64>,
+    /// If set, images that aren't square with power-of-two sides are padded
+    /// to the next size that is, instead of being rejected.
+    #[serde(default)]
+    pub pad: Option<Padding>,
+    /// If set, the basement is computed from the final (shifted) altitudes,
+    /// instead of being a copy of them.
+    #[serde(default)]
+    pub basement: Option<ProportionalBasement>,
+    /// If set, water is read from the alpha channel, overriding the altitudes
+    /// of the cells it covers.
+    #[serde(default)]
+    pub alpha_water: Option<AlphaWater>,
+    /// If set, river channels painted in a second image are carved into the
+    /// altitudes, see [`carve_rivers`].
+    #[serde(default)]
+    pub rivers: Option<Rivers>,
+    /// If set, pixels are decoded as [`terrain_rgb_altitude`]s, and `scale`,
+    /// `offset`, `channel` and `raw_altitude` are ignored.  Only 8-bit RGB
+    /// (or RGBA) images can be decoded this way.
+    #[serde(default)]
+    pub terrain_rgb: bool,
+    /// If set, deep water is raised towards a floor after shifting, before
+    /// the basement is computed.
+    #[serde(default)]
+    pub abyss: Option<AbyssalClamp>,
+    /// If set, pixel values outside this range are moved to its fill
+    /// altitude, or clamped to it, instead of being extrapolated.
+    #[serde(default)]
+    pub range: Option<InputRange>,
+}
+
+/// The range of pixel values an image is expected to hold, for sources with
+/// sentinel pixels, such as pure black or white for missing data.
+///
+/// Values are those from which altitudes are computed: between 0 and 255
+/// whatever the depth of the image, raw values with
+/// [`ImportParams::raw_altitude`], or meters with
+/// [`ImportParams::terrain_rgb`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputRange {
+    pub min: f64,
+    pub max: f64,
+    /// Altitude of pixels outside the range; if `None`, they are clamped to
+    /// the nearest end of it instead.
+    #[serde(default)]
+    pub fill: Option<f64>,
+}
+
+impl InputRange {
+    #[inline]
+    pub fn contains(&self, value: f64) -> bool { (self.min..=self.max).contains(&value) }
+}
+
+impl ImportParams {
+    /// Parameters mapping black pixels to `offset` and white ones to
+    /// `offset + scale`, with the continent scale of the tools (1.6) and
+    /// every other step left out, to be filled in with struct update syntax.
+    pub fn new(scale: f64, offset: f64) -> Self {
+        Self {
+            scale,
+            offset,
+            continent_scale: 1.6,
+            smooth_iterations: 0,
+            smooth_target: SmoothTarget::default(),
+            edges: Edges::default(),
+            channel: Channel::default(),
+            sea_to_zero: None,
+            raw_altitude: None,
+            pad: None,
+            basement: None,
+            alpha_water: None,
+            rivers: None,
+            terrain_rgb: false,
+            abyss: None,
+            range: None,
+        }
+    }
+
+    /// Altitude of a decoded pixel value, which is between 0 and 255 unless
+    /// `raw_altitude` is set.
+    #[inline]
+    pub fn altitude(&self, value: f64) -> f64 {
+        match self.raw_altitude {
+            Some(factor) => value * factor,
+            None => (value / 255.0) * self.scale + self.offset,
+        }
+    }
+
+    /// Value of a pixel with the red, green and blue values `rgb`, from which
+    /// its altitude is computed.
+    #[inline]
+    fn value(&self, rgb: [f64; 3]) -> f64 {
+        if self.terrain_rgb {
+            terrain_rgb_altitude(rgb)
+        } else {
+            self.channel.pick(rgb)
+        }
+    }
+
+    /// Altitude of a pixel with the red, green and blue values `rgb`.
+    #[inline]
+    pub fn decode(&self, rgb: [f64; 3]) -> f64 {
+        let value = self.value(rgb);
+        let value = match &self.range {
+            Some(range) if !range.contains(value) => match range.fill {
+                Some(fill) => return fill,
+                None => value.clamp(range.min, range.max),
+            },
+            _ => value,
+        };
+        if self.terrain_rgb {
+            value
+        } else {
+            self.altitude(value)
+        }
+    }
+
+    /// Whether a pixel with the red, green and blue values `rgb` lies outside
+    /// [`ImportParams::range`].
+    #[inline]
+    pub fn out_of_range(&self, rgb: [f64; 3]) -> bool {
+        self.range
+            .is_some_and(|range| !range.contains(self.value(rgb)))
+    }
+
+    /// The basement below the converted altitudes `alt`, or `None` if it is a
+    /// copy of them.  It is computed from `basement_alt` instead if given, the
+    /// altitudes smoothed differently for [`SmoothTarget`], but kept below
+    /// `alt`.  `water` marks the cells covered by [`AlphaWater`], whose
+    /// basement lies [`WATER_BASEMENT_DEPTH`] below their altitude.
+    pub fn basement_grid(
+        &self,
+        alt: &[f64],
+        basement_alt: Option<&[f64]>,
+        water: Option<&[bool]>,
+    ) -> Option<Box<[f64]>> {
+        if self.basement.is_none() && basement_alt.is_none() && water.is_none() {
+            return None;
+        }
+        Some(
+            alt.iter()
+                .zip(basement_alt.unwrap_or(alt))
+                .enumerate()
+                .map(|(i, (&alt, &under))| match (&self.basement, water) {
+                    (_, Some(water)) if water[i] => alt - WATER_BASEMENT_DEPTH,
+                    (Some(basement), _) => basement.basement(under).min(alt),
+                    (None, _) => under.min(alt),
+                })
+                .collect(),
+        )
+    }
+
+    /// Descriptions of the steps of a conversion with these parameters, in
+    /// the order [`import_altitudes`] applies them, for a
+    /// [`Provenance`](super::provenance::Provenance).
+    pub fn pipeline(&self) -> Vec<String> {
+        let mut steps = vec![match self.raw_altitude {
+            _ if self.terrain_rgb => "decode Terrain-RGB".to_owned(),
+            Some(factor) => format!("read the {:?} channel times {}", self.channel, factor),
+            None => format!(
+                "read the {:?} channel, scaled by {} and offset by {}",
+                self.channel, self.scale, self.offset
+            ),
+        }];
+        if let Some(range) = &self.range {
+            steps.push(match range.fill {
+                Some(fill) => format!(
+                    "fill values outside {}..{} with {}",
+                    range.min, range.max, fill
+                ),
+                None => format!("clamp values to {}..{}", range.min, range.max),
+            });
+        }
+        if self.smooth_iterations > 0 {
+            steps.push(format!(
+                "smooth {} {} times ({:?} edges)",
+                match self.smooth_target {
+                    SmoothTarget::Alt => "the altitudes",
+                    SmoothTarget::Basement => "the basement",
+                    SmoothTarget::Both => "the altitudes and basement",
+                },
+                self.smooth_iterations,
+                self.edges
+            ));
+        }
+        if let Some(rivers) = &self.rivers {
+            steps.push(format!(
+                "carve the rivers in {}, {} deep and {} wide",
+                rivers.image.display(),
+                rivers.params.max_depth,
+                rivers.params.width
+            ));
+        }
+        if let Some(pad) = &self.pad {
+            steps.push(format!(
+                "pad{} to a power of two",
+                if pad.centered { ", centered," } else { "" }
+            ));
+        }
+        if let Some(sea) = self.sea_to_zero {
+            steps.push(format!("move {} to sea level", sea));
+        }
+        if let Some(abyss) = &self.abyss {
+            steps.push(format!(
+                "raise the abyss to {} over {}",
+                abyss.floor, abyss.rolloff
+            ));
+        }
+        if let Some(water) = &self.alpha_water {
+            steps.push(format!(
+                "read water below {} from the alpha channel",
+                water.sea_level
+            ));
+        }
+        if let Some(basement) = &self.basement {
+            steps.push(format!(
+                "keep {} of the height above {} as basement",
+                basement.factor, basement.sea_level
+            ));
+        }
+        steps
+    }
+}
+
+/// Altitude, in meters, of a pixel in the Terrain-RGB encoding used by Mapbox
+/// and many other elevation tile sources, which spreads a 24-bit value over
+/// the red (most significant), green and blue channels of 8-bit images, in
+/// tenths of a meter above -10000.
+#[inline]
+pub fn terrain_rgb_altitude([r, g, b]: [f64; 3]) -> f64 {
+    -10000.0 + (r * 256.0 * 256.0 + g * 256.0 + b) * 0.1
+}
+
+/// Water painted into the alpha channel of an image: every cell whose pixel
+/// isn't fully transparent is underwater, whatever its color, with a depth
+/// proportional to its opacity.  Partially transparent edges thus give
+/// shorelines that slope down from the water surface.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlphaWater {
+    /// Altitude of the water surface, in final (shifted) altitudes.
+    pub sea_level: f64,
+    /// Depth of the water below a fully opaque pixel.
+    pub depth_scale: f64,
+}
+
+impl AlphaWater {
+    /// Altitude of the bottom of the water below a pixel covering `opacity`
+    /// (between 0 and 1) of its cell.
+    #[inline]
+    pub fn altitude(&self, opacity: f64) -> f64 { self.sea_level - opacity * self.depth_scale }
+}
+
+/// Depth of the basement below the bottom of water painted with
+/// [`AlphaWater`].
+pub const WATER_BASEMENT_DEPTH: f64 = 1.0;
+
+/// How images are padded to be square with power-of-two sides.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Padding {
+    /// Center the image in the map, with borders of equal width on opposite
+    /// sides, instead of placing it in its first rows and columns.
+    pub centered: bool,
+    /// Altitude of the added cells, if not that of a black pixel.
+    pub altitude: Option<f64>,
+}
+
+/// The cells of a padded map that hold the image it was converted from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageRegion {
+    pub offset: Vec2<u32>,
+    pub size: Vec2<u32>,
+}
+
+impl Padding {
+    /// Computes `map_size_lg` of the smallest square power-of-two map that
+    /// fits a `width` by `height` image, and where the image is placed in it.
+    pub fn layout(&self, width: u32, height: u32) -> Result<(Vec2<u32>, ImageRegion), Error> {
+        let side = width
+            .max(height)
+            .checked_next_power_of_two()
+            .filter(|&side| (side as usize).pow(2) <= MAX_MAP_CELLS)
+            .ok_or(Error::ImageSize { width, height })?;
+        let size = Vec2::new(width, height);
+        let offset = if self.centered {
+            (Vec2::broadcast(side) - size) / 2
+        } else {
+            Vec2::zero()
+        };
+        Ok((Vec2::broadcast(side.trailing_zeros()), ImageRegion {
+            offset,
+            size,
+        }))
+    }
+}
+
+/// Computes `map_size_lg` for an image of the given dimensions, which must be
+/// square with power-of-two sides.
+pub fn map_size_lg(width: u32, height: u32) -> Result<Vec2<u32>, Error> {
+    if width != height || !width.is_power_of_two() {
+        return Err(Error::ImageSize { width, height });
+    }
+    Ok(Vec2::broadcast(width.trailing_zeros()))
+}
+
+/// Number of pixels in each of the chunks images are split into for parallel
+/// conversion.
+const CHUNK_PIXELS: usize = 1 << 16;
+
+/// The altitudes of the pixels of an image, as returned by
+/// [`convert_pixels`].
+pub struct ConvertedPixels {
+    pub alt: Vec<f64>,
+    pub min: f64,
+    pub max: f64,
+    /// Number of pixels outside [`ImportParams::range`].
+    pub out_of_range: usize,
+}
+
+/// Converts every pixel of `img` into an altitude, without smoothing, padding
+/// or shifting, and returns the altitudes with their minimum and maximum and
+/// the number of pixels outside `params.range`.
+///
+/// Altitudes are read from the channel selected by `params`, at the full
+/// depth of the image: 16-bit and float samples are rescaled to between 0 and
+/// 255, rather than rounded to 8 bits, unless `params.raw_altitude` is set, in
+/// which case they are used as they are.  With `params.terrain_rgb`, they are
+/// decoded from all three color channels instead, which fails with
+/// [`Error::UnsupportedImage`] unless the image is 8-bit RGB.  Grayscale
+/// images have the same value in every channel.  The samples of the decoded
+/// image are converted in parallel, in a single pass that also checks that
+/// every altitude is finite, which float images need not be.
+pub fn convert_pixels(img: &DynamicImage, params: &ImportParams) -> Result<ConvertedPixels, Error> {
+    fn convert<P: Pixel>(
+        img: &ImageBuffer<P, Vec<P::Subpixel>>,
+        params: &ImportParams,
+        unit: f64,
+    ) -> Option<ConvertedPixels>
+    where
+        P::Subpixel: Into<f64> + Sync,
+    {
+        let channels = P::CHANNEL_COUNT as usize;
+        let mut alt = vec![0.0; img.width() as usize * img.height() as usize];
+        let (min, max, finite, out_of_range) = alt
+            .par_chunks_mut(CHUNK_PIXELS)
+            .zip(img.as_raw().par_chunks(CHUNK_PIXELS * channels))
+            .map(|(alt, samples)| {
+                alt.iter_mut().zip(samples.chunks_exact(channels)).fold(
+                    (f64::INFINITY, f64::NEG_INFINITY, true, 0),
+                    |(min, max, finite, out), (alt, pixel)| {
+                        let rgb = match *pixel {
+                            [r, g, b, ..] => [r.into(), g.into(), b.into()],
+                            [luma, ..] => [luma.into(); 3],
+                            [] => [0.0; 3],
+                        }
+                        .map(|v| v * unit);
+                        *alt = params.decode(rgb);
+                        (
+                            min.min(*alt),
+                            max.max(*alt),
+                            finite && alt.is_finite(),
+                            out + params.out_of_range(rgb) as usize,
+                        )
+                    },
+                )
+            })
+            .reduce(
+                || (f64::INFINITY, f64::NEG_INFINITY, true, 0),
+                |(min, max, finite, out), (other_min, other_max, other_finite, other_out)| {
+                    (
+                        min.min(other_min),
+                        max.max(other_max),
+                        finite && other_finite,
+                        out + other_out,
+                    )
+                },
+            );
+        finite.then_some(ConvertedPixels {
+            alt,
+            min,
+            max,
+            out_of_range,
+        })
+    }
+
+    if params.terrain_rgb
+        && !matches!(
+            img,
+            DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_)
+        )
+    {
+        return Err(Error::UnsupportedImage(format!(
+            "Terrain-RGB altitudes are decoded from 8-bit RGB images, but the image is {:?}",
+            img.color()
+        )));
+    }
+    // Samples are read at their full depth, and unless they are raw
+    // altitudes, rescaled so that white is 255 whatever the depth.
+    let (unit16, unit32f) = match params.raw_altitude {
+        Some(_) => (1.0, 1.0),
+        None => (255.0 / u16::MAX as f64, 255.0),
+    };
+    match img {
+        DynamicImage::ImageLuma8(img) => convert(img, params, 1.0),
+        DynamicImage::ImageLumaA8(img) => convert(img, params, 1.0),
+        DynamicImage::ImageRgb8(img) => convert(img, params, 1.0),
+        DynamicImage::ImageRgba8(img) => convert(img, params, 1.0),
+        DynamicImage::ImageLuma16(img) => convert(img, params, unit16),
+        DynamicImage::ImageLumaA16(img) => convert(img, params, unit16),
+        DynamicImage::ImageRgb16(img) => convert(img, params, unit16),
+        DynamicImage::ImageRgba16(img) => convert(img, params, unit16),
+        DynamicImage::ImageRgb32F(img) => convert(img, params, unit32f),
+        DynamicImage::ImageRgba32F(img) => convert(img, params, unit32f),
+        img => convert(&img.to_rgba32f(), params, unit32f),
+    }
+    .ok_or(Error::NonFinite)
+}
+
+/// Opacity of every pixel of `img`, between 0 and 1, or `None` if the image
+/// has no alpha channel.
+fn opacities(img: &DynamicImage) -> Option<Vec<f64>> {
+    fn alpha<P: Pixel>(img: &ImageBuffer<P, Vec<P::Subpixel>>, max: f64) -> Vec<f64>
+    where
+        P::Subpixel: Into<f64> + Sync,
+    {
+        let channels = P::CHANNEL_COUNT as usize;
+        img.as_raw()
+            .par_chunks_exact(channels)
+            .map(|pixel| (pixel[channels - 1].into() / max).clamp(0.0, 1.0))
+            .collect()
+    }
+
+    match img {
+        DynamicImage::ImageLumaA8(img) => Some(alpha(img, u8::MAX as f64)),
+        DynamicImage::ImageRgba8(img) => Some(alpha(img, u8::MAX as f64)),
+        DynamicImage::ImageLumaA16(img) => Some(alpha(img, u16::MAX as f64)),
+        DynamicImage::ImageRgba16(img) => Some(alpha(img, u16::MAX as f64)),
+        DynamicImage::ImageRgba32F(img) => Some(alpha(img, 1.0)),
+        img if img.color().has_alpha() => Some(alpha(&img.to_rgba32f(), 1.0)),
+        _ => None,
+    }
+}
+
+/// Converts `img` into a map, reading altitudes from the channel selected by
+/// `params`.
+///
+/// Image row `y` becomes map row `y`, unless the image is padded; basement
+/// is a copy of the (smoothed, padded and shifted) altitudes, unless
+/// `params.basement` or `params.alpha_water` is set, or only one of the layers
+/// is smoothed (see [`ImportParams::basement_grid`]).  Smoothing only sees the
+/// image, not the padding.
+pub fn import_image(img: &DynamicImage, params: &ImportParams) -> Result<ModernMap, Error> {
+    let ImportedAltitudes {
+        map_size_lg,
+        alt,
+        basement_alt,
+        water,
+        ..
+    } = import_altitudes(img, params)?;
+    let basement = params
+        .basement_grid(&alt, basement_alt.as_deref(), water.as_deref())
+        .unwrap_or_else(|| alt.clone().into_boxed_slice());
+    Ok(ModernMap {
+        map_size_lg,
+        continent_scale_hack: params.continent_scale,
+        alt: alt.into_boxed_slice(),
+        basement,
+    })
+}
+
+/// The altitudes of a converted image, as returned by [`import_altitudes`].
+pub struct ImportedAltitudes {
+    pub map_size_lg: Vec2<u32>,
+    pub alt: Vec<f64>,
+    /// The altitudes the basement is computed from, if `params.smooth_target`
+    /// smooths them differently from `alt`.
+    pub basement_alt: Option<Vec<f64>>,
+    /// Which cells are covered by water, if `params.alpha_water` is set.
+    pub water: Option<Vec<bool>>,
+    /// Problems with the inputs that didn't stop the conversion.
+    pub warnings: Vec<Warning>,
+    /// Number of cells raised by `params.abyss`.
+    pub abyss_cells: usize,
+    /// Number of pixels outside `params.range`.
+    pub out_of_range: usize,
+}
+
+/// Converts `img` into the altitudes of a map like [`import_image`], without
+/// computing the basement.
+///
+/// Rivers are carved after smoothing, before padding, with the sea at
+/// `params.sea_to_zero` (or 0); a rivers image that doesn't match `img` is
+/// resampled to fit it, with a warning.  Water replaces the altitudes of the
+/// cells it covers last, after padding (which adds no water), shifting and
+/// clamping the abyss.  Altitudes smoothed for the basement alone go through
+/// the same steps, bar the water.
+///
+/// Fails with [`Error::NonFinite`] if any pixel converts to an infinite or NaN
+/// altitude, and with [`Error::UnsupportedImage`] if water is to be read from
+/// an image without an alpha channel.
+pub fn import_altitudes(
+    img: &DynamicImage,
+    params: &ImportParams,
+) -> Result<ImportedAltitudes, Error> {
+    let (width, height) = img.dimensions();
+    let (map_size_lg, region) = match &params.pad {
+        Some(pad) => pad.layout(width, height)?,
+        None => (map_size_lg(width, height)?, ImageRegion {
+            offset: Vec2::zero(),
+            size: Vec2::new(width, height),
+        }),
+    };
+    let mut opacity = match params.alpha_water {
+        Some(_) => Some(opacities(img).ok_or_else(|| {
+            Error::UnsupportedImage(format!(
+                "water is read from the alpha channel, but the image is {:?}",
+                img.color()
+            ))
+        })?),
+        None => None,
+    };
+
+    let ConvertedPixels {
+        mut alt,
+        out_of_range,
+        ..
+    } = convert_pixels(img, params)?;
+    let smooth = |mut grid: Vec<f64>| {
+        for _ in 0..params.smooth_iterations {
+            grid = smooth_altitudes(&grid, width, height, params.edges);
+        }
+        grid
+    };
+    let mut basement_alt = None;
+    if params.smooth_iterations > 0 {
+        match params.smooth_target {
+            SmoothTarget::Both => alt = smooth(alt),
+            SmoothTarget::Alt => {
+                basement_alt = Some(alt.clone());
+                alt = smooth(alt);
+            },
+            SmoothTarget::Basement => basement_alt = Some(smooth(alt.clone())),
+        }
+    }
+    let mut warnings = Vec::new();
+    if let Some(rivers) = &params.rivers {
+        let size = Vec2::new(width as usize, height as usize);
+        let (mask, warning) = rivers.load(size)?;
+        warnings.extend(warning);
+        let sea_level = params.sea_to_zero.unwrap_or(0.0);
+        for grid in std::iter::once(&mut alt).chain(&mut basement_alt) {
+            carve_rivers(grid, size, &mask, &rivers.params, sea_level)?;
+        }
+    }
+    if let Some(pad) = &params.pad {
+        let fill = pad.altitude.unwrap_or_else(|| params.decode([0.0; 3]));
+        for grid in std::iter::once(&mut alt).chain(&mut basement_alt) {
+            *grid = pad_grid(grid, region, 1 << map_size_lg.x, fill);
+        }
+        opacity = opacity.map(|opacity| pad_grid(&opacity, region, 1 << map_size_lg.x, 0.0));
+    }
+    if let Some(current_sea) = params.sea_to_zero {
+        let delta = -current_sea;
+        for grid in std::iter::once(&mut alt).chain(&mut basement_alt) {
+            grid.iter_mut().for_each(|alt| *alt += delta);
+        }
+    }
+    let abyss_cells = match &params.abyss {
+        Some(abyss) => {
+            if let Some(basement_alt) = &mut basement_alt {
+                clamp_abyss_grid(basement_alt, abyss);
+            }
+            clamp_abyss_grid(&mut alt, abyss)
+        },
+        None => 0,
+    };
+    let water = params.alpha_water.zip(opacity).map(|(water, opacity)| {
+        alt.iter_mut()
+            .zip(&opacity)
+            .map(|(alt, &opacity)| {
+                if opacity > 0.0 {
+                    *alt = water.altitude(opacity);
+                }
+                opacity > 0.0
+            })
+            .collect()
+    });
+    Ok(ImportedAltitudes {
+        map_size_lg,
+        alt,
+        basement_alt,
+        water,
+        warnings,
+        abyss_cells,
+        out_of_range,
+    })
+}
+
+/// Places the row-major grid covering `region` in a square grid with sides
+/// of `side` cells, filling the rest with `fill`.
+fn pad_grid(grid: &[f64], region: ImageRegion, side: usize, fill: f64) -> Vec<f64> {
+    let (offset, size) = (
+        region.offset.map(|e| e as usize),
+        region.size.map(|e| e as usize),
+    );
+    let mut out = vec![fill; side * side];
+    for (y, row) in grid.chunks_exact(size.x).enumerate() {
+        let start = (offset.y + y) * side + offset.x;
+        out[start..start + size.x].copy_from_slice(row);
+    }
+    out
+}
+
+/// Loads the image at `path`, refusing images packed by
+/// [`packed`](super::packed), whose pixels aren't heights.
+pub fn load_image(path: impl AsRef<Path>) -> Result<DynamicImage, Error> {
+    check_not_packed(path.as_ref())?;
+    Ok(ImageReader::open(path)?.decode()?)
+}
+
+/// Loads and converts the image at `path`; see [`import_image`].
+pub fn import_file(path: impl AsRef<Path>, params: &ImportParams) -> Result<ModernMap, Error> {
+    import_image(&load_image(path)?, params)
+}
+
+/// Record of a conversion, written next to the resulting `.bin` so that it can
+/// be audited or repeated later.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImportSidecar {
+    /// Version of `veloren-world` that performed the conversion.
+    pub version: String,
+    /// Image the map was converted from.
+    pub source: PathBuf,
+    #[serde(flatten)]
+    pub params: ImportParams,
+    /// Where the image ended up in the map, if it was padded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<ImageRegion>,
+}
+
+impl ImportSidecar {
+    /// Describes a conversion of `source` by the running version of this
+    /// crate.  The source path is made absolute where possible, so the
+    /// sidecar stays valid when run from another directory.
+    pub fn new(source: &Path, params: ImportParams) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            source: source.canonicalize().unwrap_or_else(|_| source.to_owned()),
+            params,
+            region: None,
+        }
+    }
+
+    /// Path of the sidecar accompanying the map at `bin_path`, which may be
+    /// compressed.
+    pub fn path_for(bin_path: &Path) -> PathBuf { with_map_extension(bin_path, "json") }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> { read_json(path) }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> { write_json(path, self) }
+
+    /// Runs the recorded conversion again.
+    pub fn reconvert(&self) -> Result<ModernMap, Error> { import_file(&self.source, &self.params) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma, Rgb};
+
+    #[test]
+    fn pixels_map_to_scaled_altitudes() {
+        let params = ImportParams {
+            continent_scale: 1.0,
+            ..ImportParams::new(1000.0, -200.0)
+        };
+        let img = GrayImage::from_fn(2, 2, |x, y| Luma([(x * 204 + y * 51) as u8]));
+        let map = import_image(&DynamicImage::ImageLuma8(img), &params).unwrap();
+        assert_eq!(map.map_size_lg, Vec2::new(1, 1));
+        for (alt, expected) in map.alt.iter().zip([-200.0, 600.0, 0.0, 800.0]) {
+            assert!((alt - expected).abs() < 1e-9, "{} != {}", alt, expected);
+        }
+        assert_eq!(map.alt, map.basement);
+    }
+
+    #[test]
+    fn sixteen_bit_images_keep_their_low_bits() {
+        let params = ImportParams {
+            continent_scale: 1.0,
+            ..ImportParams::new(65535.0, -100.0)
+        };
+        // Values that rounding to 8 bits would merge, or split differently.
+        let values = [0, 1, 128, 65535];
+        let img = ImageBuffer::from_fn(2, 2, |x, y| Luma([values[(y * 2 + x) as usize]]));
+        let map = import_image(&DynamicImage::ImageLuma16(img), &params).unwrap();
+        for (alt, value) in map.alt.iter().zip(values) {
+            assert!((alt - (value as f64 - 100.0)).abs() < 1e-9, "{}", alt);
+        }
+    }
+
+    #[test]
+    fn pixels_outside_the_range_are_filled_or_clamped() {
+        let mut params = ImportParams {
+            continent_scale: 1.0,
+            range: Some(InputRange {
+                min: 1.0,
+                max: 254.0,
+                fill: Some(-5.0),
+            }),
+            ..ImportParams::new(255.0, 0.0)
+        };
+        // Sentinel black and white pixels around valid ones.
+        let values = [0, 10, 200, 255];
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(2, 2, |x, y| {
+            Luma([values[(y * 2 + x) as usize]])
+        }));
+        let imported = import_altitudes(&img, &params).unwrap();
+        assert_eq!(imported.alt, vec![-5.0, 10.0, 200.0, -5.0]);
+        assert_eq!(imported.out_of_range, 2);
+
+        params.range = params.range.map(|range| InputRange {
+            fill: None,
+            ..range
+        });
+        let imported = import_altitudes(&img, &params).unwrap();
+        assert_eq!(imported.alt, vec![1.0, 10.0, 200.0, 254.0]);
+        assert_eq!(imported.out_of_range, 2);
+
+        params.range = None;
+        assert_eq!(import_altitudes(&img, &params).unwrap().out_of_range, 0);
+    }
+
+    #[test]
+    fn smoothing_applies_to_the_chosen_layers() {
+        // A single bright pixel in a dark image.
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(4, 4, |x, y| {
+            Luma([if (x, y) == (1, 1) { 90 } else { 0 }])
+        }));
+        let mut params = ImportParams {
+            continent_scale: 1.0,
+            smooth_iterations: 1,
+            ..ImportParams::new(255.0, 0.0)
+        };
+        let both = import_image(&img, &params).unwrap();
+        assert!(both.alt[5] < 90.0);
+        assert_eq!(both.basement, both.alt);
+
+        params.smooth_target = SmoothTarget::Alt;
+        let alt = import_image(&img, &params).unwrap();
+        assert_eq!(alt.alt, both.alt);
+        // The unsmoothed basement is kept below the smoothed altitudes.
+        assert_eq!(alt.basement[5], alt.alt[5]);
+        assert_eq!(alt.basement[0], 0.0);
+        assert!(alt.alt[0] > 0.0);
+
+        params.smooth_target = SmoothTarget::Basement;
+        let basement = import_image(&img, &params).unwrap();
+        assert_eq!(basement.alt[5], 90.0);
+        assert_eq!(basement.basement[5], both.alt[5]);
+        assert_eq!(basement.basement[0], 0.0);
+
+        // Without smoothing, the target makes no difference.
+        params.smooth_iterations = 0;
+        assert_eq!(import_altitudes(&img, &params).unwrap().basement_alt, None);
+    }
+
+    #[test]
+    fn raw_altitudes_bypass_scale_and_offset() {
+        let mut params = ImportParams {
+            continent_scale: 1.0,
+            channel: Channel::Green,
+            raw_altitude: Some(1.0),
+            ..ImportParams::new(1000.0, -200.0)
+        };
+        let img = ImageBuffer::from_fn(2, 2, |x, y| Luma([(x * 40000 + y * 1234) as u16]));
+        let map = import_image(&DynamicImage::ImageLuma16(img), &params).unwrap();
+        assert_eq!(&*map.alt, &[0.0, 40000.0, 1234.0, 41234.0]);
+
+        params.raw_altitude = Some(0.5);
+        let img = ImageBuffer::from_fn(2, 2, |x, y| {
+            Rgb([0.0, x as f32 * 3000.0 - y as f32 * 1500.0, 0.0])
+        });
+        let map = import_image(&DynamicImage::ImageRgb32F(img), &params).unwrap();
+        assert_eq!(&*map.alt, &[0.0, 1500.0, -750.0, 750.0]);
+    }
+
+    #[test]
+    fn terrain_rgb_decodes_all_three_channels() {
+        let mut params = ImportParams {
+            continent_scale: 1.0,
+            terrain_rgb: true,
+            ..ImportParams::new(1000.0, -200.0)
+        };
+        let pixels = [[1, 134, 160], [0, 0, 0], [1, 135, 163], [255, 255, 255]];
+        let img = ImageBuffer::from_fn(2, 2, |x, y| Rgb(pixels[(y * 2 + x) as usize]));
+        let map = import_image(&DynamicImage::ImageRgb8(img), &params).unwrap();
+        for (alt, expected) in map.alt.iter().zip([0.0, -10000.0, 25.9, 1667721.5]) {
+            assert!((alt - expected).abs() < 1e-6, "{} != {}", alt, expected);
+        }
+
+        let gray = GrayImage::from_pixel(2, 2, Luma([100]));
+        assert!(matches!(
+            import_image(&DynamicImage::ImageLuma8(gray.clone()), &params),
+            Err(Error::UnsupportedImage(_))
+        ));
+        params.terrain_rgb = false;
+        assert!(import_image(&DynamicImage::ImageLuma8(gray), &params).is_ok());
+    }
+
+    #[test]
+    fn channels_select_or_average_components() {
+        let pixel = Rgba([30, 60, 120, 255]);
+        assert_eq!(Channel::default(), Channel::Red);
+        assert_eq!(Channel::Red.value(pixel), 30.0);
+        assert_eq!(Channel::Green.value(pixel), 60.0);
+        assert_eq!(Channel::Blue.value(pixel), 120.0);
+        assert_eq!(Channel::Avg.value(pixel), 70.0);
+    }
+
+    #[test]
+    fn rejects_non_square_and_non_power_of_two_images() {
+        assert!(map_size_lg(8, 4).is_err());
+        assert!(map_size_lg(6, 6).is_err());
+        assert_eq!(map_size_lg(1024, 1024).unwrap(), Vec2::new(10, 10));
+    }
+
+    #[test]
+    fn padding_centers_or_anchors_the_image() {
+        let mut params = ImportParams {
+            continent_scale: 1.0,
+            pad: Some(Padding {
+                centered: true,
+                altitude: Some(-1.0),
+            }),
+            ..ImportParams::new(255.0, 0.0)
+        };
+        // A 5x2 image needs an 8x8 map.
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(5, 2, |x, y| {
+            Luma([(10 * y + x + 1) as u8])
+        }));
+        let map = import_image(&img, &params).unwrap();
+        assert_eq!(map.map_size_lg, Vec2::new(3, 3));
+        let (_, region) = params.pad.as_ref().unwrap().layout(5, 2).unwrap();
+        assert_eq!(region, ImageRegion {
+            offset: Vec2::new(1, 3),
+            size: Vec2::new(5, 2),
+        });
+        assert_eq!(&map.alt[3 * 8..4 * 8], &[
+            -1.0, 1.0, 2.0, 3.0, 4.0, 5.0, -1.0, -1.0
+        ]);
+        assert_eq!(map.alt[4 * 8 + 1], 11.0);
+        assert_eq!(map.alt.iter().filter(|&&alt| alt == -1.0).count(), 64 - 10);
+
+        // Anchored padding fills with the altitude of a black pixel by default.
+        params.pad = Some(Padding {
+            centered: false,
+            altitude: None,
+        });
+        let map = import_image(&img, &params).unwrap();
+        assert_eq!(&map.alt[..6], &[1.0, 2.0, 3.0, 4.0, 5.0, 0.0]);
+        assert_eq!(map.alt[8], 11.0);
+
+        // Images that already have a valid size are left alone.
+        let square = DynamicImage::ImageLuma8(GrayImage::from_fn(4, 4, |_, _| Luma([7])));
+        assert_eq!(import_image(&square, &params).unwrap().alt.len(), 16);
+    }
+
+    #[test]
+    fn parallel_conversion_matches_pixel_by_pixel() {
+        use rand::prelude::*;
+
+        // The conversion of pixel values, rescaled by `unit`, before it was
+        // parallelized.
+        fn sequential<P: Pixel>(
+            img: &ImageBuffer<P, Vec<P::Subpixel>>,
+            params: &ImportParams,
+            unit: f64,
+        ) -> Vec<f64>
+        where
+            P::Subpixel: Into<f64>,
+        {
+            img.pixels()
+                .map(|pixel| match *pixel.channels() {
+                    [r, g, b, ..] => params.channel.pick([r, g, b].map(|v| v.into() * unit)),
+                    [luma, ..] => luma.into() * unit,
+                    [] => 0.0,
+                })
+                .map(|value| params.altitude(value))
+                .collect()
+        }
+
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(0x696d_706f_7274);
+        let mut params = ImportParams {
+            continent_scale: 1.0,
+            channel: Channel::Avg,
+            ..ImportParams::new(1234.5, -321.0)
+        };
+        // Large enough to span several chunks, and not a multiple of the
+        // chunk size.
+        let (w, h) = (300, 250);
+        let images = [
+            DynamicImage::ImageLuma8(GrayImage::from_fn(w, h, |_, _| Luma([rng.gen()]))),
+            DynamicImage::ImageRgba8(ImageBuffer::from_fn(w, h, |_, _| Rgba(rng.gen()))),
+            DynamicImage::ImageRgb16(ImageBuffer::from_fn(w, h, |_, _| Rgb(rng.gen()))),
+            DynamicImage::ImageRgb32F(ImageBuffer::from_fn(w, h, |_, _| {
+                Rgb([(); 3].map(|_| rng.gen_range(-10.0..10.0)))
+            })),
+        ];
+        for raw_altitude in [None, Some(0.25)] {
+            params.raw_altitude = raw_altitude;
+            for (i, img) in images.iter().enumerate() {
+                let (unit16, unit32f) = match raw_altitude {
+                    None => (255.0 / 65535.0, 255.0),
+                    Some(_) => (1.0, 1.0),
+                };
+                let expected = match img {
+                    DynamicImage::ImageLuma8(img) => sequential(img, &params, 1.0),
+                    DynamicImage::ImageRgba8(img) => sequential(img, &params, 1.0),
+                    DynamicImage::ImageRgb16(img) => sequential(img, &params, unit16),
+                    DynamicImage::ImageRgb32F(img) => sequential(img, &params, unit32f),
+                    _ => unreachable!(),
+                };
+                let ConvertedPixels { alt, min, max, .. } = convert_pixels(img, &params).unwrap();
+                assert!(
+                    alt.iter()
+                        .map(|alt| alt.to_bits())
+                        .eq(expected.iter().map(|alt| alt.to_bits())),
+                    "image {}, raw {:?}",
+                    i,
+                    raw_altitude
+                );
+                assert_eq!(min, expected.iter().copied().fold(f64::INFINITY, f64::min));
+                assert_eq!(
+                    max,
+                    expected.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+                );
+            }
+        }
+
+        let mut img = ImageBuffer::from_fn(3, 3, |_, _| Rgb([1.0f32; 3]));
+        img.put_pixel(2, 1, Rgb([f32::NAN; 3]));
+        assert!(matches!(
+            convert_pixels(&DynamicImage::ImageRgb32F(img), &params),
+            Err(Error::NonFinite)
+        ));
+    }
+
+    #[test]
+    fn painted_lakes_get_their_depth_from_alpha() {
+        let mut params = ImportParams {
+            continent_scale: 1.0,
+            alpha_water: Some(AlphaWater {
+                sea_level: 10.0,
+                depth_scale: 51.0,
+            }),
+            ..ImportParams::new(255.0, 0.0)
+        };
+        // Land at 200, with a lake whose opaque centre is surrounded by a
+        // half-transparent shore.
+        #[rustfmt::skip]
+        let alpha = [
+            0,   0,   0,   0,
+            0, 255, 255, 102,
+            0, 255, 255, 102,
+            0,   0,   0,   0,
+        ];
+        let img = DynamicImage::ImageRgba8(ImageBuffer::from_fn(4, 4, |x, y| {
+            Rgba([200, 0, 0, alpha[(y * 4 + x) as usize]])
+        }));
+        let map = import_image(&img, &params).unwrap();
+        for ((&alt, &basement), &alpha) in map.alt.iter().zip(map.basement.iter()).zip(&alpha) {
+            let (expected, expected_basement) = match alpha {
+                0 => (200.0, 200.0),
+                255 => (-41.0, -41.0 - WATER_BASEMENT_DEPTH),
+                _ => (-10.4, -10.4 - WATER_BASEMENT_DEPTH),
+            };
+            assert!((alt - expected).abs() < 1e-9, "{} != {}", alt, expected);
+            assert!((basement - expected_basement).abs() < 1e-9);
+        }
+
+        params.basement = Some(ProportionalBasement {
+            sea_level: 0.0,
+            factor: 0.5,
+        });
+        let map = import_image(&img, &params).unwrap();
+        assert_eq!(map.basement[0], 100.0);
+        assert_eq!(map.basement[5], map.alt[5] - WATER_BASEMENT_DEPTH);
+
+        let opaque = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(4, 4, Rgb([200, 0, 0])));
+        assert!(matches!(
+            import_image(&opaque, &params),
+            Err(Error::UnsupportedImage(_))
+        ));
+    }
+}
@@ -0,0 +1,347 @@
+//! Filling holes in a map, such as erased artifacts, with terrain diffused in
+//! from around them.
+
+use super::{
+    Error, map_size,
+    mask::Mask,
+    resample::local_relief,
+    stamp::{PatchCell, PoissonParams, PoissonStamp, relax},
+};
+use crate::sim::ModernMap;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use std::collections::VecDeque;
+use vek::*;
+
+/// Size in cells of the coarsest features of [`InpaintNoise`].
+const NOISE_SCALE: f64 = 4.0;
+
+/// Fractal noise added to inpainted holes, so that they aren't suspiciously
+/// smooth next to the terrain around them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InpaintNoise {
+    pub seed: u32,
+    /// Amplitude of the noise, as a fraction of the mean local relief (the
+    /// altitude range of the 3x3 neighbourhood) of the cells around the hole.
+    pub amplitude: f64,
+    /// Width in cells of the band inside the edge of the hole over which the
+    /// noise fades in, so that the fill still meets the terrain around it.
+    pub ramp: f64,
+}
+
+/// Settings of [`inpaint`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InpaintParams {
+    pub solve: PoissonParams,
+    pub noise: Option<InpaintNoise>,
+}
+
+/// What [`inpaint`] did.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Inpainted {
+    /// Number of cells that were filled.
+    pub filled: usize,
+    /// Solves of the altitudes and of the basement.
+    pub alt: PoissonStamp,
+    pub basement: PoissonStamp,
+}
+
+/// Replaces the cells of the row-major grid of size `size` that are marked
+/// in `hole` with a smooth fill that meets the cells around them: the
+/// solution of the Laplace equation, which is what the altitudes would
+/// settle to if they kept diffusing with those around the hole held fixed.
+///
+/// The fill starts from the mean of the cells around the hole, and is solved
+/// by successive over-relaxation.  Cells that aren't marked are left alone.
+pub fn inpaint_grid(
+    grid: &mut [f64],
+    size: Vec2<usize>,
+    hole: &[bool],
+    params: &PoissonParams,
+) -> PoissonStamp {
+    let mut cells = Vec::new();
+    let (mut sum, mut boundary) = (0.0, 0);
+    let (mut lo, mut hi) = (size, Vec2::zero());
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let i = y * size.x + x;
+            if !hole[i] {
+                continue;
+            }
+            lo = lo.map2(Vec2::new(x, y), usize::min);
+            hi = hi.map2(Vec2::new(x + 1, y + 1), usize::max);
+            let mut cell = PatchCell {
+                i,
+                map: i,
+                free: Vec::new(),
+                fixed: 0.0,
+                neighbours: 0.0,
+            };
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < size.x).then_some(i + 1),
+                (y > 0).then(|| i - size.x),
+                (y + 1 < size.y).then_some(i + size.x),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                cell.neighbours += 1.0;
+                if hole[n] {
+                    cell.free.push(n);
+                } else {
+                    cell.fixed += grid[n];
+                    sum += grid[n];
+                    boundary += 1;
+                }
+            }
+            // Only a map of a single cell has none.
+            if cell.neighbours > 0.0 {
+                cells.push(cell);
+            }
+        }
+    }
+    if boundary > 0 {
+        for cell in &cells {
+            grid[cell.i] = sum / boundary as f64;
+        }
+    }
+    let side = (hi.x.saturating_sub(lo.x)).max(hi.y.saturating_sub(lo.y));
+    relax(grid, &cells, side, params)
+}
+
+/// Number of steps, to any of the eight neighbours, from each cell of the
+/// grid of size `size` to the nearest cell on the other side of the edge of
+/// `hole`.  Cells on either side of the edge are 1 step from it.
+fn edge_distance(hole: &[bool], size: Vec2<usize>) -> Vec<usize> {
+    let neighbours = |i: usize| {
+        let (x, y) = ((i % size.x) as isize, (i / size.x) as isize);
+        (-1..=1)
+            .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(move |&(nx, ny)| {
+                (nx, ny) != (x, y)
+                    && nx >= 0
+                    && ny >= 0
+                    && nx < size.x as isize
+                    && ny < size.y as isize
+            })
+            .map(move |(nx, ny)| ny as usize * size.x + nx as usize)
+    };
+    let mut distance = vec![usize::MAX; hole.len()];
+    let mut queue = VecDeque::new();
+    for i in 0..hole.len() {
+        if neighbours(i).any(|n| hole[n] != hole[i]) {
+            distance[i] = 1;
+            queue.push_back(i);
+        }
+    }
+    while let Some(i) = queue.pop_front() {
+        for n in neighbours(i) {
+            if hole[n] == hole[i] && distance[n] == usize::MAX {
+                distance[n] = distance[i] + 1;
+                queue.push_back(n);
+            }
+        }
+    }
+    distance
+}
+
+/// Erases the cells of `map` with any weight in `mask` and fills them back in
+/// with [`inpaint_grid`], then adds `params.noise`, if any.
+///
+/// The altitudes and the basement are filled independently; the same noise
+/// is added to both, and the basement is lowered wherever it ends up above
+/// the altitude.  Fails if `mask` doesn't match the size of the map, or
+/// covers all of it, leaving nothing to fill it from.
+pub fn inpaint(
+    map: &mut ModernMap,
+    mask: &Mask,
+    params: &InpaintParams,
+) -> Result<Inpainted, Error> {
+    let size = map_size(map);
+    mask.check_size(size)?;
+    let hole = mask.values.iter().map(|&w| w > 0.0).collect::<Vec<_>>();
+    let filled = hole.iter().filter(|&&hole| hole).count();
+    if filled > 0 && filled == hole.len() {
+        return Err(Error::UnsupportedImage(
+            "the mask covers the whole map, leaving nothing to fill it from".to_owned(),
+        ));
+    }
+
+    let alt = inpaint_grid(&mut map.alt, size, &hole, &params.solve);
+    let basement = inpaint_grid(&mut map.basement, size, &hole, &params.solve);
+    if let (Some(noise), true) = (params.noise, filled > 0) {
+        let distance = edge_distance(&hole, size);
+        let relief = local_relief(&map.alt, size);
+        let ring = (0..hole.len())
+            .filter(|&i| !hole[i] && distance[i] as f64 <= noise.ramp.max(1.0))
+            .map(|i| relief[i])
+            .collect::<Vec<_>>();
+        let amplitude = noise.amplitude * ring.iter().sum::<f64>() / ring.len().max(1) as f64;
+        let fbm = Fbm::<Perlin>::new(noise.seed).set_octaves(4);
+        for i in (0..hole.len()).filter(|&i| hole[i]) {
+            let t = if noise.ramp > 0.0 {
+                (distance[i] as f64 / noise.ramp).min(1.0)
+            } else {
+                1.0
+            };
+            let pos = Vec2::new(i % size.x, i / size.x).map(|e| e as f64 / NOISE_SCALE);
+            let offset = fbm.get([pos.x, pos.y]) * amplitude * t * t * (3.0 - 2.0 * t);
+            map.alt[i] += offset;
+            map.basement[i] += offset;
+        }
+    }
+    for i in (0..hole.len()).filter(|&i| hole[i]) {
+        map.basement[i] = map.basement[i].min(map.alt[i]);
+    }
+    Ok(Inpainted {
+        filled,
+        alt,
+        basement,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+
+    fn terrain(x: usize, y: usize) -> f64 {
+        (x as f64 * 0.3).sin() * 40.0 + (y as f64 * 0.2).cos() * 25.0 + x as f64
+    }
+
+    /// A mask of a disc of `radius` cells around cell (20, 12), on a 32x32
+    /// map.
+    fn disc(radius: f64) -> Mask {
+        Mask {
+            size: Vec2::broadcast(32),
+            values: (0..32 * 32)
+                .map(|i| {
+                    let d = Vec2::new(i % 32, i / 32)
+                        .map(|e| e as f64)
+                        .distance(Vec2::new(20.0, 12.0));
+                    if d <= radius { 1.0 } else { 0.0 }
+                })
+                .collect(),
+        }
+    }
+
+    /// Largest difference between the altitudes of neighbouring cells on
+    /// either side of the edge of `mask`.
+    fn edge_step(map: &ModernMap, mask: &Mask) -> f64 {
+        let mut step: f64 = 0.0;
+        for i in 0..32 * 32 {
+            for n in [i + 1, i + 32] {
+                if n < 32 * 32 && (n % 32 != 0 || n == i + 32) && mask.values[i] != mask.values[n] {
+                    step = step.max((map.alt[i] - map.alt[n]).abs());
+                }
+            }
+        }
+        step
+    }
+
+    #[test]
+    fn unmasked_cells_are_untouched() {
+        let original = test_map(Vec2::new(5, 5), terrain);
+        let mut map = test_map(Vec2::new(5, 5), terrain);
+        let mask = disc(6.0);
+        let params = InpaintParams {
+            noise: Some(InpaintNoise {
+                seed: 3,
+                amplitude: 1.0,
+                ramp: 3.0,
+            }),
+            ..Default::default()
+        };
+        let result = inpaint(&mut map, &mask, &params).unwrap();
+        assert!(
+            result.alt.converged && result.basement.converged,
+            "{:?}",
+            result
+        );
+        assert_eq!(
+            result.filled,
+            mask.values.iter().filter(|&&w| w > 0.0).count()
+        );
+        for (i, &weight) in mask.values.iter().enumerate() {
+            if weight == 0.0 {
+                assert_eq!(map.alt[i].to_bits(), original.alt[i].to_bits());
+                assert_eq!(map.basement[i].to_bits(), original.basement[i].to_bits());
+            } else {
+                assert!(map.basement[i] <= map.alt[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn fills_meet_the_terrain_around_them() {
+        // A plane is filled back in exactly.
+        let plane = |x: usize, y: usize| 300.0 + 2.0 * x as f64 - y as f64;
+        let mut map = test_map(Vec2::new(5, 5), plane);
+        let params = InpaintParams {
+            solve: PoissonParams {
+                tolerance: 1e-10,
+                max_iterations: 10_000,
+            },
+            noise: None,
+        };
+        inpaint(&mut map, &disc(8.0), &params).unwrap();
+        for (i, &alt) in map.alt.iter().enumerate() {
+            assert!((alt - plane(i % 32, i / 32)).abs() < 1e-6, "{}: {}", i, alt);
+        }
+
+        // Elsewhere, the fill steps across the edge of the hole by little
+        // more than the terrain steps between its own cells, with or without
+        // noise.
+        let original = test_map(Vec2::new(5, 5), terrain);
+        let slope = (0..32 * 31)
+            .filter(|i| i % 32 != 31)
+            .map(|i| {
+                let d = |n: usize| (original.alt[i] - original.alt[n]).abs();
+                d(i + 1).max(d(i + 32))
+            })
+            .fold(0.0, f64::max);
+        for noise in [
+            None,
+            Some(InpaintNoise {
+                seed: 7,
+                amplitude: 1.0,
+                ramp: 4.0,
+            }),
+        ] {
+            let mut map = test_map(Vec2::new(5, 5), terrain);
+            let mask = disc(6.0);
+            let params = InpaintParams {
+                noise,
+                ..Default::default()
+            };
+            inpaint(&mut map, &mask, &params).unwrap();
+            let step = edge_step(&map, &mask);
+            assert!(
+                step < 1.25 * slope,
+                "{} for slopes of {} with {:?}",
+                step,
+                slope,
+                noise
+            );
+        }
+    }
+
+    #[test]
+    fn masks_must_leave_something_to_fill_from() {
+        let mut map = test_map(Vec2::new(2, 2), terrain);
+        let full = Mask {
+            size: Vec2::broadcast(4),
+            values: vec![1.0; 16],
+        };
+        assert!(matches!(
+            inpaint(&mut map, &full, &Default::default()),
+            Err(Error::UnsupportedImage(_))
+        ));
+        let wrong_size = Mask {
+            size: Vec2::broadcast(2),
+            values: vec![0.0; 4],
+        };
+        assert!(matches!(
+            inpaint(&mut map, &wrong_size, &Default::default()),
+            Err(Error::SizeMismatch { .. })
+        ));
+    }
+}
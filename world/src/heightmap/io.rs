@@ -0,0 +1,655 @@
+//! Reading and writing `.bin` world files.
+//!
+//! World files may be compressed with zstd, conventionally with a `.bin.zst`
+//! extension; loading detects compressed files by their contents, so they can
+//! be used anywhere uncompressed ones can.
+//!
+//! # On-disk format
+//!
+//! World files have no magic number or header of their own: they are just
+//! bincode's (little-endian, fixed-width) encoding of a [`WorldFile`], which
+//! starts with the `u32` index of its variant, and that index is the only
+//! version information a file carries.  A Veloren 0.7.0 map (variant 1, which
+//! every tool here writes, and the format of the maps shipped with the game)
+//! continues with `map_size_lg` as two `u32`s and `continent_scale_hack` as an
+//! `f64`, followed by the altitudes and then the basement, each as a `u64`
+//! count and that many `f64`s.  A Veloren 0.5.0 map (variant 0) stores only the
+//! altitudes and the basement, of a square map.
+//!
+//! The server can also load legacy maps, from before the variants, which are a
+//! bare [`WorldFileLegacy`] of a 1024x1024 map: the altitudes and basement as
+//! above, with nothing in front of them.  Since their first `u64` is always
+//! 1024 * 1024, which no variant index can begin, [`FileFormat::sniff`] tells
+//! them apart from versioned files, and loading accepts them too.
+//!
+//! Whatever their version, maps are loaded by [`WorldFile::load`], which the
+//! server uses too, as a [`ModernMap`] upgraded by [`WorldFile::into_latest`],
+//! so tools never match on the variants.  A new version is a new variant,
+//! written by [`WorldFile::new`] and sniffed by [`FileFormat`], whose
+//! predecessor's `into_modern` fills in what it adds.
+
+use super::{Error, validate};
+pub use crate::sim::FileFormat;
+use crate::sim::{ModernMap, SNIFFED_LEN, WORLD_FILE_VARIANT, WorldFile, ZSTD_MAGIC};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+use vek::*;
+
+/// Checks that `path` is a regular file that can be opened for reading, so
+/// that tools given a directory, or a file they can't read, say so up front
+/// rather than failing deep inside a decoder.
+pub fn check_input(path: &Path) -> Result<(), Error> {
+    let unreadable = |e| Error::Unreadable(path.to_owned(), e);
+    if !fs::metadata(path).map_err(unreadable)?.is_file() {
+        return Err(Error::NotAFile(path.to_owned()));
+    }
+    File::open(path).map_err(unreadable)?;
+    Ok(())
+}
+
+/// Loads the world file at `path`, converting it to the latest map version.
+///
+/// The map is checked with [`validate`], so its size can safely be used to
+/// index its altitudes.
+pub fn load_map(path: impl AsRef<Path>) -> Result<ModernMap, Error> {
+    let map = load_map_unchecked(path)?;
+    validate(&map)?;
+    Ok(map)
+}
+
+/// Like [`load_map`], but without validating the map, for tools that inspect
+/// broken files.
+///
+/// The file is read like the server reads it, by [`WorldFile::load`]: files
+/// starting with the zstd magic number are decompressed while they are
+/// deserialized, and legacy files are told apart from versioned ones by
+/// [`FileFormat::sniff`].  With the `memmap` feature, the file is memory-mapped
+/// rather than read through a buffer; files that can't be mapped are read
+/// normally.  Mapping loads a 4096x4096 map about 15% faster, but the pages
+/// read stay resident next to the deserialized map until the mapping is
+/// dropped, before returning, so peak memory is about twice the map's size
+/// (514 MiB rather than 259 MiB).  Truncated files produce an error, like
+/// other malformed ones.
+pub fn load_map_unchecked(path: impl AsRef<Path>) -> Result<ModernMap, Error> {
+    #[cfg(feature = "memmap")]
+    {
+        let file = File::open(path.as_ref())?;
+        // SAFETY: The mapping is only read, by bincode, which bounds-checks all
+        // reads against the mapping's length.  The file is opened read-only, and
+        // the tools loading maps don't write them while they are being loaded; a
+        // file changed by another process in the meantime may deserialize into
+        // garbage (or fail to), which is checked like any other input.  A file
+        // truncated by another process while it is mapped is not: reading the
+        // pages past its new end raises SIGBUS, which kills the tool.  Maps are
+        // only mapped for the length of one load, and tools that save them
+        // replace them by renaming (see `write_atomically`) rather than
+        // truncating them, so this takes an outside process shrinking the file.
+        if let Ok(mmap) = unsafe { memmap2::Mmap::map(&file) } {
+            let map = WorldFile::read(&mmap[..]);
+            drop(mmap);
+            return Ok(map?);
+        }
+    }
+    Ok(WorldFile::load(path)?)
+}
+
+/// What the first bytes of a world file tell about it, without loading it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileHeader {
+    /// Whether the file is compressed with zstd.
+    pub compressed: bool,
+    /// Format of the (decompressed) file, if recognized.
+    pub format: Option<FileFormat>,
+    /// Size of the map the file claims to hold, if its format is recognized:
+    /// the `map_size_lg` of 0.7.0 maps, and the size derived from the number
+    /// of cells of older ones.  It is only checked when the map is loaded.
+    pub map_size_lg: Option<Vec2<u32>>,
+}
+
+/// Number of bytes [`read_header`] reads: the variant index, and the size of
+/// the map or its number of cells after it.
+const HEADER_LEN: usize = 12;
+
+/// Reads the [`FileHeader`] of the world file at `path`, decompressing only
+/// as much as needed.
+pub fn read_header(path: impl AsRef<Path>) -> Result<FileHeader, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let compressed = reader.fill_buf()?.starts_with(&ZSTD_MAGIC);
+    let mut prefix = Vec::with_capacity(HEADER_LEN);
+    if compressed {
+        zstd::Decoder::with_buffer(reader)?
+            .take(HEADER_LEN as u64)
+            .read_to_end(&mut prefix)?;
+    } else {
+        reader.take(HEADER_LEN as u64).read_to_end(&mut prefix)?;
+    }
+    let format = FileFormat::sniff(&prefix[..prefix.len().min(SNIFFED_LEN)]);
+    let u32_at = |i: usize| Some(u32::from_le_bytes(*prefix.get(i..)?.first_chunk()?));
+    let u64_at = |i: usize| Some(u64::from_le_bytes(*prefix.get(i..)?.first_chunk()?));
+    let square = |cells: u64| Vec2::broadcast(cells.trailing_zeros() / 2);
+    let map_size_lg = match format {
+        Some(FileFormat::Legacy) => u64_at(0).map(square),
+        Some(FileFormat::Veloren0_5_0) => u64_at(4).map(square),
+        Some(FileFormat::Veloren0_7_0) => u32_at(4).zip(u32_at(8)).map(Vec2::from),
+        None => None,
+    };
+    Ok(FileHeader {
+        compressed,
+        format,
+        map_size_lg,
+    })
+}
+
+/// Whether `path` names a world file, by its `.bin` or `.bin.zst` extension.
+pub fn is_map_path(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(".bin") || name.ends_with(".bin.zst"))
+}
+
+/// `path` with its `.bin` or `.bin.zst` extension replaced by `extension`,
+/// for files derived from a world file.
+pub fn with_map_extension(path: &Path, extension: &str) -> PathBuf {
+    if path.extension().is_some_and(|ext| ext == "zst") {
+        path.with_extension("").with_extension(extension)
+    } else {
+        path.with_extension(extension)
+    }
+}
+
+/// The path under `out_dir` at which `path`, found under `root`, is mirrored,
+/// keeping the directories between them, so that `maps/seeds/42/world.bin`
+/// under `maps` becomes `out/seeds/42/world.bin` under `out`.
+///
+/// The paths are compared as written, so `path` should have been found by
+/// walking `root`, which makes relative and absolute roots behave alike.
+/// Fails if `path` isn't under `root`, or if the part between them holds
+/// `..` or other components that could lead outside `out_dir`.
+pub fn mirrored_path(root: &Path, path: &Path, out_dir: &Path) -> Result<PathBuf, Error> {
+    let escapes = || Error::OutsideRoot {
+        path: path.to_owned(),
+        root: root.to_owned(),
+    };
+    let relative = path.strip_prefix(root).map_err(|_| escapes())?;
+    if relative.as_os_str().is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(escapes());
+    }
+    Ok(out_dir.join(relative))
+}
+
+/// How world files are compressed when they are saved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// zstd compression at `level`, between 1 (fastest) and 22 (smallest).
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    /// Conventional extension of world files saved with this compression.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "bin",
+            Compression::Zstd { .. } => "bin.zst",
+        }
+    }
+}
+
+/// Output of a world file being saved, compressing it if requested.
+pub(crate) enum MapWriter<W: Write> {
+    Plain(W),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> MapWriter<W> {
+    /// Writes a world file to `writer`, such as the one given by
+    /// [`write_atomically`].
+    pub(crate) fn new(writer: W, compression: Compression) -> Result<Self, Error> {
+        Ok(match compression {
+            Compression::None => MapWriter::Plain(writer),
+            Compression::Zstd { level } => MapWriter::Zstd(zstd::Encoder::new(writer, level)?),
+        })
+    }
+
+    /// Ends the compressed stream, if any, and flushes the file.
+    pub(crate) fn finish(self) -> Result<(), Error> {
+        let mut writer = match self {
+            MapWriter::Plain(writer) => writer,
+            MapWriter::Zstd(encoder) => encoder.finish()?,
+        };
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for MapWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            MapWriter::Plain(writer) => writer.write(buf),
+            MapWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            MapWriter::Plain(writer) => writer.flush(),
+            MapWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Saves `map` to `path` as an uncompressed world file of the latest version.
+pub fn save_map(path: impl AsRef<Path>, map: ModernMap) -> Result<(), Error> {
+    save_map_compressed(path, map, Compression::None)
+}
+
+/// Saves `map` to `path` as a world file of the latest version, compressed
+/// with `compression`, atomically like [`write_atomically`].
+pub fn save_map_compressed(
+    path: impl AsRef<Path>,
+    map: ModernMap,
+    compression: Compression,
+) -> Result<(), Error> {
+    write_atomically(path, |writer| write_map(writer, map, compression))
+}
+
+/// Writes `map` to `writer` as a world file, like [`save_map_compressed`].
+fn write_map(writer: impl Write, map: ModernMap, compression: Compression) -> Result<(), Error> {
+    let mut writer = MapWriter::new(writer, compression)?;
+    bincode::serialize_into(&mut writer, &WorldFile::new(map))?;
+    writer.finish()
+}
+
+/// Writes the part of a world file preceding the altitudes, in the layout of
+/// bincode's encoding of `WorldFile::new(map)`.  The altitudes and basement
+/// follow, each written with [`write_grid_len`] and then as little-endian
+/// values.
+pub(crate) fn write_map_header(
+    mut output: impl Write,
+    map_size_lg: Vec2<u32>,
+    continent_scale: f64,
+) -> Result<(), Error> {
+    output.write_all(&WORLD_FILE_VARIANT.to_le_bytes())?;
+    output.write_all(&map_size_lg.x.to_le_bytes())?;
+    output.write_all(&map_size_lg.y.to_le_bytes())?;
+    output.write_all(&continent_scale.to_le_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn write_grid_len(mut output: impl Write, len: usize) -> Result<(), Error> {
+    Ok(output.write_all(&(len as u64).to_le_bytes())?)
+}
+
+/// Saves the map of size `map_size_lg` with altitudes `alt` and a basement
+/// identical to them, exactly like [`save_map_compressed`], but without the
+/// copy of the altitudes that the basement of a [`ModernMap`] would need.
+pub fn save_map_with_alt_basement(
+    path: impl AsRef<Path>,
+    map_size_lg: Vec2<u32>,
+    continent_scale: f64,
+    alt: &[f64],
+    compression: Compression,
+) -> Result<(), Error> {
+    write_atomically(path, |writer| {
+        let mut writer = MapWriter::new(writer, compression)?;
+        write_map_header(&mut writer, map_size_lg, continent_scale)?;
+        for _ in 0..2 {
+            write_grid_len(&mut writer, alt.len())?;
+            for alt in alt {
+                writer.write_all(&alt.to_le_bytes())?;
+            }
+        }
+        writer.finish()
+    })
+}
+
+/// Writes the file at `path` through a buffered writer passed to `write`.
+///
+/// The data goes to a temporary file in the same directory, which is renamed
+/// to `path` once `write` succeeds and the file is synced, and removed if it
+/// fails, so a failed or interrupted write never leaves a partial file at
+/// `path`.  Every world file, image and sidecar the tools write goes through
+/// this.
+pub fn write_atomically(
+    path: impl AsRef<Path>,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let path = path.as_ref();
+    let temp_path = temp_path_for(path);
+    let result = File::create(&temp_path)
+        .map_err(Error::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer
+                .into_inner()
+                .map_err(|e| e.into_error())?
+                .sync_all()?;
+            Ok(())
+        })
+        .and_then(|()| Ok(fs::rename(&temp_path, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Path of the temporary file `path` is written to by [`write_atomically`].
+pub(crate) fn temp_path_for(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{heightmap::test_map, sim::WorldMap_0_5_0};
+
+    #[test]
+    fn mirrored_paths_stay_under_the_output_directory() {
+        let out = Path::new("out");
+        for root in ["maps", "./maps", "/data/maps"] {
+            let path = Path::new(root).join("seeds/42/world.bin");
+            assert_eq!(
+                mirrored_path(Path::new(root), &path, out).unwrap(),
+                Path::new("out/seeds/42/world.bin")
+            );
+        }
+        for (root, path) in [
+            ("maps", "maps/../secret.bin"),
+            ("maps", "maps/a/../../b.bin"),
+            ("maps", "other/world.bin"),
+            ("/data/maps", "data/maps/world.bin"),
+            ("maps", "maps"),
+        ] {
+            assert!(
+                matches!(
+                    mirrored_path(Path::new(root), Path::new(path), out),
+                    Err(Error::OutsideRoot { .. })
+                ),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn inputs_must_be_readable_files() {
+        let dir = std::env::temp_dir();
+        assert!(matches!(check_input(&dir), Err(Error::NotAFile(path)) if path == dir));
+        let missing = dir.join(format!(
+            "veloren-heightmap-missing-{}.png",
+            std::process::id()
+        ));
+        assert!(matches!(
+            check_input(&missing),
+            Err(Error::Unreadable(path, e))
+                if path == missing && e.kind() == std::io::ErrorKind::NotFound
+        ));
+        let file = dir.join(format!(
+            "veloren-heightmap-input-{}.png",
+            std::process::id()
+        ));
+        fs::write(&file, b"").unwrap();
+        assert!(check_input(&file).is_ok());
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn truncated_files_fail_cleanly() {
+        let path = std::env::temp_dir().join(format!(
+            "veloren-heightmap-truncated-{}.bin",
+            std::process::id()
+        ));
+        save_map(&path, test_map(Vec2::new(2, 2), |x, y| (x + y) as f64)).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert!(load_map(&path).is_ok());
+
+        // Each length is cut from the whole file, as growing a file cut
+        // shorter would pad it with zeros, which read as an empty 0.5.0 map.
+        for truncated_len in [0, 3, 20, bytes.len() / 2, bytes.len() - 1] {
+            fs::write(&path, &bytes[..truncated_len]).unwrap();
+            assert!(
+                matches!(load_map(&path), Err(Error::Bincode(_))),
+                "{} bytes",
+                truncated_len
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn alt_basement_is_written_like_a_copy() {
+        let dir = std::env::temp_dir();
+        let copied = dir.join(format!(
+            "veloren-heightmap-copied-{}.bin",
+            std::process::id()
+        ));
+        let shared = dir.join(format!(
+            "veloren-heightmap-shared-{}.bin",
+            std::process::id()
+        ));
+        let mut map = test_map(Vec2::new(2, 1), |x, y| x as f64 * 1.5 - y as f64);
+        map.basement = map.alt.clone();
+
+        save_map_with_alt_basement(&shared, map.map_size_lg, 1.0, &map.alt, Compression::None)
+            .unwrap();
+        save_map(&copied, map).unwrap();
+        assert_eq!(
+            std::fs::read(&shared).unwrap(),
+            std::fs::read(&copied).unwrap()
+        );
+        std::fs::remove_file(&shared).unwrap();
+        std::fs::remove_file(&copied).unwrap();
+    }
+
+    #[test]
+    fn serialization_is_deterministic() {
+        let maps = || {
+            let mut edge_cases = test_map(Vec2::new(5, 4), |x, y| {
+                (x as f64 * 0.37).sin() * 1e3 + y as f64 / 3.0
+            });
+            edge_cases.alt[0] = -0.0;
+            edge_cases.alt[1] = f64::MIN_POSITIVE / 2.0;
+            edge_cases.basement[2] = f64::MAX;
+            let hills = crate::heightmap::hills::HillParams::default()
+                .generate_map(Vec2::new(6, 6), 10, 42, 1.6)
+                .unwrap();
+            [edge_cases, hills]
+        };
+        for (first, second) in maps().into_iter().zip(maps()) {
+            let bytes = bincode::serialize(&WorldFile::new(first)).unwrap();
+            assert_eq!(bincode::serialize(&WorldFile::new(second)).unwrap(), bytes);
+
+            let decoded: WorldFile = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(bincode::serialize(&decoded).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn formats_are_sniffed_from_the_first_bytes() {
+        let shipped = Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets/world/map");
+        for name in [
+            "veloren_0_9_0_0.bin",
+            "veloren_0_16_0_0.bin",
+            "veloren_0_18_0_0.bin",
+        ] {
+            assert_eq!(
+                read_header(shipped.join(name)).unwrap(),
+                FileHeader {
+                    compressed: false,
+                    format: Some(FileFormat::Veloren0_7_0),
+                    map_size_lg: Some(Vec2::new(10, 10)),
+                },
+                "{}",
+                name
+            );
+        }
+
+        let mut header = Vec::new();
+        write_map_header(&mut header, Vec2::new(10, 10), 1.0).unwrap();
+        assert_eq!(FileFormat::sniff(&header), Some(FileFormat::Veloren0_7_0));
+        let old = [0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 0, 0];
+        assert_eq!(FileFormat::sniff(&old), Some(FileFormat::Veloren0_5_0));
+        let legacy = (1024 * 1024u64).to_le_bytes();
+        assert_eq!(FileFormat::sniff(&legacy), Some(FileFormat::Legacy));
+        assert_eq!(FileFormat::sniff(&[2, 0, 0, 0]), None);
+        assert_eq!(FileFormat::sniff(&[1, 0]), None);
+    }
+
+    #[test]
+    fn legacy_files_load_like_versioned_ones() {
+        let dir = std::env::temp_dir();
+        let path = |name| dir.join(format!("veloren-heightmap-{}-{}", name, std::process::id()));
+        let (versioned, legacy, compressed) = (
+            path("versioned.bin"),
+            path("legacy.bin"),
+            path("legacy.bin.zst"),
+        );
+        let slope = |x, y| (x * 3 + y) as f64 * 0.5;
+        save_map(&versioned, test_map(Vec2::new(10, 10), slope)).unwrap();
+        let map = test_map(Vec2::new(10, 10), slope);
+        // A legacy file is a 1024x1024 versioned one without the variant
+        // index, size and continent scale in front of the grids.
+        let bytes = fs::read(&versioned).unwrap();
+        fs::write(&legacy, &bytes[20..]).unwrap();
+        fs::write(&compressed, zstd::encode_all(&bytes[20..], 0).unwrap()).unwrap();
+
+        assert_eq!(
+            read_header(&versioned).unwrap().map_size_lg,
+            Some(map.map_size_lg)
+        );
+        for path in [&legacy, &compressed] {
+            let header = read_header(path).unwrap();
+            assert_eq!(header.format, Some(FileFormat::Legacy));
+            assert_eq!(header.map_size_lg, Some(map.map_size_lg));
+            let loaded = load_map(path).unwrap();
+            assert_eq!(loaded.map_size_lg, map.map_size_lg);
+            assert_eq!(loaded.continent_scale_hack, map.continent_scale_hack);
+            assert_eq!(loaded.alt, map.alt);
+            assert_eq!(loaded.basement, map.basement);
+        }
+        for path in [versioned, legacy, compressed] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn older_versions_load_as_the_latest() {
+        let path = std::env::temp_dir().join(format!(
+            "veloren-heightmap-0_5_0-{}.bin",
+            std::process::id()
+        ));
+        let latest = test_map(Vec2::new(4, 4), |x, y| (x + y) as f64);
+        let old = WorldFile::Veloren0_5_0(WorldMap_0_5_0 {
+            alt: latest.alt.clone(),
+            basement: latest.basement.clone(),
+        });
+        fs::write(&path, bincode::serialize(&old).unwrap()).unwrap();
+        let header = read_header(&path).unwrap();
+        assert_eq!(header.format, Some(FileFormat::Veloren0_5_0));
+        assert_eq!(header.map_size_lg, Some(latest.map_size_lg));
+        // The size and continent scale missing from 0.5.0 maps are derived
+        // from the number of cells, relative to 1024x1024 maps.
+        let loaded = load_map(&path).unwrap();
+        assert_eq!(loaded.map_size_lg, latest.map_size_lg);
+        assert_eq!(loaded.continent_scale_hack, 1.0 / 64.0);
+        assert_eq!(loaded.alt, latest.alt);
+        assert_eq!(loaded.basement, latest.basement);
+        assert_eq!(WorldFile::load(&path).unwrap().alt, latest.alt);
+        assert_eq!(old.into_latest().unwrap().map_size_lg, latest.map_size_lg);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_leave_no_file() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!(
+            "veloren-heightmap-atomic-{}.png",
+            std::process::id()
+        ));
+
+        let result = write_atomically(&path, |writer| {
+            writer.write_all(&[0; 100_000])?;
+            Err(Error::UnsupportedImage(
+                "simulated encoder failure".to_string(),
+            ))
+        });
+        assert!(matches!(result, Err(Error::UnsupportedImage(_))));
+        assert!(!path.exists());
+        assert!(!temp_path_for(&path).exists());
+
+        write_atomically(&path, |writer| Ok(writer.write_all(b"done")?)).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"done");
+        assert!(!temp_path_for(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A writer that fails once `left` bytes have gone through it, like a
+    /// full disk.
+    struct Failing<W> {
+        inner: W,
+        left: usize,
+    }
+
+    impl<W: Write> Write for Failing<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if buf.len() > self.left {
+                return Err(std::io::Error::other("simulated full disk"));
+            }
+            self.left -= buf.len();
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> { self.inner.flush() }
+    }
+
+    #[test]
+    fn failed_map_writes_leave_no_file() {
+        let path = std::env::temp_dir().join(format!(
+            "veloren-heightmap-failing-{}.bin",
+            std::process::id()
+        ));
+        for compression in [Compression::None, Compression::Zstd { level: 3 }] {
+            let map = test_map(Vec2::new(3, 3), |x, y| (x * y) as f64);
+            let result = write_atomically(&path, |writer| {
+                let failing = Failing {
+                    inner: writer,
+                    left: 100,
+                };
+                write_map(failing, map, compression)
+            });
+            assert!(result.is_err());
+            assert!(!path.exists());
+            assert!(!temp_path_for(&path).exists());
+        }
+    }
+
+    #[test]
+    fn map_extensions_are_replaced_whole() {
+        for (path, expected) in [
+            ("maps/a.bin", "maps/a.json"),
+            ("maps/a.bin.zst", "maps/a.json"),
+            ("maps/a.b.bin", "maps/a.b.json"),
+        ] {
+            assert_eq!(
+                with_map_extension(Path::new(path), "json"),
+                Path::new(expected)
+            );
+        }
+        assert!(is_map_path(Path::new("a.bin")));
+        assert!(is_map_path(Path::new("a.bin.zst")));
+        assert!(!is_map_path(Path::new("a.zst")));
+        assert!(!is_map_path(Path::new("a.png")));
+    }
+}
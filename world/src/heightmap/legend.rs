@@ -0,0 +1,186 @@
+//! Legends: a color bar with labelled ticks beside an exported image, and a
+//! title beneath it, so that readers can tell which altitude a color stands
+//! for.
+
+use super::montage::{BACKGROUND, GLYPH_SIZE, TEXT, draw_text, fit_label};
+use image::{Rgb, RgbImage};
+use vek::*;
+
+/// What a legend shows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Legend {
+    /// Line of text beneath the image, shortened to fit it.
+    pub title: String,
+    /// Values at the bottom and top of the color bar, as shaded in the image.
+    pub min: f64,
+    pub max: f64,
+    /// Most ticks labelled along the bar.
+    pub max_ticks: usize,
+}
+
+/// Round values between `min` and `max`, at most about `max_count` of them,
+/// spaced by 1, 2 or 5 times a power of ten, and the spacing.  A flat range
+/// gets a single tick.
+pub fn ticks(min: f64, max: f64, max_count: usize) -> (Vec<f64>, f64) {
+    let range = max - min;
+    if !range.is_finite() || range <= 0.0 {
+        return (vec![min], 0.0);
+    }
+    let raw = range / max_count.max(1) as f64;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|&step| step >= raw)
+        .unwrap_or(10.0 * magnitude);
+    let first = (min / step).ceil() as i64;
+    let last = (max / step).floor() as i64;
+    // Adding zero turns -0 into 0, which is labelled without a sign.
+    let ticks = (first..=last).map(|i| i as f64 * step + 0.0).collect();
+    (ticks, step)
+}
+
+/// Label of a tick, with as many decimals as the spacing `step` of the ticks
+/// needs.
+pub(crate) fn tick_label(value: f64, step: f64) -> String {
+    let decimals = if step > 0.0 && step < 1.0 {
+        // Less a little, so that steps of exactly 0.1 don't get two.
+        (-step.log10() - 1e-9).ceil() as usize
+    } else {
+        0
+    };
+    format!("{:.*}", decimals, value + 0.0)
+}
+
+/// Returns a copy of `img` with `legend` beside and beneath it: a strip on the
+/// right holding a color bar from `legend.min` at the bottom to `legend.max`
+/// at the top, colored by `color`, with labelled ticks, and a row at the
+/// bottom holding the title.  The canvas grows to fit them, so no terrain is
+/// covered, and text is scaled up on larger images.
+pub fn add_legend(img: &RgbImage, legend: &Legend, color: impl Fn(f64) -> Rgb<u8>) -> RgbImage {
+    let scale = (img.height() / 512 + 1).min(4);
+    let glyph = GLYPH_SIZE * scale;
+    let advance = (GLYPH_SIZE.x + 1) * scale;
+    let gap = 2 * glyph.x;
+    let bar_width = 3 * glyph.y;
+    let tick_length = glyph.x;
+
+    let (ticks, step) = ticks(legend.min, legend.max, legend.max_ticks);
+    let labels = ticks
+        .iter()
+        .map(|&tick| tick_label(tick, step))
+        .collect::<Vec<_>>();
+    let label_width = labels
+        .iter()
+        .map(|label| label.len() as u32)
+        .max()
+        .unwrap_or(0)
+        * advance;
+    let strip_width = gap + bar_width + tick_length + gap + label_width + gap;
+    // Room for the bar to hold every label, however small the image.
+    let content_height = img
+        .height()
+        .max(2 * gap + (ticks.len() as u32 * 2 + 1) * glyph.y);
+    let title_height = glyph.y + 2 * gap;
+    let size = Vec2::new(img.width() + strip_width, content_height + title_height);
+
+    let mut canvas = RgbImage::from_pixel(size.x, size.y, BACKGROUND);
+    for (x, y, pixel) in img.enumerate_pixels() {
+        canvas.put_pixel(x, y, *pixel);
+    }
+
+    // The bar, with its ends half a glyph in so that their labels fit.
+    let bar_x = img.width() + gap;
+    let (top, bottom) = (gap + glyph.y / 2, content_height - gap - glyph.y / 2);
+    let span = (bottom - top).max(1) as f64;
+    let value_at = |y: u32| legend.max - (y - top) as f64 / span * (legend.max - legend.min);
+    for y in top..=bottom {
+        let bar_color = color(value_at(y));
+        for x in bar_x..bar_x + bar_width {
+            canvas.put_pixel(x, y, bar_color);
+        }
+    }
+
+    let y_of = |value: f64| {
+        let t = if legend.max > legend.min {
+            (legend.max - value) / (legend.max - legend.min)
+        } else {
+            0.0
+        };
+        top + (t.clamp(0.0, 1.0) * span).round() as u32
+    };
+    for (&tick, label) in ticks.iter().zip(&labels) {
+        let y = y_of(tick);
+        for x in bar_x + bar_width..bar_x + bar_width + tick_length {
+            canvas.put_pixel(x, y, TEXT);
+        }
+        let label_pos = Vec2::new(
+            bar_x + bar_width + tick_length + gap,
+            y.saturating_sub(glyph.y / 2),
+        );
+        draw_text(&mut canvas, label_pos, label, scale, TEXT);
+    }
+
+    let max_chars = ((size.x - 2 * gap) / advance) as usize;
+    let title = fit_label(&legend.title, max_chars);
+    draw_text(
+        &mut canvas,
+        Vec2::new(gap, content_height + gap),
+        &title,
+        scale,
+        TEXT,
+    );
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_fall_on_round_values() {
+        assert_eq!(
+            ticks(0.0, 1000.0, 5),
+            (vec![0.0, 200.0, 400.0, 600.0, 800.0, 1000.0], 200.0)
+        );
+        assert_eq!(ticks(-37.0, 123.0, 5), (vec![0.0, 50.0, 100.0], 50.0));
+        assert_eq!(ticks(-1.0, 0.0, 2).0, [-1.0, -0.5, 0.0]);
+        assert_eq!(ticks(5.0, 5.0, 5), (vec![5.0], 0.0));
+        assert_eq!(tick_label(0.25, 0.05), "0.25");
+        assert_eq!(tick_label(-0.0, 0.5), "0.0");
+        assert_eq!(tick_label(1200.0, 200.0), "1200");
+    }
+
+    #[test]
+    fn legends_expand_the_canvas() {
+        let terrain = Rgb([10, 200, 30]);
+        let img = RgbImage::from_pixel(64, 48, terrain);
+        let legend = Legend {
+            title: "map.png 64x48".to_owned(),
+            min: -100.0,
+            max: 500.0,
+            max_ticks: 6,
+        };
+        let gray = |alt: f64| {
+            let value = ((alt + 100.0) / 600.0 * 255.0).round() as u8;
+            Rgb([value; 3])
+        };
+        let out = add_legend(&img, &legend, gray);
+        assert!(out.width() > img.width() && out.height() > img.height());
+        // The terrain is untouched.
+        for (x, y, pixel) in img.enumerate_pixels() {
+            assert_eq!(out.get_pixel(x, y), pixel);
+        }
+        // The strip holds the bar, from its highest color at the top to its
+        // lowest at the bottom, and light text.
+        let strip = (img.width()..out.width())
+            .flat_map(|x| (0..out.height()).map(move |y| (x, y)))
+            .map(|(x, y)| *out.get_pixel(x, y))
+            .collect::<Vec<_>>();
+        assert!(strip.contains(&Rgb([255; 3])) && strip.contains(&Rgb([0; 3])));
+        assert!(strip.contains(&TEXT));
+        assert!(!strip.contains(&terrain));
+        // So does the title row.
+        assert!((0..img.width()).any(|x| *out.get_pixel(x, out.height() - 8) == TEXT));
+    }
+}
@@ -0,0 +1,135 @@
+//! Per-cell weights read from grayscale images.
+
+use super::{Error, import::load_image, resample::resample_bilinear};
+use image::{DynamicImage, GenericImageView};
+use std::path::Path;
+use vek::*;
+
+/// A grid of weights between 0 and 1, laid out like map altitudes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mask {
+    pub size: Vec2<usize>,
+    pub values: Vec<f64>,
+}
+
+impl Mask {
+    /// Reads a mask from the red channel of `img`, mapping black to 0 and
+    /// white to 1.  Image row `y` becomes mask row `y`, as for imports.
+    pub fn from_image(img: &DynamicImage) -> Self {
+        let (width, height) = img.dimensions();
+        Self {
+            size: Vec2::new(width as usize, height as usize),
+            values: img
+                .pixels()
+                .map(|(_, _, pixel)| pixel[0] as f64 / 255.0)
+                .collect(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self::from_image(&load_image(path)?))
+    }
+
+    /// Bilinearly resamples the mask to `size`.
+    pub fn resample(&self, size: Vec2<usize>) -> Self {
+        Self {
+            size,
+            values: resample_bilinear(&self.values, self.size, size),
+        }
+    }
+
+    /// Fails unless the mask has size `size`.
+    pub fn check_size(&self, size: Vec2<usize>) -> Result<(), Error> {
+        if self.size != size {
+            return Err(Error::SizeMismatch {
+                expected: size,
+                found: self.size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Spreads the mask outwards by up to `radius` cells, fading out
+    /// smoothly with distance, so that the masked areas get a soft edge.
+    ///
+    /// Each cell's weight becomes the largest of its neighbours' weights, each
+    /// reduced according to its distance; weights inside the masked areas are
+    /// left as they are.  Cells further than `radius` from any non-zero weight
+    /// stay exactly 0.
+    pub fn feathered(&self, radius: f64) -> Self {
+        let r = radius.floor() as isize;
+        if r < 1 {
+            return self.clone();
+        }
+        let (w, h) = (self.size.x as isize, self.size.y as isize);
+        let side = 2 * r + 1;
+        let falloff = (-r..=r)
+            .flat_map(|dy| (-r..=r).map(move |dx| ((dx * dx + dy * dy) as f64).sqrt()))
+            .map(|d| {
+                let t = (1.0 - d / radius).max(0.0);
+                t * t * (3.0 - 2.0 * t)
+            })
+            .collect::<Vec<_>>();
+
+        let at = |x: isize, y: isize| self.values[(y * w + x) as usize];
+        let mut out = self.values.clone();
+        for y in 0..h {
+            for x in 0..w {
+                let value = at(x, y);
+                // A cell surrounded by weights at least as large can't raise
+                // any weight further than its neighbours do: each other cell
+                // is closer to one of those neighbours.
+                let on_edge = [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|(dx, dy)| {
+                    let (nx, ny) = (x + dx, y + dy);
+                    nx >= 0 && ny >= 0 && nx < w && ny < h && at(nx, ny) < value
+                });
+                if value <= 0.0 || !on_edge {
+                    continue;
+                }
+                for dy in -r..=r {
+                    let ny = y + dy;
+                    if ny < 0 || ny >= h {
+                        continue;
+                    }
+                    for dx in -r..=r {
+                        let nx = x + dx;
+                        if nx < 0 || nx >= w {
+                            continue;
+                        }
+                        let weight = value * falloff[((dy + r) * side + dx + r) as usize];
+                        let out = &mut out[(ny * w + nx) as usize];
+                        *out = out.max(weight);
+                    }
+                }
+            }
+        }
+        Self {
+            size: self.size,
+            values: out,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feathering_spreads_outwards_only() {
+        // A single white column in the middle of a 9x3 mask.
+        let mut mask = Mask {
+            size: Vec2::new(9, 3),
+            values: vec![0.0; 27],
+        };
+        for y in 0..3 {
+            mask.values[y * 9 + 4] = 1.0;
+        }
+        let feathered = mask.feathered(4.0);
+        let row = &feathered.values[9..18];
+        assert_eq!(row[4], 1.0);
+        assert!(row[3] > row[2] && row[2] > row[1] && row[1] > 0.0);
+        assert_eq!(row[3], row[5]);
+        assert_eq!(row[0], 0.0);
+        assert_eq!(mask.feathered(0.5), mask);
+    }
+}
@@ -0,0 +1,553 @@
+//! Offline tools for working with the heightmaps stored in `.bin` world
+//! files, as produced by worldgen or by the heightmap conversion examples.
+//!
+//! Altitude grids are stored row-major: the cell at `(x, y)` lives at index
+//! `y * width + x` (see [`common::terrain::vec2_as_uniform_idx`]), so row 0 is
+//! the row with the lowest world y coordinate.
+
+pub mod adjust;
+#[cfg(feature = "gif")] pub mod animate;
+#[cfg(feature = "ndarray")] pub mod array;
+pub mod ascii;
+pub mod biome;
+#[cfg(feature = "cli")] pub mod cli;
+pub mod colormap;
+pub mod combine;
+pub mod diff;
+pub mod export;
+pub mod filter;
+pub mod flatten;
+pub mod grid;
+pub mod hills;
+pub mod hydrology;
+pub mod import;
+pub mod inpaint;
+pub mod io;
+pub mod legend;
+pub mod mask;
+pub mod montage;
+pub mod occlusion;
+pub mod overlay;
+pub mod packed;
+pub mod polyline;
+pub mod profile;
+pub mod provenance;
+pub mod relief;
+pub mod resample;
+pub mod rivers;
+pub mod seamless;
+pub mod seams;
+pub mod shadow;
+pub mod stamp;
+pub mod stats;
+pub mod stream;
+pub mod sweep;
+pub mod template;
+pub mod tile;
+pub mod transform;
+pub mod verify;
+
+pub use self::io::{
+    Compression, FileFormat, check_input, is_map_path, load_map, mirrored_path, read_header,
+    save_map, save_map_compressed, save_map_with_alt_basement, with_map_extension,
+};
+
+use crate::sim::{LoadError, ModernMap, WorldFileError};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{fmt, fs::File, io::BufReader, path::Path};
+use vek::*;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// An input path doesn't exist, or can't be opened for reading.
+    Unreadable(std::path::PathBuf, std::io::Error),
+    /// An input path is a directory, or something else that isn't a regular
+    /// file.
+    NotAFile(std::path::PathBuf),
+    /// A path found while walking a directory isn't under it, or would be
+    /// mirrored outside the output directory.
+    OutsideRoot {
+        path: std::path::PathBuf,
+        root: std::path::PathBuf,
+    },
+    Bincode(bincode::Error),
+    Json(serde_json::Error),
+    Image(image::ImageError),
+    Png(png::DecodingError),
+    /// The image can't be converted to a map, since maps must be square with
+    /// power-of-two sides.
+    ImageSize {
+        width: u32,
+        height: u32,
+    },
+    /// The image can be read, but not by the operation at hand.
+    UnsupportedImage(String),
+    /// An Esri ASCII grid is malformed or can't be converted to a map.
+    AsciiGrid(String),
+    /// A list of sites to flatten is malformed.
+    SiteList(String),
+    /// A list of markers to draw over an export is malformed.
+    Markers(String),
+    /// A list of polylines to draw into a map is malformed.
+    Polylines(String),
+    /// A colormap is malformed.
+    Colormap(String),
+    /// The ranges of a parameter sweep are malformed, or give too many
+    /// combinations.
+    Sweep(String),
+    /// The route of an elevation profile is malformed or leaves the map.
+    Profile(String),
+    /// A template naming output files is malformed, or names several of them
+    /// alike.
+    NameTemplate(String),
+    /// The world file could not be converted to the latest map version.
+    WorldFile(WorldFileError),
+    /// The requested tile size does not evenly divide the map.
+    TileSize {
+        tile_size: u32,
+        map_size: Vec2<usize>,
+    },
+    /// A set of tiles does not describe a complete, consistent map.
+    TileLayout(String),
+    /// The requested scaling factor isn't a power of two, or would shrink the
+    /// map below a single cell.
+    ScaleFactor {
+        factor: u32,
+        map_size: Vec2<usize>,
+    },
+    /// An operation produced infinite or NaN altitudes.
+    NonFinite,
+    /// The map's `map_size_lg` describes more cells than can be indexed, or
+    /// than [`MAX_MAP_CELLS`].
+    SizeOverflow {
+        map_size_lg: Vec2<u32>,
+    },
+    /// The number of altitudes stored in the map doesn't match its size.
+    GridLength {
+        expected: usize,
+        found: usize,
+    },
+    /// An array converted to a map doesn't have a power of two rows and
+    /// columns.
+    ArrayShape {
+        rows: usize,
+        columns: usize,
+    },
+    /// Two grids that should line up have different sizes.
+    SizeMismatch {
+        expected: Vec2<usize>,
+        found: Vec2<usize>,
+    },
+    /// A window onto a map extends beyond its edges.
+    Window {
+        window: grid::Window,
+        map_size: Vec2<usize>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Unreadable(path, e) => write!(f, "Could not read {}: {}", path.display(), e),
+            Error::NotAFile(path) => write!(f, "Input is not a file: {}", path.display()),
+            Error::OutsideRoot { path, root } => write!(
+                f,
+                "{} is not inside {}, so its output can't be placed under the output directory",
+                path.display(),
+                root.display()
+            ),
+            Error::Bincode(e) => write!(f, "Could not (de)serialize world file: {}", e),
+            Error::Json(e) => write!(f, "Could not (de)serialize JSON: {}", e),
+            Error::Image(e) => write!(f, "Could not read or write image: {}", e),
+            Error::Png(e) => write!(f, "Could not read PNG: {}", e),
+            Error::ImageSize { width, height } if width != height => write!(
+                f,
+                "Image width and height must be equal (found {}x{})",
+                width, height
+            ),
+            Error::ImageSize { width, height } => write!(
+                f,
+                "Image width (and height) must be a power of two (found {}x{})",
+                width, height
+            ),
+            Error::UnsupportedImage(reason) => write!(f, "Unsupported image: {}", reason),
+            Error::AsciiGrid(reason) => write!(f, "Invalid ASCII grid: {}", reason),
+            Error::SiteList(reason) => write!(f, "Invalid site list: {}", reason),
+            Error::Markers(reason) => write!(f, "Invalid marker list: {}", reason),
+            Error::Polylines(reason) => write!(f, "Invalid polylines: {}", reason),
+            Error::Colormap(reason) => write!(f, "Invalid colormap: {}", reason),
+            Error::Sweep(reason) => write!(f, "Invalid sweep: {}", reason),
+            Error::Profile(reason) => write!(f, "Invalid profile: {}", reason),
+            Error::NameTemplate(reason) => write!(f, "Invalid name template: {}", reason),
+            Error::WorldFile(e) => write!(f, "Invalid world file: {:?}", e),
+            Error::TileSize {
+                tile_size,
+                map_size,
+            } => write!(
+                f,
+                "Tile size {} does not evenly divide the {}x{} map",
+                tile_size, map_size.x, map_size.y
+            ),
+            Error::TileLayout(reason) => write!(f, "Invalid tile layout: {}", reason),
+            Error::ScaleFactor { factor, map_size } => write!(
+                f,
+                "Can't scale the {}x{} map by a factor of {}: factors must be powers of two, and \
+                 can't shrink a map below a single cell",
+                map_size.x, map_size.y, factor
+            ),
+            Error::NonFinite => write!(f, "Result contains non-finite altitudes, not writing it"),
+            Error::SizeOverflow { map_size_lg } => write!(
+                f,
+                "Map size 2^{}x2^{} is too large (at most {} cells are supported)",
+                map_size_lg.x, map_size_lg.y, MAX_MAP_CELLS
+            ),
+            Error::GridLength { expected, found } => write!(
+                f,
+                "Map should have {} cells, but stores {}",
+                expected, found
+            ),
+            Error::ArrayShape { rows, columns } => write!(
+                f,
+                "Arrays must have a power of two rows and columns to become a map (found {}x{})",
+                rows, columns
+            ),
+            Error::SizeMismatch { expected, found } => write!(
+                f,
+                "Expected a {}x{} grid, found {}x{}",
+                expected.x, expected.y, found.x, found.y
+            ),
+            Error::Window { window, map_size } => write!(
+                f,
+                "The {}x{} window at ({}, {}) extends beyond the {}x{} map",
+                window.size.x,
+                window.size.y,
+                window.offset.x,
+                window.offset.y,
+                map_size.x,
+                map_size.y
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self { Error::Io(e) }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self { Error::Bincode(e) }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self { Error::Json(e) }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self { Error::Image(e) }
+}
+
+impl From<png::DecodingError> for Error {
+    fn from(e: png::DecodingError) -> Self { Error::Png(e) }
+}
+
+impl From<WorldFileError> for Error {
+    fn from(e: WorldFileError) -> Self { Error::WorldFile(e) }
+}
+
+impl From<LoadError> for Error {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::Io(e) => Error::Io(e),
+            LoadError::Bincode(e) => Error::Bincode(e),
+            LoadError::WorldFile(e) => Error::WorldFile(e),
+        }
+    }
+}
+
+/// Largest number of cells a map may have: that of the largest map the engine
+/// supports, 2^14 by 2^14 (see [`common::terrain::MapSizeLg`]).
+pub const MAX_MAP_CELLS: usize = 1 << 28;
+
+/// Size of the map's altitude grid, in cells.
+///
+/// Only meaningful for maps that pass [`validate`], which all maps returned by
+/// [`load_map`] do.
+pub fn map_size(map: &ModernMap) -> Vec2<usize> { map.map_size_lg.map(|e| 1 << e) }
+
+/// The size, in cells, of a grid of `2^map_size_lg` cells per axis, failing
+/// with [`Error::SizeOverflow`] if it can't be computed without overflow or
+/// has more than [`MAX_MAP_CELLS`] cells.
+pub fn checked_map_size(map_size_lg: Vec2<u32>) -> Result<Vec2<usize>, Error> {
+    let overflow = || Error::SizeOverflow { map_size_lg };
+    let width = 1usize.checked_shl(map_size_lg.x).ok_or_else(overflow)?;
+    let height = 1usize.checked_shl(map_size_lg.y).ok_or_else(overflow)?;
+    width
+        .checked_mul(height)
+        .filter(|&cells| cells <= MAX_MAP_CELLS)
+        .ok_or_else(overflow)?;
+    Ok(Vec2::new(width, height))
+}
+
+/// Checks that the size of `map` can be computed without overflow, is at most
+/// [`MAX_MAP_CELLS`], and matches the number of altitudes it stores.
+pub fn validate(map: &ModernMap) -> Result<(), Error> {
+    let cells = checked_map_size(map.map_size_lg)?.product();
+    for len in [map.alt.len(), map.basement.len()] {
+        if len != cells {
+            return Err(Error::GridLength {
+                expected: cells,
+                found: len,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Signs that a valid map is probably not the one that was intended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Warning {
+    /// The altitudes barely vary, as when a solid-colored image, or the wrong
+    /// channel of one, was converted.
+    Flat { std_dev: f64 },
+    /// The rivers image didn't match the size of the heightmap, and was
+    /// resampled to fit it.
+    RiversResampled {
+        found: Vec2<usize>,
+        expected: Vec2<usize>,
+    },
+    /// Some altitudes are further from sea level than `limit`, as when the
+    /// scale or offset of a conversion is far too large.
+    OutOfRange { min: f64, max: f64, limit: f64 },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::Flat { std_dev } => write!(
+                f,
+                "Altitudes barely vary (standard deviation {:.3}); the map is probably degenerate",
+                std_dev
+            ),
+            Warning::RiversResampled { found, expected } => write!(
+                f,
+                "Rivers image is {}x{} rather than {}x{} like the heightmap; resampled it to fit",
+                found.x, found.y, expected.x, expected.y
+            ),
+            Warning::OutOfRange { min, max, limit } => write!(
+                f,
+                "Altitudes range from {:.2} to {:.2}, beyond the ±{} the game handles well; check \
+                 the scale and offset",
+                min, max, limit
+            ),
+        }
+    }
+}
+
+/// Standard deviation of the altitudes below which [`warnings`] considers a
+/// map flat by default.
+pub const DEFAULT_MIN_STD_DEV: f64 = 1.0;
+
+/// Distance from sea level beyond which [`warnings`] reports altitudes as
+/// [`Warning::OutOfRange`] by default; the engine clamps, or misrenders,
+/// terrain much further out.
+pub const DEFAULT_MAX_ALTITUDE: f64 = 8192.0;
+
+/// Checks the altitudes of a map for likely mistakes that don't make it
+/// invalid; altitudes with a standard deviation below `min_std_dev` are
+/// reported as [`Warning::Flat`], and any further than `max_altitude` from
+/// sea level as [`Warning::OutOfRange`].
+pub fn warnings(alt: &[f64], min_std_dev: f64, max_altitude: f64) -> Vec<Warning> {
+    stats_warnings(&stats::AltStats::of(alt), min_std_dev, max_altitude)
+}
+
+/// Like [`warnings`], from the statistics of the altitudes, for maps that are
+/// never held in memory.
+pub fn stats_warnings(
+    stats: &stats::AltStats,
+    min_std_dev: f64,
+    max_altitude: f64,
+) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    if stats.std_dev < min_std_dev {
+        warnings.push(Warning::Flat {
+            std_dev: stats.std_dev,
+        });
+    }
+    if stats.min < -max_altitude || stats.max > max_altitude {
+        warnings.push(Warning::OutOfRange {
+            min: stats.min,
+            max: stats.max,
+            limit: max_altitude,
+        });
+    }
+    warnings
+}
+
+pub(crate) fn read_json<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+pub(crate) fn write_json<T: Serialize>(path: impl AsRef<Path>, value: &T) -> Result<(), Error> {
+    io::write_atomically(path, |writer| {
+        Ok(serde_json::to_writer_pretty(writer, value)?)
+    })
+}
+
+#[cfg(test)]
+pub(crate) fn test_map(map_size_lg: Vec2<u32>, f: impl Fn(usize, usize) -> f64) -> ModernMap {
+    let width = 1 << map_size_lg.x;
+    let height = 1 << map_size_lg.y;
+    let alt = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| f(x, y))
+        .collect::<Box<[_]>>();
+    let basement = alt.iter().map(|alt| alt - 10.0).collect();
+    ModernMap {
+        map_size_lg,
+        continent_scale_hack: 1.0,
+        alt,
+        basement,
+    }
+}
+
+/// `cases` random grids of up to 16x16 cells, of any (not just power-of-two)
+/// size, with altitudes between -1000 and 1000, for checking properties that
+/// should hold for any input.  Some grids only use a few distinct altitudes,
+/// so that they have plateaus and sharp steps.
+///
+/// The generator is seeded, so failures are reproducible.
+#[cfg(test)]
+pub(crate) fn random_grids(cases: usize) -> impl Iterator<Item = (Vec2<usize>, Vec<f64>)> {
+    use rand::prelude::*;
+
+    let mut rng = rand_chacha::ChaChaRng::seed_from_u64(0x6d61_7067_656e);
+    (0..cases).map(move |_| {
+        let size = Vec2::new(rng.gen_range(1..=16), rng.gen_range(1..=16));
+        let terraced = rng.gen_bool(0.25);
+        let alt = (0..size.product())
+            .map(|_| {
+                let alt: f64 = rng.gen_range(-1000.0..1000.0);
+                if terraced {
+                    (alt / 250.0).round() * 250.0
+                } else {
+                    alt
+                }
+            })
+            .collect();
+        (size, alt)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    fn map_with_size_lg(map_size_lg: Vec2<u32>, cells: usize) -> ModernMap {
+        ModernMap {
+            map_size_lg,
+            continent_scale_hack: 1.0,
+            alt: vec![0.0; cells].into_boxed_slice(),
+            basement: vec![0.0; cells].into_boxed_slice(),
+        }
+    }
+
+    #[test]
+    fn validate_rejects_overflowing_sizes() {
+        // Sizes whose cell count wraps around (on any target), as well as
+        // ones that merely exceed the cap, must be rejected without trusting
+        // the (tiny) stored grids.
+        for map_size_lg in [
+            Vec2::new(64, 0),
+            Vec2::new(0, u32::MAX),
+            Vec2::new(32, 32),
+            Vec2::new(40, 40),
+            Vec2::new(15, 14),
+        ] {
+            assert!(
+                matches!(
+                    validate(&map_with_size_lg(map_size_lg, 1)),
+                    Err(Error::SizeOverflow { .. })
+                ),
+                "{:?}",
+                map_size_lg
+            );
+        }
+    }
+
+    #[test]
+    fn validate_checks_grid_lengths() {
+        assert!(validate(&map_with_size_lg(Vec2::new(2, 1), 8)).is_ok());
+        assert!(matches!(
+            validate(&map_with_size_lg(Vec2::new(2, 1), 4)),
+            Err(Error::GridLength {
+                expected: 8,
+                found: 4
+            })
+        ));
+
+        let mut map = map_with_size_lg(Vec2::new(1, 1), 4);
+        map.basement = vec![0.0; 3].into_boxed_slice();
+        assert!(matches!(validate(&map), Err(Error::GridLength { .. })));
+    }
+
+    #[test]
+    fn flat_maps_are_warned_about() {
+        let flat = test_map(Vec2::new(2, 2), |x, _| 100.0 + x as f64 * 0.01);
+        assert!(matches!(
+            &*warnings(&flat.alt, DEFAULT_MIN_STD_DEV, DEFAULT_MAX_ALTITUDE),
+            [Warning::Flat { std_dev }] if *std_dev < 0.02
+        ));
+        assert!(warnings(&flat.alt, 0.001, DEFAULT_MAX_ALTITUDE).is_empty());
+
+        let hilly = test_map(Vec2::new(2, 2), |x, y| (x * y) as f64 * 50.0);
+        assert!(warnings(&hilly.alt, DEFAULT_MIN_STD_DEV, DEFAULT_MAX_ALTITUDE).is_empty());
+    }
+
+    #[test]
+    fn extreme_altitudes_are_warned_about() {
+        let deep = test_map(Vec2::new(2, 2), |x, y| (x * y) as f64 * 50.0 - 9000.0);
+        assert_eq!(
+            warnings(&deep.alt, DEFAULT_MIN_STD_DEV, DEFAULT_MAX_ALTITUDE),
+            vec![Warning::OutOfRange {
+                min: -9000.0,
+                max: -8550.0,
+                limit: DEFAULT_MAX_ALTITUDE
+            }]
+        );
+        assert!(warnings(&deep.alt, DEFAULT_MIN_STD_DEV, 9000.0).is_empty());
+    }
+
+    #[test]
+    fn maps_serialize_to_json_arrays() {
+        #[derive(Serialize, Deserialize)]
+        struct Config {
+            name: String,
+            map: ModernMap,
+        }
+
+        // Quarters, which any JSON parser reads back exactly.
+        let map = test_map(Vec2::new(1, 1), |x, y| (x + 2 * y) as f64 * 0.25);
+        let json = serde_json::to_string(&map).unwrap();
+        assert!(json.contains(r#""map_size_lg":{"x":1,"y":1}"#), "{}", json);
+        assert!(json.contains(r#""alt":[0.0,0.25,0.5,0.75]"#), "{}", json);
+
+        let config = Config {
+            name: "debug".to_owned(),
+            map,
+        };
+        let Config { name, map: parsed } =
+            serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(name, "debug");
+        assert_eq!(parsed.map_size_lg, config.map.map_size_lg);
+        assert_eq!(parsed.continent_scale_hack, config.map.continent_scale_hack);
+        let bits = |grid: &[f64]| grid.iter().map(|e| e.to_bits()).collect::<Vec<_>>();
+        assert_eq!(bits(&parsed.alt), bits(&config.map.alt));
+        assert_eq!(bits(&parsed.basement), bits(&config.map.basement));
+    }
+}
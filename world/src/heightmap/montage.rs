@@ -0,0 +1,345 @@
+//! Contact sheets: shaded previews of several maps side by side, each
+//! labelled, for comparing candidate parameters or filters at a glance.
+
+use super::{
+    export::compute_min_max,
+    relief::{ReliefParams, render_relief},
+    resample::{Antialias, resample_antialiased},
+};
+use image::{Rgb, RgbImage};
+use vek::*;
+
+/// Pixels between the tiles of a montage, and around them.
+const GAP: u32 = 4;
+
+pub(crate) const BACKGROUND: Rgb<u8> = Rgb([32, 32, 32]);
+pub(crate) const TEXT: Rgb<u8> = Rgb([235, 235, 235]);
+
+/// Width and height of the glyphs of [`FONT`], in pixels.
+pub(crate) const GLYPH_SIZE: Vec2<u32> = Vec2::new(3, 5);
+
+/// A 3x5 pixel font of upper-case letters, digits and the punctuation common
+/// in file names and parameter strings.  Each row is 3 bits, the most
+/// significant being the leftmost pixel.  Lower-case letters are drawn in
+/// upper case, and other characters as `?`.
+#[rustfmt::skip]
+const FONT: [(char, [u8; 5]); 51] = [
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b110, 0b001, 0b010, 0b100, 0b111]),
+    ('3', [0b110, 0b001, 0b010, 0b001, 0b110]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b110, 0b001, 0b110]),
+    ('6', [0b011, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b110]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('(', [0b010, 0b100, 0b100, 0b100, 0b010]),
+    (')', [0b010, 0b001, 0b001, 0b001, 0b010]),
+    ('[', [0b110, 0b100, 0b100, 0b100, 0b110]),
+    (']', [0b011, 0b001, 0b001, 0b001, 0b011]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+];
+
+fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    FONT.iter()
+        .find(|(g, _)| *g == c)
+        .or_else(|| FONT.last())
+        .map_or([0; 5], |(_, rows)| *rows)
+}
+
+/// Draws `text` into `img` with its top left corner at `pos`, each pixel of
+/// the font `scale` pixels wide, clipping it to the image.
+pub fn draw_text(img: &mut RgbImage, pos: Vec2<u32>, text: &str, scale: u32, color: Rgb<u8>) {
+    let advance = (GLYPH_SIZE.x + 1) * scale;
+    for (i, c) in text.chars().enumerate() {
+        let origin = Vec2::new(pos.x + i as u32 * advance, pos.y);
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_SIZE.x {
+                if bits >> (GLYPH_SIZE.x - 1 - col) & 1 == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (x, y) = (
+                            origin.x + col * scale + dx,
+                            origin.y + row as u32 * scale + dy,
+                        );
+                        if x < img.width() && y < img.height() {
+                            img.put_pixel(x, y, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A map shrunk to fit a tile of a montage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preview {
+    pub alt: Vec<f64>,
+    pub size: Vec2<usize>,
+    /// Width of each cell of the preview, in cells of the map.
+    pub cell_width: f64,
+    pub label: String,
+}
+
+impl Preview {
+    /// Shrinks the altitude grid `alt` of size `size` to fit in a square of
+    /// `tile_size` pixels, keeping its aspect ratio, with a box filter so
+    /// that fine detail is averaged rather than aliased.  Grids that already
+    /// fit are kept as they are.
+    pub fn new(alt: &[f64], size: Vec2<usize>, tile_size: u32, label: impl Into<String>) -> Self {
+        let longest = size.x.max(size.y).max(1);
+        let cell_width = (longest as f64 / tile_size.max(1) as f64).max(1.0);
+        let new_size = size.map(|e| ((e as f64 / cell_width).round() as usize).max(1));
+        let alt = if new_size == size {
+            alt.to_vec()
+        } else {
+            resample_antialiased(alt, size, new_size, Antialias::Box)
+        };
+        Self {
+            alt,
+            size: new_size,
+            cell_width,
+            label: label.into(),
+        }
+    }
+}
+
+/// Layout and shading of a montage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MontageParams {
+    /// Width and height of each tile, in pixels.
+    pub tile_size: u32,
+    /// Number of tiles in each row.
+    pub columns: u32,
+    /// Width of each pixel of the labels' font.
+    pub label_scale: u32,
+    pub relief: ReliefParams,
+}
+
+impl Default for MontageParams {
+    fn default() -> Self {
+        Self {
+            tile_size: 256,
+            columns: 4,
+            label_scale: 2,
+            relief: ReliefParams::default(),
+        }
+    }
+}
+
+impl MontageParams {
+    /// Height of the band below each tile holding its label.
+    fn label_height(&self) -> u32 { (GLYPH_SIZE.y + 2) * self.label_scale }
+
+    /// Width and height of a montage of `count` tiles.
+    pub fn dimensions(&self, count: usize) -> Vec2<u32> {
+        let columns = self.columns.clamp(1, count.max(1) as u32);
+        let rows = (count as u32).div_ceil(columns).max(1);
+        Vec2::new(
+            columns * self.tile_size + (columns + 1) * GAP,
+            rows * (self.tile_size + self.label_height()) + (rows + 1) * GAP,
+        )
+    }
+
+    /// Top left corner of tile `i` of a montage.
+    pub fn tile_origin(&self, i: usize, count: usize) -> Vec2<u32> {
+        let columns = self.columns.clamp(1, count.max(1) as u32);
+        let (col, row) = (i as u32 % columns, i as u32 / columns);
+        Vec2::new(
+            GAP + col * (self.tile_size + GAP),
+            GAP + row * (self.tile_size + self.label_height() + GAP),
+        )
+    }
+}
+
+/// `label`, shortened from the left to at most `max_chars` characters if it
+/// is longer, since names of sweeps and the like differ at their end.
+pub(crate) fn fit_label(label: &str, max_chars: usize) -> String {
+    let count = label.chars().count();
+    if count <= max_chars {
+        return label.to_owned();
+    }
+    let kept = max_chars.saturating_sub(2);
+    let tail = label.chars().skip(count - kept).collect::<String>();
+    format!("{}{}", &"..."[..max_chars.min(2)], tail)
+}
+
+/// Renders `previews` as shaded reliefs in a grid of tiles, in rows of
+/// `params.columns`, each centered in its tile and labelled beneath it.
+///
+/// All previews are tinted over their combined altitude range, so the same
+/// color means the same altitude in every tile.  Their altitudes are divided
+/// by the width of their cells for shading, so that shrinking a map doesn't
+/// steepen its slopes.
+pub fn render_montage(previews: &[Preview], params: &MontageParams) -> RgbImage {
+    let dimensions = params.dimensions(previews.len());
+    let mut img = RgbImage::from_pixel(dimensions.x, dimensions.y, BACKGROUND);
+    let (min, max) = previews
+        .iter()
+        .map(|preview| compute_min_max(&preview.alt))
+        .fold(
+            (f64::INFINITY, f64::NEG_INFINITY),
+            |(min, max), (lo, hi)| (min.min(lo), max.max(hi)),
+        );
+
+    let max_chars = (params.tile_size / ((GLYPH_SIZE.x + 1) * params.label_scale.max(1))) as usize;
+    for (i, preview) in previews.iter().enumerate() {
+        let scaled = preview
+            .alt
+            .iter()
+            .map(|alt| alt / preview.cell_width)
+            .collect::<Vec<_>>();
+        let relief = ReliefParams {
+            sea_level: params.relief.sea_level / preview.cell_width,
+            ..params.relief.clone()
+        };
+        let tile = render_relief(
+            &scaled,
+            preview.size,
+            min / preview.cell_width,
+            max / preview.cell_width,
+            &relief,
+        );
+
+        let origin = params.tile_origin(i, previews.len());
+        let offset = preview
+            .size
+            .map(|e| params.tile_size.saturating_sub(e as u32) / 2);
+        for (x, y, pixel) in tile.enumerate_pixels() {
+            if x < params.tile_size && y < params.tile_size {
+                img.put_pixel(origin.x + offset.x + x, origin.y + offset.y + y, *pixel);
+            }
+        }
+        draw_text(
+            &mut img,
+            Vec2::new(origin.x, origin.y + params.tile_size + params.label_scale),
+            &fit_label(&preview.label, max_chars),
+            params.label_scale,
+            TEXT,
+        );
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    fn hills(size: usize, phase: f64) -> Vec<f64> {
+        (0..size * size)
+            .map(|i| {
+                let (x, y) = ((i % size) as f64, (i / size) as f64);
+                ((x * 0.1 + phase).sin() + (y * 0.13).cos()) * 300.0
+            })
+            .collect()
+    }
+
+    fn tile_hash(img: &RgbImage, origin: Vec2<u32>, side: u32) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for y in origin.y..origin.y + side {
+            for x in origin.x..origin.x + side {
+                img.get_pixel(x, y).0.hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    #[test]
+    fn montages_are_laid_out_in_rows() {
+        let params = MontageParams {
+            tile_size: 32,
+            columns: 2,
+            ..Default::default()
+        };
+        let previews = (0..5)
+            .map(|i| {
+                let alt = hills(128, i as f64);
+                Preview::new(&alt, Vec2::broadcast(128), 32, format!("map_{}.bin", i))
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            previews
+                .iter()
+                .all(|preview| preview.size == Vec2::broadcast(32))
+        );
+        assert_eq!(previews[0].cell_width, 4.0);
+
+        let img = render_montage(&previews, &params);
+        // Two columns and three rows of 32 pixel tiles, each with a 14 pixel
+        // label and 4 pixel gaps.
+        assert_eq!(img.dimensions(), (2 * 32 + 3 * 4, 3 * (32 + 14) + 4 * 4));
+        assert_eq!(params.dimensions(1), Vec2::new(32 + 2 * 4, 32 + 14 + 2 * 4));
+
+        let hashes = (0..5)
+            .map(|i| tile_hash(&img, params.tile_origin(i, 5), 32))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(hashes.len(), 5);
+        // The missing sixth tile is left blank, and labels are drawn.
+        let blank = params.tile_origin(5, 5);
+        assert_eq!(*img.get_pixel(blank.x + 16, blank.y + 16), BACKGROUND);
+        let label = params.tile_origin(0, 5) + Vec2::new(0, 32);
+        assert!(
+            (0..14).any(|dy| (0..32).any(|dx| *img.get_pixel(label.x + dx, label.y + dy) == TEXT))
+        );
+    }
+
+    #[test]
+    fn labels_keep_their_end() {
+        assert_eq!(fit_label("short", 8), "short");
+        assert_eq!(
+            fit_label("hills_scale800_offset-400.bin", 12),
+            "..et-400.bin"
+        );
+        let mut img = RgbImage::from_pixel(8, 5, BACKGROUND);
+        draw_text(&mut img, Vec2::zero(), "i?", 1, TEXT);
+        // The I's top row, the gap after it, and the start of the question
+        // mark, which unknown characters are also drawn as.
+        let row = (0..8)
+            .map(|x| *img.get_pixel(x, 0) == TEXT)
+            .collect::<Vec<_>>();
+        assert_eq!(row, [true, true, true, false, true, true, false, false]);
+        assert_eq!(glyph('~'), glyph('?'));
+    }
+}
@@ -0,0 +1,150 @@
+//! Ambient occlusion: how much of the sky each cell sees past the terrain
+//! around it, which makes valleys and gorges readable where a hillshade only
+//! shows which way slopes face.
+
+use super::{filter::Edges, relief::CELL_WIDTH};
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+use vek::*;
+
+/// Settings of an ambient occlusion bake.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OcclusionParams {
+    /// Number of directions around each cell, evenly spaced from the east,
+    /// along which its horizon is searched; 8 to 16 are enough.
+    pub directions: usize,
+    /// Furthest distance, in cells, at which terrain can block the sky.
+    pub radius: usize,
+    /// Factor the altitudes are multiplied by, to exaggerate shallow terrain
+    /// or tone down steep terrain.
+    pub vertical_scale: f64,
+    /// Whether horizons are searched past the edges of the map, as for
+    /// slopes.
+    pub edges: Edges,
+}
+
+impl Default for OcclusionParams {
+    fn default() -> Self {
+        Self {
+            directions: 16,
+            radius: 32,
+            vertical_scale: 1.0,
+            edges: Edges::Clamp,
+        }
+    }
+}
+
+/// The openness of every cell of the altitude grid of size `size`, in rows
+/// like the altitudes: the fraction of the sky hemisphere above it, by solid
+/// angle, left unblocked by the terrain around it, from 1 on open plains
+/// towards 0 at the bottom of narrow pits.
+///
+/// Along each direction, the horizon is the highest elevation angle of the
+/// cells up to `radius` away, at least level, and the sky below it is
+/// blocked.  This takes `directions * radius` samples a cell, so rows are
+/// baked in parallel.
+pub fn occlusion(alt: &[f64], size: Vec2<usize>, params: &OcclusionParams) -> Vec<f64> {
+    let directions = (0..params.directions.max(1))
+        .map(|i| {
+            let angle = i as f64 / params.directions.max(1) as f64 * std::f64::consts::TAU;
+            Vec2::new(angle.cos(), angle.sin())
+        })
+        .collect::<Vec<_>>();
+    let mut openness = vec![0.0; size.product()];
+    openness
+        .par_chunks_mut(size.x.max(1))
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, openness) in row.iter_mut().enumerate() {
+                let here = alt[y * size.x + x] * params.vertical_scale;
+                let visible = directions
+                    .iter()
+                    .map(|dir| {
+                        let mut horizon = 0.0f64;
+                        for step in 1..=params.radius {
+                            let offset = (*dir * step as f64).map(|e| e.round() as isize);
+                            let (Some(x), Some(y)) = (
+                                params.edges.neighbor(x, offset.x, size.x),
+                                params.edges.neighbor(y, offset.y, size.y),
+                            ) else {
+                                break;
+                            };
+                            let rise = alt[y * size.x + x] * params.vertical_scale - here;
+                            let run = offset.map(|e| e as f64).magnitude() * CELL_WIDTH;
+                            horizon = horizon.max(rise.atan2(run));
+                        }
+                        1.0 - horizon.sin()
+                    })
+                    .sum::<f64>();
+                *openness = visible / directions.len() as f64;
+            }
+        });
+    openness
+}
+
+/// Renders the `openness` of a grid of size `size`, from [`occlusion`], in
+/// grayscale, from black for cells seeing no sky to white for open ones.
+pub fn render_occlusion(openness: &[f64], size: Vec2<usize>) -> RgbImage {
+    RgbImage::from_fn(size.x as u32, size.y as u32, |x, y| {
+        Rgb([gray(openness[y as usize * size.x + x as usize]); 3])
+    })
+}
+
+/// Gray level of `openness` in [`render_occlusion`].
+pub fn gray(openness: f64) -> u8 { (openness.clamp(0.0, 1.0) * 255.0).round() as u8 }
+
+/// Darkens `img`, such as a shaded relief of the same grid, by multiplying it
+/// by the `light` of each cell, from 0 to 1, such as its openness.
+pub fn multiply(img: &mut RgbImage, light: &[f64]) {
+    for (pixel, &light) in img.pixels_mut().zip(light) {
+        pixel.0 = pixel
+            .0
+            .map(|c| (c as f64 * light.clamp(0.0, 1.0)).round() as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pits_are_darker_at_the_bottom_than_on_the_rim() {
+        // A hemispherical pit 20 cells across, in flat ground.
+        let size = Vec2::new(64, 64);
+        let radius = 20.0 * CELL_WIDTH;
+        let alt = (0..size.product())
+            .map(|i| {
+                let pos = Vec2::new((i % 64) as f64 - 32.0, (i / 64) as f64 - 32.0) * CELL_WIDTH;
+                -(radius * radius - pos.magnitude_squared()).max(0.0).sqrt()
+            })
+            .collect::<Vec<_>>();
+        let params = OcclusionParams {
+            directions: 8,
+            radius: 24,
+            ..OcclusionParams::default()
+        };
+        let openness = occlusion(&alt, size, &params);
+        let at = |x: usize, y: usize| openness[y * 64 + x];
+        let (bottom, rim, plain) = (at(32, 32), at(32, 12), at(2, 2));
+        assert!(bottom < rim && rim <= plain, "{} {} {}", bottom, rim, plain);
+        // From the bottom, the rim rises 45 degrees all around.
+        assert!((bottom - (1.0 - std::f64::consts::FRAC_1_SQRT_2)).abs() < 0.05);
+        assert_eq!(plain, 1.0);
+
+        let img = render_occlusion(&openness, size);
+        assert!(img.get_pixel(32, 32)[0] < img.get_pixel(32, 12)[0]);
+        assert_eq!(*img.get_pixel(2, 2), Rgb([255; 3]));
+
+        // Exaggerating the terrain deepens the shadows.
+        let deeper = occlusion(&alt, size, &OcclusionParams {
+            vertical_scale: 2.0,
+            ..params
+        });
+        assert!(deeper[32 * 64 + 32] < bottom);
+
+        let mut relief = RgbImage::from_pixel(64, 64, Rgb([200, 100, 50]));
+        multiply(&mut relief, &openness);
+        assert_eq!(*relief.get_pixel(2, 2), Rgb([200, 100, 50]));
+        assert!(relief.get_pixel(32, 32)[0] < 100);
+    }
+}
@@ -0,0 +1,408 @@
+//! Overlays drawn over exported images to help locate places on the map, such
+//! as a grid of chunk lines labelled with their world coordinates, and
+//! labelled markers at points of interest.
+//!
+//! Exported images are laid out like the altitude grids they are rendered
+//! from: pixel `(x, y)` shows the cell at world chunk `(x, y)`, so x grows to
+//! the right and y grows downwards, from the first row of the grid at the top
+//! of the image to the last (the north of the world) at the bottom.  Images
+//! of a [`Window`] of the map are shifted by its offset.  Overlays place
+//! things with [`cell_to_pixel`], so that they line up with each other and
+//! with the terrain at every resolution.
+
+use super::{
+    Error,
+    colormap::parse_hex,
+    grid::Window,
+    montage::{BACKGROUND, GLYPH_SIZE, TEXT, draw_text},
+};
+use image::{Rgb, RgbImage};
+use std::path::Path;
+use vek::*;
+
+/// Blocks along each side of a cell, which is one chunk of the world.
+pub const BLOCKS_PER_CELL: u32 = 32;
+
+/// Color that grid lines fade the terrain towards.
+const LINE: Rgb<u8> = Rgb([255, 255, 255]);
+
+/// Position in an image of `img_size` pixels, showing the cells of `view`
+/// (the whole map, or a window of it), of the corner of the cell at `pos`, in
+/// the cells of the map.  Images smaller than the view, such as pyramid
+/// levels, are scaled down to fit.
+pub fn cell_to_pixel(pos: Vec2<f64>, view: Window, img_size: Vec2<u32>) -> Vec2<f64> {
+    (pos - view.offset.map(|e| e as f64)) * img_size.map(f64::from)
+        / view.size.map(|e| e.max(1) as f64)
+}
+
+/// Position in blocks of the corner of the cell at `cell`.
+pub fn cell_to_block(cell: u32) -> u32 { cell * BLOCKS_PER_CELL }
+
+/// Scale of the font of overlay labels on `img`, larger on larger images.
+fn text_scale(img: &RgbImage) -> u32 { (img.height() / 512 + 1).min(4) }
+
+/// Draws `text` with its top left corner at `pos` on a dark backing, so that
+/// it stays legible over any terrain.
+fn draw_label(img: &mut RgbImage, pos: Vec2<u32>, text: &str, scale: u32) {
+    let size = Vec2::new(
+        text.len() as u32 * (GLYPH_SIZE.x + 1) * scale + scale,
+        (GLYPH_SIZE.y + 2) * scale,
+    );
+    for y in pos.y..(pos.y + size.y).min(img.height()) {
+        for x in pos.x..(pos.x + size.x).min(img.width()) {
+            img.put_pixel(x, y, BACKGROUND);
+        }
+    }
+    draw_text(img, pos + scale, text, scale, TEXT);
+}
+
+/// A grid of lines every `spacing` cells, blended over the terrain with
+/// `opacity`, from 0 for invisible to 1 for opaque.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Grid {
+    pub spacing: u32,
+    pub opacity: f64,
+}
+
+impl Grid {
+    /// Cells at which the lines across an axis lie between the cells `start`
+    /// and `end`, exclusive: every multiple of the spacing, counted from the
+    /// edge of the map at 0.
+    pub fn lines(&self, start: usize, end: usize) -> impl Iterator<Item = u32> + use<> {
+        let spacing = self.spacing.max(1) as usize;
+        (start.div_ceil(spacing) * spacing..end)
+            .step_by(spacing)
+            .map(|cell| cell as u32)
+    }
+}
+
+/// Label of the grid line at `cell`, in cells and in blocks.
+fn grid_label(cell: u32) -> String { format!("{}/{}", cell, cell_to_block(cell)) }
+
+/// Draws `grid` over `img`, which shows the cells of `view`, and labels its
+/// lines along the top and left edges with their coordinates as `CELL/BLOCK`.
+/// Labels sit on a dark backing so that they stay legible over any terrain, and
+/// are thinned out where the lines are too close for all of them to fit.
+pub fn draw_grid(img: &mut RgbImage, view: Window, grid: &Grid) {
+    let img_size = Vec2::new(img.width(), img.height());
+    // The pixel row and column of the lines through the corner of `cell`.
+    let to_pixel = |cell: u32| {
+        let pos = cell_to_pixel(Vec2::broadcast(cell as f64), view, img_size);
+        pos.map2(img_size, |e, max| {
+            (e.round() as u32).min(max.saturating_sub(1))
+        })
+    };
+    let end = view.offset + view.size;
+    let columns = grid.lines(view.offset.x, end.x).map(|x| (x, to_pixel(x).x));
+    let rows = grid.lines(view.offset.y, end.y).map(|y| (y, to_pixel(y).y));
+    let (columns, rows) = (columns.collect::<Vec<_>>(), rows.collect::<Vec<_>>());
+
+    let alpha = grid.opacity.clamp(0.0, 1.0);
+    let fade = |pixel: &mut Rgb<u8>| {
+        for (c, line) in pixel.0.iter_mut().zip(LINE.0) {
+            *c = (*c as f64 + (line as f64 - *c as f64) * alpha).round() as u8;
+        }
+    };
+    for &(_, x) in &columns {
+        for y in 0..img_size.y {
+            fade(img.get_pixel_mut(x, y));
+        }
+    }
+    for &(_, y) in &rows {
+        for x in 0..img_size.x {
+            // Crossings are faded once, like the rest of the lines.
+            if columns.iter().all(|&(_, column)| column != x) {
+                fade(img.get_pixel_mut(x, y));
+            }
+        }
+    }
+
+    let scale = text_scale(img);
+    let glyph = GLYPH_SIZE * scale;
+    let advance = (GLYPH_SIZE.x + 1) * scale;
+    // Every line's label, or every second, fourth... where they would touch.
+    let every = |lines: &[(u32, u32)], extent: u32| {
+        let gap = match lines {
+            [(_, a), (_, b), ..] => (b - a).max(1),
+            _ => return 1,
+        };
+        (extent + glyph.x).div_ceil(gap).next_power_of_two() as usize
+    };
+    let widest = columns
+        .last()
+        .map_or(0, |&(x, _)| grid_label(x).len() as u32);
+    let step = every(&columns, widest * advance + scale);
+    for &(x, pixel) in columns.iter().step_by(step) {
+        draw_label(img, Vec2::new(pixel + 1, 0), &grid_label(x), scale);
+    }
+    // Rows whose labels would cover those of the columns, such as the one
+    // along the top edge, go unlabelled.
+    let band = glyph.y + 2 * scale;
+    let step = every(&rows, band);
+    for &(y, pixel) in rows
+        .iter()
+        .step_by(step)
+        .filter(|&&(_, pixel)| pixel + 1 >= band)
+    {
+        draw_label(img, Vec2::new(0, pixel + 1), &grid_label(y), scale);
+    }
+}
+
+/// Units of the coordinates of markers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Units {
+    /// Cells of the map, which are chunks of the world.
+    #[default]
+    Cells,
+    /// Blocks of the world, [`BLOCKS_PER_CELL`] to a cell.
+    Blocks,
+}
+
+/// Color of markers not given one.
+const MARKER: Rgb<u8> = Rgb([230, 40, 40]);
+
+/// A point of interest drawn over an export, at `pos` in cells.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+    pub pos: Vec2<f64>,
+    pub label: String,
+    pub color: Rgb<u8>,
+}
+
+impl Marker {
+    /// Whether the marker lies on a map of `map_size` cells.
+    pub fn is_inside(&self, map_size: Vec2<usize>) -> bool {
+        self.pos
+            .map2(map_size, |e, size| (0.0..size as f64).contains(&e))
+            .reduce_and()
+    }
+}
+
+/// Parses a list of markers, one per line: `x, y, label, color`, with `x` and
+/// `y` in `units`, an optional label and an optional `#RRGGBB` color.  Blank
+/// lines and lines starting with `#` are skipped, as is a header line
+/// starting with `x, y`, as spreadsheets write.  Labels can't hold commas.
+pub fn parse_markers(text: &str, units: Units) -> Result<Vec<Marker>, Error> {
+    let scale = match units {
+        Units::Cells => 1.0,
+        Units::Blocks => 1.0 / BLOCKS_PER_CELL as f64,
+    };
+    let mut markers = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |reason: &str| Error::Markers(format!("line {}: {}", i + 1, reason));
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        if markers.is_empty() && fields[0].eq_ignore_ascii_case("x") {
+            continue;
+        }
+        let (x, y, label, color) = match fields[..] {
+            [x, y] => (x, y, "", None),
+            [x, y, label] => (x, y, label, None),
+            [x, y, label, color] => (x, y, label, Some(color)),
+            _ => return Err(error("expected x, y, a label and a color")),
+        };
+        let coord = |field: &str| match field.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(value * scale),
+            Ok(_) => Err(error("coordinates must be finite")),
+            Err(e) => Err(error(&e.to_string())),
+        };
+        let color = match color {
+            Some(color) if !color.is_empty() => parse_hex(color)
+                .ok_or_else(|| error(&format!("{:?} is not a #RRGGBB color", color)))?,
+            _ => MARKER,
+        };
+        markers.push(Marker {
+            pos: Vec2::new(coord(x)?, coord(y)?),
+            label: label.to_owned(),
+            color,
+        });
+    }
+    Ok(markers)
+}
+
+/// Reads the list of markers at `path`; see [`parse_markers`].
+pub fn load_markers(path: impl AsRef<Path>, units: Units) -> Result<Vec<Marker>, Error> {
+    parse_markers(&std::fs::read_to_string(path)?, units)
+}
+
+/// Draws each of `markers` over `img`, which shows the cells of `view`, as a
+/// diamond in its color outlined in black, with its label to the right.
+/// Markers outside the view are skipped, and labels overlapping each other
+/// are drawn over each other.
+pub fn draw_markers(img: &mut RgbImage, view: Window, markers: &[Marker]) {
+    let img_size = Vec2::new(img.width(), img.height());
+    let scale = text_scale(img);
+    let radius = 2 * scale as i64;
+    for marker in markers.iter().filter(|marker| view.contains(marker.pos)) {
+        let center = cell_to_pixel(marker.pos, view, img_size).map(|e| e.floor() as i64);
+        for dy in -radius - 1..=radius + 1 {
+            for dx in -radius - 1..=radius + 1 {
+                let (x, y) = (center.x + dx, center.y + dy);
+                let distance = dx.abs() + dy.abs();
+                if distance > radius + 1
+                    || !(0..img_size.x as i64).contains(&x)
+                    || !(0..img_size.y as i64).contains(&y)
+                {
+                    continue;
+                }
+                let color = if distance > radius {
+                    Rgb([0, 0, 0])
+                } else {
+                    marker.color
+                };
+                img.put_pixel(x as u32, y as u32, color);
+            }
+        }
+        if !marker.label.is_empty() {
+            let pos = center + Vec2::new(radius + 2, -(radius + 1));
+            let pos = pos.map(|e| e.max(0) as u32);
+            draw_label(img, pos, &marker.label, scale);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_map_to_pixels_at_every_resolution() {
+        let view = Window::whole(Vec2::new(64, 32));
+        let pos = Vec2::new(16.0, 8.0);
+        assert_eq!(cell_to_pixel(pos, view, Vec2::new(64, 32)), pos);
+        assert_eq!(
+            cell_to_pixel(pos, view, Vec2::new(32, 16)),
+            Vec2::new(8.0, 4.0)
+        );
+        let window = Window {
+            offset: Vec2::new(8, 4),
+            size: Vec2::new(16, 8),
+        };
+        assert_eq!(
+            cell_to_pixel(pos, window, Vec2::new(16, 8)),
+            Vec2::new(8.0, 4.0)
+        );
+        assert_eq!(cell_to_block(3), 96);
+        assert_eq!(grid_label(16), "16/512");
+    }
+
+    #[test]
+    fn grid_lines_lie_every_spacing_cells() {
+        let terrain = Rgb([0, 100, 200]);
+        let mut img = RgbImage::from_pixel(64, 48, terrain);
+        let grid = Grid {
+            spacing: 16,
+            opacity: 0.5,
+        };
+        draw_grid(&mut img, Window::whole(Vec2::new(64, 48)), &grid);
+        let faded = Rgb([128, 178, 228]);
+        // Below the labels along the top, columns 0, 16, 32 and 48 are lines,
+        // and the columns beside them untouched terrain.
+        for x in 0..64 {
+            let expected = if x % 16 == 0 { faded } else { terrain };
+            assert_eq!(*img.get_pixel(x, 40), expected, "column {}", x);
+        }
+        // So are rows 16 and 32, right of the labels along the left, and
+        // crossings are faded once.
+        for y in 8..48 {
+            let expected = if y % 16 == 0 { faded } else { terrain };
+            assert_eq!(*img.get_pixel(60, y), expected, "row {}", y);
+        }
+        assert_eq!(*img.get_pixel(32, 32), faded);
+        // The labels are drawn in the top and left margins.
+        assert!((0..64).any(|x| *img.get_pixel(x, 3) == TEXT));
+        assert!((0..8).any(|x| *img.get_pixel(x, 19) == TEXT));
+
+        // Half-resolution images get their lines at half the pixels.
+        let mut half = RgbImage::from_pixel(32, 24, terrain);
+        draw_grid(&mut half, Window::whole(Vec2::new(64, 48)), &grid);
+        assert_eq!(*half.get_pixel(8, 12), faded);
+        assert_eq!(*half.get_pixel(12, 12), terrain);
+
+        // Windows keep the lines of the whole map, shifted by their offset.
+        let mut window = RgbImage::from_pixel(40, 40, terrain);
+        let view = Window {
+            offset: Vec2::new(10, 4),
+            size: Vec2::new(40, 40),
+        };
+        draw_grid(&mut window, view, &grid);
+        for x in 0..40 {
+            let expected = if (x + 10) % 16 == 0 { faded } else { terrain };
+            assert_eq!(*window.get_pixel(x, 36), expected, "column {}", x);
+        }
+        assert_eq!(*window.get_pixel(36, 12), faded);
+    }
+
+    #[test]
+    fn markers_parse_in_cells_or_blocks() {
+        let text = "x, y, label, color\n# Found while exploring\n10, 20, Camp, #00ff00\n64, 32\n";
+        let markers = parse_markers(text, Units::Cells).unwrap();
+        assert_eq!(markers, [
+            Marker {
+                pos: Vec2::new(10.0, 20.0),
+                label: "Camp".to_owned(),
+                color: Rgb([0, 255, 0]),
+            },
+            Marker {
+                pos: Vec2::new(64.0, 32.0),
+                label: String::new(),
+                color: MARKER,
+            },
+        ]);
+        let blocks = parse_markers(text, Units::Blocks).unwrap();
+        assert_eq!(blocks[1].pos, Vec2::new(2.0, 1.0));
+        assert!(!markers[1].is_inside(Vec2::new(64, 64)));
+        assert!(markers[1].is_inside(Vec2::new(65, 33)));
+        assert!(matches!(
+            parse_markers("1, 2, Camp, green", Units::Cells),
+            Err(Error::Markers(reason)) if reason.starts_with("line 1:")
+        ));
+        assert!(parse_markers("1, NaN", Units::Cells).is_err());
+    }
+
+    #[test]
+    fn markers_are_drawn_around_their_cell() {
+        let terrain = Rgb([0, 100, 200]);
+        let mut img = RgbImage::from_pixel(64, 64, terrain);
+        let color = Rgb([255, 0, 255]);
+        let markers = [
+            Marker {
+                pos: Vec2::new(20.5, 30.5),
+                label: "Camp".to_owned(),
+                color,
+            },
+            Marker {
+                pos: Vec2::new(-1.0, 70.0),
+                label: "Lost".to_owned(),
+                color,
+            },
+        ];
+        draw_markers(&mut img, Window::whole(Vec2::new(64, 64)), &markers);
+        assert_eq!(*img.get_pixel(20, 30), color);
+        assert_eq!(*img.get_pixel(22, 30), color);
+        assert_eq!(*img.get_pixel(20, 33), Rgb([0, 0, 0]));
+        assert_eq!(*img.get_pixel(20, 36), terrain);
+        // The label is to the right.
+        assert!((24..40).any(|x| *img.get_pixel(x, 30) == TEXT));
+        // The marker outside the map is skipped.
+        let changed = img.pixels().filter(|&&pixel| pixel != terrain).count();
+        let mut camp = RgbImage::from_pixel(64, 64, terrain);
+        draw_markers(&mut camp, Window::whole(Vec2::new(64, 64)), &markers[..1]);
+        assert_eq!(
+            camp.pixels().filter(|&&pixel| pixel != terrain).count(),
+            changed
+        );
+        // So are markers outside a window, and those inside it shift with it.
+        let view = Window {
+            offset: Vec2::new(16, 16),
+            size: Vec2::new(32, 32),
+        };
+        let mut window = RgbImage::from_pixel(32, 32, terrain);
+        draw_markers(&mut window, view, &markers);
+        assert_eq!(*window.get_pixel(4, 14), color);
+    }
+}
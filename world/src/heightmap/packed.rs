@@ -0,0 +1,201 @@
+//! Storing altitudes losslessly (up to 32-bit fixed point) in RGBA PNG
+//! images, for workflows where PNG is the only practical interchange format.
+//!
+//! Each altitude is quantized to a 32-bit value between the map's minimum and
+//! maximum, whose bytes are stored, most significant first, in the red, green,
+//! blue and alpha channels of its pixel.  The images look like noise: they are
+//! containers, not pictures.  The minimum and step needed to decode them are
+//! kept in a sidecar next to the image, which also marks the image as packed
+//! so that it isn't mistaken for a grayscale heightmap.
+
+use super::{
+    Error,
+    export::{PngOptions, compute_min_max, save_rgba_png},
+    import::map_size_lg,
+    map_size, read_json, write_json,
+};
+use crate::sim::ModernMap;
+use image::{DynamicImage, ImageReader, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Value of [`PackedSidecar::format`] marking an image as packed.
+pub const PACKED_FORMAT: &str = "veloren-heightmap-rgba32";
+
+/// Decoding parameters of a packed image, stored next to it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PackedSidecar {
+    /// Always [`PACKED_FORMAT`].
+    pub format: String,
+    /// Altitude of a pixel whose packed value is 0.
+    pub min: f64,
+    /// Altitude difference between consecutive packed values.
+    pub step: f64,
+    /// `continent_scale_hack` of the packed map.
+    pub continent_scale: f64,
+}
+
+impl PackedSidecar {
+    /// Path of the sidecar accompanying the packed image at `image_path`.
+    pub fn path_for(image_path: &Path) -> PathBuf { image_path.with_extension("packed.json") }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> { read_json(path) }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> { write_json(path, self) }
+
+    /// Altitude of the packed value `value`.
+    #[inline]
+    pub fn altitude(&self, value: u32) -> f64 { self.min + value as f64 * self.step }
+}
+
+/// Fails if the image at `path` has a sidecar marking it as packed, so that
+/// packed images aren't read as grayscale heightmaps.
+pub fn check_not_packed(path: &Path) -> Result<(), Error> {
+    let sidecar_path = PackedSidecar::path_for(path);
+    if sidecar_path.exists() && PackedSidecar::load(&sidecar_path)?.format == PACKED_FORMAT {
+        return Err(Error::UnsupportedImage(format!(
+            "{} holds packed altitudes (see {}) and must be unpacked instead",
+            path.display(),
+            sidecar_path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Packs the altitudes of `map` into an RGBA image, returning it with the
+/// sidecar needed to unpack it.  Each altitude is restored to within half a
+/// step, where a step is 2^-32 of the altitude range; the basement is not
+/// stored.
+pub fn pack(map: &ModernMap) -> Result<(RgbaImage, PackedSidecar), Error> {
+    let (min, max) = compute_min_max(&map.alt);
+    if !(min.is_finite() && max.is_finite()) || map.alt.iter().any(|alt| alt.is_nan()) {
+        return Err(Error::NonFinite);
+    }
+    let sidecar = PackedSidecar {
+        format: PACKED_FORMAT.to_owned(),
+        min,
+        step: (max - min) / u32::MAX as f64,
+        continent_scale: map.continent_scale_hack,
+    };
+    let size = map_size(map);
+    let mut buf = Vec::with_capacity(map.alt.len() * 4);
+    for &alt in map.alt.iter() {
+        let value = if sidecar.step > 0.0 {
+            ((alt - min) / sidecar.step).round() as u32
+        } else {
+            0
+        };
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+    let img = RgbaImage::from_raw(size.x as u32, size.y as u32, buf)
+        .expect("Buffer matches the image size");
+    Ok((img, sidecar))
+}
+
+/// Restores the map packed into `img` by [`pack`].  Its basement is a copy of
+/// the altitudes, as for other image imports.
+pub fn unpack(img: &RgbaImage, sidecar: &PackedSidecar) -> Result<ModernMap, Error> {
+    if sidecar.format != PACKED_FORMAT {
+        return Err(Error::UnsupportedImage(format!(
+            "sidecar describes format {:?}, not {:?}",
+            sidecar.format, PACKED_FORMAT
+        )));
+    }
+    let map_size_lg = map_size_lg(img.width(), img.height())?;
+    let alt = img
+        .pixels()
+        .map(|pixel| sidecar.altitude(u32::from_be_bytes(pixel.0)))
+        .collect::<Box<[_]>>();
+    Ok(ModernMap {
+        map_size_lg,
+        continent_scale_hack: sidecar.continent_scale,
+        basement: alt.clone(),
+        alt,
+    })
+}
+
+/// Packs `map` into a PNG at `path`, writing its sidecar next to it.
+pub fn save_packed(map: &ModernMap, path: &Path) -> Result<PackedSidecar, Error> {
+    let (img, sidecar) = pack(map)?;
+    save_rgba_png(&img, path, PngOptions::default())?;
+    sidecar.save(PackedSidecar::path_for(path))?;
+    Ok(sidecar)
+}
+
+/// Loads the packed PNG at `path`, using the sidecar next to it.
+pub fn load_packed(path: &Path) -> Result<ModernMap, Error> {
+    let sidecar = PackedSidecar::load(PackedSidecar::path_for(path))?;
+    match ImageReader::open(path)?.decode()? {
+        DynamicImage::ImageRgba8(img) => unpack(&img, &sidecar),
+        img => Err(Error::UnsupportedImage(format!(
+            "packed images must be 8-bit RGBA, found {:?}",
+            img.color()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::{random_grids, test_map};
+    use vek::*;
+
+    #[test]
+    fn unpacking_restores_altitudes_within_half_a_step() {
+        let mut maps = random_grids(30)
+            .map(|(size, alt)| {
+                // Crop to a power-of-two square, as maps are.
+                let side_lg = size.x.min(size.y).ilog2();
+                test_map(Vec2::broadcast(side_lg), |x, y| alt[y * size.x + x])
+            })
+            .collect::<Vec<_>>();
+        maps.push(test_map(Vec2::new(2, 2), |_, _| 123.25));
+        maps.push(test_map(Vec2::new(3, 3), |x, y| {
+            (x as f64 * 1e-6 - y as f64 * 7.0e3) * if x % 2 == 0 { 1.0 } else { -1.0 }
+        }));
+
+        for map in maps {
+            let (img, sidecar) = pack(&map).unwrap();
+            let unpacked = unpack(&img, &sidecar).unwrap();
+            assert_eq!(unpacked.map_size_lg, map.map_size_lg);
+            assert_eq!(unpacked.continent_scale_hack, map.continent_scale_hack);
+            let tolerance = sidecar.step / 2.0 + 1e-9 * sidecar.min.abs().max(1.0);
+            for (unpacked, alt) in unpacked.alt.iter().zip(map.alt.iter()) {
+                assert!(
+                    (unpacked - alt).abs() <= tolerance,
+                    "{} unpacked as {}",
+                    alt,
+                    unpacked
+                );
+            }
+            assert_eq!(unpacked.alt, unpacked.basement);
+        }
+    }
+
+    #[test]
+    fn packed_images_are_refused_by_the_grayscale_path() {
+        let path = std::env::temp_dir().join(format!(
+            "veloren-heightmap-packed-{}.png",
+            std::process::id()
+        ));
+        let map = test_map(Vec2::new(2, 2), |x, y| x as f64 * 10.0 - y as f64);
+        save_packed(&map, &path).unwrap();
+
+        assert!(matches!(
+            crate::heightmap::import::load_image(&path),
+            Err(Error::UnsupportedImage(_))
+        ));
+        let unpacked = load_packed(&path).unwrap();
+        assert_eq!(unpacked.alt.len(), map.alt.len());
+
+        std::fs::remove_file(PackedSidecar::path_for(&path)).unwrap();
+        assert!(crate::heightmap::import::load_image(&path).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn non_finite_maps_are_not_packed() {
+        let map = test_map(Vec2::new(1, 1), |x, _| if x == 0 { f64::NAN } else { 1.0 });
+        assert!(matches!(pack(&map), Err(Error::NonFinite)));
+    }
+}
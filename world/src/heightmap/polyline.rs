@@ -0,0 +1,297 @@
+//! Drawing polylines, such as roads, walls and canals, into a map.
+
+use super::{Error, map_size, read_json};
+use crate::sim::ModernMap;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use vek::*;
+
+/// What a [`Polyline`] does to the terrain beneath it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolylineMode {
+    /// Lower the terrain by the depth, as for a canal or a pass.
+    Carve,
+    /// Raise the terrain by the depth, as for a causeway or a wall.
+    Raise,
+    /// Grade the terrain to the altitude beneath the first point, as for a
+    /// road.
+    Flatten,
+}
+
+/// How a [`Polyline`] is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PolylineParams {
+    /// Width in cells of the part of the line drawn at full strength.
+    pub width: f64,
+    pub mode: PolylineMode,
+    /// Meters the terrain is lowered or raised by; unused when flattening.
+    #[serde(default, alias = "height")]
+    pub depth: f64,
+    /// Width in cells of the band on either side of the line over which it
+    /// blends into the surrounding terrain.
+    #[serde(default)]
+    pub feather: f64,
+}
+
+/// A line through a list of points, in cell coordinates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polyline {
+    pub points: Vec<Vec2<f64>>,
+    pub params: PolylineParams,
+}
+
+/// The parts of a GeoJSON `FeatureCollection` read by [`load_polylines`];
+/// other members, such as `"type"`, are ignored.
+#[derive(Deserialize)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    geometry: Geometry,
+    properties: PolylineParams,
+}
+
+/// GeoJSON positions may have a third, vertical, coordinate, which is ignored.
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Geometry {
+    LineString { coordinates: Vec<Vec<f64>> },
+    MultiLineString { coordinates: Vec<Vec<Vec<f64>>> },
+}
+
+impl Polyline {
+    /// Checks that the line has points and that all of its values are finite
+    /// and its widths aren't negative.
+    pub fn check(&self) -> Result<(), Error> {
+        let PolylineParams {
+            width,
+            depth,
+            feather,
+            ..
+        } = self.params;
+        if self.points.is_empty() {
+            return Err(Error::Polylines("a line has no points".into()));
+        }
+        if self
+            .points
+            .iter()
+            .any(|p| !p.x.is_finite() || !p.y.is_finite())
+            || ![width, depth, feather].iter().all(|e| e.is_finite())
+        {
+            return Err(Error::Polylines("values must be finite".into()));
+        }
+        if width < 0.0 || feather < 0.0 {
+            return Err(Error::Polylines("widths can't be negative".into()));
+        }
+        Ok(())
+    }
+
+    /// Distance from `pos` to the nearest point on the line.
+    fn distance(&self, pos: Vec2<f64>) -> f64 {
+        let Some(&first) = self.points.first() else {
+            return f64::INFINITY;
+        };
+        let segments = self.points.windows(2).map(|w| (w[0], w[1]));
+        std::iter::once((first, first))
+            .chain(segments)
+            .map(|(a, b)| {
+                let ab = b - a;
+                let len = ab.magnitude_squared();
+                let t = if len > 0.0 {
+                    ((pos - a).dot(ab) / len).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                pos.distance(a + ab * t)
+            })
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Weight, between 0 and 1, with which the line affects the cell at `pos`.
+    fn weight(&self, pos: Vec2<f64>) -> f64 {
+        let d = self.distance(pos) - self.params.width / 2.0;
+        if d <= 0.0 {
+            1.0
+        } else if d >= self.params.feather {
+            0.0
+        } else {
+            let t = 1.0 - d / self.params.feather;
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+/// Reads the `LineString` and `MultiLineString` features of the GeoJSON
+/// file at `path`, whose coordinates are in cells and whose properties are
+/// the [`PolylineParams`] of each line, such as
+/// `{"width": 3, "mode": "carve", "depth": 20, "feather": 4}`.
+pub fn load_polylines(path: impl AsRef<Path>) -> Result<Vec<Polyline>, Error> {
+    let collection: FeatureCollection = read_json(path)?;
+    let point = |position: Vec<f64>| match position[..] {
+        [x, y, ..] => Ok(Vec2::new(x, y)),
+        _ => Err(Error::Polylines("positions need two coordinates".into())),
+    };
+    let mut polylines = Vec::new();
+    for feature in collection.features {
+        let lines = match feature.geometry {
+            Geometry::LineString { coordinates } => vec![coordinates],
+            Geometry::MultiLineString { coordinates } => coordinates,
+        };
+        for line in lines {
+            let polyline = Polyline {
+                points: line.into_iter().map(point).collect::<Result<_, _>>()?,
+                params: feature.properties,
+            };
+            polyline.check()?;
+            polylines.push(polyline);
+        }
+    }
+    Ok(polylines)
+}
+
+/// Draws each of `polylines` into `map` in turn, returning the number of
+/// cells each changed.
+///
+/// Cells within half the width of a line are affected fully, and those in
+/// the feathered band beyond partly, with a smooth falloff.  Later lines see
+/// the result of earlier ones, so a line flattened across a carved one grades
+/// over it.  Lines are clipped to the map, and the altitude a flattened line
+/// is graded to is taken from the cell nearest its first point.  The basement
+/// is lowered wherever it would end up above the new altitude, and is
+/// otherwise left alone, so raised terrain is sediment.
+pub fn draw_polylines(map: &mut ModernMap, polylines: &[Polyline]) -> Vec<usize> {
+    let size = map_size(map);
+    if size.product() == 0 {
+        return vec![0; polylines.len()];
+    }
+    let last = size.map(|e| (e - 1) as f64);
+    polylines
+        .iter()
+        .map(|line| {
+            let Some(&first) = line.points.first() else {
+                return 0;
+            };
+            // The cells within reach of the line, clipped to the map.
+            let reach = line.params.width / 2.0 + line.params.feather;
+            let (lo, hi) = line.points.iter().fold((first, first), |(lo, hi), &p| {
+                (lo.map2(p, f64::min), hi.map2(p, f64::max))
+            });
+            let lo = (lo - reach).map(|e| e.floor().max(0.0) as usize);
+            let hi = (hi + reach).map2(size, |e, len| {
+                (e.ceil() + 1.0).clamp(0.0, len as f64) as usize
+            });
+
+            let nearest = first.map2(last, |e, last| e.round().clamp(0.0, last) as usize);
+            let target = map.alt[nearest.y * size.x + nearest.x];
+            let mut changed = 0;
+            for y in lo.y..hi.y.max(lo.y) {
+                for x in lo.x..hi.x.max(lo.x) {
+                    let weight = line.weight(Vec2::new(x, y).as_());
+                    if weight <= 0.0 {
+                        continue;
+                    }
+                    let i = y * size.x + x;
+                    let alt = map.alt[i];
+                    map.alt[i] = match line.params.mode {
+                        PolylineMode::Carve => alt - line.params.depth * weight,
+                        PolylineMode::Raise => alt + line.params.depth * weight,
+                        PolylineMode::Flatten if weight >= 1.0 => target,
+                        PolylineMode::Flatten => alt + (target - alt) * weight,
+                    };
+                    map.basement[i] = map.basement[i].min(map.alt[i]);
+                    changed += 1;
+                }
+            }
+            changed
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+
+    fn line(points: &[(f64, f64)], mode: PolylineMode, depth: f64) -> Polyline {
+        Polyline {
+            points: points.iter().map(|&(x, y)| Vec2::new(x, y)).collect(),
+            params: PolylineParams {
+                width: 3.0,
+                mode,
+                depth,
+                feather: 2.0,
+            },
+        }
+    }
+
+    #[test]
+    fn carved_lines_have_a_flat_bottom_and_feathered_banks() {
+        let mut map = test_map(Vec2::new(5, 5), |_, _| 100.0);
+        let canal = line(&[(4.0, 16.0), (28.0, 16.0)], PolylineMode::Carve, 20.0);
+        draw_polylines(&mut map, &[canal]);
+
+        // The cross-section halfway along the canal, from row 16 outwards.
+        let profile = (0..6)
+            .map(|d| map.alt[(16 + d) * 32 + 16])
+            .collect::<Vec<_>>();
+        assert_eq!(profile[0], 80.0);
+        assert_eq!(profile[1], 80.0);
+        // 1.5 cells from the bottom's edge is 0.5 cells into the feather.
+        assert!((profile[2] - (100.0 - 20.0 * 0.84375)).abs() < 1e-9);
+        assert!((profile[3] - (100.0 - 20.0 * 0.15625)).abs() < 1e-9);
+        assert_eq!(profile[4], 100.0);
+        assert_eq!(profile[5], 100.0);
+        // Both banks are alike.
+        for (d, &alt) in profile.iter().enumerate() {
+            assert_eq!(map.alt[(16 - d) * 32 + 16], alt);
+        }
+        // The canal ends in a rounded cap, two cells past its last point.
+        assert_eq!(map.alt[16 * 32 + 29], 80.0);
+        assert!(map.alt[16 * 32 + 31] > 80.0);
+        assert!(map.alt.iter().zip(&*map.basement).all(|(a, b)| b <= a));
+    }
+
+    #[test]
+    fn lines_are_clipped_to_the_map() {
+        let alt = |x: usize, y: usize| x as f64 * 2.0 + y as f64;
+        let mut map = test_map(Vec2::new(4, 4), alt);
+        // A causeway running off the map along its southern edge.
+        let causeway = line(&[(-10.0, 0.0), (30.0, 0.0)], PolylineMode::Raise, 5.0);
+        let outside = line(&[(-20.0, -20.0), (-10.0, -20.0)], PolylineMode::Carve, 5.0);
+        let changed = draw_polylines(&mut map, &[causeway, outside]);
+        // Rows 0 and 1 at full strength, and rows 2 and 3 in the feather.
+        assert_eq!(changed, vec![16 * 4, 0]);
+        for x in 0..16 {
+            assert_eq!(map.alt[x], alt(x, 0) + 5.0);
+            assert_eq!(map.alt[4 * 16 + x], alt(x, 4));
+            assert_eq!(map.basement[x], alt(x, 0) - 10.0);
+        }
+    }
+
+    #[test]
+    fn flattened_lines_are_graded_to_their_first_point() {
+        let alt = |x: usize, y: usize| x as f64 * 10.0 + y as f64;
+        let mut map = test_map(Vec2::new(4, 4), alt);
+        let road = line(&[(2.0, 8.0), (13.0, 8.0)], PolylineMode::Flatten, 0.0);
+        draw_polylines(&mut map, &[road]);
+        for x in 2..14 {
+            assert_eq!(map.alt[8 * 16 + x], alt(2, 8));
+        }
+        assert!(map.alt.iter().zip(&*map.basement).all(|(a, b)| b <= a));
+    }
+
+    #[test]
+    fn malformed_lines_are_refused() {
+        let mut bad = line(&[], PolylineMode::Carve, 1.0);
+        assert!(matches!(bad.check(), Err(Error::Polylines(_))));
+        bad.points.push(Vec2::new(1.0, f64::NAN));
+        assert!(matches!(bad.check(), Err(Error::Polylines(_))));
+        bad.points[0].y = 1.0;
+        assert!(bad.check().is_ok());
+        bad.params.width = -1.0;
+        assert!(matches!(bad.check(), Err(Error::Polylines(_))));
+    }
+}
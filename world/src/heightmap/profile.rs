@@ -0,0 +1,330 @@
+//! Elevation profiles: the altitude of a map along a route, such as a road
+//! planned over a mountain pass, as a table and as a chart.
+
+use super::{
+    Error,
+    legend::{tick_label, ticks},
+    montage::{BACKGROUND, GLYPH_SIZE, TEXT, draw_text},
+    overlay::{BLOCKS_PER_CELL, Units},
+};
+use image::{Rgb, RgbImage};
+use std::io::Write;
+use vek::*;
+
+/// The altitude of the row-major grid `alt` of size `size` at `pos`, in
+/// cells, interpolated bilinearly between the four cells around it.  Cell
+/// `(x, y)` is sampled at `pos == (x, y)`, and positions beyond the grid take
+/// the value of the nearest edge.
+pub fn sample_bilinear(alt: &[f64], size: Vec2<usize>, pos: Vec2<f64>) -> f64 {
+    let max = size.map(|e| e.saturating_sub(1) as f64);
+    let pos = pos.map2(max, |e, max| e.clamp(0.0, max));
+    let lo = pos.map(|e| e.floor() as usize);
+    let hi = lo.map2(size, |e, size| (e + 1).min(size - 1));
+    let t = pos - lo.map(|e| e as f64);
+    let at = |x: usize, y: usize| alt[y * size.x + x];
+    let top = at(lo.x, lo.y) + (at(hi.x, lo.y) - at(lo.x, lo.y)) * t.x;
+    let bottom = at(lo.x, hi.y) + (at(hi.x, hi.y) - at(lo.x, hi.y)) * t.x;
+    top + (bottom - top) * t.y
+}
+
+/// Parses a point given as `X,Y` in `units`, into cells.
+pub fn parse_point(text: &str, units: Units) -> Result<Vec2<f64>, Error> {
+    let error = |reason: &str| Error::Profile(format!("point {:?}: {}", text, reason));
+    let coords = text
+        .split(',')
+        .map(|field| field.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| error(&e.to_string()))?;
+    let pos = match coords[..] {
+        [x, y] if x.is_finite() && y.is_finite() => Vec2::new(x, y),
+        [_, _] => return Err(error("coordinates must be finite")),
+        _ => return Err(error("expected X,Y")),
+    };
+    Ok(match units {
+        Units::Cells => pos,
+        Units::Blocks => pos / BLOCKS_PER_CELL as f64,
+    })
+}
+
+/// A sample of a [`profile`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProfileSample {
+    /// Distance along the route from its first point, in cells.
+    pub distance: f64,
+    /// Position of the sample, in cells.
+    pub pos: Vec2<f64>,
+    pub alt: f64,
+}
+
+/// Samples the altitude grid `alt` of size `size` along the polyline through
+/// `points`, in cells, with [`sample_bilinear`], every `step` cells or less:
+/// each segment is split evenly into as few pieces as that allows, so that
+/// every point of the route is sampled exactly.
+///
+/// The route needs at least two points, all of them within the grid, between
+/// the centres of its first and last cells, and `step` must be positive.
+pub fn profile(
+    alt: &[f64],
+    size: Vec2<usize>,
+    points: &[Vec2<f64>],
+    step: f64,
+) -> Result<Vec<ProfileSample>, Error> {
+    if points.len() < 2 {
+        return Err(Error::Profile(
+            "a route needs at least two points".to_owned(),
+        ));
+    }
+    if !(step > 0.0 && step.is_finite()) {
+        return Err(Error::Profile(format!(
+            "the step must be positive, not {}",
+            step
+        )));
+    }
+    let max = size.map(|e| e.saturating_sub(1) as f64);
+    if let Some(outside) = points.iter().find(|pos| {
+        !pos.map2(max, |e, max| (0.0..=max).contains(&e))
+            .reduce_and()
+    }) {
+        return Err(Error::Profile(format!(
+            "point ({}, {}) is outside the {}x{} map",
+            outside.x, outside.y, size.x, size.y
+        )));
+    }
+
+    let sample = |distance: f64, pos: Vec2<f64>| ProfileSample {
+        distance,
+        pos,
+        alt: sample_bilinear(alt, size, pos),
+    };
+    let mut samples = vec![sample(0.0, points[0])];
+    let mut distance = 0.0;
+    for segment in points.windows(2) {
+        let (from, to) = (segment[0], segment[1]);
+        let length = from.distance(to);
+        let pieces = (length / step).ceil().max(1.0) as usize;
+        for i in 1..=pieces {
+            let t = i as f64 / pieces as f64;
+            samples.push(sample(distance + length * t, from + (to - from) * t));
+        }
+        distance += length;
+    }
+    Ok(samples)
+}
+
+/// Writes `samples` as CSV, with a header, one `distance,x,y,altitude` line
+/// per sample: the distance in blocks, which are meters like the altitude,
+/// and the position in cells.
+pub fn write_csv(samples: &[ProfileSample], mut writer: impl Write) -> Result<(), Error> {
+    writeln!(writer, "distance,x,y,altitude")?;
+    for sample in samples {
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            sample.distance * BLOCKS_PER_CELL as f64,
+            sample.pos.x,
+            sample.pos.y,
+            sample.alt
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Color of the ground under the line of a profile chart.
+const GROUND: Rgb<u8> = Rgb([90, 120, 70]);
+
+/// Renders `samples` as a chart of `size` pixels: the altitude along the
+/// route, with the ground beneath it filled in, over axes labelled with round
+/// altitudes on the left and distances in blocks along the bottom.
+pub fn render_profile(samples: &[ProfileSample], size: Vec2<u32>) -> RgbImage {
+    let mut img = RgbImage::from_pixel(size.x, size.y, BACKGROUND);
+    let scale = (size.y / 512 + 1).min(4);
+    let glyph = GLYPH_SIZE * scale;
+    let advance = (GLYPH_SIZE.x + 1) * scale;
+    let gap = 2 * glyph.x;
+
+    let (lo, hi) = samples
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), sample| {
+            (lo.min(sample.alt), hi.max(sample.alt))
+        });
+    let length = samples.last().map_or(0.0, |sample| sample.distance) * BLOCKS_PER_CELL as f64;
+    let (alt_ticks, alt_step) = ticks(lo, hi, 6);
+    let (distance_ticks, distance_step) = ticks(0.0, length, 8);
+    let alt_labels = alt_ticks
+        .iter()
+        .map(|&tick| tick_label(tick, alt_step))
+        .collect::<Vec<_>>();
+    let label_width = alt_labels
+        .iter()
+        .map(|label| label.len() as u32)
+        .max()
+        .unwrap_or(0)
+        * advance;
+
+    // The plot area, right of the altitude labels and above the distance
+    // labels, with room for half a label above and beside it.
+    let left = gap + label_width + gap;
+    let (top, right) = (gap, size.x.saturating_sub(gap + 2 * advance));
+    let bottom = size.y.saturating_sub(gap + glyph.y + gap);
+    if samples.is_empty() || right <= left || bottom <= top {
+        return img;
+    }
+    let (width, height) = ((right - left) as f64, (bottom - top) as f64);
+    let x_of = |distance: f64| {
+        left + if length > 0.0 {
+            (distance / length * width).round() as u32
+        } else {
+            0
+        }
+    };
+    let y_of = |alt: f64| {
+        bottom
+            - if hi > lo {
+                ((alt - lo) / (hi - lo) * height).round() as u32
+            } else {
+                (height / 2.0) as u32
+            }
+    };
+
+    // The ground, one column at a time, at the altitude interpolated linearly
+    // between the samples on either side, with the line along its top.
+    let mut next = 0;
+    for x in left..=right {
+        let distance = (x - left) as f64 / width * length / BLOCKS_PER_CELL as f64;
+        while next + 1 < samples.len() && samples[next + 1].distance < distance {
+            next += 1;
+        }
+        let alt = match samples.get(next..next + 2) {
+            Some([a, b]) if b.distance > a.distance => {
+                let t = ((distance - a.distance) / (b.distance - a.distance)).clamp(0.0, 1.0);
+                a.alt + (b.alt - a.alt) * t
+            },
+            _ => samples[next].alt,
+        };
+        let surface = y_of(alt);
+        for y in surface..=bottom {
+            img.put_pixel(x, y, GROUND);
+        }
+        img.put_pixel(x, surface, TEXT);
+        if surface > top {
+            img.put_pixel(x, surface - 1, TEXT);
+        }
+    }
+
+    // The axes, their ticks and their labels.
+    for y in top..=bottom {
+        img.put_pixel(left, y, TEXT);
+    }
+    for x in left..=right {
+        img.put_pixel(x, bottom, TEXT);
+    }
+    for (&tick, label) in alt_ticks.iter().zip(&alt_labels) {
+        let y = y_of(tick);
+        for x in left - glyph.x..left {
+            img.put_pixel(x, y, TEXT);
+        }
+        let width = label.len() as u32 * advance;
+        let pos = Vec2::new(
+            left - glyph.x - scale - width,
+            y.saturating_sub(glyph.y / 2),
+        );
+        draw_text(&mut img, pos, label, scale, TEXT);
+    }
+    for &tick in &distance_ticks {
+        let x = x_of(tick);
+        for y in bottom..bottom + glyph.x {
+            img.put_pixel(x, y, TEXT);
+        }
+        let label = tick_label(tick, distance_step);
+        let width = label.len() as u32 * advance;
+        let pos = Vec2::new(x.saturating_sub(width / 2), bottom + glyph.x + scale);
+        draw_text(&mut img, pos, &label, scale, TEXT);
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plane rising 2 m per cell along x and 3 m along y.
+    fn ramp(size: Vec2<usize>) -> Vec<f64> {
+        (0..size.product())
+            .map(|i| 2.0 * (i % size.x) as f64 + 3.0 * (i / size.x) as f64)
+            .collect()
+    }
+
+    #[test]
+    fn profiles_of_ramps_follow_the_plane() {
+        let size = Vec2::new(16, 12);
+        let alt = ramp(size);
+        let route = [
+            Vec2::new(1.0, 1.0),
+            Vec2::new(10.0, 5.5),
+            Vec2::new(4.25, 11.0),
+        ];
+        let samples = profile(&alt, size, &route, 0.3).unwrap();
+        let lengths = [route[0].distance(route[1]), route[1].distance(route[2])];
+        let last = samples.last().unwrap();
+        assert!((last.distance - lengths.iter().sum::<f64>()).abs() < 1e-9);
+        assert_eq!(last.pos, route[2]);
+        for sample in &samples {
+            let expected = 2.0 * sample.pos.x + 3.0 * sample.pos.y;
+            assert!((sample.alt - expected).abs() < 1e-9, "{:?}", sample);
+            // The distance is that along the route to the sample.
+            let along = if sample.distance <= lengths[0] {
+                route[0].distance(sample.pos)
+            } else {
+                lengths[0] + route[1].distance(sample.pos)
+            };
+            assert!((sample.distance - along).abs() < 1e-9, "{:?}", sample);
+        }
+        assert!(
+            samples
+                .windows(2)
+                .all(|pair| pair[1].distance - pair[0].distance <= 0.3 + 1e-9)
+        );
+        assert!(samples.iter().any(|sample| sample.pos == route[1]));
+    }
+
+    #[test]
+    fn routes_must_stay_on_the_map() {
+        let size = Vec2::new(4, 4);
+        let alt = ramp(size);
+        let inside = [Vec2::new(0.0, 0.0), Vec2::new(3.0, 3.0)];
+        assert!(profile(&alt, size, &inside, 1.0).is_ok());
+        for route in [
+            &[Vec2::new(0.0, 0.0), Vec2::new(3.5, 1.0)][..],
+            &[Vec2::new(-0.1, 0.0), Vec2::new(1.0, 1.0)],
+            &[Vec2::new(1.0, 1.0)],
+        ] {
+            assert!(matches!(
+                profile(&alt, size, route, 1.0),
+                Err(Error::Profile(_))
+            ));
+        }
+        assert!(profile(&alt, size, &inside, 0.0).is_err());
+        assert_eq!(
+            parse_point("64, 32", Units::Blocks).unwrap(),
+            Vec2::new(2.0, 1.0)
+        );
+        assert!(parse_point("1,2,3", Units::Cells).is_err());
+    }
+
+    #[test]
+    fn csv_lists_distances_in_blocks() {
+        let size = Vec2::new(4, 1);
+        let samples =
+            profile(&ramp(size), size, &[Vec2::zero(), Vec2::new(3.0, 0.0)], 1.5).unwrap();
+        let mut csv = Vec::new();
+        write_csv(&samples, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "distance,x,y,altitude\n0,0,0,0\n48,1.5,0,3\n96,3,0,6\n"
+        );
+        let chart = render_profile(&samples, Vec2::new(200, 100));
+        assert!(chart.pixels().any(|&pixel| pixel == GROUND));
+        assert!(chart.pixels().any(|&pixel| pixel == TEXT));
+    }
+}
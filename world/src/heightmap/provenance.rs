@@ -0,0 +1,331 @@
+//! Records of how each written map was produced, kept next to it in a
+//! `.meta.json` sidecar, so that a map can be traced back to its inputs and
+//! settings long after it was made.
+
+use super::{
+    Compression, Error,
+    io::{save_map_compressed, temp_path_for},
+    read_json,
+};
+use crate::sim::ModernMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, ErrorKind, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// A file a map was produced from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputFile {
+    pub path: PathBuf,
+    /// Hash of the contents of the file, as `fnv1a64:` followed by 16 hex
+    /// digits.
+    pub hash: String,
+    /// How the file was produced, if it is a map with a record of its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Box<Provenance>>,
+}
+
+impl InputFile {
+    /// Describes the file at `path`, hashing its contents and loading the
+    /// record of how it was produced, if there is one.  The path is made
+    /// absolute where possible.
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            path: path.canonicalize().unwrap_or_else(|_| path.to_owned()),
+            hash: hash_file(path)?,
+            provenance: Provenance::load_for(path)?.map(Box::new),
+        })
+    }
+}
+
+/// Record of how a map was produced, written next to it by
+/// [`Provenance::save_map`] or [`Provenance::save_with`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Version of `veloren-world` that wrote the map.
+    pub version: String,
+    /// Tool or subcommand that wrote the map.
+    pub command: String,
+    /// Every setting of the command, including those left at their defaults.
+    pub params: serde_json::Value,
+    #[serde(default)]
+    pub inputs: Vec<InputFile>,
+    /// The steps applied to the inputs, in order, where the command applies
+    /// more than one.
+    #[serde(default)]
+    pub pipeline: Vec<String>,
+    /// Seeds of the random number generators used.
+    #[serde(default)]
+    pub seeds: Vec<u64>,
+    /// When the map was written, in UTC, such as `2024-03-01T12:30:00Z`.
+    pub created: String,
+}
+
+impl Provenance {
+    /// Starts a record of `command`, run with `params` by the running version
+    /// of this crate, now.
+    pub fn new(command: &str, params: &impl Serialize) -> Result<Self, Error> {
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            command: command.to_owned(),
+            params: serde_json::to_value(params)?,
+            inputs: Vec::new(),
+            pipeline: Vec::new(),
+            seeds: Vec::new(),
+            created: utc_timestamp(seconds),
+        })
+    }
+
+    /// Adds the file at `path` to the inputs; see [`InputFile::new`].
+    pub fn with_input(mut self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        self.inputs.push(InputFile::new(path.as_ref())?);
+        Ok(self)
+    }
+
+    pub fn with_step(mut self, step: impl Into<String>) -> Self {
+        self.pipeline.push(step.into());
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seeds.push(seed);
+        self
+    }
+
+    /// Path of the record accompanying the map at `bin_path`: the path with
+    /// `.meta.json` appended, such as `map.bin.meta.json`.
+    pub fn path_for(bin_path: &Path) -> PathBuf {
+        let mut name = bin_path.file_name().unwrap_or_default().to_owned();
+        name.push(".meta.json");
+        bin_path.with_file_name(name)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> { read_json(path) }
+
+    /// Loads the record accompanying the map at `bin_path`, if there is one.
+    pub fn load_for(bin_path: &Path) -> Result<Option<Self>, Error> {
+        match Self::load(Self::path_for(bin_path)) {
+            Ok(provenance) => Ok(Some(provenance)),
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Saves `map` to `path` like [`save_map_compressed`], along with this
+    /// record; see [`Provenance::save_with`].
+    pub fn save_map(
+        &self,
+        path: impl AsRef<Path>,
+        map: ModernMap,
+        compression: Compression,
+    ) -> Result<(), Error> {
+        self.save_with(path, |temp| save_map_compressed(temp, map, compression))
+    }
+
+    /// Writes the map at `path` with `save`, which is passed the path to
+    /// write it to, along with this record.
+    ///
+    /// Both are first written to temporary files in the same directory, and
+    /// only renamed into place once both are complete, so a failed write
+    /// leaves any previous pair untouched.  The previous record is removed
+    /// before the new map replaces the old one, so even an interruption
+    /// between the two renames can't leave a map next to the record of
+    /// another: at worst the new map is left without one.
+    pub fn save_with(
+        &self,
+        path: impl AsRef<Path>,
+        save: impl FnOnce(&Path) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let path = path.as_ref();
+        let meta_path = Self::path_for(path);
+        let (temp, temp_meta) = (temp_path_for(path), temp_path_for(&meta_path));
+        let result = save(&temp)
+            .and_then(|()| {
+                let mut writer = BufWriter::new(File::create(&temp_meta)?);
+                serde_json::to_writer_pretty(&mut writer, self)?;
+                writer
+                    .into_inner()
+                    .map_err(|e| e.into_error())?
+                    .sync_all()?;
+                File::open(&temp)?.sync_all()?;
+                Ok(())
+            })
+            .and_then(|()| match fs::remove_file(&meta_path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            })
+            .and_then(|()| Ok(fs::rename(&temp, path)?))
+            .and_then(|()| Ok(fs::rename(&temp_meta, &meta_path)?));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+            let _ = fs::remove_file(&temp_meta);
+        }
+        result
+    }
+}
+
+impl fmt::Display for Provenance {
+    /// Describes the record over several lines, with the inputs' own records
+    /// indented beneath them.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let indent = " ".repeat(f.width().unwrap_or(0));
+        writeln!(
+            f,
+            "{}Written by {} (veloren-world {}) at {}",
+            indent, self.command, self.version, self.created
+        )?;
+        writeln!(f, "{}  parameters: {}", indent, self.params)?;
+        for input in &self.inputs {
+            writeln!(
+                f,
+                "{}  input: {} ({})",
+                indent,
+                input.path.display(),
+                input.hash
+            )?;
+            if let Some(provenance) = &input.provenance {
+                write!(f, "{:width$}", provenance, width = indent.len() + 4)?;
+            }
+        }
+        if !self.pipeline.is_empty() {
+            writeln!(f, "{}  pipeline: {}", indent, self.pipeline.join(", then "))?;
+        }
+        if !self.seeds.is_empty() {
+            let seeds = self.seeds.iter().map(u64::to_string).collect::<Vec<_>>();
+            writeln!(f, "{}  seeds: {}", indent, seeds.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The 64-bit FNV-1a hash of the contents of the file at `path`.
+fn hash_file(path: &Path) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 1 << 16];
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    loop {
+        let read = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for &byte in &buf[..read] {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    Ok(format!("fnv1a64:{:016x}", hash))
+}
+
+/// `seconds` since the Unix epoch as an ISO 8601 date and time in UTC.
+fn utc_timestamp(seconds: u64) -> String {
+    let (days, time) = ((seconds / 86400) as i64, seconds % 86400);
+    // Howard Hinnant's conversion of days to a proleptic Gregorian date.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::{load_map, test_map};
+    use vek::*;
+
+    #[test]
+    fn provenance_round_trips_through_json() {
+        let inner = Provenance {
+            version: "0.1.0".to_owned(),
+            command: "hills".to_owned(),
+            params: serde_json::json!({ "count": 64, "seed": 7 }),
+            inputs: Vec::new(),
+            pipeline: Vec::new(),
+            seeds: vec![7],
+            created: utc_timestamp(0),
+        };
+        let provenance = Provenance {
+            command: "adjust".to_owned(),
+            params: serde_json::json!({ "scale": 1.5, "tilt": null }),
+            inputs: vec![InputFile {
+                path: PathBuf::from("/maps/hills.bin"),
+                hash: "fnv1a64:0123456789abcdef".to_owned(),
+                provenance: Some(Box::new(inner.clone())),
+            }],
+            pipeline: vec!["scale 1.5".to_owned(), "shift 20".to_owned()],
+            ..inner
+        };
+        let json = serde_json::to_string(&provenance).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Provenance>(&json).unwrap(),
+            provenance
+        );
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_timestamp(1_709_296_199), "2024-03-01T12:29:59Z");
+    }
+
+    #[test]
+    fn failed_writes_leave_the_previous_pair() {
+        let path = std::env::temp_dir().join(format!(
+            "veloren-heightmap-provenance-{}.bin",
+            std::process::id()
+        ));
+        let meta_path = Provenance::path_for(&path);
+        assert!(meta_path.to_string_lossy().ends_with(".bin.meta.json"));
+        let provenance = Provenance {
+            version: "0.1.0".to_owned(),
+            command: "test".to_owned(),
+            params: serde_json::Value::Null,
+            inputs: Vec::new(),
+            pipeline: Vec::new(),
+            seeds: Vec::new(),
+            created: utc_timestamp(0),
+        };
+        let map = test_map(Vec2::new(2, 2), |x, y| (x * y) as f64);
+        provenance.save_map(&path, map, Compression::None).unwrap();
+        let (bin, meta) = (fs::read(&path).unwrap(), fs::read(&meta_path).unwrap());
+
+        let result = Provenance {
+            command: "failing".to_owned(),
+            ..provenance
+        }
+        .save_with(&path, |temp| {
+            fs::write(temp, b"partial")?;
+            Err(Error::UnsupportedImage("simulated failure".to_owned()))
+        });
+        assert!(matches!(result, Err(Error::UnsupportedImage(_))));
+        assert_eq!(fs::read(&path).unwrap(), bin);
+        assert_eq!(fs::read(&meta_path).unwrap(), meta);
+        assert!(!temp_path_for(&path).exists());
+        assert!(!temp_path_for(&meta_path).exists());
+        assert_eq!(load_map(&path).unwrap().alt[5], 1.0);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&meta_path).unwrap();
+    }
+}
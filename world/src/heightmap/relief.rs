@@ -0,0 +1,277 @@
+//! Shaded relief: a hillshade composited over a hypsometric tint, the classic
+//! look of cartographic maps.
+
+use super::{
+    colormap::{Colormap, Stop, fraction},
+    export::gradient,
+    filter::Edges,
+};
+use image::RgbImage;
+use rayon::prelude::*;
+use vek::*;
+
+/// Width of a cell, a chunk, in meters, which sets how steep a given change
+/// in altitude between neighbouring cells is.
+pub(crate) const CELL_WIDTH: f64 = 32.0;
+
+/// Colors of the tint below sea level, from the deepest cell to the shore.
+const WATER: Colormap =
+    Colormap::from_static(&[Stop::new(0.0, [15, 35, 95]), Stop::new(1.0, [95, 155, 210])]);
+
+/// Colors of the tint above sea level, from the shore to the highest cell.
+const LAND: Colormap = Colormap::from_static(&[
+    Stop::new(0.0, [75, 125, 65]),
+    Stop::new(0.25, [160, 175, 95]),
+    Stop::new(0.55, [180, 140, 90]),
+    Stop::new(0.8, [145, 125, 115]),
+    Stop::new(1.0, [250, 250, 250]),
+]);
+
+/// How the hillshade is composited over the tint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Blend {
+    /// Darken the tint by the shade, leaving fully lit slopes unchanged.
+    #[default]
+    Multiply,
+    /// Darken shaded slopes and lighten lit ones, leaving flat ground, lit
+    /// at [`hillshade`]`(Vec2::zero())`, unchanged.
+    SoftLight,
+}
+
+/// Settings of a shaded relief render.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReliefParams {
+    /// Altitude at which the tint changes from water to land.
+    pub sea_level: f64,
+    pub blend: Blend,
+    /// Strength of the hillshade, from 0 for the bare tint to 1 for the full
+    /// shade.
+    pub contrast: f64,
+    /// How slopes are computed along the edges of the map.
+    pub edges: Edges,
+    /// If set, the tint runs through this colormap from the lowest altitude
+    /// to the highest, regardless of the sea level.
+    pub colormap: Option<Colormap>,
+}
+
+impl Default for ReliefParams {
+    fn default() -> Self {
+        Self {
+            sea_level: 0.0,
+            blend: Blend::Multiply,
+            contrast: 1.0,
+            edges: Edges::Clamp,
+            colormap: None,
+        }
+    }
+}
+
+impl ReliefParams {
+    /// Color of the tint at `alt` under the hillshade, for a map spanning
+    /// `min` to `max`: from the colormap if one is set, and otherwise
+    /// [`tint`].
+    pub fn tint(&self, alt: f64, min: f64, max: f64) -> [f64; 3] {
+        match &self.colormap {
+            Some(colormap) => colormap.sample(fraction(alt, min, max)),
+            None => tint(alt, min, max, self.sea_level),
+        }
+    }
+}
+
+/// Color of the hypsometric tint at `alt`, for a map spanning `min` to `max`,
+/// with components between 0 and 255.
+pub fn tint(alt: f64, min: f64, max: f64, sea_level: f64) -> [f64; 3] {
+    if alt < sea_level {
+        WATER.sample(fraction(alt, min, sea_level))
+    } else {
+        LAND.sample(fraction(alt, sea_level, max))
+    }
+}
+
+/// Brightness, between 0 and 1, of ground with the slope `grad` (in meters
+/// per cell, as from [`gradient`]) lit from the northwest at 45 degrees.
+pub fn hillshade(grad: Vec2<f64>) -> f64 {
+    hillshade_towards(grad, Vec3::new(-0.5, 0.5, std::f64::consts::FRAC_1_SQRT_2))
+}
+
+/// Brightness of ground with the slope `grad` like [`hillshade`], lit from
+/// `light`, a unit vector towards the light such as from [`light_towards`].
+pub fn hillshade_towards(grad: Vec2<f64>, light: Vec3<f64>) -> f64 {
+    normal(grad, CELL_WIDTH).dot(light).max(0.0)
+}
+
+/// Unit normal of ground with the slope `grad`, in meters per cell as from
+/// [`gradient`], for cells `cell_width` meters wide.
+pub fn normal(grad: Vec2<f64>, cell_width: f64) -> Vec3<f64> {
+    Vec3::new(-grad.x / cell_width, -grad.y / cell_width, 1.0).normalized()
+}
+
+/// Renders the altitude grid of size `size` as a tangent-space normal map,
+/// for cells `cell_width` meters wide, with slopes computed along its edges
+/// like `edges`.
+///
+/// Each component of the normal, from -1 to 1, is stored as `n * 0.5 + 0.5`
+/// of the full range of a channel: x (east) in red, and z (up) in blue.  As
+/// rows run from south at the top of the image to north at its bottom, and
+/// engines expect green to point up the image (the OpenGL convention), it
+/// holds the southward component.
+pub fn render_normals(alt: &[f64], size: Vec2<usize>, cell_width: f64, edges: Edges) -> RgbImage {
+    let mut buf = vec![0; size.product() * 3];
+    buf.par_chunks_mut((size.x * 3).max(1))
+        .enumerate()
+        .for_each(|(y, pixels)| {
+            for (x, pixel) in pixels.chunks_exact_mut(3).enumerate() {
+                let n = normal(gradient(alt, size, x, y, edges), cell_width);
+                let encoded = [n.x, -n.y, n.z].map(|e| ((e * 0.5 + 0.5) * 255.0).round() as u8);
+                pixel.copy_from_slice(&encoded);
+            }
+        });
+    RgbImage::from_raw(size.x as u32, size.y as u32, buf).expect("Buffer matches the image size")
+}
+
+/// Unit vector towards a light at `azimuth` degrees clockwise from north
+/// (towards higher y) and `elevation` degrees above the horizon, like the
+/// [`Sun`](super::shadow::Sun) of cast shadows.
+pub fn light_towards(azimuth: f64, elevation: f64) -> Vec3<f64> {
+    let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+    Vec3::new(
+        azimuth.sin() * elevation.cos(),
+        azimuth.cos() * elevation.cos(),
+        elevation.sin(),
+    )
+}
+
+/// Composites `shade`, from [`hillshade`], over the tint `color` using `blend`,
+/// weakened towards the neutral shade by `contrast`.
+pub(crate) fn composite(color: [f64; 3], shade: f64, blend: Blend, contrast: f64) -> [f64; 3] {
+    match blend {
+        Blend::Multiply => color.map(|c| c * (1.0 - contrast * (1.0 - shade))),
+        Blend::SoftLight => {
+            // Flat ground blends as the neutral 0.5, darker shades below it
+            // and lighter ones above.
+            let flat = hillshade(Vec2::zero());
+            let shade = if shade < flat {
+                0.5 * shade / flat
+            } else {
+                0.5 + 0.5 * (shade - flat) / (1.0 - flat)
+            };
+            let shade = 0.5 + (shade - 0.5) * contrast;
+            color.map(|c| {
+                let c = c / 255.0;
+                255.0 * ((1.0 - 2.0 * shade) * c * c + 2.0 * shade * c)
+            })
+        },
+    }
+}
+
+/// Renders the altitude grid of size `size`, spanning `min` to `max`, as a
+/// shaded relief.
+///
+/// The gradient of each cell is computed once, for its hillshade, and rows
+/// are rendered in parallel like
+/// [`render_grayscale`](super::export::render_grayscale).
+pub fn render_relief(
+    alt: &[f64],
+    size: Vec2<usize>,
+    min: f64,
+    max: f64,
+    params: &ReliefParams,
+) -> RgbImage {
+    let mut buf = vec![0; size.product() * 3];
+    buf.par_chunks_mut((size.x * 3).max(1))
+        .enumerate()
+        .for_each(|(y, pixels)| {
+            for (x, pixel) in pixels.chunks_exact_mut(3).enumerate() {
+                let color = params.tint(alt[y * size.x + x], min, max);
+                let shade = hillshade(gradient(alt, size, x, y, params.edges));
+                let color = composite(color, shade, params.blend, params.contrast);
+                pixel.copy_from_slice(&color.map(|c| c.round().clamp(0.0, 255.0) as u8));
+            }
+        });
+    RgbImage::from_raw(size.x as u32, size.y as u32, buf).expect("Buffer matches the image size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tint_follows_the_ramps() {
+        assert_eq!(tint(-100.0, -100.0, 500.0, 0.0), [15.0, 35.0, 95.0]);
+        // Between stops, colors are interpolated in Oklab.
+        assert_eq!(tint(-50.0, -100.0, 500.0, 0.0).map(f64::round), [
+            51.0, 93.0, 151.0
+        ]);
+        assert_eq!(tint(0.0, -100.0, 500.0, 0.0), [75.0, 125.0, 65.0]);
+        assert_eq!(tint(500.0, -100.0, 500.0, 0.0), [250.0, 250.0, 250.0]);
+        // Flat maps are still tinted.
+        assert_eq!(tint(5.0, 5.0, 5.0, 0.0), [250.0, 250.0, 250.0]);
+    }
+
+    #[test]
+    fn slopes_facing_the_light_are_brighter() {
+        let flat = hillshade(Vec2::zero());
+        assert!((flat - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-12);
+        // Rising to the southeast faces the light in the northwest.
+        assert!(hillshade(Vec2::new(20.0, -20.0)) > flat);
+        assert!(hillshade(Vec2::new(-20.0, 20.0)) < flat);
+        assert_eq!(hillshade(Vec2::new(-1e6, 1e6)), 0.0);
+        // The default light is the sun in the northwest, 45 degrees high.
+        let northwest = light_towards(315.0, 45.0);
+        let grad = Vec2::new(30.0, 10.0);
+        assert!((hillshade_towards(grad, northwest) - hillshade(grad)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn contrast_scales_the_shading() {
+        let color = [200.0, 100.0, 50.0];
+        for blend in [Blend::Multiply, Blend::SoftLight] {
+            let bare = composite(color, 0.2, blend, 0.0);
+            let half = composite(color, 0.2, blend, 0.5);
+            let full = composite(color, 0.2, blend, 1.0);
+            assert!(bare.iter().zip(color).all(|(c, o)| (c - o).abs() < 1e-9));
+            assert!(full[0] < half[0] && half[0] < bare[0], "{:?}", blend);
+        }
+        // Soft light brightens lit slopes, which multiplying never does, and
+        // leaves flat ground as it is.
+        assert!(composite(color, 0.9, Blend::SoftLight, 1.0)[0] > color[0]);
+        let flat = composite(color, hillshade(Vec2::zero()), Blend::SoftLight, 1.0);
+        assert!(flat.iter().zip(color).all(|(c, o)| (c - o).abs() < 1e-9));
+        assert_eq!(composite(color, 1.0, Blend::Multiply, 1.0), color);
+    }
+
+    #[test]
+    fn normal_maps_encode_the_slope() {
+        // Rising 32 m a cell towards the east, a 45 degree slope.
+        let size = Vec2::new(4, 3);
+        let alt = (0..size.product())
+            .map(|i| (i % size.x) as f64 * CELL_WIDTH)
+            .collect::<Vec<_>>();
+        let img = render_normals(&alt, size, CELL_WIDTH, Edges::Clamp);
+        let tilted = (255.0 * (0.5 - 0.5 * std::f64::consts::FRAC_1_SQRT_2)).round() as u8;
+        let up = (255.0 * (0.5 + 0.5 * std::f64::consts::FRAC_1_SQRT_2)).round() as u8;
+        assert_eq!(img.get_pixel(1, 1).0, [tilted, 128, up]);
+        // Wider cells flatten slopes, to a hair off straight up.
+        let wide = render_normals(&alt, size, CELL_WIDTH * 1e6, Edges::Clamp);
+        assert_eq!(wide.get_pixel(1, 1).0, [127, 128, 255]);
+        // Slopes rising to the north lean the normal south, up the image.
+        let north = (0..size.product())
+            .map(|i| (i / size.x) as f64 * CELL_WIDTH)
+            .collect::<Vec<_>>();
+        let img = render_normals(&north, size, CELL_WIDTH, Edges::Clamp);
+        assert_eq!(img.get_pixel(1, 1).0, [128, up, up]);
+    }
+
+    #[test]
+    fn ridges_are_shaded_on_their_far_side() {
+        // A ridge running north-south; its western flank faces the light.
+        let size = Vec2::new(7, 3);
+        let alt = (0..size.product())
+            .map(|i| 300.0 - ((i % size.x) as f64 - 3.0).abs() * 100.0)
+            .collect::<Vec<_>>();
+        let img = render_relief(&alt, size, 0.0, 300.0, &ReliefParams::default());
+        let brightness = |x| img.get_pixel(x, 1).0.iter().map(|&c| c as u32).sum::<u32>();
+        assert!(brightness(1) > brightness(5));
+    }
+}
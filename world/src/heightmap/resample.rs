@@ -0,0 +1,597 @@
+//! Resampling altitude grids to a different resolution.
+
+use super::{
+    Error, MAX_MAP_CELLS,
+    export::gradient,
+    filter::{Edges, convolve_separable, gaussian_kernel},
+    map_size,
+};
+use crate::sim::ModernMap;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use serde::Serialize;
+use vek::*;
+
+/// Bilinearly resamples the row-major grid of size `size` to `new_size`.
+///
+/// Cell centres are mapped so that both grids cover the same area; samples
+/// falling beyond the outermost cell centres take the value of the nearest
+/// edge cell.
+pub fn resample_bilinear(grid: &[f64], size: Vec2<usize>, new_size: Vec2<usize>) -> Vec<f64> {
+    let scale = size.map2(new_size, |old, new| old as f64 / new as f64);
+    let sample_axis = |pos: usize, scale: f64, len: usize| {
+        let src = ((pos as f64 + 0.5) * scale - 0.5).clamp(0.0, (len - 1) as f64);
+        let lo = src.floor() as usize;
+        (lo, (lo + 1).min(len - 1), src - lo as f64)
+    };
+
+    let mut out = Vec::with_capacity(new_size.product());
+    for y in 0..new_size.y {
+        let (y0, y1, ty) = sample_axis(y, scale.y, size.y);
+        for x in 0..new_size.x {
+            let (x0, x1, tx) = sample_axis(x, scale.x, size.x);
+            let at = |x, y| grid[y * size.x + x];
+            let top = at(x0, y0) * (1.0 - tx) + at(x1, y0) * tx;
+            let bottom = at(x0, y1) * (1.0 - tx) + at(x1, y1) * tx;
+            out.push(top * (1.0 - ty) + bottom * ty);
+        }
+    }
+    out
+}
+
+/// How the cells of a block are combined into one when downsampling.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum BlockReduce {
+    /// The mean of the block, which blends thin features such as coastlines
+    /// rather than aliasing them.
+    #[default]
+    Mean,
+    /// The lowest cell of the block, which keeps valleys and rivers.
+    Min,
+    /// The highest cell of the block, which keeps peaks and ridgelines.
+    Max,
+}
+
+/// Reduces the resolution of the row-major grid of size `size` by `factor`,
+/// combining each block of (up to) `factor` by `factor` cells with `reduce`,
+/// and returns the new grid with its size.
+///
+/// The last row and column of blocks only cover the cells left when `factor`
+/// doesn't divide `size`.
+pub fn downsample(
+    grid: &[f64],
+    size: Vec2<usize>,
+    factor: usize,
+    reduce: BlockReduce,
+) -> (Vec<f64>, Vec2<usize>) {
+    let factor = factor.max(1);
+    let new_size = size.map(|e| e.div_ceil(factor));
+    let mut out = Vec::with_capacity(new_size.product());
+    for y in 0..new_size.y {
+        let rows = factor * y..(factor * y + factor).min(size.y);
+        for x in 0..new_size.x {
+            let columns = factor * x..(factor * x + factor).min(size.x);
+            let cells = rows
+                .clone()
+                .flat_map(|y| grid[y * size.x..][columns.clone()].iter().copied());
+            out.push(match reduce {
+                BlockReduce::Mean => cells.sum::<f64>() / (rows.len() * columns.len()) as f64,
+                BlockReduce::Min => cells.fold(f64::INFINITY, f64::min),
+                BlockReduce::Max => cells.fold(f64::NEG_INFINITY, f64::max),
+            });
+        }
+    }
+    (out, new_size)
+}
+
+/// Halves the resolution of the row-major grid of size `size`, averaging
+/// each 2x2 block; see [`downsample`].
+pub fn downsample_half(grid: &[f64], size: Vec2<usize>) -> (Vec<f64>, Vec2<usize>) {
+    downsample(grid, size, 2, BlockReduce::Mean)
+}
+
+/// Reduces the resolution of both the altitude and basement of `map` by
+/// `factor`, which must be a power of two no larger than either side of the
+/// map, combining blocks with `reduce`.
+pub fn downsample_map(
+    map: &ModernMap,
+    factor: u32,
+    reduce: BlockReduce,
+) -> Result<ModernMap, Error> {
+    let size = map_size(map);
+    if !factor.is_power_of_two() || factor as usize > size.reduce_min() {
+        return Err(Error::ScaleFactor {
+            factor,
+            map_size: size,
+        });
+    }
+    let factor_lg = factor.trailing_zeros();
+    let (alt, _) = downsample(&map.alt, size, factor as usize, reduce);
+    let (basement, _) = downsample(&map.basement, size, factor as usize, reduce);
+    Ok(ModernMap {
+        map_size_lg: map.map_size_lg.map(|e| e - factor_lg),
+        continent_scale_hack: map.continent_scale_hack,
+        alt: alt.into_boxed_slice(),
+        basement: basement.into_boxed_slice(),
+    })
+}
+
+/// Value at `t`, between 0 and 1, of the Catmull-Rom spline through `p`, the
+/// values at -1, 0, 1 and 2.
+///
+/// It is written in terms of differences from `p[1]`, so that constant runs
+/// interpolate exactly.
+#[inline]
+fn catmull_rom(p: [f64; 4], t: f64) -> f64 {
+    let (d0, d2, d3) = (p[0] - p[1], p[2] - p[1], p[3] - p[1]);
+    let cubic = -d0 - 3.0 * d2 + d3;
+    let quadratic = 2.0 * d0 + 4.0 * d2 - d3;
+    p[1] + 0.5 * t * ((d2 - d0) + t * (quadratic + t * cubic))
+}
+
+/// Resamples the row-major grid of size `size` to `new_size` with bicubic
+/// (Catmull-Rom) interpolation, which keeps slopes smoother than
+/// [`resample_bilinear`] when enlarging grids, but may overshoot the input
+/// range near sharp steps.
+///
+/// Cell centres are mapped like in [`resample_bilinear`]; samples beyond the
+/// grid repeat its edge cells.
+pub fn resample_bicubic(grid: &[f64], size: Vec2<usize>, new_size: Vec2<usize>) -> Vec<f64> {
+    let scale = size.map2(new_size, |old, new| old as f64 / new as f64);
+    let taps = |pos: usize, scale: f64, len: usize| {
+        let src = ((pos as f64 + 0.5) * scale - 0.5).clamp(0.0, (len - 1) as f64);
+        let lo = src.floor() as isize;
+        let tap = |d: isize| (lo + d).clamp(0, len as isize - 1) as usize;
+        ([tap(-1), tap(0), tap(1), tap(2)], src - lo as f64)
+    };
+
+    let columns = (0..new_size.x)
+        .map(|x| taps(x, scale.x, size.x))
+        .collect::<Vec<_>>();
+    let mut out = Vec::with_capacity(new_size.product());
+    for y in 0..new_size.y {
+        let (rows, ty) = taps(y, scale.y, size.y);
+        for &(xs, tx) in &columns {
+            let row = |y: usize| catmull_rom(xs.map(|x| grid[y * size.x + x]), tx);
+            out.push(catmull_rom(rows.map(row), ty));
+        }
+    }
+    out
+}
+
+/// Fractal noise added when upscaling, so that the new cells get detail of
+/// their own rather than looking like a blurry blow-up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DetailNoise {
+    pub seed: u32,
+    /// Amplitude of the noise, as a fraction of the local relief: the
+    /// altitude range of the source cells around each cell.
+    pub amplitude: f64,
+}
+
+/// Altitude range of the 3x3 neighbourhood of every cell of the row-major
+/// grid of size `size`.
+pub(super) fn local_relief(grid: &[f64], size: Vec2<usize>) -> Vec<f64> {
+    (0..size.product())
+        .map(|i| {
+            let (x, y) = (i % size.x, i / size.x);
+            let (lo, hi) = (x.saturating_sub(1)..=(x + 1).min(size.x - 1))
+                .flat_map(|x| (y.saturating_sub(1)..=(y + 1).min(size.y - 1)).map(move |y| (x, y)))
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), (x, y)| {
+                    let alt = grid[y * size.x + x];
+                    (lo.min(alt), hi.max(alt))
+                });
+            hi - lo
+        })
+        .collect()
+}
+
+/// Enlarges the altitude and basement of `map` by `factor`, a power of two,
+/// with [`resample_bicubic`], adding `detail` noise if given.
+///
+/// The same noise is added to both grids, and its coarsest features are the
+/// size of the source cells, so that it only adds detail that the source
+/// resolution couldn't hold.  Wherever interpolation overshoot or noise lifts
+/// the basement above the altitude, it is lowered back to it.
+pub fn upscale_map(
+    map: &ModernMap,
+    factor: u32,
+    detail: Option<DetailNoise>,
+) -> Result<ModernMap, Error> {
+    let size = map_size(map);
+    let map_size_lg = upscaled_size_lg(map, factor)?;
+    let new_size = map_size_lg.map(|e| 1usize << e);
+
+    let mut alt = resample_bicubic(&map.alt, size, new_size);
+    let mut basement = resample_bicubic(&map.basement, size, new_size);
+    if let Some(detail) = detail {
+        let relief = resample_bilinear(&local_relief(&map.alt, size), size, new_size);
+        let noise = Fbm::<Perlin>::new(detail.seed).set_octaves(4);
+        for (i, (alt, basement)) in alt.iter_mut().zip(&mut basement).enumerate() {
+            // Sampled in units of source cells.
+            let pos =
+                Vec2::new(i % new_size.x, i / new_size.x).map(|e| (e as f64 + 0.5) / factor as f64);
+            let offset = noise.get([pos.x, pos.y]) * detail.amplitude * relief[i];
+            *alt += offset;
+            *basement += offset;
+        }
+    }
+    Ok(upscaled_map(map, map_size_lg, alt, basement))
+}
+
+/// Fractal noise added by [`upscale_map_fractal`], strongest on flat ground,
+/// where a bilinear blow-up looks most featureless, and fading on steep
+/// slopes, whose shape the source already holds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FractalDetail {
+    pub seed: u32,
+    /// Spectral exponent of the noise: each octave, at twice the frequency of
+    /// the one before, has `2^-exponent` times its amplitude, so that higher
+    /// exponents give smoother detail and 0 gives equally strong octaves.
+    pub exponent: f64,
+    /// Amplitude of the noise on flat ground, in meters.  It is halved where
+    /// the source rises by as much per cell.
+    pub amplitude: f64,
+    /// Number of octaves, the coarsest the size of the source cells.
+    pub octaves: usize,
+}
+
+/// Enlarges the altitude and basement of `map` by `factor`, a power of two,
+/// with [`resample_bilinear`], adding the same `fractal` noise to both.
+///
+/// Unlike [`upscale_map`] with [`DetailNoise`], which scales its noise with
+/// the local relief, the noise here shrinks with the slope of the source, as
+/// `amplitude / (1 + slope / amplitude)`, synthesizing detail on plains and
+/// plateaus without roughening cliffs.  Wherever the noise lifts the
+/// basement above the altitude, it is lowered back to it.
+pub fn upscale_map_fractal(
+    map: &ModernMap,
+    factor: u32,
+    fractal: FractalDetail,
+) -> Result<ModernMap, Error> {
+    let size = map_size(map);
+    let map_size_lg = upscaled_size_lg(map, factor)?;
+    let new_size = map_size_lg.map(|e| 1usize << e);
+
+    let mut alt = resample_bilinear(&map.alt, size, new_size);
+    let mut basement = resample_bilinear(&map.basement, size, new_size);
+    let slopes = (0..size.product())
+        .map(|i| gradient(&map.alt, size, i % size.x, i / size.x, Edges::Clamp).magnitude())
+        .collect::<Vec<_>>();
+    let slopes = resample_bilinear(&slopes, size, new_size);
+    let noise = Fbm::<Perlin>::new(fractal.seed)
+        .set_octaves(fractal.octaves.max(1))
+        .set_persistence(2f64.powf(-fractal.exponent));
+    let amplitude = fractal.amplitude.abs();
+    for (i, (alt, basement)) in alt.iter_mut().zip(&mut basement).enumerate() {
+        // Sampled in units of source cells.
+        let pos =
+            Vec2::new(i % new_size.x, i / new_size.x).map(|e| (e as f64 + 0.5) / factor as f64);
+        let weight = if amplitude > 0.0 {
+            amplitude / (1.0 + slopes[i] / amplitude)
+        } else {
+            0.0
+        };
+        let offset = noise.get([pos.x, pos.y]) * weight;
+        *alt += offset;
+        *basement += offset;
+    }
+    Ok(upscaled_map(map, map_size_lg, alt, basement))
+}
+
+/// The `map_size_lg` of `map` enlarged by `factor`, which must be a power of
+/// two leaving the map within [`MAX_MAP_CELLS`].
+fn upscaled_size_lg(map: &ModernMap, factor: u32) -> Result<Vec2<u32>, Error> {
+    if !factor.is_power_of_two() {
+        return Err(Error::ScaleFactor {
+            factor,
+            map_size: map_size(map),
+        });
+    }
+    let map_size_lg = map.map_size_lg.map(|e| e + factor.trailing_zeros());
+    if map_size_lg.sum() > MAX_MAP_CELLS.trailing_zeros() {
+        return Err(Error::SizeOverflow { map_size_lg });
+    }
+    Ok(map_size_lg)
+}
+
+/// `map` with its grids replaced by the upscaled `alt` and `basement`, the
+/// basement lowered to the altitude wherever it lies above.
+fn upscaled_map(
+    map: &ModernMap,
+    map_size_lg: Vec2<u32>,
+    alt: Vec<f64>,
+    mut basement: Vec<f64>,
+) -> ModernMap {
+    for (basement, &alt) in basement.iter_mut().zip(&alt) {
+        *basement = basement.min(alt);
+    }
+    ModernMap {
+        map_size_lg,
+        continent_scale_hack: map.continent_scale_hack,
+        alt: alt.into_boxed_slice(),
+        basement: basement.into_boxed_slice(),
+    }
+}
+
+/// Filter applied before shrinking a grid, so that detail finer than the new
+/// cells is blended away instead of aliasing into noise and moiré.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Antialias {
+    /// A box filter as wide as the new cells.
+    Box,
+    /// A Gaussian filter with a standard deviation of half a new cell, which
+    /// is smoother but blurs more.
+    #[default]
+    Gaussian,
+}
+
+impl Antialias {
+    /// Kernel filtering an axis shrunk by `scale` old cells per new cell;
+    /// axes that aren't shrunk aren't filtered.
+    fn kernel(self, scale: f64) -> Vec<f64> {
+        if scale <= 1.0 {
+            return vec![1.0];
+        }
+        match self {
+            Antialias::Box => vec![1.0; 2 * ((scale - 1.0) / 2.0).ceil() as usize + 1],
+            Antialias::Gaussian => gaussian_kernel(scale / 2.0),
+        }
+    }
+}
+
+/// Resamples the row-major grid of size `size` to `new_size` like
+/// [`resample_bilinear`], first filtering it with `antialias` along the axes
+/// it shrinks, over a radius proportional to how much they shrink.
+pub fn resample_antialiased(
+    grid: &[f64],
+    size: Vec2<usize>,
+    new_size: Vec2<usize>,
+    antialias: Antialias,
+) -> Vec<f64> {
+    let scale = size.map2(new_size, |old, new| old as f64 / new as f64);
+    if scale.x <= 1.0 && scale.y <= 1.0 {
+        return resample_bilinear(grid, size, new_size);
+    }
+    let filtered = convolve_separable(
+        grid,
+        size.x as u32,
+        size.y as u32,
+        &antialias.kernel(scale.x),
+        &antialias.kernel(scale.y),
+        Edges::Clamp,
+    );
+    resample_bilinear(&filtered, size, new_size)
+}
+
+/// Resamples both the altitude and basement of `map` to a map of size
+/// `2^map_size_lg`, antialiasing them with `antialias` if given.
+pub fn resample_map(
+    map: &ModernMap,
+    map_size_lg: Vec2<u32>,
+    antialias: Option<Antialias>,
+) -> ModernMap {
+    let size = map_size(map);
+    let new_size = map_size_lg.map(|e| 1usize << e);
+    let resample = |grid: &[f64]| match antialias {
+        Some(antialias) => resample_antialiased(grid, size, new_size, antialias),
+        None => resample_bilinear(grid, size, new_size),
+    };
+    ModernMap {
+        map_size_lg,
+        continent_scale_hack: map.continent_scale_hack,
+        alt: resample(&map.alt).into_boxed_slice(),
+        basement: resample(&map.basement).into_boxed_slice(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::{random_grids, test_map};
+
+    #[test]
+    fn same_size_is_identity() {
+        let grid = (0..16).map(|i| (i as f64).sqrt()).collect::<Vec<_>>();
+        let size = Vec2::new(4, 4);
+        assert_eq!(resample_bilinear(&grid, size, size), grid);
+    }
+
+    #[test]
+    fn upsampling_interpolates_linearly() {
+        // A ramp along x stays a ramp, clamped at the edge cells.
+        let grid = [0.0, 10.0, 0.0, 10.0];
+        let out = resample_bilinear(&grid, Vec2::new(2, 2), Vec2::new(4, 1));
+        assert_eq!(out, vec![0.0, 2.5, 7.5, 10.0]);
+    }
+
+    #[test]
+    fn bicubic_interpolation_passes_through_samples() {
+        // Enlarging by 3 puts every third cell centre on a source centre.
+        let grid = [0.0, 0.0, 10.0, 10.0];
+        let out = resample_bicubic(&grid, Vec2::new(4, 1), Vec2::new(12, 1));
+        for (x, &expected) in grid.iter().enumerate() {
+            assert!((out[3 * x + 1] - expected).abs() < 1e-9, "{:?}", out);
+        }
+        // Unlike bilinear interpolation, it overshoots around the step.
+        assert!(out.iter().any(|&alt| alt < 0.0));
+        assert!(out.iter().any(|&alt| alt > 10.0));
+    }
+
+    #[test]
+    fn flat_maps_stay_flat_without_noise() {
+        let map = test_map(Vec2::new(2, 2), |_, _| 37.3);
+        let big = upscale_map(&map, 4, None).unwrap();
+        assert_eq!(big.map_size_lg, Vec2::new(4, 4));
+        assert!(big.alt.iter().all(|&alt| alt == 37.3));
+        assert!(big.basement.iter().all(|&alt| alt == 37.3 - 10.0));
+        assert!(matches!(
+            upscale_map(&map, 3, None),
+            Err(Error::ScaleFactor { .. })
+        ));
+    }
+
+    #[test]
+    fn detail_noise_is_deterministic_and_keeps_the_basement_below() {
+        let map = test_map(Vec2::new(3, 3), |x, y| {
+            if (x + y) % 3 == 0 {
+                200.0
+            } else {
+                x as f64 * 10.0
+            }
+        });
+        let noise = |seed| DetailNoise {
+            seed,
+            amplitude: 0.5,
+        };
+        let a = upscale_map(&map, 2, Some(noise(7))).unwrap();
+        let b = upscale_map(&map, 2, Some(noise(7))).unwrap();
+        let c = upscale_map(&map, 2, Some(noise(8))).unwrap();
+        assert_eq!((&a.alt, &a.basement), (&b.alt, &b.basement));
+        assert_ne!(a.alt, c.alt);
+        assert_ne!(a.alt, upscale_map(&map, 2, None).unwrap().alt);
+        assert!(
+            a.alt
+                .iter()
+                .zip(&*a.basement)
+                .all(|(alt, basement)| basement <= alt)
+        );
+    }
+
+    #[test]
+    fn fractal_detail_is_seeded_and_spares_steep_slopes() {
+        // Flat ground beside a cliff.
+        let map = test_map(Vec2::new(3, 3), |x, _| if x < 4 { 0.0 } else { 2000.0 });
+        let fractal = |seed| FractalDetail {
+            seed,
+            exponent: 1.0,
+            amplitude: 20.0,
+            octaves: 4,
+        };
+        let a = upscale_map_fractal(&map, 4, fractal(3)).unwrap();
+        let b = upscale_map_fractal(&map, 4, fractal(3)).unwrap();
+        assert_eq!(a.map_size_lg, Vec2::new(5, 5));
+        assert_eq!((&a.alt, &a.basement), (&b.alt, &b.basement));
+        assert_ne!(a.alt, upscale_map_fractal(&map, 4, fractal(4)).unwrap().alt);
+        assert!(
+            a.alt
+                .iter()
+                .zip(&*a.basement)
+                .all(|(alt, basement)| basement <= alt)
+        );
+
+        // Without noise, it is a bilinear upscale.
+        let none = FractalDetail {
+            amplitude: 0.0,
+            ..fractal(3)
+        };
+        let smooth = upscale_map_fractal(&map, 4, none).unwrap();
+        assert_eq!(
+            &*smooth.alt,
+            resample_bilinear(&map.alt, Vec2::new(8, 8), Vec2::new(32, 32))
+        );
+        let change = |i: usize| (a.alt[i] - smooth.alt[i]).abs();
+        let plain = (0..32 * 32).filter(|i| i % 32 < 8);
+        assert!(plain.clone().any(|i| change(i) > 0.1));
+        assert!(plain.clone().all(|i| change(i) <= 20.0));
+        // Where the cliff rises 1000 m per cell, the noise is 50 times weaker.
+        assert!((0..32).all(|y| change(y * 32 + 16) <= 20.0 / 50.0));
+    }
+
+    #[test]
+    fn antialiasing_removes_aliasing_when_shrinking() {
+        // Shrinking a checkerboard by 3 picks single cells, which alternate.
+        let size = Vec2::new(9, 9);
+        let grid = (0..81)
+            .map(|i| if (i % 9 + i / 9) % 2 == 0 { 0.0 } else { 10.0 })
+            .collect::<Vec<_>>();
+        let new_size = Vec2::new(3, 3);
+        let spread = |out: &[f64]| {
+            let min = out.iter().copied().fold(f64::INFINITY, f64::min);
+            out.iter().copied().fold(f64::NEG_INFINITY, f64::max) - min
+        };
+        assert_eq!(spread(&resample_bilinear(&grid, size, new_size)), 10.0);
+        for antialias in [Antialias::Box, Antialias::Gaussian] {
+            let out = resample_antialiased(&grid, size, new_size, antialias);
+            assert!(spread(&out) < 2.0, "{:?}: {:?}", antialias, out);
+        }
+
+        // Growing grids are left unfiltered.
+        let grid = (0..16).map(|i| (i * i) as f64).collect::<Vec<_>>();
+        let (size, new_size) = (Vec2::new(4, 4), Vec2::new(8, 8));
+        assert_eq!(
+            resample_antialiased(&grid, size, new_size, Antialias::Box),
+            resample_bilinear(&grid, size, new_size)
+        );
+    }
+
+    #[test]
+    fn halving_averages_blocks() {
+        let grid = [
+            0.0, 2.0, 10.0, 20.0, //
+            4.0, 6.0, 30.0, 40.0,
+        ];
+        let (out, size) = downsample_half(&grid, Vec2::new(4, 2));
+        assert_eq!((out, size), (vec![3.0, 25.0], Vec2::new(2, 1)));
+
+        // Odd sides leave partial blocks at the end.
+        let grid = [
+            1.0, 3.0, 9.0, //
+            5.0, 7.0, 11.0, //
+            2.0, 4.0, 6.0,
+        ];
+        let (out, size) = downsample_half(&grid, Vec2::new(3, 3));
+        assert_eq!((out, size), (vec![4.0, 10.0, 3.0, 6.0], Vec2::new(2, 2)));
+
+        let (out, size) = downsample_half(&[8.0], Vec2::new(1, 1));
+        assert_eq!((out, size), (vec![8.0], Vec2::new(1, 1)));
+    }
+
+    #[test]
+    fn checkerboards_reduce_to_their_mean_or_extremes() {
+        let size = Vec2::new(4, 4);
+        let grid = (0..16)
+            .map(|i| if (i % 4 + i / 4) % 2 == 0 { 10.0 } else { 30.0 })
+            .collect::<Vec<_>>();
+        for (reduce, expected) in [
+            (BlockReduce::Mean, 20.0),
+            (BlockReduce::Min, 10.0),
+            (BlockReduce::Max, 30.0),
+        ] {
+            let (out, new_size) = downsample(&grid, size, 2, reduce);
+            assert_eq!(new_size, Vec2::new(2, 2));
+            assert_eq!(out, vec![expected; 4], "{:?}", reduce);
+        }
+    }
+
+    #[test]
+    fn maps_shrink_by_powers_of_two() {
+        let map = test_map(Vec2::new(3, 2), |x, y| (x + 8 * y) as f64);
+        let small = downsample_map(&map, 4, BlockReduce::Mean).unwrap();
+        assert_eq!(small.map_size_lg, Vec2::new(1, 0));
+        assert_eq!(&*small.alt, &[13.5, 17.5]);
+        assert_eq!(&*small.basement, &[3.5, 7.5]);
+        for factor in [0, 3, 8] {
+            assert!(matches!(
+                downsample_map(&map, factor, BlockReduce::Mean),
+                Err(Error::ScaleFactor { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn resampling_stays_within_input_range() {
+        for (size, grid) in random_grids(100) {
+            let min = grid.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = grid.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            for new_size in [size.yx(), size * 3, size.map(|e| e.div_ceil(2))] {
+                let out = resample_bilinear(&grid, size, new_size);
+                assert_eq!(out.len(), new_size.product());
+                assert!(out.iter().all(|e| (min - 1e-9..=max + 1e-9).contains(e)));
+            }
+        }
+    }
+}
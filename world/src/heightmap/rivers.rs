@@ -0,0 +1,243 @@
+//! Carving river channels painted in a separate image into a map.
+
+use super::{Error, Warning, mask::Mask};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, path::PathBuf};
+use vek::*;
+
+/// How deep and wide channels are carved along painted rivers.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RiverParams {
+    /// Depth carved below a white river pixel; darker pixels carve
+    /// proportionally shallower channels.
+    pub max_depth: f64,
+    /// Width in cells over which channels spread around the painted lines,
+    /// their banks sloping up smoothly.
+    pub width: f64,
+}
+
+/// Rivers carved while converting an image, read from a second grayscale
+/// image in which they are painted as bright lines.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rivers {
+    pub image: PathBuf,
+    #[serde(flatten)]
+    pub params: RiverParams,
+}
+
+impl Rivers {
+    /// Loads the rivers image as a mask for a grid of `size` cells.
+    ///
+    /// Images of another size are bilinearly resampled to `size`, which is
+    /// reported with a [`Warning::RiversResampled`].
+    pub fn load(&self, size: Vec2<usize>) -> Result<(Mask, Option<Warning>), Error> {
+        let mask = Mask::load(&self.image)?;
+        if mask.size == size {
+            return Ok((mask, None));
+        }
+        let warning = Warning::RiversResampled {
+            found: mask.size,
+            expected: size,
+        };
+        Ok((mask.resample(size), Some(warning)))
+    }
+}
+
+/// Lowers the row-major grid `alt` of `size` cells along the rivers painted in
+/// `rivers`, so that water follows them to the sea.
+///
+/// Each cell is lowered by `params.max_depth` times its weight in `rivers`,
+/// feathered over `params.width` cells so that channels aren't single-cell
+/// trenches.  The painted cells themselves are then lowered where needed so
+/// that each connected river descends, without pooling, towards its mouth:
+/// its cells at or below `sea_level`, or its lowest cell if it never reaches
+/// the sea.  Rivers connect diagonally as well as straight.
+pub fn carve_rivers(
+    alt: &mut [f64],
+    size: Vec2<usize>,
+    rivers: &Mask,
+    params: &RiverParams,
+    sea_level: f64,
+) -> Result<(), Error> {
+    rivers.check_size(size)?;
+    let channels = rivers.feathered(params.width / 2.0);
+    for (alt, weight) in alt.iter_mut().zip(&channels.values) {
+        *alt -= params.max_depth * weight;
+    }
+
+    let (w, h) = (size.x as isize, size.y as isize);
+    let painted = |idx: usize| rivers.values[idx] > 0.0;
+    let neighbors = |idx: usize| {
+        let (x, y) = ((idx % size.x) as isize, (idx / size.x) as isize);
+        (-1..=1)
+            .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(move |&(nx, ny)| (nx, ny) != (x, y) && nx >= 0 && ny >= 0 && nx < w && ny < h)
+            .map(move |(nx, ny)| (ny * w + nx) as usize)
+    };
+
+    let mut in_river = vec![false; alt.len()];
+    let mut downstream = vec![usize::MAX; alt.len()];
+    for start in 0..alt.len() {
+        if !painted(start) || in_river[start] {
+            continue;
+        }
+        // Collect the connected river containing `start`.
+        let mut river = vec![start];
+        in_river[start] = true;
+        let mut i = 0;
+        while let Some(&idx) = river.get(i) {
+            for neighbor in neighbors(idx) {
+                if painted(neighbor) && !in_river[neighbor] {
+                    in_river[neighbor] = true;
+                    river.push(neighbor);
+                }
+            }
+            i += 1;
+        }
+
+        // Walk upstream from the mouth, so that every cell is reached from
+        // the cell it drains into.
+        let mut mouth = river
+            .iter()
+            .copied()
+            .filter(|&idx| alt[idx] <= sea_level)
+            .collect::<Vec<_>>();
+        if mouth.is_empty() {
+            mouth.extend(
+                river
+                    .iter()
+                    .copied()
+                    .min_by(|&a, &b| alt[a].total_cmp(&alt[b])),
+            );
+        }
+        let mut order = Vec::with_capacity(river.len());
+        let mut queue = VecDeque::new();
+        for idx in mouth {
+            downstream[idx] = idx;
+            queue.push_back(idx);
+        }
+        while let Some(idx) = queue.pop_front() {
+            order.push(idx);
+            for neighbor in neighbors(idx) {
+                if painted(neighbor) && downstream[neighbor] == usize::MAX {
+                    downstream[neighbor] = idx;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        // Then lower every cell that is higher than a cell upstream of it,
+        // starting from the sources.
+        for &idx in order.iter().rev() {
+            let next = downstream[idx];
+            alt[next] = alt[next].min(alt[idx]);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_descend_along_the_painted_line() {
+        // A slope rising to the east, with a bump halfway up, and a river
+        // painted straight across it.
+        let size = Vec2::new(16, 9);
+        let ground = |x: usize, _| 100.0 + x as f64 * 10.0 - if x == 8 { 0.0 } else { 30.0 };
+        let mut alt = (0..size.y)
+            .flat_map(|y| (0..size.x).map(move |x| ground(x, y)))
+            .collect::<Vec<_>>();
+        let mut rivers = Mask {
+            size,
+            values: vec![0.0; size.product()],
+        };
+        rivers.values[4 * size.x..5 * size.x].fill(1.0);
+        let params = RiverParams {
+            max_depth: 20.0,
+            width: 4.0,
+        };
+        carve_rivers(&mut alt, size, &rivers, &params, 0.0).unwrap();
+
+        let profile = &alt[4 * size.x..5 * size.x];
+        assert!(
+            profile.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{:?}",
+            profile
+        );
+        assert_eq!(profile[0], ground(0, 4) - 20.0);
+        assert_eq!(profile[15], ground(15, 4) - 20.0);
+        // The bump is cut through, to the level of the channel above it.
+        assert_eq!(profile[8], profile[9]);
+        // The banks slope up to untouched ground.
+        let column = (0..size.y).map(|y| alt[y * size.x + 3]).collect::<Vec<_>>();
+        assert!(column[3] > column[4] && column[2] > column[3]);
+        assert_eq!(column[0], ground(3, 0));
+    }
+
+    #[test]
+    fn rivers_drain_to_the_sea() {
+        // Painted cells at and below sea level are mouths, even when the
+        // river passes lower ground inland.
+        let size = Vec2::new(5, 1);
+        let mut alt = vec![-5.0, 0.0, 10.0, 2.0, 30.0];
+        let rivers = Mask {
+            size,
+            values: vec![1.0; 5],
+        };
+        let params = RiverParams {
+            max_depth: 1.0,
+            width: 0.0,
+        };
+        carve_rivers(&mut alt, size, &rivers, &params, 0.0).unwrap();
+        assert_eq!(alt, [-6.0, -1.0, 1.0, 1.0, 29.0]);
+    }
+
+    #[test]
+    fn mismatched_images_are_resampled_with_a_warning() {
+        let path = std::env::temp_dir().join(format!(
+            "veloren-heightmap-rivers-{}.png",
+            std::process::id()
+        ));
+        image::GrayImage::from_pixel(2, 2, image::Luma([255]))
+            .save(&path)
+            .unwrap();
+        let rivers = Rivers {
+            image: path.clone(),
+            params: RiverParams {
+                max_depth: 1.0,
+                width: 1.0,
+            },
+        };
+
+        let (mask, warning) = rivers.load(Vec2::new(2, 2)).unwrap();
+        assert_eq!((mask.size, warning), (Vec2::new(2, 2), None));
+        let (mask, warning) = rivers.load(Vec2::new(4, 4)).unwrap();
+        assert_eq!(mask.size, Vec2::new(4, 4));
+        assert_eq!(
+            warning,
+            Some(Warning::RiversResampled {
+                found: Vec2::new(2, 2),
+                expected: Vec2::new(4, 4),
+            })
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rivers_must_match_the_grid() {
+        let rivers = Mask {
+            size: Vec2::new(2, 2),
+            values: vec![0.0; 4],
+        };
+        let params = RiverParams {
+            max_depth: 1.0,
+            width: 1.0,
+        };
+        assert!(matches!(
+            carve_rivers(&mut [0.0; 9], Vec2::new(3, 3), &rivers, &params, 0.0),
+            Err(Error::SizeMismatch { .. })
+        ));
+    }
+}
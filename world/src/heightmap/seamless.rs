@@ -0,0 +1,130 @@
+//! Making maps seamless, so that worlds which wrap around show no step where
+//! their opposite edges meet.
+
+use super::map_size;
+use crate::sim::ModernMap;
+use vek::*;
+
+/// Largest difference, between `grid` of size `size` and the same grid
+/// wrapped around, across either pair of opposite edges: row 0 against row
+/// `height - 1`, and column 0 against column `width - 1`.
+pub fn edge_mismatch(grid: &[f64], size: Vec2<usize>) -> f64 {
+    if size.product() == 0 {
+        return 0.0;
+    }
+    let (w, h) = (size.x, size.y);
+    let rows = (0..w).map(|x| (grid[x], grid[(h - 1) * w + x]));
+    let columns = (0..h).map(|y| (grid[y * w], grid[y * w + w - 1]));
+    rows.chain(columns)
+        .map(|(a, b)| (a - b).abs())
+        .fold(0.0, f64::max)
+}
+
+/// Cross-fades the `band` cells along each edge of `grid` with those along
+/// the opposite edge, mirrored, so they meet halfway: cell `i` from an edge
+/// takes `(1 - i / band) / 2` of its mirror, and cells further in than `band`
+/// are untouched.  Each band is limited to half the width or height.
+///
+/// The columns are blended first and the rows then blend the result, so the
+/// corners, which lie in both bands, end up blended between all four corners
+/// once rather than faded twice; the two blends commute, so the order doesn't
+/// matter.  Afterwards row 0 equals row `height - 1` and column 0 equals column
+/// `width - 1` exactly.
+pub fn make_seamless(grid: &mut [f64], size: Vec2<usize>, band: usize) {
+    let (w, h) = (size.x, size.y);
+    blend_edges(grid, (h, w), (w, 1), band);
+    blend_edges(grid, (w, 1), (h, w), band);
+}
+
+/// Blends each of `lines` lines of `grid`, which start `line_stride` apart,
+/// with its own mirror image near both of its ends; a line is made of `len`
+/// cells `stride` apart.
+fn blend_edges(
+    grid: &mut [f64],
+    (lines, line_stride): (usize, usize),
+    (len, stride): (usize, usize),
+    band: usize,
+) {
+    if len < 2 {
+        return;
+    }
+    let band = band.clamp(1, len / 2);
+    for line in 0..lines {
+        let start = line * line_stride;
+        for i in 0..band {
+            let t = 0.5 * (1.0 - i as f64 / band as f64);
+            let (near, far) = (start + i * stride, start + (len - 1 - i) * stride);
+            let (a, b) = (grid[near], grid[far]);
+            grid[near] = a * (1.0 - t) + b * t;
+            grid[far] = b * (1.0 - t) + a * t;
+        }
+    }
+}
+
+/// Largest [`edge_mismatch`] of either grid of `map`.
+pub fn map_edge_mismatch(map: &ModernMap) -> f64 {
+    let size = map_size(map);
+    edge_mismatch(&map.alt, size).max(edge_mismatch(&map.basement, size))
+}
+
+/// Makes both grids of `map` seamless with [`make_seamless`].  Since both are
+/// blended with the same weights, a basement that was below the altitudes
+/// stays below them.
+pub fn make_map_seamless(map: &mut ModernMap, band: usize) {
+    let size = map_size(map);
+    make_seamless(&mut map.alt, size, band);
+    make_seamless(&mut map.basement, size, band);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::{random_grids, test_map};
+
+    #[test]
+    fn opposite_edges_match_afterwards() {
+        for (size, mut grid) in random_grids(64) {
+            let original = grid.clone();
+            make_seamless(&mut grid, size, 3);
+            assert_eq!(edge_mismatch(&grid, size), 0.0, "{:?}", size);
+            // Cells outside both bands keep their altitudes.
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let inside = |pos: usize, len: usize| {
+                        len < 2 || (3.min(len / 2)..len - 3.min(len / 2)).contains(&pos)
+                    };
+                    if inside(x, size.x) && inside(y, size.y) {
+                        let i = y * size.x + x;
+                        assert_eq!(grid[i], original[i], "({}, {}) of {:?}", x, y, size);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corners_are_blended_once() {
+        // Only the corners of a 4x4 grid differ from 0.
+        let size = Vec2::new(4, 4);
+        let mut grid = vec![0.0; 16];
+        grid[0] = 8.0;
+        grid[3] = 4.0;
+        grid[12] = 2.0;
+        grid[15] = 2.0;
+        make_seamless(&mut grid, size, 1);
+        for corner in [0, 3, 12, 15] {
+            assert_eq!(grid[corner], 4.0);
+        }
+    }
+
+    #[test]
+    fn maps_wrap_without_a_step() {
+        let mut map = test_map(Vec2::new(4, 3), |x, y| x as f64 * 10.0 - y as f64 * 3.0);
+        assert_eq!(map_edge_mismatch(&map), 150.0);
+        make_map_seamless(&mut map, 3);
+        assert_eq!(map_edge_mismatch(&map), 0.0);
+        // Halfway across the map is left alone.
+        assert_eq!(map.alt[3 * 16 + 8], 80.0 - 9.0);
+        assert!(map.alt.iter().zip(&*map.basement).all(|(a, b)| b <= a));
+    }
+}
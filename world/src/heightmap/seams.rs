@@ -0,0 +1,189 @@
+//! Finding seams: lines between two rows or columns of a map across which
+//! the altitudes jump far more than they do across nearby lines, as left by
+//! stitching tiles whose edges don't match.
+
+use std::fmt;
+use vek::*;
+
+/// Direction of the line a seam runs along.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Axis {
+    /// A seam between two columns, running from the top of the map to the
+    /// bottom.
+    Column,
+    /// A seam between two rows.
+    Row,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Seam {
+    pub axis: Axis,
+    /// Index of the column or row just after the seam, so that the seam
+    /// between two tiles of `n` cells is at a multiple of `n`.
+    pub position: usize,
+    /// Average difference of altitude across the seam.
+    pub jump: f64,
+    /// Median of the average differences across the nearby lines.
+    pub baseline: f64,
+}
+
+impl Seam {
+    /// How many times larger the jump across the seam is than those across
+    /// nearby lines.
+    pub fn ratio(&self) -> f64 { self.jump / self.baseline }
+}
+
+impl fmt::Display for Seam {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (what, coordinate) = match self.axis {
+            Axis::Column => ("column", 'x'),
+            Axis::Row => ("row", 'y'),
+        };
+        write!(
+            f,
+            "{} seam at {} = {}: altitudes jump {:.2} on average, against {:.2} across nearby {}s",
+            what, coordinate, self.position, self.jump, self.baseline, what
+        )?;
+        if self.baseline > 0.0 {
+            write!(f, " ({:.1}x)", self.ratio())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SeamParams {
+    /// Number of lines on each side of a line that it is compared with.
+    pub radius: usize,
+    /// How many times larger than the median of its neighbours the jump
+    /// across a line must be for it to be reported.
+    pub min_ratio: f64,
+    /// Smallest jump reported, so that tiny steps on flat ground aren't.
+    pub min_jump: f64,
+}
+
+impl Default for SeamParams {
+    fn default() -> Self {
+        Self {
+            radius: 8,
+            min_ratio: 4.0,
+            min_jump: 1.0,
+        }
+    }
+}
+
+/// The average absolute difference of altitude across each line between two
+/// columns (or rows) of `grid` of size `size`: element `i` is the jump
+/// between column (or row) `i` and `i + 1`.
+pub fn line_jumps(grid: &[f64], size: Vec2<usize>, axis: Axis) -> Vec<f64> {
+    let (w, h) = (size.x, size.y);
+    match axis {
+        Axis::Column => {
+            let mut sums = vec![0.0; w.saturating_sub(1)];
+            for row in grid.chunks_exact(w.max(1)).take(h) {
+                for (sum, pair) in sums.iter_mut().zip(row.windows(2)) {
+                    *sum += (pair[1] - pair[0]).abs();
+                }
+            }
+            sums.into_iter().map(|sum| sum / h as f64).collect()
+        },
+        Axis::Row => (1..h)
+            .map(|y| {
+                let (above, below) = (&grid[(y - 1) * w..y * w], &grid[y * w..(y + 1) * w]);
+                above
+                    .iter()
+                    .zip(below)
+                    .map(|(a, b)| (b - a).abs())
+                    .sum::<f64>()
+                    / w as f64
+            })
+            .collect(),
+    }
+}
+
+/// Finds the seams of `grid` of size `size`: the lines whose average jump
+/// is at least `params.min_jump`, and `params.min_ratio` times the median
+/// jump of the `params.radius` lines on either side of them.  Seams are
+/// returned columns first, each in order of position.
+///
+/// A seam along only part of a line, such as between two of four tiles, is
+/// averaged with the rest of the line, so it must be proportionally larger
+/// to be found.
+pub fn find_seams(grid: &[f64], size: Vec2<usize>, params: &SeamParams) -> Vec<Seam> {
+    [Axis::Column, Axis::Row]
+        .into_iter()
+        .flat_map(|axis| {
+            let jumps = line_jumps(grid, size, axis);
+            (0..jumps.len())
+                .filter_map(|i| {
+                    let mut nearby = jumps[i.saturating_sub(params.radius)..i]
+                        .iter()
+                        .chain(jumps.iter().skip(i + 1).take(params.radius))
+                        .copied()
+                        .filter(|jump| jump.is_finite())
+                        .collect::<Vec<_>>();
+                    if nearby.is_empty() {
+                        return None;
+                    }
+                    nearby.sort_by(f64::total_cmp);
+                    let baseline = nearby[nearby.len() / 2];
+                    let jump = jumps[i];
+                    (jump >= params.min_jump && jump >= params.min_ratio * baseline).then_some(
+                        Seam {
+                            axis,
+                            position: i + 1,
+                            jump,
+                            baseline,
+                        },
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::{map_size, test_map};
+
+    fn hills(x: usize, y: usize) -> f64 {
+        ((x as f64 * 0.3).sin() + (y as f64 * 0.2).cos()) * 40.0 + y as f64 * 2.0
+    }
+
+    #[test]
+    fn natural_slopes_have_no_seams() {
+        let map = test_map(Vec2::new(5, 5), hills);
+        assert_eq!(
+            find_seams(&map.alt, map_size(&map), &SeamParams::default()),
+            []
+        );
+        // A single line has nothing to be compared with.
+        assert_eq!(
+            find_seams(&[1.0, 50.0], Vec2::new(2, 1), &SeamParams::default()),
+            []
+        );
+    }
+
+    #[test]
+    fn mismatched_tiles_leave_seams() {
+        // Tiles of 16 cells, the right half raised and the bottom half
+        // lowered.
+        let map = test_map(Vec2::new(5, 5), |x, y| {
+            hills(x, y) + if x >= 16 { 60.0 } else { 0.0 } - if y >= 16 { 60.0 } else { 0.0 }
+        });
+        let seams = find_seams(&map.alt, map_size(&map), &SeamParams::default());
+        assert_eq!(
+            seams
+                .iter()
+                .map(|seam| (seam.axis, seam.position))
+                .collect::<Vec<_>>(),
+            [(Axis::Column, 16), (Axis::Row, 16)]
+        );
+        assert!(seams[0].jump > 50.0 && seams[0].ratio() > 4.0);
+
+        let jumps = line_jumps(&map.alt, map_size(&map), Axis::Row);
+        assert_eq!(jumps.len(), 31);
+        assert_eq!(seams[1].jump, jumps[15]);
+    }
+}
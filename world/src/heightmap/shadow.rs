@@ -0,0 +1,273 @@
+//! Cast shadows: which cells the terrain between them and the sun hides it
+//! from, for a given sun position, unlike a hillshade, which only looks at
+//! the slope of each cell.
+
+use super::{filter::Edges, relief::CELL_WIDTH};
+use image::{Rgb, RgbImage};
+use vek::*;
+
+/// Position of the sun, and how the edges of shadows are drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sun {
+    /// Direction of the sun, in degrees clockwise from north (towards higher
+    /// y), so that 90 is east and 270 west.
+    pub azimuth: f64,
+    /// Height of the sun above the horizon, in degrees.
+    pub elevation: f64,
+    /// Depth below the edge of a shadow, in meters, over which it fades in,
+    /// for soft edges; 0 for a sharp, binary mask.
+    pub softness: f64,
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Self {
+            azimuth: 315.0,
+            elevation: 20.0,
+            softness: 0.0,
+        }
+    }
+}
+
+/// How shadowed every cell of the altitude grid of size `size` is with the
+/// sun at `sun`, in rows like the altitudes, from 0 for cells in sunlight to
+/// 1 for cells in full shadow.  A cell is shadowed if terrain further towards
+/// the sun rises above the ray from it to the sun.
+///
+/// Rather than marching a ray from each cell, the grid is swept once, along
+/// lines of cells running away from the sun, keeping the highest shadow line
+/// cast so far on each: a cell is in shadow if that line passes above it,
+/// and raises it otherwise.  Each cell is visited once, or twice where
+/// `edges` wraps the lines around the map, so that terrain near one edge
+/// shadows the opposite one.  With the sun at or below the horizon every cell
+/// is in shadow, and with it overhead none are.
+pub fn shadows(alt: &[f64], size: Vec2<usize>, sun: &Sun, edges: Edges) -> Vec<f64> {
+    if sun.elevation <= 0.0 {
+        return vec![1.0; size.product()];
+    }
+    let mut shadow = vec![0.0; size.product()];
+    if sun.elevation >= 90.0 || size.product() == 0 {
+        return shadow;
+    }
+    let azimuth = sun.azimuth.to_radians();
+    // Towards the sun, in cells, and the rise of its rays per cell towards
+    // it, in meters.
+    let dir = Vec2::new(azimuth.sin(), azimuth.cos());
+    let rise = sun.elevation.to_radians().tan() * CELL_WIDTH;
+    // Lines step one cell at a time along the major axis of the direction,
+    // and by `slope` cells along the other, away from the sun.
+    let swap = dir.y.abs() > dir.x.abs();
+    let (dir, size_major, size_minor) = if swap {
+        (Vec2::new(dir.y, dir.x), size.y, size.x)
+    } else {
+        (dir, size.x, size.y)
+    };
+    let slope = dir.y / dir.x;
+    let index = |major: usize, minor: usize| {
+        if swap {
+            major * size.x + minor
+        } else {
+            minor * size.x + major
+        }
+    };
+    // Wrapped lines are swept for an extra lap before the map, in which
+    // cells are only looked at to find the shadow line cast onto it by the
+    // terrain behind its start.
+    let len = size_major as isize;
+    let majors = match edges {
+        Edges::Clamp => 0..len,
+        Edges::Wrap if dir.x > 0.0 => 0..2 * len,
+        Edges::Wrap => -len..len,
+    };
+    let majors = if dir.x > 0.0 {
+        majors.rev().collect::<Vec<_>>()
+    } else {
+        majors.collect()
+    };
+    // The line through a cell is told by its minor coordinate at major 0.
+    let shear = |major: isize| (major as f64 * slope).round() as isize;
+    let lines = match edges {
+        Edges::Clamp => {
+            let shears = [shear(0), shear(len - 1)];
+            let (lowest, highest) = (shears[0].min(shears[1]), shears[0].max(shears[1]));
+            -highest..size_minor as isize - lowest
+        },
+        Edges::Wrap => 0..size_minor as isize,
+    };
+    for line in lines {
+        let mut shadow_line = f64::NEG_INFINITY;
+        for &major in &majors {
+            let minor = line + shear(major);
+            let i = match edges {
+                Edges::Clamp if !(0..size_minor as isize).contains(&minor) => continue,
+                Edges::Clamp => index(major as usize, minor as usize),
+                Edges::Wrap => index(
+                    major.rem_euclid(len) as usize,
+                    minor.rem_euclid(size_minor as isize) as usize,
+                ),
+            };
+            // Altitudes less the rise of the sun's rays towards it, so that
+            // terrain shadows the cells left lower than it.
+            let towards_sun = major as f64 * dir.x + minor as f64 * dir.y;
+            let height = alt[i] - rise * towards_sun;
+            let depth = shadow_line - height;
+            if (0..len).contains(&major) {
+                shadow[i] = if depth <= 0.0 {
+                    0.0
+                } else if sun.softness > 0.0 {
+                    (depth / sun.softness).min(1.0)
+                } else {
+                    1.0
+                };
+            }
+            shadow_line = shadow_line.max(height);
+        }
+    }
+    shadow
+}
+
+/// Renders the `shadow` of a grid of size `size`, from [`shadows`], as a
+/// mask, from white in sunlight to black in full shadow.
+pub fn render_shadows(shadow: &[f64], size: Vec2<usize>) -> RgbImage {
+    RgbImage::from_fn(size.x as u32, size.y as u32, |x, y| {
+        Rgb([gray(shadow[y as usize * size.x + x as usize]); 3])
+    })
+}
+
+/// Gray level of `shadow` in [`render_shadows`].
+pub fn gray(shadow: f64) -> u8 { ((1.0 - shadow.clamp(0.0, 1.0)) * 255.0).round() as u8 }
+
+/// The light left in each cell of `shadow` when shadows darken an image by
+/// `strength`, from 0 for not at all to 1 for black, to
+/// [`multiply`](super::occlusion::multiply) it by.
+pub fn light(shadow: &[f64], strength: f64) -> Vec<f64> {
+    shadow
+        .iter()
+        .map(|shadow| 1.0 - shadow * strength)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spikes_cast_shadows_away_from_the_sun() {
+        // A spike 330 m high, which casts a shadow ten cells long with the
+        // sun 45 degrees high.
+        let size = Vec2::new(32, 24);
+        let spike = Vec2::new(20, 12);
+        let mut alt = vec![0.0; size.product()];
+        alt[spike.y * size.x + spike.x] = 330.0;
+        let at = |shadow: &[f64], x: usize, y: usize| shadow[y * size.x + x];
+
+        // In the morning, its shadow falls west.
+        let east = Sun {
+            azimuth: 90.0,
+            elevation: 45.0,
+            softness: 0.0,
+        };
+        let shadow = shadows(&alt, size, &east, Edges::Clamp);
+        for x in spike.x - 10..spike.x {
+            assert_eq!(at(&shadow, x, spike.y), 1.0, "x = {}", x);
+        }
+        for x in (0..spike.x - 10).chain(spike.x..size.x) {
+            assert_eq!(at(&shadow, x, spike.y), 0.0, "x = {}", x);
+        }
+        assert_eq!(at(&shadow, spike.x - 5, spike.y + 1), 0.0);
+        assert_eq!(shadow.iter().filter(|&&shadow| shadow > 0.0).count(), 10);
+
+        // From the north, at a lower sun, it falls south, and further.
+        let north = Sun {
+            azimuth: 0.0,
+            elevation: 30.0,
+            softness: 0.0,
+        };
+        let shadow = shadows(&alt, size, &north, Edges::Clamp);
+        assert_eq!(at(&shadow, spike.x, spike.y - 12), 1.0);
+        assert_eq!(at(&shadow, spike.x, spike.y + 1), 0.0);
+        // From the northeast, it falls southwest.
+        let northeast = Sun {
+            azimuth: 45.0,
+            ..east
+        };
+        let shadow = shadows(&alt, size, &northeast, Edges::Clamp);
+        assert_eq!(at(&shadow, spike.x - 4, spike.y - 4), 1.0);
+        assert_eq!(at(&shadow, spike.x - 4, spike.y), 0.0);
+
+        // Soft shadows fade in from their edge.
+        let soft = shadows(
+            &alt,
+            size,
+            &Sun {
+                softness: 100.0,
+                ..east
+            },
+            Edges::Clamp,
+        );
+        let (near, far) = (
+            at(&soft, spike.x - 1, spike.y),
+            at(&soft, spike.x - 10, spike.y),
+        );
+        assert!(near == 1.0 && far > 0.0 && far < 1.0, "{} {}", near, far);
+
+        assert!(
+            shadows(
+                &alt,
+                size,
+                &Sun {
+                    elevation: -5.0,
+                    ..east
+                },
+                Edges::Clamp
+            )
+            .iter()
+            .all(|&shadow| shadow == 1.0)
+        );
+        let img = render_shadows(&soft, size);
+        assert_eq!(
+            *img.get_pixel(spike.x as u32 - 1, spike.y as u32),
+            Rgb([0; 3])
+        );
+        assert_eq!(*img.get_pixel(0, 0), Rgb([255; 3]));
+        assert_eq!(light(&[0.0, 1.0], 0.5), [1.0, 0.5]);
+    }
+
+    #[test]
+    fn wrapped_shadows_fall_across_the_edge() {
+        // The spike of the test above, three cells from the west edge.
+        let size = Vec2::new(32, 24);
+        let spike = Vec2::new(3, 12);
+        let mut alt = vec![0.0; size.product()];
+        alt[spike.y * size.x + spike.x] = 330.0;
+        let at = |shadow: &[f64], x: usize, y: usize| shadow[y * size.x + x];
+        let east = Sun {
+            azimuth: 90.0,
+            elevation: 45.0,
+            softness: 0.0,
+        };
+
+        let clamped = shadows(&alt, size, &east, Edges::Clamp);
+        assert_eq!(clamped.iter().filter(|&&shadow| shadow > 0.0).count(), 3);
+        let wrapped = shadows(&alt, size, &east, Edges::Wrap);
+        for x in (0..spike.x).chain(25..size.x) {
+            assert_eq!(at(&wrapped, x, spike.y), 1.0, "x = {}", x);
+        }
+        assert_eq!(at(&wrapped, 24, spike.y), 0.0);
+        assert_eq!(wrapped.iter().filter(|&&shadow| shadow > 0.0).count(), 10);
+
+        // Away from the edges, wrapping changes nothing, whatever the sun.
+        let centered = Vec2::new(16, 12);
+        let mut alt = vec![0.0; size.product()];
+        alt[centered.y * size.x + centered.x] = 330.0;
+        for azimuth in [0.0, 45.0, 100.0, 200.0, 300.0] {
+            let sun = Sun { azimuth, ..east };
+            assert_eq!(
+                shadows(&alt, size, &sun, Edges::Wrap),
+                shadows(&alt, size, &sun, Edges::Clamp),
+                "azimuth {}",
+                azimuth
+            );
+        }
+    }
+}
@@ -0,0 +1,516 @@
+//! Compositing small features, such as volcanoes or craters, onto a map.
+
+use super::{
+    Error,
+    import::{Channel, load_image},
+    map_size,
+};
+use crate::sim::ModernMap;
+use image::{DynamicImage, GenericImageView};
+use serde::Serialize;
+use std::path::Path;
+use vek::*;
+
+/// A feature to stamp onto a map: a grid of heights, each with a weight
+/// between 0 and 1 controlling how strongly it affects the map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Brush {
+    pub size: Vec2<usize>,
+    pub height: Vec<f64>,
+    pub weight: Vec<f64>,
+}
+
+/// How a brush is combined with the map beneath it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum StampMode {
+    /// Raise (or, for negative heights, lower) the map by the brush height.
+    Add,
+    /// Raise the map to the brush altitude where it is lower.
+    Max,
+    /// Move the map to the brush altitude.
+    Replace,
+    /// Keep the slopes of the brush, but not its altitudes, which are solved
+    /// for to meet the map all around it; see [`stamp_poisson`].
+    Poisson,
+}
+
+/// Settings of the solves done by [`stamp_poisson`] and
+/// [`inpaint`](super::inpaint::inpaint).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoissonParams {
+    /// The solve stops once no altitude changes by more than this many meters
+    /// in an iteration.
+    pub tolerance: f64,
+    pub max_iterations: usize,
+}
+
+impl Default for PoissonParams {
+    fn default() -> Self {
+        Self {
+            tolerance: 1e-3,
+            max_iterations: 20_000,
+        }
+    }
+}
+
+/// What [`stamp_poisson`] did.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoissonStamp {
+    /// Number of cells of the map that were solved for.
+    pub affected: usize,
+    pub iterations: usize,
+    /// Largest change of an altitude in the last iteration.
+    pub change: f64,
+    /// Whether `change` fell below the tolerance within the maximum number of
+    /// iterations.
+    pub converged: bool,
+}
+
+impl Brush {
+    /// Uses the altitudes of `map` as brush heights, at full weight.
+    pub fn from_map(map: &ModernMap) -> Self {
+        Self {
+            size: map_size(map),
+            height: map.alt.to_vec(),
+            weight: vec![1.0; map.alt.len()],
+        }
+    }
+
+    /// Reads brush heights between 0 and 1 from `channel` of `img`, and
+    /// weights from its alpha channel (full weight for images without one).
+    pub fn from_image(img: &DynamicImage, channel: Channel) -> Self {
+        let (width, height) = img.dimensions();
+        let (heights, weights) = img
+            .pixels()
+            .map(|(_, _, pixel)| (channel.value(pixel) / 255.0, pixel[3] as f64 / 255.0))
+            .unzip();
+        Self {
+            size: Vec2::new(width as usize, height as usize),
+            height: heights,
+            weight: weights,
+        }
+    }
+
+    pub fn load_image(path: impl AsRef<Path>, channel: Channel) -> Result<Self, Error> {
+        Ok(Self::from_image(&load_image(path)?, channel))
+    }
+
+    /// Fades the weights out towards the edge of the brush, so that it
+    /// blends in without a visible rectangular outline.
+    ///
+    /// Weights fall off smoothly over the outer `falloff` fraction of the
+    /// ellipse inscribed in the brush, and are zero outside it.  A `falloff`
+    /// of 0 leaves the weights unchanged.
+    pub fn feather(&mut self, falloff: f64) {
+        if falloff <= 0.0 {
+            return;
+        }
+        let half = self.size.map(|e| e as f64 / 2.0);
+        for (i, weight) in self.weight.iter_mut().enumerate() {
+            let pos = Vec2::new(i % self.size.x, i / self.size.x).map(|e| e as f64 + 0.5);
+            let r = ((pos - half) / half).magnitude();
+            let t = ((1.0 - r) / falloff).clamp(0.0, 1.0);
+            *weight *= t * t * (3.0 - 2.0 * t);
+        }
+    }
+}
+
+/// Composites `brush`, with heights multiplied by `scale`, onto `map` with its
+/// centre at cell `center`.  For [`StampMode::Max`] and
+/// [`StampMode::Replace`], the brush altitude is `base` plus the scaled
+/// height.
+///
+/// Basement altitudes move by the same amount as the altitude above them.
+/// Parts of the brush outside the map are ignored.  Returns the number of
+/// cells that were affected.
+///
+/// [`StampMode::Poisson`] ignores `base`, and solves with the default
+/// [`PoissonParams`].
+pub fn stamp(
+    map: &mut ModernMap,
+    brush: &Brush,
+    center: Vec2<i64>,
+    scale: f64,
+    base: f64,
+    mode: StampMode,
+) -> usize {
+    if mode == StampMode::Poisson {
+        return stamp_poisson(map, brush, center, scale, &PoissonParams::default()).affected;
+    }
+    let size = map_size(map).map(|e| e as i64);
+    let origin = center - brush.size.map(|e| e as i64 / 2);
+    let mut affected = 0;
+    for by in 0..brush.size.y {
+        let y = origin.y + by as i64;
+        if y < 0 || y >= size.y {
+            continue;
+        }
+        for bx in 0..brush.size.x {
+            let x = origin.x + bx as i64;
+            if x < 0 || x >= size.x {
+                continue;
+            }
+            let b = by * brush.size.x + bx;
+            let weight = brush.weight[b];
+            if weight <= 0.0 {
+                continue;
+            }
+
+            let i = (y * size.x + x) as usize;
+            let alt = map.alt[i];
+            let height = brush.height[b] * scale;
+            let target = match mode {
+                StampMode::Add => alt + height,
+                StampMode::Max => alt.max(base + height),
+                StampMode::Replace => base + height,
+                StampMode::Poisson => unreachable!("Solved by stamp_poisson"),
+            };
+            let delta = (target - alt) * weight;
+            map.alt[i] += delta;
+            map.basement[i] += delta;
+            affected += 1;
+        }
+    }
+    affected
+}
+
+/// A cell solved for by [`relax`].
+pub(super) struct PatchCell {
+    /// Index of the cell in the solved grid.
+    pub i: usize,
+    /// Index of the cell in the map.
+    pub map: usize,
+    /// Indices of its neighbours that are solved for too.
+    pub free: Vec<usize>,
+    /// Sum of the fixed altitudes of its other neighbours, and of the guidance
+    /// field: the differences between its target slopes and those of all of
+    /// its neighbours.
+    pub fixed: f64,
+    pub neighbours: f64,
+}
+
+/// Solves the Poisson equation for `cells` of `solved` by successive
+/// over-relaxation, starting from the values already there, with the
+/// over-relaxation factor that is optimal for a square of side `side`.
+pub(super) fn relax(
+    solved: &mut [f64],
+    cells: &[PatchCell],
+    side: usize,
+    params: &PoissonParams,
+) -> PoissonStamp {
+    let omega = 2.0 / (1.0 + (std::f64::consts::PI / (side as f64 + 1.0)).sin());
+    let mut result = PoissonStamp {
+        affected: cells.len(),
+        converged: cells.is_empty(),
+        ..Default::default()
+    };
+    while !result.converged && result.iterations < params.max_iterations {
+        let mut change: f64 = 0.0;
+        for cell in cells {
+            let sum = cell.free.iter().map(|&n| solved[n]).sum::<f64>() + cell.fixed;
+            let delta = omega * (sum / cell.neighbours - solved[cell.i]);
+            solved[cell.i] += delta;
+            change = change.max(delta.abs());
+        }
+        result.iterations += 1;
+        result.change = change;
+        result.converged = change <= params.tolerance;
+    }
+    result
+}
+
+/// Composites the slopes of `brush`, with heights multiplied by `scale`, onto
+/// `map` with its centre at cell `center`, solving for altitudes that match
+/// the map along the edge of the brush.
+///
+/// The cells of the brush with any weight on the map form the patch.  Its
+/// altitudes are found by solving the Poisson equation whose guidance field
+/// is the gradient of the brush, with the altitudes of the map around the
+/// patch as boundary values, by successive over-relaxation.  So the brush is
+/// inserted without a step whatever its absolute altitudes; the edges of the
+/// map are left free.  Weights don't otherwise matter, since the solve
+/// itself blends the brush in.
+///
+/// Basement altitudes move by the same amount as the altitude above them.
+pub fn stamp_poisson(
+    map: &mut ModernMap,
+    brush: &Brush,
+    center: Vec2<i64>,
+    scale: f64,
+    params: &PoissonParams,
+) -> PoissonStamp {
+    let size = map_size(map).map(|e| e as i64);
+    let origin = center - brush.size.map(|e| e as i64 / 2);
+    let brush_size = brush.size.map(|e| e as i64);
+    let brush_index = |pos: Vec2<i64>| {
+        let b = pos - origin;
+        (b.x >= 0 && b.y >= 0 && b.x < brush_size.x && b.y < brush_size.y)
+            .then(|| (b.y * brush_size.x + b.x) as usize)
+    };
+    let lo = origin.map(|e| e.max(0));
+    let hi = (origin + brush_size).map2(size, |e, len| e.min(len));
+    if hi.x <= lo.x || hi.y <= lo.y {
+        return PoissonStamp::default();
+    }
+    let local = |pos: Vec2<i64>| ((pos.y - lo.y) * (hi.x - lo.x) + pos.x - lo.x) as usize;
+    let in_patch = |pos: Vec2<i64>| {
+        pos.x >= lo.x
+            && pos.y >= lo.y
+            && pos.x < hi.x
+            && pos.y < hi.y
+            && brush_index(pos).is_some_and(|b| brush.weight[b] > 0.0)
+    };
+
+    // Start from the brush offset to the mean altitude of the map around it,
+    // which is already close to the solution.
+    let height = |b: usize| brush.height[b] * scale;
+    let mut cells = Vec::new();
+    let (mut offset, mut boundary) = (0.0, 0);
+    for y in lo.y..hi.y {
+        for x in lo.x..hi.x {
+            let pos = Vec2::new(x, y);
+            if !in_patch(pos) {
+                continue;
+            }
+            let own = height(brush_index(pos).expect("Patch cells lie on the brush"));
+            let mut cell = PatchCell {
+                i: local(pos),
+                map: (y * size.x + x) as usize,
+                free: Vec::new(),
+                fixed: 0.0,
+                neighbours: 0.0,
+            };
+            for step in [
+                Vec2::new(1, 0),
+                Vec2::new(-1, 0),
+                Vec2::new(0, 1),
+                Vec2::new(0, -1),
+            ] {
+                let n = pos + step;
+                if n.x < 0 || n.y < 0 || n.x >= size.x || n.y >= size.y {
+                    continue;
+                }
+                cell.neighbours += 1.0;
+                if let Some(b) = brush_index(n) {
+                    cell.fixed += own - height(b);
+                }
+                if in_patch(n) {
+                    cell.free.push(local(n));
+                } else {
+                    let alt = map.alt[(n.y * size.x + n.x) as usize];
+                    cell.fixed += alt;
+                    offset += alt - own;
+                    boundary += 1;
+                }
+            }
+            // Only a map of a single cell has none.
+            if cell.neighbours > 0.0 {
+                cells.push(cell);
+            }
+        }
+    }
+    if boundary > 0 {
+        offset /= boundary as f64;
+    }
+    let mut solved = vec![0.0; ((hi.x - lo.x) * (hi.y - lo.y)) as usize];
+    for y in lo.y..hi.y {
+        for x in lo.x..hi.x {
+            if let Some(b) = brush_index(Vec2::new(x, y)) {
+                solved[local(Vec2::new(x, y))] = height(b) + offset;
+            }
+        }
+    }
+
+    let side = (hi.x - lo.x).max(hi.y - lo.y) as usize;
+    let result = relax(&mut solved, &cells, side, params);
+
+    for cell in &cells {
+        let delta = solved[cell.i] - map.alt[cell.map];
+        map.alt[cell.map] += delta;
+        map.basement[cell.map] += delta;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+
+    fn square_brush(side: usize, height: f64) -> Brush {
+        Brush {
+            size: Vec2::broadcast(side),
+            height: vec![height; side * side],
+            weight: vec![1.0; side * side],
+        }
+    }
+
+    #[test]
+    fn modes_on_a_flat_map() {
+        let brush = Brush {
+            size: Vec2::new(2, 1),
+            height: vec![1.0, 3.0],
+            weight: vec![1.0, 0.5],
+        };
+        let flat = || test_map(Vec2::new(2, 2), |_, _| 20.0);
+
+        let mut map = flat();
+        assert_eq!(
+            stamp(&mut map, &brush, Vec2::new(2, 1), 10.0, 0.0, StampMode::Add),
+            2
+        );
+        assert_eq!(&map.alt[5..7], &[30.0, 35.0]);
+        assert_eq!(&map.basement[5..7], &[20.0, 25.0]);
+
+        let mut map = flat();
+        stamp(&mut map, &brush, Vec2::new(2, 1), 10.0, 0.0, StampMode::Max);
+        assert_eq!(&map.alt[5..7], &[20.0, 25.0]);
+
+        let mut map = flat();
+        stamp(
+            &mut map,
+            &brush,
+            Vec2::new(2, 1),
+            10.0,
+            -40.0,
+            StampMode::Replace,
+        );
+        assert_eq!(&map.alt[5..7], &[-30.0, 5.0]);
+        assert_eq!(map.alt[4], 20.0);
+    }
+
+    #[test]
+    fn stamps_are_clipped_at_the_map_edge() {
+        let brush = square_brush(4, 1.0);
+
+        // Centred on the corner, only the bottom right quarter of the brush
+        // lies on the map.
+        let mut map = test_map(Vec2::new(3, 3), |_, _| 0.0);
+        assert_eq!(
+            stamp(&mut map, &brush, Vec2::new(0, 0), 1.0, 0.0, StampMode::Add),
+            4
+        );
+        let raised = (0..64).filter(|&i| map.alt[i] == 1.0).collect::<Vec<_>>();
+        assert_eq!(raised, vec![0, 1, 8, 9]);
+
+        // Likewise at the opposite corner.
+        let mut map = test_map(Vec2::new(3, 3), |_, _| 0.0);
+        assert_eq!(
+            stamp(&mut map, &brush, Vec2::new(8, 7), 1.0, 0.0, StampMode::Add),
+            6
+        );
+        let raised = (0..64).filter(|&i| map.alt[i] == 1.0).collect::<Vec<_>>();
+        assert_eq!(raised, vec![46, 47, 54, 55, 62, 63]);
+
+        // Entirely off the map.
+        let mut map = test_map(Vec2::new(3, 3), |_, _| 0.0);
+        assert_eq!(
+            stamp(
+                &mut map,
+                &brush,
+                Vec2::new(-10, 3),
+                1.0,
+                0.0,
+                StampMode::Add
+            ),
+            0
+        );
+        assert!(map.alt.iter().all(|alt| *alt == 0.0));
+    }
+
+    #[test]
+    fn flat_patches_take_the_slope_of_the_map() {
+        // A plane is its own Poisson solution, whatever the patch altitude.
+        let plane = |x: usize, y: usize| 500.0 + 3.0 * x as f64 + y as f64;
+        let mut map = test_map(Vec2::new(5, 5), plane);
+        let params = PoissonParams {
+            tolerance: 1e-10,
+            max_iterations: 10_000,
+        };
+        let result = stamp_poisson(
+            &mut map,
+            &square_brush(8, -1000.0),
+            Vec2::new(16, 16),
+            1.0,
+            &params,
+        );
+        assert!(result.converged, "{:?}", result);
+        assert_eq!(result.affected, 64);
+        for (i, &alt) in map.alt.iter().enumerate() {
+            assert!((alt - plane(i % 32, i / 32)).abs() < 1e-6, "{}: {}", i, alt);
+        }
+    }
+
+    #[test]
+    fn patches_meet_the_map_without_a_step() {
+        let plane = |x: usize, y: usize| 500.0 + 3.0 * x as f64 + y as f64;
+        let mut map = test_map(Vec2::new(6, 6), plane);
+        // A hill 40 meters high on ground 1500 meters below the map.
+        let mut brush = square_brush(16, 0.0);
+        for (i, height) in brush.height.iter_mut().enumerate() {
+            let r = Vec2::new(i % 16, i / 16)
+                .map(|e| e as f64 - 7.5)
+                .magnitude();
+            *height = -1000.0 + 40.0 * (-r * r / 18.0).exp();
+        }
+        let result = stamp_poisson(
+            &mut map,
+            &brush,
+            Vec2::new(32, 32),
+            1.0,
+            &Default::default(),
+        );
+        assert!(result.converged, "{:?}", result);
+
+        // Across the edge of the patch, at cells 24 and 39, altitudes differ
+        // by little more than the slope of the map.
+        for j in 24..40 {
+            for (inside, outside) in [(24, 23), (39, 40)] {
+                for (a, b) in [((inside, j), (outside, j)), ((j, inside), (j, outside))] {
+                    let step = map.alt[a.1 * 64 + a.0] - map.alt[b.1 * 64 + b.0];
+                    assert!(step.abs() < 3.5, "{:?} to {:?}: {}", a, b, step);
+                }
+            }
+        }
+        // The hill stands on the map about as high as it was.
+        let rise = map.alt[32 * 64 + 32] - plane(32, 32);
+        let height = brush.height[8 * 16 + 8] + 1000.0;
+        assert!((rise - height).abs() < 2.0, "{} for {}", rise, height);
+        assert!(map.alt.iter().zip(&*map.basement).all(|(a, b)| b < a));
+        assert_eq!(map.basement[32 * 64 + 32], map.alt[32 * 64 + 32] - 10.0);
+    }
+
+    #[test]
+    fn poisson_mode_solves_with_default_params() {
+        let mut map = test_map(Vec2::new(3, 3), |_, _| 0.0);
+        let brush = square_brush(4, 7.0);
+        assert_eq!(
+            stamp(
+                &mut map,
+                &brush,
+                Vec2::new(0, 0),
+                1.0,
+                5.0,
+                StampMode::Poisson
+            ),
+            4
+        );
+        assert!(map.alt.iter().all(|alt| alt.abs() < 1e-2));
+    }
+
+    #[test]
+    fn feathering_fades_edges() {
+        let mut brush = square_brush(16, 1.0);
+        brush.feather(0.5);
+        // Corners are outside the inscribed circle, the centre is untouched.
+        assert_eq!(brush.weight[0], 0.0);
+        assert_eq!(brush.weight[15 * 16 + 15], 0.0);
+        assert_eq!(brush.weight[8 * 16 + 8], 1.0);
+        // Weights decrease monotonically from the centre to the edge.
+        let row = &brush.weight[8 * 16 + 8..9 * 16];
+        assert!(row.windows(2).all(|w| w[0] >= w[1]));
+        assert!(row[7] < 0.1);
+    }
+}
@@ -0,0 +1,612 @@
+//! Summary statistics of altitude grids.
+
+use super::{export::compute_min_max, filter::Edges};
+use rayon::prelude::*;
+use std::{fmt, ops::Range};
+use vek::*;
+
+/// Altitude range, spread and land coverage of a grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AltStats {
+    pub min: f64,
+    pub max: f64,
+    /// Population standard deviation of the altitudes.
+    pub std_dev: f64,
+    /// Fraction of cells strictly above sea level (0.0).
+    pub land_fraction: f64,
+}
+
+impl AltStats {
+    /// Computes statistics over `alt`, ignoring NaNs when finding the range.
+    /// An empty grid has an infinite, inverted range, no spread and no land.
+    pub fn of(alt: &[f64]) -> Self {
+        let (min, max) = alt
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &alt| {
+                (min.min(alt), max.max(alt))
+            });
+        let land = alt.iter().filter(|&&alt| alt > 0.0).count();
+        let cells = alt.len().max(1) as f64;
+        let mean = alt.iter().sum::<f64>() / cells;
+        let variance = alt.iter().map(|alt| (alt - mean).powi(2)).sum::<f64>() / cells;
+        Self {
+            min,
+            max,
+            std_dev: variance.sqrt(),
+            land_fraction: land as f64 / cells,
+        }
+    }
+}
+
+impl fmt::Display for AltStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "min {:.2}, max {:.2}, std dev {:.2}, land {:.1}%",
+            self.min,
+            self.max,
+            self.std_dev,
+            self.land_fraction * 100.0
+        )
+    }
+}
+
+/// [`AltStats`] of a grid handed over a strip at a time, as when converting
+/// a map without holding it in memory.
+///
+/// Each strip's mean and spread are computed like [`AltStats::of`] does, and
+/// merged into those of the previous strips (Chan et al.'s pairwise update),
+/// so the result matches `AltStats::of` of the whole grid up to rounding.
+#[derive(Clone, Copy, Debug)]
+pub struct AltStatsAccumulator {
+    cells: usize,
+    land: usize,
+    min: f64,
+    max: f64,
+    mean: f64,
+    /// Sum of squared deviations from `mean`.
+    squares: f64,
+}
+
+impl Default for AltStatsAccumulator {
+    fn default() -> Self {
+        Self {
+            cells: 0,
+            land: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0.0,
+            squares: 0.0,
+        }
+    }
+}
+
+impl AltStatsAccumulator {
+    /// Adds the altitudes of the next strip.
+    pub fn add(&mut self, strip: &[f64]) {
+        if strip.is_empty() {
+            return;
+        }
+        let n = strip.len() as f64;
+        let mean = strip.iter().sum::<f64>() / n;
+        let squares = strip.iter().map(|alt| (alt - mean).powi(2)).sum::<f64>();
+        let cells = self.cells as f64;
+        let total = cells + n;
+        let delta = mean - self.mean;
+        self.mean += delta * n / total;
+        self.squares += squares + delta * delta * cells * n / total;
+        self.cells += strip.len();
+        self.land += strip.iter().filter(|&&alt| alt > 0.0).count();
+        for &alt in strip {
+            self.min = self.min.min(alt);
+            self.max = self.max.max(alt);
+        }
+    }
+
+    /// Statistics of all the strips added so far.
+    pub fn stats(&self) -> AltStats {
+        let cells = self.cells.max(1) as f64;
+        AltStats {
+            min: self.min,
+            max: self.max,
+            std_dev: (self.squares / cells).sqrt(),
+            land_fraction: self.land as f64 / cells,
+        }
+    }
+}
+
+/// Percentiles reported by `mapgen inspect --quantiles`.
+pub const REPORTED_PERCENTILES: [f64; 7] = [0.0, 10.0, 25.0, 50.0, 75.0, 90.0, 100.0];
+
+/// The altitudes below which `percentiles` percent (between 0 and 100) of the
+/// cells of `alt` lie, ignoring NaNs, so that e.g. a sea level at the 30th
+/// percentile submerges 30% of the map.
+///
+/// Sorts a copy of the altitudes, and interpolates linearly between the two
+/// closest ranks.  An empty grid (or one of NaNs) has NaN quantiles.
+pub fn quantiles(alt: &[f64], percentiles: &[f64]) -> Vec<f64> {
+    let mut sorted = alt
+        .iter()
+        .copied()
+        .filter(|alt| !alt.is_nan())
+        .collect::<Vec<_>>();
+    sorted.sort_unstable_by(f64::total_cmp);
+    percentiles
+        .iter()
+        .map(|&percentile| {
+            if sorted.is_empty() {
+                return f64::NAN;
+            }
+            let rank = (percentile / 100.0).clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+            let (below, above) = (sorted[rank.floor() as usize], sorted[rank.ceil() as usize]);
+            below + (above - below) * rank.fract()
+        })
+        .collect()
+}
+
+/// Number of bins each pass of [`value_at_rank`] sorts the values into.
+const RANK_BINS: usize = 4096;
+
+/// Number of remaining candidates below which [`value_at_rank`] copies them
+/// and selects among them.
+const RANK_CANDIDATES: usize = 1 << 16;
+
+/// Number of cells in each of the chunks the grid is split into for parallel
+/// histograms.
+const CHUNK_CELLS: usize = 1 << 16;
+
+/// Count, lowest and highest value of the values in a bin of a histogram.
+#[derive(Clone, Copy)]
+struct Bin {
+    count: usize,
+    min: f64,
+    max: f64,
+}
+
+impl Bin {
+    const EMPTY: Bin = Bin {
+        count: 0,
+        min: f64::INFINITY,
+        max: f64::NEG_INFINITY,
+    };
+
+    fn merge(self, other: Bin) -> Bin {
+        Bin {
+            count: self.count + other.count,
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+}
+
+/// The `rank`th smallest (counting from 0) of the values of `alt` between
+/// `lo` and `hi` inclusive, of which there are `count`.
+///
+/// Rather than sorting the altitudes, each pass sorts those in the range into
+/// a histogram of [`RANK_BINS`] bins, in parallel, and narrows the range down
+/// to the values of the bin holding the rank, until few enough remain to copy
+/// and select among.  Since the lowest and highest values of the range fall
+/// in the first and last bins, every pass narrows it.
+fn value_at_rank(
+    alt: &[f64],
+    (mut lo, mut hi): (f64, f64),
+    mut count: usize,
+    mut rank: usize,
+) -> f64 {
+    loop {
+        let in_range = |alt: &&f64| (lo..=hi).contains(*alt);
+        if lo == hi {
+            return lo;
+        }
+        // Bins can't split an infinite range.
+        if count <= RANK_CANDIDATES || !(hi - lo).is_finite() {
+            let mut candidates = alt.par_iter().filter(in_range).copied().collect::<Vec<_>>();
+            return *candidates.select_nth_unstable_by(rank, f64::total_cmp).1;
+        }
+        let width = (hi - lo) / RANK_BINS as f64;
+        let histogram = alt
+            .par_chunks(CHUNK_CELLS)
+            .fold(
+                || vec![Bin::EMPTY; RANK_BINS],
+                |mut bins, chunk| {
+                    for &alt in chunk.iter().filter(in_range) {
+                        let bin = &mut bins[(((alt - lo) / width) as usize).min(RANK_BINS - 1)];
+                        *bin = bin.merge(Bin {
+                            count: 1,
+                            min: alt,
+                            max: alt,
+                        });
+                    }
+                    bins
+                },
+            )
+            .reduce(
+                || vec![Bin::EMPTY; RANK_BINS],
+                |a, b| a.into_iter().zip(b).map(|(a, b)| a.merge(b)).collect(),
+            );
+        let bin = histogram
+            .into_iter()
+            .find(|bin| {
+                if rank < bin.count {
+                    true
+                } else {
+                    rank -= bin.count;
+                    false
+                }
+            })
+            .expect("The rank is below the number of values in range");
+        (lo, hi, count) = (bin.min, bin.max, bin.count);
+    }
+}
+
+/// The altitude below which `percentile` percent of the cells of `alt` lie,
+/// exactly like [`quantiles`], but found with parallel histograms rather
+/// than by sorting a copy of the altitudes, for use on large maps.
+pub fn quantile(alt: &[f64], percentile: f64) -> f64 {
+    let count = alt.par_iter().filter(|alt| !alt.is_nan()).count();
+    if count == 0 {
+        return f64::NAN;
+    }
+    let range = compute_min_max(alt);
+    let rank = (percentile / 100.0).clamp(0.0, 1.0) * (count - 1) as f64;
+    let below = value_at_rank(alt, range, count, rank.floor() as usize);
+    if rank.fract() == 0.0 {
+        return below;
+    }
+    let above = value_at_rank(alt, range, count, rank.ceil() as usize);
+    below + (above - below) * rank.fract()
+}
+
+/// The altitudes between the `percentile` and `100 - percentile` percentiles
+/// of `alt` (see [`quantile`]), such as the range excluding the 0.1% highest
+/// and lowest cells, so that a few outliers don't stretch it.  `percentile`
+/// is clamped between 0 and 50.
+pub fn percentile_range(alt: &[f64], percentile: f64) -> (f64, f64) {
+    let percentile = percentile.clamp(0.0, 50.0);
+    (quantile(alt, percentile), quantile(alt, 100.0 - percentile))
+}
+
+/// The hypsometric curve of `alt`: `bins + 1` pairs of an altitude, rising in
+/// steps of a `bins`th of the range from the lowest to the highest altitude,
+/// and the fraction of cells in the bins from it up, ignoring NaNs.  Each bin
+/// holds the cells from its lower edge up to the next, and the highest those
+/// at the highest altitude too, so every fraction but the last is that of the
+/// cells at or above its altitude: the curve starts at `(min, 1.0)` and ends
+/// at `(max, 0.0)`, the top of the highest bin.  Its shape shows whether a map
+/// is mostly lowland with a few peaks or a plateau cut by valleys.
+///
+/// Cells are counted into bins, so fractions are exact at the bin edges and
+/// the whole curve takes a single allocation, for the pairs themselves.  An
+/// empty grid (or one of NaNs) has an empty curve, and `bins` is at least 1.
+pub fn hypsometry(alt: &[f64], bins: usize) -> Vec<(f64, f64)> {
+    let (min, max) = compute_min_max(alt);
+    if min > max {
+        return Vec::new();
+    }
+    let bins = bins.max(1);
+    let width = (max - min) / bins as f64;
+    // Counts of cells in each bin, in the second half of the pairs.
+    let mut curve = vec![(0.0, 0.0); bins + 1];
+    let mut cells = 0;
+    for &alt in alt.iter().filter(|alt| !alt.is_nan()) {
+        let bin = if width > 0.0 {
+            (((alt - min) / width) as usize).min(bins - 1)
+        } else {
+            0
+        };
+        curve[bin].1 += 1.0;
+        cells += 1;
+    }
+    let mut above = cells as f64;
+    for (i, (edge, fraction)) in curve.iter_mut().enumerate() {
+        let count = *fraction;
+        *edge = if i == bins {
+            max
+        } else {
+            min + i as f64 * width
+        };
+        *fraction = above / cells as f64;
+        above -= count;
+    }
+    curve
+}
+
+/// The roughness of every cell of the altitude grid of size `size`: the
+/// population standard deviation of the altitudes in the `window` by `window`
+/// cells centred on it, cut off by the edges of the grid unless `edges` wraps
+/// them around, in rows like the altitudes.  Noisy terrain is rough, and 8-bit
+/// flat patches have none.
+///
+/// Windows reach `window / 2` cells either side of their cell, so even sizes
+/// act like the next odd one, and those wider than the grid cover it once.
+/// Sums over them are read off integral images of the altitudes and their
+/// squares, so large windows cost no more than small ones.
+pub fn roughness(alt: &[f64], size: Vec2<usize>, window: usize, edges: Edges) -> Vec<f64> {
+    let radius = window / 2;
+    // Altitudes relative to their mean, so that the squares of high plateaus
+    // don't swamp their variance in rounding error.
+    let mean = alt.iter().sum::<f64>() / alt.len().max(1) as f64;
+    // Sums over the cells above and left of each corner, with a row and a
+    // column of zeros before the first.
+    let stride = size.x + 1;
+    let mut sums = vec![(0.0, 0.0); stride * (size.y + 1)];
+    for y in 0..size.y {
+        let (mut row_sum, mut row_squares) = (0.0, 0.0);
+        for x in 0..size.x {
+            let alt = alt[y * size.x + x] - mean;
+            row_sum += alt;
+            row_squares += alt * alt;
+            let above = sums[y * stride + x + 1];
+            sums[(y + 1) * stride + x + 1] = (above.0 + row_sum, above.1 + row_squares);
+        }
+    }
+    (0..size.product())
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % size.x, i / size.x);
+            let at = |x: usize, y: usize| sums[y * stride + x];
+            let (mut sum, mut squares, mut cells) = (0.0, 0.0, 0);
+            for xs in window_ranges(x, radius, size.x, edges) {
+                for ys in window_ranges(y, radius, size.y, edges) {
+                    let ((x0, x1), (y0, y1)) = ((xs.start, xs.end), (ys.start, ys.end));
+                    for ((s, q), sign) in [at(x1, y1), at(x0, y0), at(x1, y0), at(x0, y1)]
+                        .into_iter()
+                        .zip([1.0, 1.0, -1.0, -1.0])
+                    {
+                        sum += sign * s;
+                        squares += sign * q;
+                    }
+                    cells += xs.len() * ys.len();
+                }
+            }
+            let cells = cells as f64;
+            let mean = sum / cells;
+            (squares / cells - mean * mean).max(0.0).sqrt()
+        })
+        .collect()
+}
+
+/// The cells of an axis of length `len` within `radius` of `center`, as one
+/// range, or two where `edges` wraps the window past the end of the axis.
+fn window_ranges(center: usize, radius: usize, len: usize, edges: Edges) -> [Range<usize>; 2] {
+    match edges {
+        Edges::Wrap if 2 * radius + 1 < len => {
+            let start = (center + len - radius) % len;
+            let end = start + 2 * radius + 1;
+            if end <= len {
+                [start..end, 0..0]
+            } else {
+                [start..len, 0..end - len]
+            }
+        },
+        Edges::Wrap => [0..len, 0..0],
+        Edges::Clamp => [
+            center.saturating_sub(radius)..(center + radius + 1).min(len),
+            0..0,
+        ],
+    }
+}
+
+/// Number of edges between land cells, strictly above `sea_level`, and sea
+/// cells in the row-major grid `alt` of `size` cells.
+///
+/// Everything outside the map counts as sea, so land along the map's border
+/// contributes an edge for each side facing the border.  Together with the
+/// land fraction, this measures how fragmented the land of a map is.
+pub fn coastline_length(alt: &[f64], size: Vec2<u32>, sea_level: f64) -> u64 {
+    let (w, h) = (size.x as usize, size.y as usize);
+    let is_land = |x: usize, y: usize| alt[y * w + x] > sea_level;
+    let mut edges = 0;
+    for y in 0..h {
+        for x in 0..w {
+            let land = is_land(x, y);
+            // Edges to the right and below, which covers each interior edge
+            // once.
+            if x + 1 < w && is_land(x + 1, y) != land {
+                edges += 1;
+            }
+            if y + 1 < h && is_land(x, y + 1) != land {
+                edges += 1;
+            }
+            if land {
+                edges += [x == 0, x + 1 == w, y == 0, y + 1 == h]
+                    .iter()
+                    .filter(|&&on_border| on_border)
+                    .count() as u64;
+            }
+        }
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::random_grids;
+
+    #[test]
+    fn range_and_land_fraction() {
+        let stats = AltStats::of(&[-5.0, 0.0, 2.5, 10.0]);
+        assert_eq!(stats.min, -5.0);
+        assert_eq!(stats.max, 10.0);
+        assert_eq!(stats.land_fraction, 0.5);
+        assert_eq!(AltStats::of(&[]).land_fraction, 0.0);
+        assert_eq!(AltStats::of(&[]).std_dev, 0.0);
+    }
+
+    #[test]
+    fn std_dev_measures_spread() {
+        assert_eq!(AltStats::of(&[3.0; 16]).std_dev, 0.0);
+        assert_eq!(AltStats::of(&[-1.0, 1.0, -1.0, 1.0]).std_dev, 1.0);
+        assert_eq!(
+            AltStats::of(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]).std_dev,
+            2.0
+        );
+    }
+
+    #[test]
+    fn accumulated_strips_match_the_whole_grid() {
+        let alt = (0..1000)
+            .map(|i| ((i * 37) % 101) as f64 * 12.5 - 300.0)
+            .collect::<Vec<_>>();
+        let expected = AltStats::of(&alt);
+        for strip in [1, 7, 64, 1000] {
+            let mut acc = AltStatsAccumulator::default();
+            alt.chunks(strip).for_each(|strip| acc.add(strip));
+            let stats = acc.stats();
+            assert_eq!((stats.min, stats.max), (expected.min, expected.max));
+            assert_eq!(stats.land_fraction, expected.land_fraction);
+            assert!(
+                (stats.std_dev - expected.std_dev).abs() < 1e-9 * expected.std_dev,
+                "strips of {}: {} != {}",
+                strip,
+                stats.std_dev,
+                expected.std_dev
+            );
+        }
+        assert_eq!(AltStatsAccumulator::default().stats(), AltStats::of(&[]));
+    }
+
+    #[test]
+    fn quantiles_interpolate_between_ranks() {
+        let alt = [40.0, f64::NAN, -10.0, 0.0, 10.0, 20.0];
+        assert_eq!(quantiles(&alt, &[0.0, 25.0, 50.0, 62.5, 100.0]), [
+            -10.0, 0.0, 10.0, 15.0, 40.0
+        ]);
+        assert_eq!(quantiles(&[7.0], &REPORTED_PERCENTILES), [7.0; 7]);
+        assert!(quantiles(&[], &[50.0])[0].is_nan());
+    }
+
+    #[test]
+    fn hypsometry_falls_from_all_cells_to_none() {
+        let alt = [0.0, 10.0, 10.0, 20.0, 30.0, 40.0, f64::NAN, 100.0];
+        assert_eq!(hypsometry(&alt, 4), [
+            (0.0, 1.0),
+            (25.0, 3.0 / 7.0),
+            (50.0, 1.0 / 7.0),
+            (75.0, 1.0 / 7.0),
+            (100.0, 0.0)
+        ]);
+        assert_eq!(hypsometry(&[5.0; 3], 2), [
+            (5.0, 1.0),
+            (5.0, 0.0),
+            (5.0, 0.0)
+        ]);
+        assert!(hypsometry(&[f64::NAN], 8).is_empty());
+    }
+
+    #[test]
+    fn histogram_quantiles_match_sorted_ones() {
+        let percentiles = [0.0, 0.1, 1.0, 37.5, 50.0, 99.0, 99.9, 100.0];
+        for (_, alt) in random_grids(50) {
+            for percentile in percentiles {
+                assert_eq!(
+                    quantile(&alt, percentile),
+                    quantiles(&alt, &[percentile])[0]
+                );
+            }
+        }
+        // Enough cells to need histograms, mostly within a few meters of each
+        // other but for one far outlier, with many of them duplicated.
+        let mut alt = (0..300_000)
+            .map(|i| ((i * 37) % 1000) as f64 / 250.0)
+            .collect::<Vec<_>>();
+        alt[1234] = 9000.0;
+        alt[4321] = f64::NAN;
+        for percentile in percentiles {
+            assert_eq!(
+                quantile(&alt, percentile),
+                quantiles(&alt, &[percentile])[0]
+            );
+        }
+        assert!(quantile(&[f64::NAN], 50.0).is_nan());
+    }
+
+    #[test]
+    fn roughness_matches_the_spread_of_each_window() {
+        for (size, alt) in random_grids(20) {
+            let rough = roughness(&alt, size, 5, Edges::Clamp);
+            for (i, &rough) in rough.iter().enumerate() {
+                let (x, y) = (i % size.x, i / size.x);
+                let window = (y.saturating_sub(2)..(y + 3).min(size.y))
+                    .flat_map(|y| (x.saturating_sub(2)..(x + 3).min(size.x)).map(move |x| (x, y)))
+                    .map(|(x, y)| alt[y * size.x + x])
+                    .collect::<Vec<_>>();
+                let expected = AltStats::of(&window).std_dev;
+                assert!((rough - expected).abs() < 1e-6, "{} != {}", rough, expected);
+            }
+
+            // Wrapped windows are all full, but cover narrow grids once.
+            let rough = roughness(&alt, size, 5, Edges::Wrap);
+            let wrapped = |center: usize, len: usize| {
+                let mut cells = (0..5)
+                    .map(|d| (center + len * 2 + d - 2) % len)
+                    .collect::<Vec<_>>();
+                cells.sort_unstable();
+                cells.dedup();
+                cells
+            };
+            for (i, &rough) in rough.iter().enumerate() {
+                let (x, y) = (i % size.x, i / size.x);
+                let window = wrapped(y, size.y)
+                    .into_iter()
+                    .flat_map(|y| wrapped(x, size.x).into_iter().map(move |x| (x, y)))
+                    .map(|(x, y)| alt[y * size.x + x])
+                    .collect::<Vec<_>>();
+                let expected = AltStats::of(&window).std_dev;
+                assert!((rough - expected).abs() < 1e-6, "{} != {}", rough, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn noise_is_rougher_than_flat_ground() {
+        use rand::prelude::*;
+
+        // White noise on the left half of a high plateau, flat on the right.
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(7);
+        let size = Vec2::new(64, 32);
+        let alt = (0..size.product())
+            .map(|i| {
+                4000.0
+                    + if i % 64 < 32 {
+                        rng.gen_range(-50.0..50.0)
+                    } else {
+                        0.0
+                    }
+            })
+            .collect::<Vec<_>>();
+        let rough = roughness(&alt, size, 9, Edges::Clamp);
+        let at = |x: usize, y: usize| rough[y * size.x + x];
+        // Uniform noise over 100 m spreads by 100 / sqrt(12), about 29 m.
+        assert!((20.0..40.0).contains(&at(12, 16)), "{}", at(12, 16));
+        // Up to the rounding error of the sums over the noise beside it.
+        assert!(at(50, 16) < 1e-3, "{}", at(50, 16));
+        // Windows straddling the border see half of each.
+        assert!(at(32, 16) > 10.0 && at(32, 16) < at(12, 16) * 1.5);
+        let (lo, hi) = percentile_range(&rough, 1.0);
+        assert!(lo < 1e-3 && hi > 20.0);
+    }
+
+    #[test]
+    fn coastline_counts_land_sea_edges() {
+        // A 2x2 island in the middle of a 4x4 sea, and a single land cell in
+        // the corner of a 3x3 sea.
+        let island = (0..16)
+            .map(|i| {
+                if (1..3).contains(&(i % 4)) && (1..3).contains(&(i / 4)) {
+                    5.0
+                } else {
+                    -5.0
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(coastline_length(&island, Vec2::new(4, 4), 0.0), 8);
+        let corner = [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        assert_eq!(coastline_length(&corner, Vec2::new(3, 3), 0.0), 4);
+
+        // All land only borders the edge of the map; all sea has no coast.
+        assert_eq!(coastline_length(&[1.0; 12], Vec2::new(4, 3), 0.0), 14);
+        assert_eq!(coastline_length(&[1.0; 12], Vec2::new(4, 3), 1.0), 0);
+    }
+}
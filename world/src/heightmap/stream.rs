@@ -0,0 +1,353 @@
+//! Converting heightmap images into maps without holding the whole map in
+//! memory.
+//!
+//! [`import_image`](super::import::import_image) needs the decoded image, the
+//! altitude grid and a smoothing buffer at once, which for a 16384x16384 map
+//! is several gigabytes.  The functions here instead decode the image a row at
+//! a time, smooth it in horizontal strips with enough overlap for the
+//! smoothing kernel, and write the altitudes straight to the output.  Memory
+//! use is proportional to the strip height times the image width, and the
+//! output is bit-identical to saving the result of the in-memory conversion.
+//!
+//! Only non-interlaced 8-bit PNG images are supported.
+
+use super::{
+    Error,
+    filter::{Edges, smooth_altitudes},
+    import::{ImportParams, SmoothTarget, map_size_lg},
+    io::{Compression, MapWriter, write_atomically, write_grid_len, write_map_header},
+    packed::check_not_packed,
+    stats::{AltStats, AltStatsAccumulator},
+};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+use vek::*;
+
+/// Rows of altitudes decoded from a PNG image, before smoothing.
+struct AltRows<'a, R: Read> {
+    reader: png::Reader<R>,
+    samples: usize,
+    params: &'a ImportParams,
+}
+
+impl<'a, R: Read> AltRows<'a, R> {
+    fn new(input: R, params: &'a ImportParams) -> Result<Self, Error> {
+        let mut decoder = png::Decoder::new(input);
+        // Expand palettes and low bit depths like `image` does, so pixels
+        // come out the same as in the in-memory path.
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let reader = decoder.read_info()?;
+        if reader.info().interlaced {
+            return Err(Error::UnsupportedImage(
+                "interlaced images can't be converted in strips".to_owned(),
+            ));
+        }
+        let (color_type, bit_depth) = reader.output_color_type();
+        if bit_depth != png::BitDepth::Eight {
+            return Err(Error::UnsupportedImage(format!(
+                "only 8-bit images can be converted in strips, found {:?}",
+                bit_depth
+            )));
+        }
+        if params.terrain_rgb && color_type.samples() < 3 {
+            return Err(Error::UnsupportedImage(format!(
+                "Terrain-RGB altitudes are decoded from RGB images, found {:?}",
+                color_type
+            )));
+        }
+        Ok(Self {
+            samples: color_type.samples(),
+            reader,
+            params,
+        })
+    }
+
+    fn size(&self) -> (u32, u32) {
+        let info = self.reader.info();
+        (info.width, info.height)
+    }
+
+    /// Appends the altitudes of the next row to `out`.
+    fn read_row(&mut self, out: &mut Vec<f64>) -> Result<(), Error> {
+        let row = self
+            .reader
+            .next_row()?
+            .ok_or_else(|| Error::UnsupportedImage("image ended before its last row".to_owned()))?;
+        let params = self.params;
+        out.extend(row.data().chunks_exact(self.samples).map(|pixel| {
+            let rgb = match *pixel {
+                [v] | [v, _] => [v; 3],
+                [r, g, b] | [r, g, b, _] => [r, g, b],
+                _ => unreachable!("PNG pixels have between 1 and 4 samples"),
+            };
+            params.decode(rgb.map(f64::from))
+        }));
+        Ok(())
+    }
+}
+
+/// Applies `iterations` passes of [`smooth_altitudes`] to a `width` by
+/// `height` grid that is produced a row at a time by `read_row`, handing the
+/// smoothed grid to `emit` in strips of at most `strip_rows` rows.
+///
+/// Every pass only looks one row up and down, so a strip is smoothed exactly
+/// like the full grid as long as it is padded with `iterations` extra rows on
+/// each side; only those padding rows see the artificial edge.
+pub fn smooth_in_strips(
+    width: u32,
+    height: u32,
+    iterations: u32,
+    strip_rows: usize,
+    mut read_row: impl FnMut(&mut Vec<f64>) -> Result<(), Error>,
+    mut emit: impl FnMut(&[f64]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let w = width as usize;
+    let h = height as usize;
+    let halo = iterations as usize;
+    let strip_rows = strip_rows.max(1);
+
+    // Rows `window_start..window_start + window.len() / w` of the grid.
+    let mut window = Vec::new();
+    let mut window_start = 0;
+    for start in (0..h).step_by(strip_rows) {
+        let end = (start + strip_rows).min(h);
+        let needed_start = start.saturating_sub(halo);
+        let needed_end = (end + halo).min(h);
+
+        window.drain(..(needed_start - window_start) * w);
+        window_start = needed_start;
+        while window_start + window.len() / w < needed_end {
+            read_row(&mut window)?;
+        }
+
+        let rows = needed_end - needed_start;
+        let mut smoothed = window.clone();
+        for _ in 0..iterations {
+            smoothed = smooth_altitudes(&smoothed, width, rows as u32, Edges::Clamp);
+        }
+        emit(&smoothed[(start - needed_start) * w..(end - needed_start) * w])?;
+    }
+    Ok(())
+}
+
+/// What [`stream_import`] found out about the map it wrote.
+#[derive(Clone, Copy, Debug)]
+pub struct StreamedMap {
+    pub map_size_lg: Vec2<u32>,
+    /// Statistics of the altitudes, gathered a strip at a time, for the
+    /// checks of [`stats_warnings`](super::stats_warnings).
+    pub stats: AltStats,
+}
+
+/// Converts a PNG image into a map like
+/// [`import_image`](super::import::import_image), writing the map as a world
+/// file to `output` without holding it in memory.
+///
+/// `open` is called twice to read the image, since basement altitudes are
+/// written after (and are computed from) the altitudes.  Padding, alpha water,
+/// rivers, wrapped edges and smoothing only one of the altitudes and basement
+/// are not supported.
+pub fn stream_import<R: Read>(
+    mut open: impl FnMut() -> Result<R, Error>,
+    mut output: impl Write,
+    params: &ImportParams,
+    strip_rows: usize,
+) -> Result<StreamedMap, Error> {
+    if params.pad.is_some() {
+        return Err(Error::UnsupportedImage(
+            "padding is not supported when streaming".to_string(),
+        ));
+    }
+    if params.alpha_water.is_some() || params.rivers.is_some() {
+        return Err(Error::UnsupportedImage(
+            "alpha water and rivers are not supported when streaming".to_string(),
+        ));
+    }
+    if params.smooth_iterations > 0 && params.smooth_target != SmoothTarget::Both {
+        return Err(Error::UnsupportedImage(
+            "smoothing only the altitudes or the basement is not supported when streaming"
+                .to_string(),
+        ));
+    }
+    if params.edges == Edges::Wrap {
+        return Err(Error::UnsupportedImage(
+            "wrapped edges are not supported when streaming".to_string(),
+        ));
+    }
+    let (width, height) = AltRows::new(open()?, params)?.size();
+    let map_size_lg = map_size_lg(width, height)?;
+    let delta = params.sea_to_zero.map(|current_sea| -current_sea);
+    let mut stats = AltStatsAccumulator::default();
+    let mut values = Vec::new();
+
+    write_map_header(&mut output, map_size_lg, params.continent_scale)?;
+    // Altitudes, then basement.
+    for pass in 0..2 {
+        let basement = params.basement.filter(|_| pass == 1);
+        let mut rows = AltRows::new(open()?, params)?;
+        write_grid_len(&mut output, width as usize * height as usize)?;
+        smooth_in_strips(
+            width,
+            height,
+            params.smooth_iterations,
+            strip_rows,
+            |out| rows.read_row(out),
+            |strip| {
+                values.clear();
+                values.extend(strip.iter().map(|&alt| {
+                    let alt = match delta {
+                        Some(delta) => alt + delta,
+                        None => alt,
+                    };
+                    match &params.abyss {
+                        Some(abyss) => abyss.clamp(alt),
+                        None => alt,
+                    }
+                }));
+                if pass == 0 {
+                    stats.add(&values);
+                }
+                for &alt in &values {
+                    let value = match &basement {
+                        Some(basement) => basement.basement(alt),
+                        None => alt,
+                    };
+                    output.write_all(&value.to_le_bytes())?;
+                }
+                Ok(())
+            },
+        )?;
+    }
+    output.flush()?;
+    Ok(StreamedMap {
+        map_size_lg,
+        stats: stats.stats(),
+    })
+}
+
+/// Converts the PNG image at `input` into the world file `output`, compressed
+/// with `compression`; see [`stream_import`].
+pub fn stream_import_file(
+    input: &Path,
+    output: &Path,
+    params: &ImportParams,
+    strip_rows: usize,
+    compression: Compression,
+) -> Result<StreamedMap, Error> {
+    check_not_packed(input)?;
+    let mut streamed = None;
+    write_atomically(output, |writer| {
+        let mut writer = MapWriter::new(writer, compression)?;
+        streamed = Some(stream_import(
+            || Ok(BufReader::new(File::open(input)?)),
+            &mut writer,
+            params,
+            strip_rows,
+        )?);
+        writer.finish()
+    })?;
+    Ok(streamed.expect("the map was written"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        heightmap::{
+            adjust::ProportionalBasement,
+            import::{Channel, import_image},
+        },
+        sim::WorldFile,
+    };
+
+    #[test]
+    fn strips_smooth_like_the_full_grid() {
+        let (width, height) = (8, 13);
+        let grid = (0..width * height)
+            .map(|i| ((i * 37) % 11) as f64 * 0.3)
+            .collect::<Vec<_>>();
+        for iterations in 0..4 {
+            let mut expected = grid.clone();
+            for _ in 0..iterations {
+                expected = smooth_altitudes(&expected, width as u32, height as u32, Edges::Clamp);
+            }
+            for strip_rows in [1, 2, 5, 13, 64] {
+                let mut rows = grid.chunks(width);
+                let mut out = Vec::new();
+                smooth_in_strips(
+                    width as u32,
+                    height as u32,
+                    iterations,
+                    strip_rows,
+                    |row| {
+                        row.extend_from_slice(rows.next().unwrap());
+                        Ok(())
+                    },
+                    |strip| {
+                        out.extend_from_slice(strip);
+                        Ok(())
+                    },
+                )
+                .unwrap();
+                assert_eq!(
+                    out, expected,
+                    "{} iterations, {} rows",
+                    iterations, strip_rows
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn matches_in_memory_conversion() {
+        let size = 16;
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, size, size);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let data = (0..size * size * 3)
+            .map(|i| (i * 97 % 256) as u8)
+            .collect::<Vec<_>>();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&data).unwrap();
+        writer.finish().unwrap();
+
+        let mut params = ImportParams {
+            smooth_iterations: 2,
+            channel: Channel::Avg,
+            sea_to_zero: Some(17.25),
+            ..ImportParams::new(1234.5, -321.0)
+        };
+        let img = image::load_from_memory(&png).unwrap();
+        for basement in [
+            None,
+            Some(ProportionalBasement {
+                sea_level: 100.0,
+                factor: 0.4,
+            }),
+        ] {
+            params.basement = basement;
+            let map = import_image(&img, &params).unwrap();
+            let expected_stats = AltStats::of(&map.alt);
+            let expected = bincode::serialize(&WorldFile::new(map)).unwrap();
+
+            let mut streamed = Vec::new();
+            let StreamedMap { map_size_lg, stats } =
+                stream_import(|| Ok(&png[..]), &mut streamed, &params, 3).unwrap();
+            assert_eq!(streamed, expected);
+            assert_eq!(map_size_lg, Vec2::new(4, 4));
+            assert_eq!(
+                (stats.min, stats.max, stats.land_fraction),
+                (
+                    expected_stats.min,
+                    expected_stats.max,
+                    expected_stats.land_fraction
+                )
+            );
+            assert!((stats.std_dev - expected_stats.std_dev).abs() < 1e-9);
+        }
+    }
+}
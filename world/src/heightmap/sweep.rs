@@ -0,0 +1,260 @@
+//! Converting an image with many scales and offsets at once, to compare the
+//! resulting maps side by side.
+//!
+//! The image is decoded and smoothed only once, to altitudes between 0 and 1
+//! (see [`unit_params`]); since smoothing commutes with the linear mapping of
+//! pixel values to altitudes, each combination is then just [`apply`]'d to
+//! them.
+
+use super::{
+    Error,
+    import::ImportParams,
+    template::{NameTemplate, first_duplicate},
+};
+use std::path::PathBuf;
+
+/// Values from `start` to `end`, inclusive, `step` apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SweepRange {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+}
+
+impl SweepRange {
+    /// A range of the single value `value`.
+    pub fn single(value: f64) -> Self {
+        Self {
+            start: value,
+            end: value,
+            step: 1.0,
+        }
+    }
+
+    /// Parses `START..END:STEP`, such as `800..1600:200`, or a single value.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let error = |reason: &str| Error::Sweep(format!("{:?}: {}", text, reason));
+        let number = |text: &str| {
+            text.trim()
+                .parse::<f64>()
+                .map_err(|e| error(&e.to_string()))
+                .and_then(|value| {
+                    if value.is_finite() {
+                        Ok(value)
+                    } else {
+                        Err(error("values must be finite"))
+                    }
+                })
+        };
+        let Some((start, rest)) = text.split_once("..") else {
+            return Ok(Self::single(number(text)?));
+        };
+        let Some((end, step)) = rest.split_once(':') else {
+            return Err(error("expected START..END:STEP"));
+        };
+        let range = Self {
+            start: number(start)?,
+            end: number(end)?,
+            step: number(step)?,
+        };
+        if range.step <= 0.0 {
+            return Err(error("the step must be positive"));
+        }
+        if range.end < range.start {
+            return Err(error("the end can't be below the start"));
+        }
+        Ok(range)
+    }
+
+    /// Number of values in the range.  The end is included if it lies within
+    /// a millionth of a step of a value, so that ranges such as `0..1:0.1`
+    /// aren't cut short by rounding.
+    pub fn count(&self) -> usize {
+        if self.end <= self.start {
+            1
+        } else {
+            ((self.end - self.start) / self.step + 1e-6).floor() as usize + 1
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.count()).map(|i| self.start + i as f64 * self.step)
+    }
+}
+
+/// Every pair of a scale from `scale` and an offset from `offset`, offsets
+/// varying fastest, refusing sweeps of more than `max_outputs` combinations.
+pub fn combinations(
+    scale: &SweepRange,
+    offset: &SweepRange,
+    max_outputs: usize,
+) -> Result<Vec<(f64, f64)>, Error> {
+    let count = scale.count().saturating_mul(offset.count());
+    if count > max_outputs {
+        return Err(Error::Sweep(format!(
+            "{} scales and {} offsets give {} maps, more than the limit of {}",
+            scale.count(),
+            offset.count(),
+            count,
+            max_outputs
+        )));
+    }
+    Ok(scale
+        .values()
+        .flat_map(|scale| offset.values().map(move |offset| (scale, offset)))
+        .collect())
+}
+
+/// Placeholders of the [`output_paths`] template: the file stem of the
+/// input and the extension of the maps, which are the same for every
+/// combination, and its scale and offset.
+pub const PLACEHOLDERS: [&str; 4] = ["stem", "scale", "offset", "ext"];
+
+/// The path of each combination, with `{scale}` and `{offset}` in `template`
+/// replaced by its values, and the other [`PLACEHOLDERS`] by theirs in
+/// `values`.  Fails if two combinations would be written to the same path,
+/// as when the template lacks a value that varies.
+pub fn output_paths(
+    template: &NameTemplate,
+    values: &[(&str, &str)],
+    combinations: &[(f64, f64)],
+) -> Result<Vec<PathBuf>, Error> {
+    let paths = combinations
+        .iter()
+        .map(|(scale, offset)| {
+            let (scale, offset) = (scale.to_string(), offset.to_string());
+            let mut values = values.to_vec();
+            values.extend([("scale", scale.as_str()), ("offset", offset.as_str())]);
+            PathBuf::from(template.expand(&values))
+        })
+        .collect::<Vec<_>>();
+    if let Some(path) = first_duplicate(&paths) {
+        return Err(Error::Sweep(format!(
+            "{} is the output of several combinations; include {{scale}} and {{offset}} in the \
+             template",
+            path.display()
+        )));
+    }
+    Ok(paths)
+}
+
+/// `params` converting pixel values to altitudes between 0 (black) and 1
+/// (white), which [`apply`] maps to those of any scale and offset.
+///
+/// Fails if `params` has steps other than smoothing that don't commute with
+/// that mapping, such as shifting the sea level or reading raw altitudes.
+pub fn unit_params(params: &ImportParams) -> Result<ImportParams, Error> {
+    let nonlinear = [
+        (params.raw_altitude.is_some(), "raw altitudes"),
+        (params.terrain_rgb, "Terrain-RGB"),
+        (params.sea_to_zero.is_some(), "shifting the sea level"),
+        (
+            params
+                .pad
+                .as_ref()
+                .is_some_and(|pad| pad.altitude.is_some()),
+            "padding with a fixed altitude",
+        ),
+        (params.basement.is_some(), "a proportional basement"),
+        (params.alpha_water.is_some(), "alpha water"),
+        (params.rivers.is_some(), "carving rivers"),
+        (params.abyss.is_some(), "clamping the abyss"),
+        (
+            params.range.is_some_and(|range| range.fill.is_some()),
+            "filling values outside the range",
+        ),
+    ];
+    if let Some((_, step)) = nonlinear.iter().find(|(used, _)| *used) {
+        return Err(Error::Sweep(format!("{} can't be swept", step)));
+    }
+    Ok(ImportParams {
+        scale: 1.0,
+        offset: 0.0,
+        ..params.clone()
+    })
+}
+
+/// The altitudes of the combination of `scale` and `offset`, from those
+/// converted with [`unit_params`].
+pub fn apply(unit: &[f64], scale: f64, offset: f64) -> Vec<f64> {
+    unit.iter().map(|&alt| alt * scale + offset).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::import::import_image;
+    use image::{DynamicImage, GrayImage, Luma};
+
+    #[test]
+    fn ranges_are_parsed_inclusively() {
+        let range = SweepRange::parse("800..1600:200").unwrap();
+        assert_eq!(range.values().collect::<Vec<_>>(), [
+            800.0, 1000.0, 1200.0, 1400.0, 1600.0
+        ]);
+        let offsets = SweepRange::parse("-400..-100:100").unwrap();
+        assert_eq!(offsets.count(), 4);
+        assert_eq!(SweepRange::parse("0..1:0.1").unwrap().count(), 11);
+        assert_eq!(SweepRange::parse("0..1:0.3").unwrap().count(), 4);
+        assert_eq!(
+            SweepRange::parse(" -600 ").unwrap(),
+            SweepRange::single(-600.0)
+        );
+        for bad in ["1..2", "1..2:0", "2..1:1", "a..2:1", "1..2:inf", ""] {
+            assert!(
+                matches!(SweepRange::parse(bad), Err(Error::Sweep(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn sweeps_are_limited_and_named_apart() {
+        let scale = SweepRange::parse("800..1600:200").unwrap();
+        let offset = SweepRange::parse("-400..-100:100").unwrap();
+        assert!(matches!(
+            combinations(&scale, &offset, 19),
+            Err(Error::Sweep(_))
+        ));
+        let combinations = combinations(&scale, &offset, 20).unwrap();
+        assert_eq!(combinations.len(), 20);
+        assert_eq!(combinations[1], (800.0, -300.0));
+
+        let template = |text| NameTemplate::parse(text, &PLACEHOLDERS).unwrap();
+        let paths = output_paths(
+            &template("maps/{stem}_{scale}_{offset}.{ext}"),
+            &[("stem", "m"), ("ext", "bin")],
+            &combinations,
+        )
+        .unwrap();
+        assert_eq!(paths[1], PathBuf::from("maps/m_800_-300.bin"));
+        assert!(matches!(
+            output_paths(&template("maps/m_{scale}.bin"), &[], &combinations),
+            Err(Error::Sweep(_))
+        ));
+    }
+
+    #[test]
+    fn sweeps_match_separate_conversions() {
+        let mut params = ImportParams {
+            smooth_iterations: 2,
+            ..ImportParams::new(1000.0, -600.0)
+        };
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(16, 16, |x, y| {
+            Luma([(x * 13 + y * y) as u8])
+        }));
+        let unit = import_image(&img, &unit_params(&params).unwrap()).unwrap();
+        for (scale, offset) in [(800.0, -400.0), (1600.0, -100.0)] {
+            params.scale = scale;
+            params.offset = offset;
+            let direct = import_image(&img, &params).unwrap();
+            for (swept, direct) in apply(&unit.alt, scale, offset).iter().zip(&*direct.alt) {
+                assert!((swept - direct).abs() < 1e-9, "{} != {}", swept, direct);
+            }
+        }
+
+        params.sea_to_zero = Some(10.0);
+        assert!(matches!(unit_params(&params), Err(Error::Sweep(_))));
+    }
+}
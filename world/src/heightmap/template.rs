@@ -0,0 +1,184 @@
+//! Templates naming the files written by tools with many outputs, such as
+//! sweeps and pyramids, in which placeholders like `{stem}` and `{scale}`
+//! are replaced by the values of each output.
+
+use super::Error;
+use std::{collections::HashSet, path::PathBuf};
+
+/// A parsed template, such as `{stem}_scale{scale}.bin`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(String),
+}
+
+impl NameTemplate {
+    /// Parses `text`, in which `{name}` is replaced by the value of `name`
+    /// and `{{` and `}}` stand for literal braces.
+    ///
+    /// Fails on unmatched braces and on placeholders other than
+    /// `placeholders`, those the tool at hand fills in, so that typos are
+    /// caught before anything is written.
+    pub fn parse(text: &str, placeholders: &[&str]) -> Result<Self, Error> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) if c != '{' => name.push(c),
+                            _ => {
+                                return Err(Error::NameTemplate(format!(
+                                    "unclosed {{ in {:?}; write {{{{ for a literal brace",
+                                    text
+                                )));
+                            },
+                        }
+                    }
+                    if !placeholders.contains(&name.as_str()) {
+                        return Err(Error::NameTemplate(format!(
+                            "unknown placeholder {{{}}} in {:?}; expected {}",
+                            name,
+                            text,
+                            placeholders
+                                .iter()
+                                .map(|name| format!("{{{}}}", name))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )));
+                    }
+                    parts.push(Part::Text(std::mem::take(&mut literal)));
+                    parts.push(Part::Placeholder(name));
+                },
+                '}' => {
+                    return Err(Error::NameTemplate(format!(
+                        "unmatched }} in {:?}; write }}}} for a literal brace",
+                        text
+                    )));
+                },
+                c => literal.push(c),
+            }
+        }
+        parts.push(Part::Text(literal));
+        parts.retain(|part| *part != Part::Text(String::new()));
+        Ok(Self { parts })
+    }
+
+    /// `text` with its braces doubled, so that it is kept as is in a
+    /// template, such as a directory prepended to one.
+    pub fn escape(text: &str) -> String { text.replace('{', "{{").replace('}', "}}") }
+
+    /// Whether the template contains the placeholder `name`.
+    pub fn uses(&self, name: &str) -> bool {
+        self.parts
+            .iter()
+            .any(|part| *part == Part::Placeholder(name.to_owned()))
+    }
+
+    /// The template with each placeholder replaced by its value in `values`,
+    /// made [`safe`].
+    ///
+    /// # Panics
+    ///
+    /// If a placeholder of the template has no value, as [`parse`](Self::parse)
+    /// only accepts those the tool fills in.
+    pub fn expand(&self, values: &[(&str, &str)]) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Placeholder(name) => {
+                    let (_, value) = values
+                        .iter()
+                        .find(|(placeholder, _)| placeholder == name)
+                        .unwrap_or_else(|| panic!("No value for the placeholder {{{}}}", name));
+                    safe(value)
+                },
+            })
+            .collect()
+    }
+}
+
+/// `value` made safe to put in a file name: path separators and control
+/// characters are replaced by underscores, as are values made only of dots,
+/// so that values can't move outputs to another directory.  Other
+/// characters, which the names of existing files may hold, are kept.
+pub fn safe(value: &str) -> String {
+    if !value.is_empty() && value.chars().all(|c| c == '.') {
+        return "_".repeat(value.len());
+    }
+    value
+        .chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// The first path of `paths` that appears more than once in it, if any, to
+/// check that the outputs of a run are all named apart before writing any.
+pub fn first_duplicate(paths: &[PathBuf]) -> Option<&PathBuf> {
+    let mut seen = HashSet::new();
+    paths.iter().find(|path| !seen.insert(*path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_expand_safely_and_reject_typos() {
+        let template =
+            NameTemplate::parse("out/{stem}_{width}{{x}}.png", &["stem", "width"]).unwrap();
+        assert!(template.uses("width") && !template.uses("scale"));
+        assert_eq!(
+            template.expand(&[("stem", "map"), ("width", "512")]),
+            "out/map_512{x}.png"
+        );
+        // Values never add directories.
+        assert_eq!(
+            template.expand(&[("stem", "../a\\b/c"), ("width", "..")]),
+            "out/.._a_b_c___{x}.png"
+        );
+        assert_eq!(safe("map: v2.bin"), "map: v2.bin");
+
+        for bad in ["{stme}.png", "{stem.png", "stem}.png", "{{stem}_{seed}"] {
+            assert!(
+                matches!(
+                    NameTemplate::parse(bad, &["stem"]),
+                    Err(Error::NameTemplate(_))
+                ),
+                "{}",
+                bad
+            );
+        }
+        let dir = NameTemplate::escape("{odd} dir/");
+        let template = NameTemplate::parse(&(dir + "{stem}"), &["stem"]).unwrap();
+        assert_eq!(template.expand(&[("stem", "map")]), "{odd} dir/map");
+
+        let paths = ["a", "b", "a"].map(PathBuf::from);
+        assert_eq!(first_duplicate(&paths), Some(&paths[0]));
+        assert_eq!(first_duplicate(&paths[..2]), None);
+    }
+}
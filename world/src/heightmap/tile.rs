@@ -0,0 +1,513 @@
+//! Splitting a map into a grid of smaller maps, and stitching such a grid
+//! back together.
+//!
+//! Tiles are plain copies of a square window of the source map, so splitting
+//! and stitching round-trip bit-exactly.
+
+use super::{
+    Compression, Error, checked_map_size,
+    io::{MapWriter, write_atomically, write_grid_len, write_map_header},
+    load_map, map_size, read_json, write_json,
+};
+use crate::sim::ModernMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+use vek::*;
+
+/// Describes how a set of tiles fits together into a single map.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TileManifest {
+    /// Base two logarithm of the size of the stitched map, per dimension.
+    pub map_size_lg: Vec2<u32>,
+    /// Base two logarithm of the size of each tile, per dimension.
+    pub tile_size_lg: Vec2<u32>,
+    /// `continent_scale_hack` of the source map.
+    pub continent_scale_hack: f64,
+    pub tiles: Vec<TileEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TileEntry {
+    /// Tile coordinates of this tile within the grid.
+    pub pos: Vec2<u32>,
+    /// Path of the tile's world file, relative to the manifest.
+    pub file: String,
+}
+
+impl TileManifest {
+    /// Number of tiles along each axis, failing on sizes that [`stitch`]
+    /// would reject.
+    pub fn grid_size(&self) -> Result<Vec2<usize>, Error> {
+        Ok(tile_grid(self.map_size_lg, self.tile_size_lg)?.grid)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> { read_json(path) }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> { write_json(path, self) }
+}
+
+/// Splits `map` into square tiles of `tile_size` cells per side.
+///
+/// Tiles are returned in row-major order together with their tile
+/// coordinates, and keep the `continent_scale_hack` of the source map.
+/// `tile_size` must evenly divide both dimensions of the map, which (since
+/// map dimensions are powers of two) means it must be a power of two no larger
+/// than either dimension.
+pub fn split(map: &ModernMap, tile_size: u32) -> Result<Vec<(Vec2<u32>, ModernMap)>, Error> {
+    let size = map_size(map);
+    let tile = tile_size as usize;
+    if tile == 0 || size.x % tile != 0 || size.y % tile != 0 {
+        return Err(Error::TileSize {
+            tile_size,
+            map_size: size,
+        });
+    }
+    let tile_size_lg = tile_size.trailing_zeros();
+    let grid = size.map(|e| e / tile);
+
+    let copy_tile = |src: &[f64], tx: usize, ty: usize| {
+        (0..tile)
+            .flat_map(|y| {
+                let start = (ty * tile + y) * size.x + tx * tile;
+                src[start..start + tile].iter().copied()
+            })
+            .collect::<Box<[_]>>()
+    };
+
+    Ok((0..grid.y)
+        .flat_map(|ty| (0..grid.x).map(move |tx| (tx, ty)))
+        .map(|(tx, ty)| {
+            let tile_map = ModernMap {
+                map_size_lg: Vec2::broadcast(tile_size_lg),
+                continent_scale_hack: map.continent_scale_hack,
+                alt: copy_tile(&map.alt, tx, ty),
+                basement: copy_tile(&map.basement, tx, ty),
+            };
+            (Vec2::new(tx as u32, ty as u32), tile_map)
+        })
+        .collect())
+}
+
+/// Sizes of a map split into tiles.
+struct TileGrid {
+    /// Size of the map, in cells.
+    size: Vec2<usize>,
+    /// Size of each tile, in cells.
+    tile: Vec2<usize>,
+    /// Number of tiles along each axis.
+    grid: Vec2<usize>,
+}
+
+/// The sizes of a map of size `2^map_size_lg` split into tiles of size
+/// `2^tile_size_lg`, failing on maps too large to load (see
+/// [`checked_map_size`]).
+fn tile_grid(map_size_lg: Vec2<u32>, tile_size_lg: Vec2<u32>) -> Result<TileGrid, Error> {
+    if tile_size_lg.x > map_size_lg.x || tile_size_lg.y > map_size_lg.y {
+        return Err(Error::TileLayout(format!(
+            "tiles of size 2^{:?} are larger than the map of size 2^{:?}",
+            tile_size_lg, map_size_lg
+        )));
+    }
+    let size = checked_map_size(map_size_lg)?;
+    let tile = checked_map_size(tile_size_lg)?;
+    Ok(TileGrid {
+        size,
+        tile,
+        grid: size.map2(tile, |map, tile| map / tile),
+    })
+}
+
+/// Marks the tile at `pos` as `seen`, the row-major flags of a grid of size
+/// `grid`, failing if it lies outside the grid or was seen already.  Returns
+/// the tile's index in `seen`.
+fn place_tile(pos: Vec2<u32>, grid: Vec2<usize>, seen: &mut [bool]) -> Result<usize, Error> {
+    let pos = pos.map(|e| e as usize);
+    if pos.x >= grid.x || pos.y >= grid.y {
+        return Err(Error::TileLayout(format!(
+            "tile ({}, {}) lies outside the {}x{} grid",
+            pos.x, pos.y, grid.x, grid.y
+        )));
+    }
+    let index = pos.y * grid.x + pos.x;
+    if seen[index] {
+        return Err(Error::TileLayout(format!(
+            "tile ({}, {}) appears more than once",
+            pos.x, pos.y
+        )));
+    }
+    seen[index] = true;
+    Ok(index)
+}
+
+/// Fails if any tile of the grid of size `grid` hasn't been `seen`.
+fn check_complete(grid: Vec2<usize>, seen: &[bool]) -> Result<(), Error> {
+    match seen.iter().position(|seen| !seen) {
+        Some(missing) => Err(Error::TileLayout(format!(
+            "tile ({}, {}) is missing",
+            missing % grid.x,
+            missing / grid.x
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Fails if `tile_map`, the tile at `pos`, isn't of size `2^tile_size_lg`.
+fn check_tile_size(
+    pos: Vec2<u32>,
+    tile_map: &ModernMap,
+    tile_size_lg: Vec2<u32>,
+) -> Result<(), Error> {
+    let cells = checked_map_size(tile_size_lg)?.product();
+    if tile_map.map_size_lg != tile_size_lg
+        || tile_map.alt.len() != cells
+        || tile_map.basement.len() != cells
+    {
+        return Err(Error::TileLayout(format!(
+            "tile ({}, {}) does not have the expected size 2^{:?}",
+            pos.x, pos.y, tile_size_lg
+        )));
+    }
+    Ok(())
+}
+
+/// Reassembles a map of size `2^map_size_lg` from tiles of size
+/// `2^tile_size_lg`, as produced by [`split`].
+///
+/// Every position in the tile grid must be covered by exactly one tile.
+pub fn stitch(
+    map_size_lg: Vec2<u32>,
+    tile_size_lg: Vec2<u32>,
+    continent_scale_hack: f64,
+    tiles: impl IntoIterator<Item = (Vec2<u32>, ModernMap)>,
+) -> Result<ModernMap, Error> {
+    let TileGrid { size, tile, grid } = tile_grid(map_size_lg, tile_size_lg)?;
+
+    let mut alt = vec![0.0; size.product()];
+    let mut basement = vec![0.0; size.product()];
+    let mut seen = vec![false; grid.product()];
+
+    for (pos, tile_map) in tiles {
+        place_tile(pos, grid, &mut seen)?;
+        check_tile_size(pos, &tile_map, tile_size_lg)?;
+        let pos = pos.map(|e| e as usize);
+
+        for y in 0..tile.y {
+            let src = y * tile.x..(y + 1) * tile.x;
+            let start = (pos.y * tile.y + y) * size.x + pos.x * tile.x;
+            alt[start..start + tile.x].copy_from_slice(&tile_map.alt[src.clone()]);
+            basement[start..start + tile.x].copy_from_slice(&tile_map.basement[src]);
+        }
+    }
+
+    check_complete(grid, &seen)?;
+
+    Ok(ModernMap {
+        map_size_lg,
+        continent_scale_hack,
+        alt: alt.into_boxed_slice(),
+        basement: basement.into_boxed_slice(),
+    })
+}
+
+/// Assembles a map from tile files like [`stitch`], writing it as a world
+/// file without ever holding more than one row of tiles in memory.
+///
+/// Each row of tiles is loaded when the rows of the map it covers are
+/// written, once for the altitudes and once more for the basement, which
+/// follows them in the file.
+#[derive(Clone, Debug)]
+pub struct LazyStitch {
+    map_size_lg: Vec2<u32>,
+    tile_size_lg: Vec2<u32>,
+    continent_scale_hack: f64,
+    tiles: Vec<(Vec2<u32>, PathBuf)>,
+}
+
+impl LazyStitch {
+    /// Starts assembling a map of size `2^map_size_lg` from tiles of size
+    /// `2^tile_size_lg`, none of which have been added yet.
+    pub fn new(map_size_lg: Vec2<u32>, tile_size_lg: Vec2<u32>, continent_scale_hack: f64) -> Self {
+        Self {
+            map_size_lg,
+            tile_size_lg,
+            continent_scale_hack,
+            tiles: Vec::new(),
+        }
+    }
+
+    /// Starts assembling the map described by `manifest`, with tile paths
+    /// relative to `base_dir`.
+    pub fn from_manifest(manifest: &TileManifest, base_dir: &Path) -> Self {
+        let mut stitch = Self::new(
+            manifest.map_size_lg,
+            manifest.tile_size_lg,
+            manifest.continent_scale_hack,
+        );
+        for entry in &manifest.tiles {
+            stitch.tile(entry.pos, base_dir.join(&entry.file));
+        }
+        stitch
+    }
+
+    /// Adds the tile file at `path`, at tile coordinates `pos`.
+    pub fn tile(&mut self, pos: Vec2<u32>, path: impl Into<PathBuf>) -> &mut Self {
+        self.tiles.push((pos, path.into()));
+        self
+    }
+
+    /// The paths of the tiles in row-major order, once checked to cover every
+    /// position in the grid exactly once.
+    fn layout(&self) -> Result<Vec<&Path>, Error> {
+        let grid = tile_grid(self.map_size_lg, self.tile_size_lg)?.grid;
+        let mut seen = vec![false; grid.product()];
+        let mut paths = vec![Path::new(""); grid.product()];
+        for (pos, path) in &self.tiles {
+            paths[place_tile(*pos, grid, &mut seen)?] = path;
+        }
+        check_complete(grid, &seen)?;
+        Ok(paths)
+    }
+
+    /// Checks that the tiles cover the grid with no gaps or overlaps, without
+    /// loading them.
+    pub fn validate(&self) -> Result<(), Error> { self.layout().map(drop) }
+
+    /// Writes the assembled world file to `output`, loading tiles with
+    /// `load`.  The layout is validated before anything is written, and each
+    /// tile's size once it is loaded.
+    pub fn write(
+        &self,
+        mut load: impl FnMut(&Path) -> Result<ModernMap, Error>,
+        mut output: impl Write,
+    ) -> Result<(), Error> {
+        let paths = self.layout()?;
+        let TileGrid { size, tile, grid } = tile_grid(self.map_size_lg, self.tile_size_lg)?;
+        write_map_header(&mut output, self.map_size_lg, self.continent_scale_hack)?;
+        // Altitudes, then basement.
+        for pass in 0..2 {
+            write_grid_len(&mut output, size.product())?;
+            for (ty, row) in paths.chunks_exact(grid.x).enumerate() {
+                let strip = row
+                    .iter()
+                    .enumerate()
+                    .map(|(tx, path)| {
+                        let tile_map = load(path)?;
+                        check_tile_size(Vec2::new(tx, ty).as_(), &tile_map, self.tile_size_lg)?;
+                        Ok(if pass == 0 {
+                            tile_map.alt
+                        } else {
+                            tile_map.basement
+                        })
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                for y in 0..tile.y {
+                    for cells in &strip {
+                        for alt in &cells[y * tile.x..(y + 1) * tile.x] {
+                            output.write_all(&alt.to_le_bytes())?;
+                        }
+                    }
+                }
+            }
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Writes the assembled map to the world file at `path`, compressed with
+    /// `compression`, loading tiles with [`load_map`].
+    pub fn save(&self, path: impl AsRef<Path>, compression: Compression) -> Result<(), Error> {
+        write_atomically(path, |writer| {
+            let mut writer = MapWriter::new(writer, compression)?;
+            self.write(|path| load_map(path), &mut writer)?;
+            writer.finish()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+
+    fn bits(alt: &[f64]) -> Vec<u64> { alt.iter().map(|e| e.to_bits()).collect() }
+
+    #[test]
+    fn split_and_stitch_round_trip() {
+        // Use values that aren't exactly representable, so that any arithmetic
+        // on the way through would show up as a bit difference.
+        let map = test_map(Vec2::new(4, 3), |x, y| {
+            (x as f64 * 0.1 - y as f64 / 3.0).sin()
+        });
+        let tiles = split(&map, 4).unwrap();
+        assert_eq!(tiles.len(), 4 * 2);
+
+        let stitched = stitch(
+            map.map_size_lg,
+            Vec2::broadcast(2),
+            map.continent_scale_hack,
+            tiles,
+        )
+        .unwrap();
+        assert_eq!(stitched.map_size_lg, map.map_size_lg);
+        assert_eq!(bits(&stitched.alt), bits(&map.alt));
+        assert_eq!(bits(&stitched.basement), bits(&map.basement));
+    }
+
+    #[test]
+    fn tiles_are_row_major_windows() {
+        let map = test_map(Vec2::new(2, 2), |x, y| (y * 4 + x) as f64);
+        let tiles = split(&map, 2).unwrap();
+        let positions = tiles.iter().map(|(pos, _)| *pos).collect::<Vec<_>>();
+        assert_eq!(positions, vec![
+            Vec2::new(0, 0),
+            Vec2::new(1, 0),
+            Vec2::new(0, 1),
+            Vec2::new(1, 1)
+        ]);
+        assert_eq!(&*tiles[1].1.alt, &[2.0, 3.0, 6.0, 7.0]);
+        assert_eq!(&*tiles[2].1.alt, &[8.0, 9.0, 12.0, 13.0]);
+        assert!(
+            tiles
+                .iter()
+                .all(|(_, tile)| tile.map_size_lg == Vec2::new(1, 1))
+        );
+    }
+
+    #[test]
+    fn split_rejects_uneven_tile_sizes() {
+        let map = test_map(Vec2::new(3, 3), |_, _| 0.0);
+        for tile_size in [0, 3, 6, 16] {
+            assert!(matches!(
+                split(&map, tile_size),
+                Err(Error::TileSize { .. })
+            ));
+        }
+        assert_eq!(split(&map, 8).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn stitch_rejects_incomplete_grids() {
+        let map = test_map(Vec2::new(2, 2), |x, y| (x + y) as f64);
+        let mut tiles = split(&map, 2).unwrap();
+        tiles.pop();
+        let result = stitch(map.map_size_lg, Vec2::new(1, 1), 1.0, tiles);
+        assert!(matches!(result, Err(Error::TileLayout(_))));
+
+        let mut tiles = split(&map, 2).unwrap();
+        tiles[3].0 = Vec2::new(0, 0);
+        let result = stitch(map.map_size_lg, Vec2::new(1, 1), 1.0, tiles);
+        assert!(matches!(result, Err(Error::TileLayout(_))));
+    }
+
+    #[test]
+    fn oversized_manifests_are_rejected_without_overflowing() {
+        for map_size_lg in [Vec2::new(15, 14), Vec2::new(64, 1), Vec2::new(40, 40)] {
+            let manifest = TileManifest {
+                map_size_lg,
+                tile_size_lg: Vec2::new(1, 1),
+                continent_scale_hack: 1.0,
+                tiles: Vec::new(),
+            };
+            assert!(
+                matches!(manifest.grid_size(), Err(Error::SizeOverflow { .. })),
+                "{:?}",
+                map_size_lg
+            );
+            let lazy = LazyStitch::from_manifest(&manifest, Path::new(""));
+            assert!(matches!(lazy.validate(), Err(Error::SizeOverflow { .. })));
+            assert!(matches!(
+                stitch(map_size_lg, Vec2::new(1, 1), 1.0, Vec::new()),
+                Err(Error::SizeOverflow { .. })
+            ));
+        }
+        let manifest = TileManifest {
+            map_size_lg: Vec2::new(3, 2),
+            tile_size_lg: Vec2::new(1, 1),
+            continent_scale_hack: 1.0,
+            tiles: Vec::new(),
+        };
+        assert_eq!(manifest.grid_size().unwrap(), Vec2::new(4, 2));
+    }
+
+    /// Reads the grids of a world file written by [`LazyStitch::write`].
+    fn read_grids(bytes: &[u8]) -> (Vec2<u32>, Vec<f64>, Vec<f64>) {
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        let grid_at = |start: usize| {
+            let len = u64_at(start) as usize;
+            let cells = (0..len)
+                .map(|i| f64::from_bits(u64_at(start + 8 + i * 8)))
+                .collect::<Vec<_>>();
+            (cells, start + 8 + len * 8)
+        };
+        let (alt, end) = grid_at(20);
+        let (basement, end) = grid_at(end);
+        assert_eq!(end, bytes.len());
+        (Vec2::new(u32_at(4), u32_at(8)), alt, basement)
+    }
+
+    #[test]
+    fn lazy_stitch_matches_stitch_and_loads_one_row_at_a_time() {
+        let map = test_map(Vec2::new(3, 2), |x, y| {
+            (x as f64 * 0.3 + y as f64 / 7.0).cos()
+        });
+        let tiles = split(&map, 2).unwrap();
+        let mut lazy = LazyStitch::new(map.map_size_lg, Vec2::new(1, 1), 1.0);
+        // Added out of order, which they needn't be.
+        for (pos, _) in tiles.iter().rev() {
+            lazy.tile(*pos, format!("{}_{}.bin", pos.x, pos.y));
+        }
+        lazy.validate().unwrap();
+
+        let mut loads = Vec::new();
+        let mut bytes = Vec::new();
+        lazy.write(
+            |path| {
+                let name = path.to_str().unwrap();
+                loads.push(name.to_owned());
+                let (_, tile) = tiles
+                    .iter()
+                    .find(|(pos, _)| format!("{}_{}.bin", pos.x, pos.y) == name)
+                    .unwrap();
+                Ok(ModernMap {
+                    map_size_lg: tile.map_size_lg,
+                    continent_scale_hack: tile.continent_scale_hack,
+                    alt: tile.alt.clone(),
+                    basement: tile.basement.clone(),
+                })
+            },
+            &mut bytes,
+        )
+        .unwrap();
+
+        let (map_size_lg, alt, basement) = read_grids(&bytes);
+        assert_eq!(map_size_lg, map.map_size_lg);
+        assert_eq!(bits(&alt), bits(&map.alt));
+        assert_eq!(bits(&basement), bits(&map.basement));
+        // Each row of tiles in turn, once per grid.
+        let row = ["0_0.bin", "1_0.bin", "2_0.bin", "3_0.bin"];
+        assert_eq!(&loads[..4], &row);
+        assert_eq!(loads.len(), 2 * tiles.len());
+        assert_eq!(&loads[8..12], &row);
+    }
+
+    #[test]
+    fn lazy_stitch_checks_the_layout_before_loading() {
+        let mut lazy = LazyStitch::new(Vec2::new(2, 2), Vec2::new(1, 1), 1.0);
+        for (x, y) in [(0, 0), (1, 0), (0, 1)] {
+            lazy.tile(Vec2::new(x, y), "tile.bin");
+        }
+        assert!(matches!(lazy.validate(), Err(Error::TileLayout(_))));
+        lazy.tile(Vec2::new(0, 1), "tile.bin");
+        assert!(matches!(lazy.validate(), Err(Error::TileLayout(_))));
+
+        let load = |_: &Path| -> Result<ModernMap, Error> { panic!("loaded a tile") };
+        assert!(matches!(
+            lazy.write(load, Vec::new()),
+            Err(Error::TileLayout(_))
+        ));
+    }
+}
@@ -0,0 +1,196 @@
+//! Flips, rotations and transposition of maps.
+//!
+//! Directions are given in world coordinates, where y points north.  Since
+//! exported images put row 0 at the top, they show the map flipped vertically,
+//! so e.g. a counterclockwise rotation looks clockwise in an exported image.
+
+use super::map_size;
+use crate::sim::ModernMap;
+use serde::Serialize;
+use vek::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Transform {
+    /// Mirror along the x axis, swapping the west and east edges.
+    FlipX,
+    /// Mirror along the y axis, swapping the north and south edges.
+    FlipY,
+    /// Swap the x and y axes.
+    Transpose,
+    /// Rotate by 90 degrees counterclockwise.
+    Rotate90,
+    /// Rotate by 180 degrees.
+    Rotate180,
+    /// Rotate by 270 degrees counterclockwise (90 degrees clockwise).
+    Rotate270,
+}
+
+impl Transform {
+    /// The transform undoing this one.
+    pub fn inverse(self) -> Self {
+        match self {
+            Transform::Rotate90 => Transform::Rotate270,
+            Transform::Rotate270 => Transform::Rotate90,
+            other => other,
+        }
+    }
+
+    /// Whether this transform swaps the dimensions of the map.
+    pub fn swaps_axes(self) -> bool {
+        matches!(
+            self,
+            Transform::Transpose | Transform::Rotate90 | Transform::Rotate270
+        )
+    }
+
+    /// Position in a grid of size `src_size` of the cell that ends up at `pos`
+    /// in the transformed grid.
+    fn source_pos(self, pos: Vec2<usize>, src_size: Vec2<usize>) -> Vec2<usize> {
+        let max = src_size - 1;
+        match self {
+            Transform::FlipX => Vec2::new(max.x - pos.x, pos.y),
+            Transform::FlipY => Vec2::new(pos.x, max.y - pos.y),
+            Transform::Transpose => Vec2::new(pos.y, pos.x),
+            Transform::Rotate90 => Vec2::new(pos.y, max.y - pos.x),
+            Transform::Rotate180 => Vec2::new(max.x - pos.x, max.y - pos.y),
+            Transform::Rotate270 => Vec2::new(max.x - pos.y, pos.x),
+        }
+    }
+}
+
+/// Applies `transform` to a row-major grid of size `size`.
+pub fn transform_grid<T: Copy>(grid: &[T], size: Vec2<usize>, transform: Transform) -> Vec<T> {
+    let out_size = if transform.swaps_axes() {
+        size.yx()
+    } else {
+        size
+    };
+    (0..out_size.y)
+        .flat_map(|y| (0..out_size.x).map(move |x| Vec2::new(x, y)))
+        .map(|pos| {
+            let src = transform.source_pos(pos, size);
+            grid[src.y * size.x + src.x]
+        })
+        .collect()
+}
+
+/// Applies `transform` to both the altitude and basement of `map`.
+pub fn transform_map(map: &ModernMap, transform: Transform) -> ModernMap {
+    let size = map_size(map);
+    ModernMap {
+        map_size_lg: if transform.swaps_axes() {
+            map.map_size_lg.yx()
+        } else {
+            map.map_size_lg
+        },
+        continent_scale_hack: map.continent_scale_hack,
+        alt: transform_grid(&map.alt, size, transform).into_boxed_slice(),
+        basement: transform_grid(&map.basement, size, transform).into_boxed_slice(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::{random_grids, test_map};
+
+    const ALL: [Transform; 6] = [
+        Transform::FlipX,
+        Transform::FlipY,
+        Transform::Transpose,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+    ];
+
+    /// A 4x2 map where each cell's altitude encodes its position.
+    fn corners_map() -> ModernMap { test_map(Vec2::new(2, 1), |x, y| (y * 10 + x) as f64) }
+
+    fn at(map: &ModernMap, x: usize, y: usize) -> f64 { map.alt[y * map_size(map).x + x] }
+
+    #[test]
+    fn corners_move_where_expected() {
+        let map = corners_map();
+        // Original corners: SW = 0, SE = 3, NW = 10, NE = 13.
+
+        let flipped = transform_map(&map, Transform::FlipX);
+        assert_eq!(flipped.map_size_lg, Vec2::new(2, 1));
+        assert_eq!([at(&flipped, 0, 0), at(&flipped, 3, 1)], [3.0, 10.0]);
+
+        let flipped = transform_map(&map, Transform::FlipY);
+        assert_eq!([at(&flipped, 0, 0), at(&flipped, 3, 1)], [10.0, 3.0]);
+
+        let transposed = transform_map(&map, Transform::Transpose);
+        assert_eq!(transposed.map_size_lg, Vec2::new(1, 2));
+        assert_eq!([at(&transposed, 1, 0), at(&transposed, 0, 3)], [10.0, 3.0]);
+
+        // Counterclockwise: SE moves to NE, NE to NW.
+        let rotated = transform_map(&map, Transform::Rotate90);
+        assert_eq!(rotated.map_size_lg, Vec2::new(1, 2));
+        assert_eq!([at(&rotated, 1, 3), at(&rotated, 0, 3)], [3.0, 13.0]);
+        assert_eq!([at(&rotated, 1, 0), at(&rotated, 0, 0)], [0.0, 10.0]);
+
+        let rotated = transform_map(&map, Transform::Rotate180);
+        assert_eq!(rotated.map_size_lg, Vec2::new(2, 1));
+        assert_eq!([at(&rotated, 0, 0), at(&rotated, 3, 1)], [13.0, 0.0]);
+
+        // Clockwise: SE moves to SW, SW to NW.
+        let rotated = transform_map(&map, Transform::Rotate270);
+        assert_eq!(rotated.map_size_lg, Vec2::new(1, 2));
+        assert_eq!([at(&rotated, 0, 0), at(&rotated, 0, 3)], [3.0, 0.0]);
+    }
+
+    #[test]
+    fn basement_is_transformed_with_alt() {
+        let map = corners_map();
+        for transform in ALL {
+            let transformed = transform_map(&map, transform);
+            for (alt, basement) in transformed.alt.iter().zip(transformed.basement.iter()) {
+                assert_eq!(alt - 10.0, *basement);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_restores_original() {
+        let map = test_map(Vec2::new(3, 2), |x, y| (x as f64 * 0.37).cos() * y as f64);
+        for transform in ALL {
+            let restored = transform_map(&transform_map(&map, transform), transform.inverse());
+            assert_eq!(restored.map_size_lg, map.map_size_lg);
+            assert_eq!(restored.alt, map.alt, "{:?}", transform);
+            assert_eq!(restored.basement, map.basement, "{:?}", transform);
+        }
+    }
+
+    #[test]
+    fn four_quarter_turns_are_identity() {
+        let map = corners_map();
+        let mut rotated = transform_map(&map, Transform::Rotate90);
+        for _ in 0..3 {
+            rotated = transform_map(&rotated, Transform::Rotate90);
+        }
+        assert_eq!(rotated.alt, map.alt);
+    }
+
+    #[test]
+    fn inverses_restore_random_grids() {
+        for (size, grid) in random_grids(100) {
+            for transform in ALL {
+                let transformed = transform_grid(&grid, size, transform);
+                let new_size = if transform.swaps_axes() {
+                    size.yx()
+                } else {
+                    size
+                };
+                let restored = transform_grid(&transformed, new_size, transform.inverse());
+                assert_eq!(
+                    restored, grid,
+                    "{:?} on a {}x{} grid",
+                    transform, size.x, size.y
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Checking maps for problems that would break, or spoil, a world using them.
+
+use super::{DEFAULT_MIN_STD_DEV, Warning, map_size, warnings};
+use crate::sim::ModernMap;
+use common::terrain::MapSizeLg;
+use std::fmt;
+use vek::*;
+
+/// Number of example coordinates recorded for each failed check.
+pub const MAX_EXAMPLES: usize = 5;
+
+/// The invariants checked by [`verify`], in the order they are checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// `map_size_lg` is within the bounds supported by the engine.
+    MapSize,
+    /// The altitude and basement grids each store one value per cell.
+    GridLength,
+    /// No altitude or basement is NaN or infinite.
+    Finite,
+    /// The basement is nowhere above the altitude.
+    BasementBelowAlt,
+    /// All altitudes are within [`VerifyParams`]' range.
+    AltRange,
+}
+
+impl Check {
+    pub const ALL: [Check; 5] = [
+        Check::MapSize,
+        Check::GridLength,
+        Check::Finite,
+        Check::BasementBelowAlt,
+        Check::AltRange,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::MapSize => "map size",
+            Check::GridLength => "grid length",
+            Check::Finite => "finite",
+            Check::BasementBelowAlt => "basement <= alt",
+            Check::AltRange => "alt range",
+        }
+    }
+}
+
+/// The result of a single check.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed {
+        /// Number of offending cells, or grids for [`Check::GridLength`].
+        count: usize,
+        /// Coordinates of the first few offending cells.
+        examples: Vec<Vec2<usize>>,
+        detail: String,
+    },
+    /// The check was not run because the map's size is unusable.
+    Skipped,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "ok"),
+            Outcome::Skipped => write!(f, "skipped"),
+            Outcome::Failed {
+                examples, detail, ..
+            } => {
+                write!(f, "{}", detail)?;
+                for (i, pos) in examples.iter().enumerate() {
+                    let sep = if i == 0 { ", e.g. at" } else { "," };
+                    write!(f, "{} ({}, {})", sep, pos.x, pos.y)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VerifyParams {
+    /// Lowest plausible altitude.
+    pub min_alt: f64,
+    /// Highest plausible altitude.
+    pub max_alt: f64,
+    /// Standard deviation of the altitudes below which a map is reported as
+    /// [`Warning::Flat`].
+    pub min_std_dev: f64,
+}
+
+impl Default for VerifyParams {
+    fn default() -> Self {
+        Self {
+            min_alt: -2000.0,
+            max_alt: 8000.0,
+            min_std_dev: DEFAULT_MIN_STD_DEV,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    /// The outcome of each of [`Check::ALL`], in that order.
+    pub outcomes: Vec<(Check, Outcome)>,
+    /// Likely mistakes, which don't fail the report; only looked for if the
+    /// map's size is usable.
+    pub warnings: Vec<Warning>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.outcomes
+            .iter()
+            .all(|(_, outcome)| !matches!(outcome, Outcome::Failed { .. }))
+    }
+
+    pub fn outcome(&self, check: Check) -> &Outcome {
+        &self
+            .outcomes
+            .iter()
+            .find(|(c, _)| *c == check)
+            .expect("Report contains every check")
+            .1
+    }
+}
+
+/// Runs every [`Check`] on `map`, which need not have passed
+/// [`validate`](super::validate).
+///
+/// The per-cell checks are skipped if the map's size or grid lengths are
+/// wrong, since cell coordinates are meaningless then.
+pub fn verify(map: &ModernMap, params: &VerifyParams) -> Report {
+    let mut outcomes = Vec::with_capacity(Check::ALL.len());
+    let mut usable = true;
+    for check in Check::ALL {
+        let outcome = if usable {
+            run_check(map, params, check)
+        } else {
+            Outcome::Skipped
+        };
+        if matches!(check, Check::MapSize | Check::GridLength) && outcome != Outcome::Passed {
+            usable = false;
+        }
+        outcomes.push((check, outcome));
+    }
+    Report {
+        outcomes,
+        warnings: if usable {
+            // Altitudes out of range fail `Check::AltRange` instead.
+            warnings(&map.alt, params.min_std_dev, f64::INFINITY)
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+fn run_check(map: &ModernMap, params: &VerifyParams, check: Check) -> Outcome {
+    let width = || map_size(map).x;
+    let cell_pairs = || map.alt.iter().zip(map.basement.iter());
+    match check {
+        Check::MapSize => {
+            if MapSizeLg::new(map.map_size_lg).is_ok() {
+                Outcome::Passed
+            } else {
+                Outcome::Failed {
+                    count: 1,
+                    examples: Vec::new(),
+                    detail: format!(
+                        "map_size_lg {}x{} is not supported by the engine",
+                        map.map_size_lg.x, map.map_size_lg.y
+                    ),
+                }
+            }
+        },
+        Check::GridLength => {
+            let cells = map_size(map).product();
+            let wrong = [("alt", map.alt.len()), ("basement", map.basement.len())]
+                .into_iter()
+                .filter(|(_, len)| *len != cells)
+                .map(|(name, len)| format!("{} stores {} of {} cells", name, len, cells))
+                .collect::<Vec<_>>();
+            if wrong.is_empty() {
+                Outcome::Passed
+            } else {
+                Outcome::Failed {
+                    count: wrong.len(),
+                    examples: Vec::new(),
+                    detail: wrong.join(", "),
+                }
+            }
+        },
+        Check::Finite => check_cells(
+            width(),
+            cell_pairs().map(|(alt, basement)| !alt.is_finite() || !basement.is_finite()),
+            "cells are NaN or infinite",
+        ),
+        Check::BasementBelowAlt => check_cells(
+            width(),
+            // Non-finite cells are only reported by `Check::Finite`.
+            cell_pairs()
+                .map(|(alt, basement)| alt.is_finite() && basement.is_finite() && basement > alt),
+            "cells have their basement above their altitude",
+        ),
+        Check::AltRange => check_cells(
+            width(),
+            map.alt
+                .iter()
+                .map(|alt| alt.is_finite() && !(params.min_alt..=params.max_alt).contains(alt)),
+            &format!(
+                "cells have altitudes outside {}..={}",
+                params.min_alt, params.max_alt
+            ),
+        ),
+    }
+}
+
+/// Counts the cells for which `failed` yields true, recording the coordinates
+/// of the first few.
+fn check_cells(width: usize, failed: impl Iterator<Item = bool>, what: &str) -> Outcome {
+    let mut count = 0;
+    let mut examples = Vec::new();
+    for (i, _) in failed.enumerate().filter(|(_, failed)| *failed) {
+        count += 1;
+        if examples.len() < MAX_EXAMPLES {
+            examples.push(Vec2::new(i % width, i / width));
+        }
+    }
+    if count == 0 {
+        Outcome::Passed
+    } else {
+        Outcome::Failed {
+            count,
+            examples,
+            detail: format!("{} {}", count, what),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::test_map;
+
+    fn failed_count(report: &Report, check: Check) -> Option<usize> {
+        match report.outcome(check) {
+            Outcome::Failed { count, .. } => Some(*count),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn valid_maps_pass() {
+        let map = test_map(Vec2::new(3, 2), |x, y| (x * 100 + y) as f64);
+        let report = verify(&map, &VerifyParams::default());
+        assert!(report.passed());
+        assert!(report.warnings.is_empty());
+        assert!(
+            report
+                .outcomes
+                .iter()
+                .all(|(_, outcome)| *outcome == Outcome::Passed)
+        );
+    }
+
+    #[test]
+    fn bad_cells_are_counted_with_examples() {
+        let mut map = test_map(Vec2::new(2, 2), |_, _| 100.0);
+        map.alt[4 + 1] = f64::NAN;
+        map.basement[3 * 4 + 2] = f64::INFINITY;
+        map.basement[2] = 150.0;
+        map.alt[7] = 9000.0;
+        map.alt[8] = -2500.0;
+        map.basement[8] = -2600.0;
+
+        let report = verify(&map, &VerifyParams::default());
+        assert!(!report.passed());
+        assert_eq!(report.outcome(Check::MapSize), &Outcome::Passed);
+        assert_eq!(report.outcome(Check::GridLength), &Outcome::Passed);
+        assert_eq!(report.outcome(Check::Finite), &Outcome::Failed {
+            count: 2,
+            examples: vec![Vec2::new(1, 1), Vec2::new(2, 3)],
+            detail: "2 cells are NaN or infinite".to_string(),
+        });
+        assert_eq!(failed_count(&report, Check::BasementBelowAlt), Some(1));
+        assert_eq!(failed_count(&report, Check::AltRange), Some(2));
+
+        let lenient = VerifyParams {
+            min_alt: -3000.0,
+            max_alt: 10000.0,
+            ..VerifyParams::default()
+        };
+        assert_eq!(
+            verify(&map, &lenient).outcome(Check::AltRange),
+            &Outcome::Passed
+        );
+    }
+
+    #[test]
+    fn unusable_sizes_skip_cell_checks() {
+        let mut map = test_map(Vec2::new(2, 2), |_, _| f64::NAN);
+        map.map_size_lg = Vec2::new(15, 2);
+        let report = verify(&map, &VerifyParams::default());
+        assert_eq!(failed_count(&report, Check::MapSize), Some(1));
+        assert_eq!(report.outcome(Check::Finite), &Outcome::Skipped);
+
+        let mut map = test_map(Vec2::new(2, 2), |_, _| f64::NAN);
+        map.basement = vec![0.0; 15].into_boxed_slice();
+        let report = verify(&map, &VerifyParams::default());
+        assert_eq!(report.outcome(Check::GridLength), &Outcome::Failed {
+            count: 1,
+            examples: Vec::new(),
+            detail: "basement stores 15 of 16 cells".to_string(),
+        });
+        assert_eq!(report.outcome(Check::AltRange), &Outcome::Skipped);
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn flat_maps_pass_with_a_warning() {
+        let map = test_map(Vec2::new(2, 2), |_, _| 50.0);
+        let report = verify(&map, &VerifyParams::default());
+        assert!(report.passed());
+        assert_eq!(report.warnings, vec![Warning::Flat { std_dev: 0.0 }]);
+    }
+}
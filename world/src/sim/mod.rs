@@ -4,15 +4,19 @@ mod location;
 mod map;
 mod util;
 mod way;
+mod world_file;
 
 // Reexports
 use self::erosion::Compute;
+#[cfg(feature = "heightmap")]
+pub(crate) use self::world_file::{SNIFFED_LEN, WORLD_FILE_VARIANT, ZSTD_MAGIC};
 pub use self::{
     diffusion::diffusion,
     location::Location,
     map::{sample_pos, sample_wpos},
     util::get_horizon_map,
     way::{Path, Way},
+    world_file::{FileFormat, LoadError},
 };
 pub(crate) use self::{
     erosion::{
@@ -66,7 +70,7 @@ use serde::{Deserialize, Serialize};
 use std::{
     f32,
     fs::File,
-    io::{BufReader, BufWriter},
+    io::BufWriter,
     ops::{Add, Div, Mul, Neg, Sub},
     path::PathBuf,
     sync::Arc,
@@ -180,9 +184,12 @@ pub enum FileOpts {
     /// If set, load the world file from this path in legacy format (errors if
     /// path not found).  This option may be removed at some point, since it
     /// only applies to maps generated before map saving was merged into
-    /// master.
+    /// master, which `Load` also recognizes.
     LoadLegacy(PathBuf),
     /// If set, load the world file from this path (errors if path not found).
+    ///
+    /// Files of every version are accepted, including legacy ones, and may be
+    /// compressed with zstd, like the files written by the heightmap tools.
     Load(PathBuf),
     /// If set, look for  the world file at this asset specifier (errors if
     /// asset is not found).
@@ -254,54 +261,20 @@ impl FileOpts {
     // whether to log error
     fn try_load_map(&self) -> Option<ModernMap> {
         let map = match self {
-            Self::LoadLegacy(path) => {
-                let file = match File::open(path) {
-                    Ok(file) => file,
-                    Err(e) => {
-                        warn!(?e, ?path, "Couldn't read path for maps");
-                        return None;
-                    },
-                };
-
-                let reader = BufReader::new(file);
-                let map: WorldFileLegacy = match bincode::deserialize_from(reader) {
-                    Ok(map) => map,
-                    Err(e) => {
-                        warn!(
-                            ?e,
-                            "Couldn't parse legacy map.  Maybe you meant to try a regular load?"
-                        );
-                        return None;
-                    },
-                };
-
-                map.into_modern()
-            },
-            Self::Load(path) => {
-                let file = match File::open(path) {
-                    Ok(file) => file,
-                    Err(e) => {
-                        warn!(?e, ?path, "Couldn't read path for maps");
-                        return None;
-                    },
-                };
-
-                let reader = BufReader::new(file);
-                let map: WorldFile = match bincode::deserialize_from(reader) {
-                    Ok(map) => map,
-                    Err(e) => {
-                        warn!(
-                            ?e,
-                            "Couldn't parse modern map.  Maybe you meant to try a legacy load?"
-                        );
-                        return None;
-                    },
-                };
-
-                map.into_modern()
+            Self::LoadLegacy(path) | Self::Load(path) => match WorldFile::load(path) {
+                Ok(map) => Ok(map),
+                Err(LoadError::Io(e)) => {
+                    warn!(?e, ?path, "Couldn't read path for maps");
+                    return None;
+                },
+                Err(LoadError::Bincode(e)) => {
+                    warn!(?e, ?path, "Couldn't parse map");
+                    return None;
+                },
+                Err(LoadError::WorldFile(e)) => Err(e),
             },
             Self::LoadAsset(specifier) => match WorldFile::load_owned(specifier) {
-                Ok(map) => map.into_modern(),
+                Ok(map) => map.into_latest(),
                 Err(err) => {
                     match err.reason().downcast_ref::<std::io::Error>() {
                         Some(e) => {
@@ -324,22 +297,20 @@ impl FileOpts {
                 // always defined
                 let path = self.map_path().unwrap();
 
-                let file = match File::open(&path) {
-                    Ok(file) => file,
-                    Err(e) => {
+                // Maps of older versions are upgraded first, so that their size
+                // and scale are compared in the latest representation.
+                let map = match WorldFile::load(&path) {
+                    Ok(map) => map,
+                    Err(LoadError::Io(e)) => {
                         warn!(?e, ?path, "Couldn't find needed map. Generating...");
                         return None;
                     },
-                };
-
-                let reader = BufReader::new(file);
-                let map: WorldFile = match bincode::deserialize_from(reader) {
-                    Ok(map) => map,
-                    Err(e) => {
-                        warn!(
-                            ?e,
-                            "Couldn't parse modern map.  Maybe you meant to try a legacy load?"
-                        );
+                    Err(LoadError::Bincode(e)) => {
+                        warn!(?e, ?path, "Couldn't parse needed map. Generating...");
+                        return None;
+                    },
+                    Err(LoadError::WorldFile(e)) => {
+                        warn!(?e, ?path, "Couldn't upgrade needed map. Generating...");
                         return None;
                     },
                 };
@@ -359,13 +330,6 @@ impl FileOpts {
                 let GenOpts {
                     x_lg, y_lg, scale, ..
                 } = opts;
-                let map = match map {
-                    WorldFile::Veloren0_7_0(map) => map,
-                    WorldFile::Veloren0_5_0(_) => {
-                        panic!("World file v0.5.0 isn't supported with LoadOrGenerate.")
-                    },
-                };
-
                 if map.continent_scale_hack != *scale || map.map_size_lg != Vec2::new(*x_lg, *y_lg)
                 {
                     if *overwrite {
@@ -385,7 +349,7 @@ impl FileOpts {
                     return None;
                 }
 
-                map.into_modern()
+                Ok(map)
             },
             Self::Generate { .. } | Self::Save { .. } => return None,
         };
@@ -488,6 +452,12 @@ pub struct WorldMap_0_5_0 {
 }
 
 /// Version of the world map intended for use in Veloren 0.7.0.
+///
+/// The serde derives, which [`WorldFile`] relies on for bincode, also let the
+/// map be embedded in other serializable types: `map_size_lg` serializes as a
+/// struct with `x` and `y` fields, and the grids as sequences, so JSON holds
+/// them as arrays of numbers.  Formats without infinities or NaNs, JSON among
+/// them, can only round-trip maps whose altitudes are all finite.
 #[derive(Serialize, Deserialize)]
 #[repr(C)]
 pub struct WorldMap_0_7_0 {
@@ -660,6 +630,24 @@ impl WorldFile {
             WorldFile::Veloren0_7_0(map) => map.into_modern(),
         }
     }
+
+    /// The map data, if this file is of version 0.7.0, without converting
+    /// other versions.
+    pub fn as_v0_7_0(&self) -> Option<&WorldMap_0_7_0> {
+        match self {
+            WorldFile::Veloren0_7_0(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// The map data, if this file is of version 0.7.0, or the file itself
+    /// back otherwise, so that callers can convert it or report its version.
+    pub fn into_v0_7_0(self) -> Result<WorldMap_0_7_0, WorldFile> {
+        match self {
+            WorldFile::Veloren0_7_0(map) => Ok(map),
+            file => Err(file),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -2837,3 +2825,84 @@ impl SimChunk {
             .or_else(|| self.poi.map(|poi| civs_pois[poi].name.clone()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_maps_load_like_uncompressed_ones() {
+        let map = || WorldMap_0_7_0 {
+            map_size_lg: Vec2::new(1, 2),
+            continent_scale_hack: 1.5,
+            alt: (0..8).map(f64::from).collect(),
+            basement: vec![-1.0; 8].into(),
+        };
+        let bytes = bincode::serialize(&WorldFile::new(map())).unwrap();
+        let dir = std::env::temp_dir();
+        let plain = dir.join(format!("veloren-sim-plain-{}.bin", std::process::id()));
+        let compressed = dir.join(format!(
+            "veloren-sim-compressed-{}.bin.zst",
+            std::process::id()
+        ));
+        std::fs::write(&plain, &bytes).unwrap();
+        std::fs::write(&compressed, zstd::encode_all(&bytes[..], 3).unwrap()).unwrap();
+
+        for path in [&plain, &compressed] {
+            let loaded = FileOpts::Load(path.clone())
+                .try_load_map()
+                .unwrap_or_else(|| panic!("{} doesn't load", path.display()));
+            assert_eq!(loaded.map_size_lg, map().map_size_lg);
+            assert_eq!(loaded.continent_scale_hack, map().continent_scale_hack);
+            assert_eq!(loaded.alt, map().alt);
+            assert_eq!(loaded.basement, map().basement);
+        }
+
+        // Files that aren't maps, compressed or not, load as nothing.
+        std::fs::write(&plain, b"not a map").unwrap();
+        std::fs::write(&compressed, zstd::encode_all(&b"not a map"[..], 3).unwrap()).unwrap();
+        for path in [plain, compressed] {
+            assert!(FileOpts::Load(path.clone()).try_load_map().is_none());
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn older_maps_are_upgraded_before_load_or_generate_compares_them() {
+        let name = format!("veloren-sim-upgrade-{}", std::process::id());
+        let opts = |x_lg, scale| FileOpts::LoadOrGenerate {
+            name: name.clone(),
+            opts: GenOpts {
+                x_lg,
+                y_lg: x_lg,
+                scale,
+                ..GenOpts::default()
+            },
+            overwrite: true,
+        };
+        let path = opts(1, 1.0).map_path().unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let save = |cells| {
+            let map = WorldFile::Veloren0_5_0(WorldMap_0_5_0 {
+                alt: vec![1.0; cells].into(),
+                basement: vec![0.0; cells].into(),
+            });
+            std::fs::write(&path, bincode::serialize(&map).unwrap()).unwrap();
+        };
+
+        // A 2x2 0.5.0 map upgrades to `map_size_lg` (1, 1), at the scale
+        // recommended for its size.
+        save(4);
+        let map = opts(1, 1.0 / 512.0).try_load_map().unwrap();
+        assert_eq!(map.map_size_lg, Vec2::new(1, 1));
+        assert!(opts(1, 1.0).try_load_map().is_none());
+        assert!(opts(2, 1.0 / 512.0).try_load_map().is_none());
+        // Maps that can't be upgraded are regenerated rather than panicking.
+        save(8);
+        assert!(opts(1, 1.0 / 512.0).try_load_map().is_none());
+
+        std::fs::remove_file(&path).unwrap();
+        // Left in place if it holds anything besides the test's map.
+        let _ = std::fs::remove_dir(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,158 @@
+//! Loading world files, shared by the server and the heightmap tools so that
+//! every file one of them accepts, the other does too.
+//!
+//! World files are bincode's encoding of a [`WorldFile`], or of a bare
+//! [`WorldFileLegacy`] for maps from before the versions, and may be
+//! compressed with zstd (the layout is described in the documentation of
+//! `heightmap::io`).  Loading tells them all apart by their first bytes and
+//! upgrades them to a [`ModernMap`].
+
+use super::{ModernMap, WorldFile, WorldFileError, WorldFileLegacy};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+/// First bytes of every zstd frame.
+pub(crate) const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Number of bytes [`FileFormat::sniff`] looks at.
+pub(crate) const SNIFFED_LEN: usize = 8;
+
+/// Number of cells of every legacy map, which is also the first `u64` of its
+/// file.
+const LEGACY_CELLS: u64 = 1024 * 1024;
+
+/// Index of the `WorldFile::Veloren0_7_0` variant, which bincode writes in
+/// front of the map.
+pub(crate) const WORLD_FILE_VARIANT: u32 = 1;
+
+/// Layout of an uncompressed world file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    /// A bare `WorldFileLegacy`, of a 1024x1024 map.
+    Legacy,
+    /// `WorldFile::Veloren0_5_0`.
+    Veloren0_5_0,
+    /// `WorldFile::Veloren0_7_0`, which the tools write.
+    Veloren0_7_0,
+}
+
+impl FileFormat {
+    /// The format of the uncompressed world file starting with `prefix`,
+    /// which should hold its first 8 bytes, or `None` if they match no
+    /// format.
+    ///
+    /// The first `u64` of a legacy file is always 1024 * 1024, which no
+    /// variant index can begin, so legacy files are told apart from
+    /// versioned ones.
+    pub fn sniff(prefix: &[u8]) -> Option<Self> {
+        if prefix
+            .first_chunk()
+            .is_some_and(|&first| u64::from_le_bytes(first) == LEGACY_CELLS)
+        {
+            return Some(FileFormat::Legacy);
+        }
+        match u32::from_le_bytes(*prefix.first_chunk()?) {
+            0 => Some(FileFormat::Veloren0_5_0),
+            WORLD_FILE_VARIANT => Some(FileFormat::Veloren0_7_0),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FileFormat::Legacy => "legacy (unversioned 1024x1024)",
+            FileFormat::Veloren0_5_0 => "Veloren 0.5.0",
+            FileFormat::Veloren0_7_0 => "Veloren 0.7.0",
+        })
+    }
+}
+
+/// Errors when loading a world file.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file couldn't be read, or its compressed stream is corrupt.
+    Io(io::Error),
+    /// The file isn't a world file, or is truncated.
+    Bincode(bincode::Error),
+    /// The map can't be upgraded to the latest version.
+    WorldFile(WorldFileError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "I/O error: {}", e),
+            LoadError::Bincode(e) => write!(f, "Could not deserialize world file: {}", e),
+            LoadError::WorldFile(e) => write!(f, "Invalid world file: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self { LoadError::Io(e) }
+}
+
+impl From<bincode::Error> for LoadError {
+    fn from(e: bincode::Error) -> Self { LoadError::Bincode(e) }
+}
+
+impl From<WorldFileError> for LoadError {
+    fn from(e: WorldFileError) -> Self { LoadError::WorldFile(e) }
+}
+
+impl WorldFile {
+    /// Converts the map to the latest version, the one [`ModernMap`] stands
+    /// for, whatever the version of the file.
+    ///
+    /// This is [`into_modern`](Self::into_modern) under the name the loaders
+    /// use: each version converts into the next one, filling in the fields it
+    /// adds, until the latest, so that callers never match on variants.
+    #[inline]
+    pub fn into_latest(self) -> Result<ModernMap, WorldFileError> { self.into_modern() }
+
+    /// Loads the world file at `path`, of any [`FileFormat`], compressed with
+    /// zstd or not, and converts it to the latest map version with
+    /// [`into_latest`](Self::into_latest).
+    pub fn load(path: impl AsRef<Path>) -> Result<ModernMap, LoadError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Like [`load`](Self::load), but reading the file from `reader`, such as
+    /// the bytes of a memory-mapped file.
+    ///
+    /// Files starting with the zstd magic number are decompressed while they
+    /// are deserialized.
+    pub fn read(mut reader: impl BufRead) -> Result<ModernMap, LoadError> {
+        if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+            read_uncompressed(zstd::Decoder::with_buffer(reader)?)
+        } else {
+            read_uncompressed(reader)
+        }
+    }
+}
+
+/// Deserializes an uncompressed world file of any [`FileFormat`] from
+/// `reader`.
+fn read_uncompressed(mut reader: impl Read) -> Result<ModernMap, LoadError> {
+    let mut prefix = Vec::with_capacity(SNIFFED_LEN);
+    (&mut reader)
+        .take(SNIFFED_LEN as u64)
+        .read_to_end(&mut prefix)?;
+    let format = FileFormat::sniff(&prefix);
+    let reader = prefix.as_slice().chain(reader);
+    Ok(match format {
+        Some(FileFormat::Legacy) => {
+            bincode::deserialize_from::<_, WorldFileLegacy>(reader)?.into_modern()?
+        },
+        // Unknown variants are left for bincode to report.
+        _ => bincode::deserialize_from::<_, WorldFile>(reader)?.into_latest()?,
+    })
+}
@@ -0,0 +1,301 @@
+//! Round trips of synthetic heightmap images through `.bin` world files and
+//! back, pinning down the scaling and orientation of the conversions.
+
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+use std::collections::HashSet;
+use vek::*;
+use veloren_world::{
+    heightmap::{
+        self, Compression,
+        export::{compute_min_max, render_grayscale},
+        import::{ImportParams, import_image},
+        stats::percentile_range,
+    },
+    sim::ModernMap,
+};
+
+const SCALE: f64 = 1234.5;
+const OFFSET: f64 = -321.0;
+
+fn params() -> ImportParams { ImportParams::new(SCALE, OFFSET) }
+
+/// Saves `map` to a temporary world file and loads it again.
+fn through_file(map: ModernMap, name: &str) -> ModernMap {
+    let path = std::env::temp_dir().join(format!(
+        "veloren-heightmap-roundtrip-{}-{}.bin",
+        std::process::id(),
+        name
+    ));
+    heightmap::save_map(&path, map).unwrap();
+    let loaded = heightmap::load_map(&path);
+    std::fs::remove_file(&path).unwrap();
+    loaded.unwrap()
+}
+
+/// Imports `img`, saves and reloads the map, and renders it again using the
+/// altitude range of the import, so that pixel values should be unchanged.
+fn round_trip(img: &GrayImage, name: &str) -> (ModernMap, RgbImage) {
+    let map = import_image(&DynamicImage::ImageLuma8(img.clone()), &params()).unwrap();
+    let map = through_file(map, name);
+    let rendered = render_grayscale(&map.alt, heightmap::map_size(&map), OFFSET, OFFSET + SCALE);
+    (map, rendered)
+}
+
+fn assert_pixels_preserved(img: &GrayImage, rendered: &RgbImage) {
+    assert_eq!(img.dimensions(), rendered.dimensions());
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let v = pixel[0];
+        assert_eq!(
+            rendered.get_pixel(x, y),
+            &Rgb([v, v, v]),
+            "at ({}, {})",
+            x,
+            y
+        );
+    }
+}
+
+#[test]
+fn gradient_round_trips() {
+    let img = GrayImage::from_fn(16, 16, |x, y| Luma([(x * 16 + y) as u8]));
+    let (map, rendered) = round_trip(&img, "gradient");
+    assert_eq!(map.map_size_lg.x, 4);
+    assert_eq!(map.map_size_lg.y, 4);
+    assert_eq!(map.continent_scale_hack, 1.6);
+    assert_pixels_preserved(&img, &rendered);
+
+    // Altitudes are exact up to floating point error, not just within the
+    // quantization of the exported image.
+    for (i, alt) in map.alt.iter().enumerate() {
+        let (x, y) = (i % 16, i / 16);
+        let expected = ((x * 16 + y) as f64 / 255.0) * SCALE + OFFSET;
+        assert!((alt - expected).abs() < 1e-9, "{} != {}", alt, expected);
+    }
+    assert_eq!(map.alt, map.basement);
+}
+
+#[test]
+fn checkerboard_round_trips() {
+    let img = GrayImage::from_fn(8, 8, |x, y| Luma([if (x + y) % 2 == 0 { 0 } else { 255 }]));
+    let (map, rendered) = round_trip(&img, "checkerboard");
+    assert_eq!(map.alt[0], OFFSET);
+    assert_eq!(map.alt[1], OFFSET + SCALE);
+    assert_pixels_preserved(&img, &rendered);
+}
+
+/// A single bright pixel away from the diagonal must end up in the same row
+/// and column of the map, and of the exported image: map cell `(x, y)` is
+/// stored at index `y * width + x`, and image row `y` is map row `y`, so
+/// neither conversion mirrors or transposes the map.
+#[test]
+fn spike_keeps_its_position() {
+    let (width, spike) = (8, (5, 2));
+    let img = GrayImage::from_fn(width, width, |x, y| {
+        Luma([if (x, y) == spike { 200 } else { 10 }])
+    });
+    let (map, rendered) = round_trip(&img, "spike");
+
+    let peak = map
+        .alt
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .unwrap()
+        .0;
+    assert_eq!(peak, spike.1 as usize * width as usize + spike.0 as usize);
+    assert!(map.alt[spike.0 as usize * width as usize + spike.1 as usize] < map.alt[peak]);
+
+    assert_eq!(rendered.get_pixel(spike.0, spike.1), &Rgb([200; 3]));
+    assert_eq!(rendered.get_pixel(spike.1, spike.0), &Rgb([10; 3]));
+    assert_eq!(
+        rendered.get_pixel(spike.0, width - 1 - spike.1),
+        &Rgb([10; 3])
+    );
+    assert_pixels_preserved(&img, &rendered);
+}
+
+/// Exporting with the map's own altitude range stretches it to the full
+/// 0-255 range, losing at most half a step of precision.
+#[test]
+fn export_quantization_error_is_bounded() {
+    let img = GrayImage::from_fn(4, 4, |x, y| Luma([(40 + x * 9 + y * 31) as u8]));
+    let map = import_image(&DynamicImage::ImageLuma8(img), &params()).unwrap();
+    let map = through_file(map, "quantization");
+    let (min, max) = heightmap::export::compute_min_max(&map.alt);
+    let rendered = render_grayscale(&map.alt, heightmap::map_size(&map), min, max);
+
+    let step = (max - min) / 255.0;
+    for (i, alt) in map.alt.iter().enumerate() {
+        let value = rendered.get_pixel(i as u32 % 4, i as u32 / 4)[0] as f64;
+        let restored = min + value * step;
+        assert!(
+            (restored - alt).abs() <= step / 2.0 + 1e-9,
+            "{} restored as {}",
+            alt,
+            restored
+        );
+    }
+}
+
+/// A single spike squeezes the rest of a map into the darkest shades of an
+/// export over its own range; clipping it at a percentile gives them back the
+/// full range of grays.
+#[test]
+fn percentile_clipping_recovers_the_visible_range() {
+    let size = Vec2::new(64, 64);
+    let mut alt = (0..64 * 64)
+        .map(|i| (i % 64 + i / 64) as f64 * 10.0)
+        .collect::<Vec<_>>();
+    let spike = 100;
+    alt[spike] = 9000.0;
+    // The grays of all cells but the spike.
+    let grays = |img: &RgbImage| {
+        img.pixels()
+            .enumerate()
+            .filter(|(i, _)| *i != spike)
+            .map(|(_, pixel)| pixel[0])
+            .collect::<HashSet<_>>()
+    };
+
+    let (min, max) = compute_min_max(&alt);
+    let raw = render_grayscale(&alt, size, min, max);
+    assert!(grays(&raw).len() <= 40);
+    assert!(grays(&raw).iter().all(|&gray| gray < 40));
+
+    let (lo, hi) = percentile_range(&alt, 0.1);
+    assert!(lo < 50.0 && hi < 1300.0, "{}..{}", lo, hi);
+    let clipped = render_grayscale(&alt, size, lo, hi);
+    assert!(grays(&clipped).len() >= 100);
+    assert!(grays(&clipped).contains(&255));
+    assert_eq!(
+        clipped.get_pixel(spike as u32 % 64, spike as u32 / 64)[0],
+        255
+    );
+}
+
+/// A window of a map exported with `--region --region-full-range` is exactly
+/// the same crop of an export of the whole map, in every mode coloring cells
+/// by their own altitude, while a `--region` alone is shaded over its own
+/// range.
+#[cfg(feature = "cli")]
+#[test]
+fn region_exports_match_crops_of_full_exports() {
+    use clap::Parser;
+    use veloren_world::heightmap::cli::{self, ExportArgs};
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        export: ExportArgs,
+    }
+
+    let size = Vec2::new(64, 32);
+    let map = ModernMap {
+        map_size_lg: Vec2::new(6, 5),
+        continent_scale_hack: 1.0,
+        alt: (0..size.product())
+            .map(|i| ((i % 64) as f64 * 0.3).sin() * 400.0 + (i / 64) as f64 * 25.0)
+            .collect(),
+        basement: vec![0.0; size.product()].into(),
+    };
+    let path = |name: &str| {
+        std::env::temp_dir().join(format!(
+            "veloren-heightmap-region-{}-{}.png",
+            std::process::id(),
+            name
+        ))
+    };
+    // Exports `map` with the options `options`, returning the image.
+    let export = |name: &str, options: &[&str]| {
+        let args = Cli::try_parse_from(["export"].iter().chain(options)).unwrap();
+        let path = path(name);
+        cli::export(&map, &path, &args.export).unwrap();
+        let img = image::open(&path).unwrap().into_rgb8();
+        std::fs::remove_file(&path).unwrap();
+        img
+    };
+    let crop_of = |img: &RgbImage| RgbImage::from_fn(20, 16, |x, y| *img.get_pixel(12 + x, 5 + y));
+
+    for color in ["gray", "hypsometric"] {
+        let full = export("full", &["--color", color]);
+        assert_eq!(full.dimensions(), (64, 32));
+        let region = export("window", &[
+            "--color",
+            color,
+            "--region",
+            "12,5,20,16",
+            "--region-full-range",
+        ]);
+        assert_eq!(region.dimensions(), (20, 16));
+        assert_eq!(region, crop_of(&full), "{}", color);
+
+        // Shaded over its own range instead, the window differs.
+        let own = export("own", &["--color", color, "--region", "12,5,20,16"]);
+        assert_eq!(own.dimensions(), (20, 16));
+        assert_ne!(own, crop_of(&full), "{}", color);
+    }
+    // ...and spans every gray.
+    let own = export("own", &["--region", "12,5,20,16"]);
+    assert!(own.pixels().any(|pixel| pixel[0] == 0));
+    assert!(own.pixels().any(|pixel| pixel[0] == 255));
+
+    let args = Cli::try_parse_from(["export", "--region", "60,0,9,1"]).unwrap();
+    assert!(cli::export(&map, &path("beyond"), &args.export).is_err());
+}
+
+/// Compressed world files load into exactly the map that was saved, and
+/// decompress to the bytes of the uncompressed file.
+#[test]
+fn compressed_maps_round_trip() {
+    // Gentle hills quantized to 8 bits, like most converted heightmaps.
+    let img = GrayImage::from_fn(256, 256, |x, y| {
+        let hills = (x as f64 / 40.0).sin() * (y as f64 / 25.0).cos();
+        Luma([((hills + 1.0) * 127.0) as u8])
+    });
+    let import = || import_image(&DynamicImage::ImageLuma8(img.clone()), &params()).unwrap();
+    let dir = std::env::temp_dir();
+    let plain = dir.join(format!(
+        "veloren-heightmap-plain-{}.bin",
+        std::process::id()
+    ));
+    let compressed = dir.join(format!(
+        "veloren-heightmap-compressed-{}.bin.zst",
+        std::process::id()
+    ));
+    heightmap::save_map(&plain, import()).unwrap();
+    heightmap::save_map_compressed(&compressed, import(), Compression::Zstd { level: 3 }).unwrap();
+
+    let expected = import();
+    for path in [&plain, &compressed] {
+        let map = heightmap::load_map(path).unwrap();
+        assert_eq!(map.map_size_lg, expected.map_size_lg);
+        assert_eq!(
+            map.continent_scale_hack.to_bits(),
+            expected.continent_scale_hack.to_bits()
+        );
+        for (grid, expected) in [
+            (&map.alt, &expected.alt),
+            (&map.basement, &expected.basement),
+        ] {
+            assert!(
+                grid.iter()
+                    .map(|v| v.to_bits())
+                    .eq(expected.iter().map(|v| v.to_bits()))
+            );
+        }
+    }
+
+    let plain_bytes = std::fs::read(&plain).unwrap();
+    let compressed_bytes = std::fs::read(&compressed).unwrap();
+    assert_eq!(
+        zstd::decode_all(&compressed_bytes[..]).unwrap(),
+        plain_bytes
+    );
+    assert!(compressed_bytes.len() < plain_bytes.len());
+    println!(
+        "Compression ratio of the 256x256 fixture: {:.1}",
+        plain_bytes.len() as f64 / compressed_bytes.len() as f64
+    );
+    std::fs::remove_file(&plain).unwrap();
+    std::fs::remove_file(&compressed).unwrap();
+}